@@ -0,0 +1,267 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Response curves for actuators. A curve is split into two parts:
+//
+// - The output range (min/max), which gets written into the step limit of the device feature in the
+//   user config. The server scales non-zero commands into that range itself, so it applies to every
+//   client, which is what actually fixes the "nothing happens below 30%" dead zone problem.
+// - The shape, which remaps 0.0-1.0 input to 0.0-1.0 output before the range is applied. The engine
+//   doesn't give us a hook into the client command path, so shapes are applied on command paths the
+//   bridge owns: the backdoor, and websocket clients, which get proxied (see websocket_proxy) if any
+//   device has curves when the engine starts.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ActuatorCurveShape {
+  Linear,
+  EaseIn { exponent: f64 },
+  // List of (input, output) pairs, both 0.0-1.0. Values between points are linearly interpolated.
+  Custom { points: Vec<(f64, f64)> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActuatorCurve {
+  // Index into the feature list of the user device definition.
+  pub feature_index: u32,
+  pub min: f64,
+  pub max: f64,
+  pub shape: ActuatorCurveShape,
+}
+
+impl ActuatorCurveShape {
+  pub fn apply(&self, value: f64) -> f64 {
+    let value = value.clamp(0.0, 1.0);
+    // Off always means off, no matter what the curve says.
+    if value == 0.0 {
+      return 0.0;
+    }
+    let output = match self {
+      ActuatorCurveShape::Linear => value,
+      ActuatorCurveShape::EaseIn { exponent } => {
+        if *exponent > 0.0 {
+          value.powf(*exponent)
+        } else {
+          value
+        }
+      }
+      ActuatorCurveShape::Custom { points } => interpolate(points, value),
+    };
+    output.clamp(0.0, 1.0)
+  }
+}
+
+pub(crate) fn interpolate(points: &[(f64, f64)], value: f64) -> f64 {
+  let mut sorted = points.to_vec();
+  sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+  let (Some(first), Some(last)) = (sorted.first(), sorted.last()) else {
+    return value;
+  };
+  if value <= first.0 {
+    return first.1;
+  }
+  if value >= last.0 {
+    return last.1;
+  }
+  for window in sorted.windows(2) {
+    let (lower, upper) = (window[0], window[1]);
+    if value >= lower.0 && value <= upper.0 {
+      if upper.0 == lower.0 {
+        return upper.1;
+      }
+      return lower.1 + (value - lower.0) / (upper.0 - lower.0) * (upper.1 - lower.1);
+    }
+  }
+  value
+}
+
+impl ActuatorCurve {
+  pub fn validate(&self) -> Result<()> {
+    if !(0.0..=1.0).contains(&self.min) || !(0.0..=1.0).contains(&self.max) {
+      return Err(anyhow::Error::msg(
+        "Curve range values must be between 0.0 and 1.0",
+      ));
+    }
+    if self.min >= self.max {
+      return Err(anyhow::Error::msg(
+        "Curve range minimum must be less than maximum",
+      ));
+    }
+    match &self.shape {
+      ActuatorCurveShape::EaseIn { exponent } if *exponent <= 0.0 => Err(anyhow::Error::msg(
+        "Ease in exponent must be greater than 0",
+      )),
+      ActuatorCurveShape::Custom { points } => {
+        if points.len() < 2 {
+          return Err(anyhow::Error::msg("Custom curves need at least 2 points"));
+        }
+        if points
+          .iter()
+          .any(|(x, y)| !(0.0..=1.0).contains(x) || !(0.0..=1.0).contains(y))
+        {
+          return Err(anyhow::Error::msg(
+            "Custom curve points must be between 0.0 and 1.0",
+          ));
+        }
+        Ok(())
+      }
+      _ => Ok(()),
+    }
+  }
+}
+
+// Rewrites the scalar values of any ScalarCmd messages in a serialized message array. The lookup
+// takes a device index and scalar index, and returns the shape to apply, if any. If nothing in the
+// message needs changing, returns None so the caller can pass the original through untouched.
+pub fn shape_scalar_commands<F>(msg: &str, lookup: F) -> Option<String>
+where
+  F: Fn(u32, u32) -> Option<ActuatorCurveShape>,
+{
+  let mut messages: Value = serde_json::from_str(msg).ok()?;
+  let mut changed = false;
+  for message in messages.as_array_mut()? {
+    let Some(scalar_cmd) = message.get_mut("ScalarCmd") else {
+      continue;
+    };
+    let Some(device_index) = scalar_cmd.get("DeviceIndex").and_then(|x| x.as_u64()) else {
+      continue;
+    };
+    let Some(scalars) = scalar_cmd.get_mut("Scalars").and_then(|x| x.as_array_mut()) else {
+      continue;
+    };
+    for scalar in scalars {
+      let (Some(index), Some(value)) = (
+        scalar.get("Index").and_then(|x| x.as_u64()),
+        scalar.get("Scalar").and_then(|x| x.as_f64()),
+      ) else {
+        continue;
+      };
+      if let Some(shape) = lookup(device_index as u32, index as u32) {
+        scalar["Scalar"] = Value::from(shape.apply(value));
+        changed = true;
+      }
+    }
+  }
+  if changed {
+    serde_json::to_string(&messages).ok()
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn curve(min: f64, max: f64, shape: ActuatorCurveShape) -> ActuatorCurve {
+    ActuatorCurve {
+      feature_index: 0,
+      min,
+      max,
+      shape,
+    }
+  }
+
+  #[test]
+  fn shapes() {
+    let linear = ActuatorCurveShape::Linear;
+    assert_eq!(linear.apply(0.3), 0.3);
+    assert_eq!(linear.apply(1.5), 1.0);
+    let ease_in = ActuatorCurveShape::EaseIn { exponent: 2.0 };
+    assert_eq!(ease_in.apply(0.5), 0.25);
+    assert_eq!(ease_in.apply(1.0), 1.0);
+    let custom = ActuatorCurveShape::Custom {
+      points: vec![(1.0, 1.0), (0.0, 0.2), (0.5, 0.4)],
+    };
+    assert!((custom.apply(0.25) - 0.3).abs() < 1e-9);
+    assert!((custom.apply(0.75) - 0.7).abs() < 1e-9);
+  }
+
+  #[test]
+  fn off_stays_off() {
+    let custom = ActuatorCurveShape::Custom {
+      points: vec![(0.0, 0.5), (1.0, 1.0)],
+    };
+    assert_eq!(custom.apply(0.0), 0.0);
+    assert_eq!(custom.apply(-1.0), 0.0);
+    assert_eq!(custom.apply(0.01), 0.505);
+  }
+
+  #[test]
+  fn interpolate_clamps_to_end_points() {
+    let points = [(0.2, 0.3), (0.8, 0.9)];
+    assert_eq!(interpolate(&points, 0.1), 0.3);
+    assert_eq!(interpolate(&points, 0.9), 0.9);
+    assert_eq!(interpolate(&[], 0.4), 0.4);
+  }
+
+  #[test]
+  fn validation() {
+    assert!(curve(0.2, 0.8, ActuatorCurveShape::Linear)
+      .validate()
+      .is_ok());
+    assert!(curve(-0.1, 0.8, ActuatorCurveShape::Linear)
+      .validate()
+      .is_err());
+    assert!(curve(0.8, 0.8, ActuatorCurveShape::Linear)
+      .validate()
+      .is_err());
+    assert!(
+      curve(0.0, 1.0, ActuatorCurveShape::EaseIn { exponent: 0.0 })
+        .validate()
+        .is_err()
+    );
+    assert!(curve(
+      0.0,
+      1.0,
+      ActuatorCurveShape::Custom {
+        points: vec![(0.0, 0.0)]
+      }
+    )
+    .validate()
+    .is_err());
+    assert!(curve(
+      0.0,
+      1.0,
+      ActuatorCurveShape::Custom {
+        points: vec![(0.0, 0.0), (1.0, 1.1)]
+      }
+    )
+    .validate()
+    .is_err());
+  }
+
+  #[test]
+  fn shapes_scalar_commands() {
+    let msg = json!([
+      {"Ping": {"Id": 1}},
+      {"ScalarCmd": {"Id": 2, "DeviceIndex": 3, "Scalars": [
+        {"Index": 0, "Scalar": 0.5, "ActuatorType": "Vibrate"},
+        {"Index": 1, "Scalar": 0.5, "ActuatorType": "Vibrate"},
+      ]}},
+    ])
+    .to_string();
+    let shaped = shape_scalar_commands(&msg, |device_index, scalar_index| {
+      (device_index == 3 && scalar_index == 1)
+        .then_some(ActuatorCurveShape::EaseIn { exponent: 2.0 })
+    })
+    .unwrap();
+    let shaped: Value = serde_json::from_str(&shaped).unwrap();
+    assert_eq!(shaped[0], json!({"Ping": {"Id": 1}}));
+    let scalars = &shaped[1]["ScalarCmd"]["Scalars"];
+    assert_eq!(scalars[0]["Scalar"], 0.5);
+    assert_eq!(scalars[1]["Scalar"], 0.25);
+    assert_eq!(scalars[1]["ActuatorType"], "Vibrate");
+  }
+
+  #[test]
+  fn unshaped_commands_are_left_alone() {
+    let msg = json!([{"ScalarCmd": {"Id": 1, "DeviceIndex": 0, "Scalars": [
+      {"Index": 0, "Scalar": 0.5, "ActuatorType": "Vibrate"},
+    ]}}])
+    .to_string();
+    assert!(shape_scalar_commands(&msg, |_, _| None).is_none());
+    assert!(shape_scalar_commands("not json", |_, _| Some(ActuatorCurveShape::Linear)).is_none());
+  }
+}
@@ -0,0 +1,208 @@
+use crate::{
+  api::{self, ExposedUserDeviceIdentifier, DEVICE_CONFIG_MANAGER},
+  backdoor,
+  events::{self, BridgeEvent},
+};
+use anyhow::Result;
+use buttplug::server::device::configuration::{
+  DeviceConfigurationManager, UserDeviceCustomization, UserDeviceDefinition, UserDeviceIdentifier,
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::RwLock;
+
+// Allow and deny rules for whole ranges of device addresses, e.g. "C4:8A:*" for every device from a
+// neighbour's brand. Patterns take * for any run of characters and ? for any one, and don't care
+// about case. Like device settings, these are saved as their own JSON document, as the Buttplug user
+// config only takes exact addresses.
+//
+// The engine only knows about exact addresses, so rules work by setting allow or deny on the
+// matching devices in the user config. That happens for every device in the config when rules are
+// set, and for new devices as they connect. A device the engine has never seen can't be turned away
+// before its first connection, so a new device matching a deny rule is stopped and reported as
+// blocked when it connects, and denied from then on. Likewise, allow rules only let in devices
+// that have connected before.
+//
+// Deny wins if a device matches rules both ways. Allow and deny set by a rule stay on the device's
+// entry if the rule is removed, where they can be changed like any other.
+
+const ADDRESS_RULES_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AddressRuleAction {
+  Allow,
+  Deny,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AddressRule {
+  pub pattern: String,
+  pub action: AddressRuleAction,
+  // Only match devices using this protocol, if set.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub protocol: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum BlockReason {
+  AddressRule { pattern: String },
+  // Allowlist-only mode is on and the device isn't allowed (see allowlist).
+  NotAllowlisted,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AddressRulesFile {
+  version: u32,
+  #[serde(default)]
+  rules: Vec<AddressRule>,
+}
+
+lazy_static! {
+  static ref ADDRESS_RULES: RwLock<Vec<AddressRule>> = RwLock::new(vec![]);
+}
+
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+  match pattern.split_first() {
+    None => text.is_empty(),
+    Some(('*', rest)) => (0..=text.len()).any(|x| glob_matches(rest, &text[x..])),
+    Some((c, rest)) => text.split_first().is_some_and(|(t, text)| {
+      (*c == '?' || c.eq_ignore_ascii_case(t)) && glob_matches(rest, text)
+    }),
+  }
+}
+
+impl AddressRule {
+  pub fn validate(&self) -> Result<()> {
+    if self.pattern.trim().is_empty() {
+      return Err(anyhow::Error::msg("Address rule pattern cannot be empty"));
+    }
+    Ok(())
+  }
+
+  fn matches(&self, identifier: &UserDeviceIdentifier) -> bool {
+    let pattern: Vec<char> = self.pattern.trim().chars().collect();
+    let address: Vec<char> = identifier.address().chars().collect();
+    self
+      .protocol
+      .as_ref()
+      .is_none_or(|x| x == identifier.protocol())
+      && glob_matches(&pattern, &address)
+  }
+}
+
+pub fn load(rules_json: &str) -> Result<()> {
+  let file: AddressRulesFile = serde_json::from_str(rules_json)?;
+  if file.version > ADDRESS_RULES_VERSION {
+    return Err(anyhow::Error::msg(format!(
+      "Address rules version {} is newer than supported version {}",
+      file.version, ADDRESS_RULES_VERSION
+    )));
+  }
+  set(file.rules)
+}
+
+pub fn save() -> String {
+  let file = AddressRulesFile {
+    version: ADDRESS_RULES_VERSION,
+    rules: rules(),
+  };
+  serde_json::to_string(&file).expect("Address rules should always serialize")
+}
+
+pub fn rules() -> Vec<AddressRule> {
+  ADDRESS_RULES.read().unwrap().clone()
+}
+
+pub fn set(rules: Vec<AddressRule>) -> Result<()> {
+  for rule in &rules {
+    rule.validate()?;
+  }
+  *ADDRESS_RULES.write().unwrap() = rules;
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let identifiers: Vec<UserDeviceIdentifier> = dcm
+    .user_device_definitions()
+    .iter()
+    .map(|kv| kv.key().clone())
+    .collect();
+  for identifier in identifiers {
+    apply(&dcm, &identifier);
+  }
+  Ok(())
+}
+
+// Sets allow or deny on the device's entry if a rule matches it, returning the deny rule's pattern
+// if it was newly denied.
+fn apply(dcm: &DeviceConfigurationManager, identifier: &UserDeviceIdentifier) -> Option<String> {
+  let (deny_rule, allow_rule) = {
+    let rules = ADDRESS_RULES.read().unwrap();
+    let find = |action: AddressRuleAction| {
+      rules
+        .iter()
+        .find(|x| x.action == action && x.matches(identifier))
+        .map(|x| x.pattern.clone())
+    };
+    (
+      find(AddressRuleAction::Deny),
+      find(AddressRuleAction::Allow),
+    )
+  };
+  if deny_rule.is_none() && allow_rule.is_none() {
+    return None;
+  }
+  let definition = dcm
+    .user_device_definitions()
+    .get(identifier)
+    .map(|x| x.value().clone())?;
+  let config = definition.user_config();
+  let (allow, deny) = match deny_rule {
+    Some(_) => (false, true),
+    None => (true, false),
+  };
+  if config.allow() == allow && config.deny() == deny {
+    return None;
+  }
+  let _ = dcm.add_user_device_definition(
+    identifier,
+    &UserDeviceDefinition::new(
+      definition.name(),
+      definition.features(),
+      &UserDeviceCustomization::new(config.display_name(), allow, deny, config.index()),
+    ),
+  );
+  deny_rule
+}
+
+// The engine has already connected the device by the time we hear about it, so the best we can do
+// for a newly denied one is stop it and let the frontend know. Returns whether it was blocked.
+pub fn device_connected(index: u32, identifier: &ExposedUserDeviceIdentifier) -> bool {
+  if ADDRESS_RULES.read().unwrap().is_empty() {
+    return false;
+  }
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let user_identifier: UserDeviceIdentifier = identifier.clone().into();
+  let Some(pattern) = apply(&dcm, &user_identifier) else {
+    return false;
+  };
+  info!(
+    "Device {:?} matches deny rule {}, stopping it",
+    identifier, pattern
+  );
+  block(index, identifier, BlockReason::AddressRule { pattern });
+  true
+}
+
+pub(crate) fn block(index: u32, identifier: &ExposedUserDeviceIdentifier, reason: BlockReason) {
+  api::send_bridge_backdoor_message(
+    json!([{ "StopDeviceCmd": { "Id": backdoor::next_message_id(), "DeviceIndex": index } }])
+      .to_string(),
+  );
+  events::emit(BridgeEvent::DeviceBlocked {
+    identifier: identifier.clone(),
+    reason,
+  });
+}
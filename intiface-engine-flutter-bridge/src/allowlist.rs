@@ -0,0 +1,71 @@
+use crate::{
+  address_rules::{self, BlockReason},
+  api::{EngineOptionsExternal, ExposedUserDeviceIdentifier, DEVICE_CONFIG_MANAGER},
+  options::BridgeEngineOptions,
+};
+use buttplug::server::device::configuration::UserDeviceIdentifier;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Allowlist-only mode, for people sharing a place with other people's devices. Only devices with
+// allow set on their user config entry are connected, anything else is left alone.
+//
+// Most of this is the engine's doing: once any device in the user config is allowed, it refuses to
+// connect anything that isn't, before touching it. With nothing allowed the engine connects
+// everything, so in that case hardware discovery is turned off for the run instead. Simulated and
+// websocket devices still come in over the device websocket server, as they have to be set up to
+// connect on purpose.
+//
+// The engine doesn't tell us about devices it refuses, so those go by quietly. Anything that does
+// get connected without being allowed (a websocket device, or one that connected after the last
+// allow was taken away mid-run) is stopped and reported as blocked.
+
+static ALLOWLIST_ONLY: AtomicBool = AtomicBool::new(false);
+
+fn any_allowed() -> bool {
+  DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .user_device_definitions()
+    .iter()
+    .any(|kv| kv.value().user_config().allow())
+}
+
+fn allowed(identifier: &ExposedUserDeviceIdentifier) -> bool {
+  let user_identifier: UserDeviceIdentifier = identifier.clone().into();
+  DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .user_device_definitions()
+    .get(&user_identifier)
+    .is_some_and(|x| x.user_config().allow())
+}
+
+pub fn plan(options: &BridgeEngineOptions, args: &mut EngineOptionsExternal) {
+  ALLOWLIST_ONLY.store(options.allowlist_only, Ordering::Relaxed);
+  if !options.allowlist_only || any_allowed() {
+    return;
+  }
+  info!("Allowlist-only mode with no allowed devices, turning off device discovery");
+  args.use_bluetooth_le = false;
+  args.use_serial_port = false;
+  args.use_hid = false;
+  args.use_lovense_dongle_serial = false;
+  args.use_lovense_dongle_hid = false;
+  args.use_xinput = false;
+  args.use_lovense_connect = false;
+}
+
+pub fn device_connected(index: u32, identifier: &ExposedUserDeviceIdentifier) {
+  if !ALLOWLIST_ONLY.load(Ordering::Relaxed) || allowed(identifier) {
+    return;
+  }
+  info!(
+    "Device {:?} isn't allowed in allowlist-only mode, stopping it",
+    identifier
+  );
+  address_rules::block(index, identifier, BlockReason::NotAllowlisted);
+}
+
+pub fn clear() {
+  ALLOWLIST_ONLY.store(false, Ordering::Relaxed);
+}
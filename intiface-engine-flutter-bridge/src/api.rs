@@ -1,8 +1,84 @@
 use crate::{
-  in_process_frontend::FlutterIntifaceEngineFrontend, logging::FlutterTracingWriter, mobile_init,
+  actuator_curve::{self, ActuatorCurve},
+  address_rules::{self, AddressRule},
+  allowlist,
+  app_lifecycle::{self, AndroidPowerEvent},
+  auto_reconnect, auto_scan, backdoor,
+  backdoor_client::{
+    self, ActuatorTestResult, BackdoorDevice, BackdoorLinearCommand, BackdoorRotateCommand,
+    BackdoorScalarCommand,
+  },
+  backdoor_events::{self, BackdoorEvent},
+  backdoor_throttle::BackdoorThrottle,
+  backdoor_validation, battery, binary_stream,
+  ble_probe::{self, BleAdvertisement},
+  bluetooth_state::{self, BluetoothState},
+  bridge_error::BridgeError,
+  client_permissions::{self, ClientPermissions},
+  client_reconnect::{self, WebsocketClientState},
+  client_sessions::{self, DeviceControl},
+  clients::{self, ClientLatency, ConnectedClient},
+  clock,
+  config_archive::{self, UserConfigArchive},
+  config_watcher,
+  connection_payload::{self, ConnectionPayload},
+  device_config_updater::{self, DeviceConfigUpdate},
+  device_events::{self, DeviceEvent},
+  device_list::{self, DeviceListSync},
+  device_settings,
+  device_stats::{self, CommandTracker, DeviceCommandStats},
+  device_submission::{self, DeviceCapture},
+  devices,
+  endpoint_map::{self, DiscoveredService},
+  engine_supervisor::{self, EngineRestartState},
+  error_reporting::{self, ErrorSource},
+  events::{self, BridgeEvent},
+  hardware_info::{self, DeviceHardwareInfo},
+  idle_stop,
+  in_process_frontend::FlutterIntifaceEngineFrontend,
+  log_sanitizer::{self, LogSanitization},
+  logging::{self, FlutterTracingWriter, LogSinkOptions},
+  lovense_connect::{self, LovenseConnectStatus},
+  mdns_broadcast,
+  mdns_browse::{self, MdnsServerEvent},
+  message_sink::{SharedMessageSink, SwappableSink},
+  metrics::{self, SessionStats, ENGINE_METRICS},
+  mobile_init,
+  network_interfaces::{self, NetworkInterface, PortCheck},
+  options::{self, BridgeEngineOptions},
+  options_validation::{self, OptionsFinding},
+  patterns::{self, Pattern},
+  port_mapping::{self, PortMapping},
+  protocol_info::{self, ProtocolInfo},
+  ramp::RampLimiter,
+  raw_endpoints::{self, RawReadingEvent},
+  repeater_monitor::{self, RepeaterStatus},
+  scan_timeout,
+  sensor_stream::{self, SensorReadingEvent},
+  serial_devices,
+  server_ready::{self, ServerAddresses},
+  session_recording,
+  share_code::{self, DeviceShareCodeInfo},
+  shutdown::{self, ShutdownStage},
+  simulator,
+  system_check::{self, SystemCheckReport},
+  traffic_capture::{self, CaptureDirection},
+  transports::{self, Transport},
+  tunnel::{self, TunnelStatus},
+  usage_metrics,
+  user_config_diff::{self, UserConfigDiff},
+  user_config_file::{self, ExposedUserConfig},
+  user_config_migration,
+  user_config_validation::{self, UserConfigError},
+  websocket_devices::{self, WebsocketDeviceConnection, WebsocketDeviceDiscovery},
+  websocket_proxy::{self, ListeningServer},
+  websocket_tls::{self, TlsCertificate},
+  xinput::{self, XInputMotors, XInputSlot},
 };
 use anyhow::Result;
-use buttplug::server::device::configuration::{DeviceConfigurationManagerBuilder, SerialSpecifier};
+use buttplug::server::device::configuration::{
+  BluetoothLESpecifier, DeviceConfigurationManagerBuilder, HIDSpecifier, SerialSpecifier,
+};
 pub use buttplug::{
   core::message::{
     ButtplugActuatorFeatureMessageType, ButtplugDeviceMessageType,
@@ -11,33 +87,43 @@ pub use buttplug::{
   },
   server::device::{
     configuration::{
-      DeviceConfigurationManager, ProtocolCommunicationSpecifier, UserDeviceCustomization,
-      UserDeviceDefinition, UserDeviceIdentifier, WebsocketSpecifier,
+      DeviceConfigurationManager, ProtocolAttributesType, ProtocolCommunicationSpecifier,
+      ServerDeviceIdentifier, USBSpecifier, UserDeviceCustomization, UserDeviceDefinition,
+      UserDeviceIdentifier, WebsocketSpecifier,
     },
     protocol::get_default_protocol_map,
   },
-  util::device_configuration::{load_protocol_configs, save_user_config},
+  util::device_configuration::{
+    load_protocol_configs, save_user_config, DEVICE_CONFIGURATION_JSON,
+  },
 };
-use flutter_rust_bridge::{frb, StreamSink};
+use flutter_rust_bridge::{frb, StreamSink, ZeroCopyBuffer};
 use futures::{pin_mut, StreamExt};
 use lazy_static::lazy_static;
 use once_cell::sync::OnceCell;
 use sentry::ClientInitGuard;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::{
   collections::HashSet,
   fs,
   ops::RangeInclusive,
+  path::Path,
   sync::{
     atomic::{AtomicBool, Ordering},
     Arc, Mutex, RwLock,
   },
-  thread,
-  time::Duration,
+  time::{Duration, Instant},
 };
+use thiserror::Error;
 use tokio::{
   runtime::Runtime,
   select,
-  sync::{broadcast, Notify},
+  sync::{
+    broadcast::{self, error::RecvError},
+    Notify,
+  },
+  task::JoinHandle,
 };
 use tracing_futures::Instrument;
 
@@ -49,6 +135,11 @@ lazy_static! {
   static ref RUNTIME: Arc<Mutex<Option<Runtime>>> = Arc::new(Mutex::new(None));
   static ref LOGGER: Arc<Mutex<Option<FlutterTracingWriter>>> = Arc::new(Mutex::new(None));
   static ref RUN_STATUS: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+  // Main engine task and the sink it sends to, so stop_engine can cancel it if it won't stop.
+  static ref ENGINE_TASK: Mutex<Option<(JoinHandle<()>, SharedMessageSink)>> = Mutex::new(None);
+  // What the running engine sends its messages through, for reattach to swap out.
+  static ref ENGINE_SINK: Mutex<Option<Arc<SwappableSink>>> = Mutex::new(None);
+  static ref RAW_MESSAGES_ALLOWED: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
   static ref ENGINE_BROADCASTER: Arc<broadcast::Sender<IntifaceMessage>> =
     Arc::new(broadcast::channel(255).0);
   static ref BACKDOOR_INCOMING_BROADCASTER: Arc<broadcast::Sender<String>> =
@@ -64,7 +155,7 @@ lazy_static! {
   // lock. While this probably shouldn't happen, it does. A lot. So we'll need to check for an
   // active runtime whenever we try to get write locks, and clear poisoning if there's no runtime
   // active.
-  static ref DEVICE_CONFIG_MANAGER: Arc<RwLock<Arc<DeviceConfigurationManager>>> =
+  pub(crate) static ref DEVICE_CONFIG_MANAGER: Arc<RwLock<Arc<DeviceConfigurationManager>>> =
     Arc::new(RwLock::new(Arc::new(load_protocol_configs(&None, &None, false).unwrap().finish().unwrap())));
 }
 
@@ -103,20 +194,87 @@ pub fn runtime_started() -> bool {
   RUNTIME.lock().unwrap().is_some()
 }
 
+// Gets the kind of error back out of a failed call's message, for the ones that have one. See
+// bridge_error.
+pub fn parse_bridge_error(message: String) -> Option<BridgeError> {
+  BridgeError::parse(&message)
+}
+
+// Checks options (along with the bridge options already set) before run_engine, returning anything
+// wrong with them, errors first. Port checks are skipped while the engine is running, as it has the
+// ports itself. Can take a couple of seconds, as it tries to connect to the websocket client address.
+pub fn validate_engine_options(args: EngineOptionsExternal) -> Vec<OptionsFinding> {
+  options_validation::validate(&args, &options::bridge_options(), runtime_started())
+}
+
 pub fn run_engine(sink: StreamSink<String>, args: EngineOptionsExternal) -> Result<()> {
+  start_engine(Arc::new(sink), args)
+}
+
+// Catches ports something else is already listening on, which the engine would otherwise only
+// report by stopping right after it started.
+fn check_port_free(port: Option<u16>) -> Result<(), BridgeError> {
+  match port {
+    Some(port) if network_interfaces::port_bind_error(port, false).is_some() => {
+      Err(BridgeError::PortInUse { port })
+    }
+    _ => Ok(()),
+  }
+}
+
+pub(crate) fn start_engine(sink: SharedMessageSink, mut args: EngineOptionsExternal) -> Result<()> {
   if RUN_STATUS.load(Ordering::Relaxed) {
-    return Err(anyhow::Error::msg("Server already running!"));
+    return Err(BridgeError::AlreadyRunning.into());
+  }
+  if args.websocket_client_address.is_none() {
+    check_port_free(args.websocket_port)?;
   }
+  check_port_free(args.device_websocket_server_port)?;
+  let bridge_options = options::bridge_options();
+  let mdns_broadcast = mdns_broadcast::plan(&bridge_options, &mut args);
+  let port_mapping_plan = port_mapping::plan(&bridge_options, &args);
+  let tunnel_plan = tunnel::plan(&bridge_options, &args);
+  let repeater_relay =
+    repeater_monitor::plan(&mut args, bridge_options.repeater_auth_token.clone())?;
+  let client_mode = args.websocket_client_address.is_some();
+  let websocket_proxy_plan = websocket_proxy::plan(&bridge_options, &mut args)?;
+  let simulator_plan = simulator::plan(&bridge_options, &mut args)?;
+  allowlist::plan(&bridge_options, &mut args);
+  usage_metrics::engine_started(&args);
+  let tls_proxy_plan = websocket_proxy_plan.clone();
+  let device_tls_proxy_plan = websocket_proxy_plan.clone();
+  let mut servers = websocket_proxy_plan.servers(&bridge_options);
+  // Nobody connects in to an engine that's connecting out.
+  if client_mode {
+    servers.retain(|x| {
+      matches!(
+        x,
+        ListeningServer::DeviceWebsocket | ListeningServer::SecureDeviceWebsocket
+      )
+    });
+  }
+  server_ready::begin(servers, websocket_proxy_plan.engine_listening());
   RUN_STATUS.store(true, Ordering::Relaxed);
+  // The engine needs the Bluetooth adapter to itself.
+  ble_probe::stop();
+  RAW_MESSAGES_ALLOWED.store(args.allow_raw_messages, Ordering::Relaxed);
+  app_lifecycle::set_max_ping_time(args.max_ping_time);
+  auto_scan::set_enabled(bridge_options.scan_on_client_connect);
+  scan_timeout::set_default_timeout(bridge_options.scan_timeout_ms);
+  shutdown::engine_started();
 
   let mut runtime_storage = RUNTIME.lock().unwrap();
 
   if runtime_storage.is_some() {
-    return Err(anyhow::Error::msg("Runtime already created!"));
+    return Err(BridgeError::AlreadyRunning.into());
   }
 
-  let runtime = mobile_init::create_runtime(sink.clone())
-    .expect("Runtime should work, otherwise we can't function.");
+  let runtime = mobile_init::create_runtime().map_err(|e| {
+    RUN_STATUS.store(false, Ordering::Relaxed);
+    BridgeError::RuntimeInitFailed {
+      detail: format!("{:?}", e),
+    }
+  })?;
 
   if ENGINE_NOTIFIER.get().is_none() {
     info!("Creating notifier");
@@ -127,28 +285,59 @@ pub fn run_engine(sink: StreamSink<String>, args: EngineOptionsExternal) -> Resu
     info!("Notifier already created");
   }
 
+  let engine_sink = Arc::new(SwappableSink::new(sink));
+  *ENGINE_SINK.lock().unwrap() = Some(engine_sink.clone());
+  let sink: SharedMessageSink = engine_sink;
+
   let frontend = Arc::new(FlutterIntifaceEngineFrontend::new(
     sink.clone(),
     ENGINE_BROADCASTER.clone(),
   ));
   info!("Frontend logging set up.");
   let frontend_waiter = frontend.notify_on_creation();
-  let engine = Arc::new(IntifaceEngine::default());
+  let backdoor_frontend = frontend.clone();
+  // Swapped for a fresh one when the engine is run again for a transport change (see transports).
+  let engine = Arc::new(Mutex::new(Arc::new(IntifaceEngine::default())));
   let engine_clone = engine.clone();
   let engine_clone_clone = engine.clone();
+  let engine_transports = engine.clone();
   let notify = ENGINE_NOTIFIER.get().expect("Should be set").clone();
   let notify_clone = notify.clone();
   let notify_clone_clone = notify.clone();
-  let options = args.into();
+  let notify_metrics = notify.clone();
+  let notify_battery = notify.clone();
+  let notify_device_stats = notify.clone();
+  let notify_tls = notify.clone();
+  let notify_device_tls = notify.clone();
+  let notify_proxy = notify.clone();
+  let notify_mdns = notify.clone();
+  let notify_port_mapping = notify.clone();
+  let notify_tunnel = notify.clone();
+  let notify_repeater = notify.clone();
+  let notify_reconnect = notify.clone();
+  let notify_simulator = notify.clone();
+  let notify_patterns = notify.clone();
+  let notify_lovense_connect = notify.clone();
+  let notify_idle_stop = notify.clone();
+  let notify_auto_reconnect = notify.clone();
+  let notify_transports = notify.clone();
+  // Set once we've been asked to stop, so client reconnects know not to bother.
+  let stopping = Arc::new(AtomicBool::new(false));
+  let stopping_clone = stopping.clone();
+  let use_lovense_connect = args.use_lovense_connect;
+  transports::engine_started(&args);
+  let mut engine_args = args.clone();
+  let mut options: EngineOptions = args.into();
 
   let mut backdoor_incoming = BACKDOOR_INCOMING_BROADCASTER.subscribe();
   let outgoing_sink = sink.clone();
   let sink_clone = sink.clone();
+  let task_sink = sink.clone();
 
   // TODO This is not doing what its supposed to. We're taking our Arc from the read guard, then
   // just dropping the read guard.
   let dcm = (*DEVICE_CONFIG_MANAGER.read().unwrap()).clone();
-  runtime.spawn(
+  let engine_task = runtime.spawn(
     async move {
       info!("Entering main join.");
 
@@ -158,45 +347,121 @@ pub fn run_engine(sink: StreamSink<String>, args: EngineOptionsExternal) -> Resu
           // Once we finish our waiter, continue. If we cancel the server run before then, just kill the
           // task.
           info!("Entering backdoor waiter task");
-          select! {
-            _ = frontend_waiter => {
-              // This firing means the frontend is set up, and we just want to continue to creating our backdoor server.
-            }
-            _ = notify_clone.notified() => {
-              return;
-            }
-          };
-          // At this point we know we'll have a server.
-          let backdoor_server = if let Some(backdoor_server) = engine_clone.backdoor_server() {
-            backdoor_server
-          } else {
-            // If we somehow *don't* have a server here, something has gone very wrong. Just die.
-            error!("No backdoor server available!");
-            return;
-          };
-          let backdoor_server_stream = backdoor_server.event_stream();
-          pin_mut!(backdoor_server_stream);
-          loop {
+          let mut frontend_waiter = frontend_waiter;
+          'servers: loop {
             select! {
-              msg = backdoor_incoming.recv() => {
-                match msg {
-                  Ok(msg) => {
-                    let backdoor_server_clone = backdoor_server.clone();
-                    backdoor_server_clone.parse_message(&msg).await;
+              _ = &mut frontend_waiter => {
+                // This firing means the frontend is set up, and we just want to continue to creating our backdoor server.
+              }
+              _ = notify_clone.notified() => {
+                return;
+              }
+            };
+            // At this point we know we'll have a server.
+            let backdoor_server = engine_clone.lock().unwrap().backdoor_server();
+            let backdoor_server = if let Some(backdoor_server) = backdoor_server {
+              backdoor_server
+            } else {
+              // If we somehow *don't* have a server here, something has gone very wrong. Just die.
+              error!("No backdoor server available!");
+              break;
+            };
+            let backdoor_server_stream = backdoor_server.event_stream();
+            pin_mut!(backdoor_server_stream);
+            let mut ramp_limiter = RampLimiter::default();
+            let mut ramp_ticker = clock::Ticker::new(backdoor::RAMP_STEP_INTERVAL);
+            let mut commands = CommandTracker::default();
+            let mut throttle = BackdoorThrottle::new(
+              options::bridge_options().backdoor_rate_limit,
+              clock::now(),
+            );
+            loop {
+              select! {
+                msg = backdoor_incoming.recv() => {
+                  match msg {
+                    Ok(msg) => {
+                      ENGINE_METRICS.record_backdoor_incoming();
+                      let msg = match throttle.check(msg, clock::now()) {
+                        Ok(msg) => msg,
+                        Err(replies) => {
+                          let _ = sink.add(replies);
+                          continue;
+                        }
+                      };
+                      let errors = backdoor_validation::validate(&msg);
+                      if !errors.is_empty() {
+                        warn!("Rejecting invalid backdoor message: {}", errors[0].message);
+                        let replies = backdoor_validation::error_replies(&msg, &errors);
+                        events::emit(BridgeEvent::BackdoorMessageRejected { errors });
+                        if backdoor::is_bridge_response(&replies) {
+                          backdoor::route_bridge_response(&replies);
+                        } else {
+                          let _ = sink.add(replies);
+                        }
+                        continue;
+                      }
+                      sensor_stream::track_frontend_message(&msg);
+                      raw_endpoints::track_frontend_message(&msg);
+                      app_lifecycle::track_frontend_message(&msg);
+                      session_recording::record_json(&msg);
+                      let msg = backdoor::apply_command_filters(msg, &mut ramp_limiter, clock::now());
+                      let messages = serde_json::from_str::<Vec<Value>>(&msg).unwrap_or_default();
+                      commands.track_sent(&messages);
+                      scan_timeout::track_client_messages(&messages);
+                      traffic_capture::record(
+                        CaptureDirection::ClientToServer,
+                        Some(traffic_capture::BACKDOOR_CLIENT_NAME),
+                        &messages,
+                      );
+                      let backdoor_server_clone = backdoor_server.clone();
+                      backdoor_server_clone.parse_message(&msg).await;
+                    }
+                    // Flooded faster than we could keep up, even with the rate limit.
+                    Err(RecvError::Lagged(count)) => throttle.lagged(count, clock::now()),
+                    Err(RecvError::Closed) => break 'servers
                   }
-                  Err(_) => break
-                }
-              },
-              outgoing = backdoor_server_stream.next() => {
-                match outgoing {
-                  Some(msg) => {
-                    let _ = sink.add(msg);
-                  },
-                  None => break
-                }
-              },
-              _ = notify_clone.notified() => break
+                },
+                _ = ramp_ticker.tick() => {
+                  throttle.flush(clock::now());
+                  for msg in ramp_limiter.step(clock::now()) {
+                    let msg = actuator_curve::shape_scalar_commands(
+                      &msg,
+                      device_settings::curve_shape_for_scalar,
+                    )
+                    .unwrap_or(msg);
+                    backdoor_server.parse_message(&msg).await;
+                  }
+                },
+                outgoing = backdoor_server_stream.next() => {
+                  match outgoing {
+                    Some(msg) => {
+                      ENGINE_METRICS.record_backdoor_outgoing();
+                      let messages = serde_json::from_str::<Vec<Value>>(&msg).unwrap_or_default();
+                      commands.track_replies(&messages);
+                      app_lifecycle::track_server_message(&msg);
+                      scan_timeout::track_server_messages(&messages);
+                      backdoor_events::route_server_message(&msg).await;
+                      traffic_capture::record(
+                        CaptureDirection::ServerToClient,
+                        Some(traffic_capture::BACKDOOR_CLIENT_NAME),
+                        &messages,
+                      );
+                      // Responses to messages the bridge sent itself stay in the bridge.
+                      if backdoor::is_bridge_response(&msg) {
+                        backdoor::route_bridge_response(&msg);
+                      } else if sensor_stream::route_readings(&msg) && raw_endpoints::route_readings(&msg) {
+                        let _ = sink.add(msg);
+                      }
+                    },
+                    // Server went away, as it does when the engine is run again on a restart or
+                    // transport change. Wait for the next one.
+                    None => break
+                  }
+                },
+                _ = notify_clone.notified() => break 'servers
+              }
             }
+            frontend_waiter = backdoor_frontend.notify_on_creation();
           }
           info!("Exiting backdoor waiter task");
         }
@@ -204,8 +469,83 @@ pub fn run_engine(sink: StreamSink<String>, args: EngineOptionsExternal) -> Resu
         // Main engine task.
         async move {
           info!("Entering main engine waiter task");
-          if let Err(e) = engine.run(&options, Some(frontend), &Some(dcm)).await {
-            error!("Error running engine: {:?}", e);
+          let mut attempt = 0;
+          loop {
+            if client_mode {
+              client_reconnect::emit_state(WebsocketClientState::Connecting { attempt });
+            }
+            let run_started_at = Instant::now();
+            let current_engine = engine.lock().unwrap().clone();
+            let result = current_engine.run(&options, Some(frontend.clone()), &Some(dcm.clone())).await;
+            if let Err(e) = &result {
+              error!("Error running engine: {:?}", e);
+            }
+            if stopping.load(Ordering::Relaxed) {
+              break;
+            }
+            if transports::apply_pending(&mut engine_args) {
+              options = engine_args.clone().into();
+              // Checked under the lock, so a stop can't land on the engine we're replacing.
+              let mut current_engine = engine.lock().unwrap();
+              if stopping.load(Ordering::Relaxed) {
+                break;
+              }
+              *current_engine = Arc::new(IntifaceEngine::default());
+              attempt = 0;
+              continue;
+            }
+            if !client_mode {
+              // Server mode only goes around again if the engine fell over and restarts are on.
+              let Err(e) = result else {
+                break;
+              };
+              let error = format!("{:?}", e);
+              if run_started_at.elapsed() >= engine_supervisor::STABLE_RUN_TIME {
+                attempt = 0;
+              }
+              attempt += 1;
+              let Some(delay) = engine_supervisor::restart_delay(&bridge_options.engine_restart, attempt)
+              else {
+                if bridge_options.engine_restart.enabled {
+                  engine_supervisor::emit_state(EngineRestartState::GaveUp {
+                    attempts: attempt - 1,
+                    error,
+                  });
+                }
+                break;
+              };
+              warn!("Engine stopped with an error, restarting in {:?}", delay);
+              engine_supervisor::emit_state(EngineRestartState::Restarting {
+                attempt,
+                delay_ms: delay.as_millis() as u32,
+                error,
+              });
+              if !client_reconnect::wait(delay, &stopping, &notify_reconnect).await {
+                break;
+              }
+              continue;
+            }
+            client_reconnect::emit_state(WebsocketClientState::Disconnected {
+              error: result.err().map(|e| format!("{:?}", e)),
+            });
+            attempt += 1;
+            let Some(delay) = client_reconnect::reconnect_delay(&bridge_options.client_reconnect, attempt)
+            else {
+              if bridge_options.client_reconnect.enabled {
+                client_reconnect::emit_state(WebsocketClientState::GaveUp {
+                  attempts: attempt - 1,
+                });
+              }
+              break;
+            };
+            info!("Engine client disconnected, reconnecting in {:?}", delay);
+            client_reconnect::emit_state(WebsocketClientState::Reconnecting {
+              attempt,
+              delay_ms: delay.as_millis() as u32,
+            });
+            if !client_reconnect::wait(delay, &stopping, &notify_reconnect).await {
+              break;
+            }
           }
           info!("Exiting main engine waiter task");
           notify_clone_clone.notify_waiters();
@@ -217,21 +557,433 @@ pub fn run_engine(sink: StreamSink<String>, args: EngineOptionsExternal) -> Resu
           info!("Entering engine stop notification task");
           notify.notified().await;
           info!("Notifier called, stopping engine");
-          engine_clone_clone.stop();
+          stopping_clone.store(true, Ordering::Relaxed);
+          engine_clone_clone.lock().unwrap().stop();
+        },
+        // Metrics endpoint, if requested. Shuts down with everything else when notified.
+        async move {
+          if let Some(port) = bridge_options.metrics_port {
+            metrics::serve_metrics(port, notify_metrics).await;
+          }
+        },
+        // Battery monitor, if requested.
+        async move {
+          if let Some(interval) = bridge_options.battery_poll_interval_ms {
+            battery::run_battery_monitor(
+              Duration::from_millis(interval.max(1000) as u64),
+              notify_battery,
+            )
+            .await;
+          }
+        },
+        // Device command stats events, if requested.
+        async move {
+          if let Some(interval) = bridge_options.device_stats_interval_ms {
+            device_stats::run_stats_reporter(
+              Duration::from_millis(interval.max(1000) as u64),
+              notify_device_stats,
+            )
+            .await;
+          }
+        },
+        // TLS front for the websocket server, if requested.
+        async move {
+          match (bridge_options.websocket_tls, tls_proxy_plan.engine_websocket_port) {
+            (Some(tls_options), Some(port)) => {
+              websocket_tls::run_tls_proxy(
+                ListeningServer::SecureWebsocket,
+                tls_options,
+                tls_proxy_plan.addresses,
+                port,
+                tls_proxy_plan.token,
+                notify_tls,
+              )
+              .await
+            }
+            (Some(_), None) => {
+              warn!("Websocket TLS requested without a websocket port, not starting TLS server")
+            }
+            _ => {}
+          }
+        },
+        // TLS front for the device websocket server, if requested.
+        async move {
+          match (
+            bridge_options.device_websocket_tls,
+            device_tls_proxy_plan.engine_device_websocket_port,
+          ) {
+            (Some(tls_options), Some(port)) => {
+              websocket_tls::run_tls_proxy(
+                ListeningServer::SecureDeviceWebsocket,
+                tls_options,
+                device_tls_proxy_plan.addresses,
+                port,
+                // Devices have no way of passing a token.
+                None,
+                notify_device_tls,
+              )
+              .await
+            }
+            (Some(_), None) => {
+              warn!("Device websocket TLS requested without the device websocket server, not starting TLS server")
+            }
+            _ => {}
+          }
+        },
+        // Fronts for the engine's websocket servers, if it can't serve them how we were asked to.
+        websocket_proxy::run_proxies(websocket_proxy_plan, notify_proxy),
+        // mDNS broadcast, if requested.
+        async move {
+          if let Some(broadcast) = mdns_broadcast {
+            mdns_broadcast::run_mdns_broadcast(broadcast, notify_mdns).await;
+          }
+        },
+        // Router port mapping, if requested.
+        async move {
+          if let Some(plan) = port_mapping_plan {
+            port_mapping::run_port_mapping(plan, notify_port_mapping).await;
+          }
+        },
+        // Tunnel through a relay, if requested.
+        async move {
+          if let Some(plan) = tunnel_plan {
+            tunnel::run_tunnel(plan, notify_tunnel).await;
+          }
+        },
+        // Relay for watching the repeater's remote connection.
+        async move {
+          if let Some(relay) = repeater_relay {
+            repeater_monitor::run_repeater_relay(relay, notify_repeater).await;
+          }
+        },
+        // Pattern playback. Idles until a pattern is started.
+        patterns::run_patterns(notify_patterns),
+        // Simulated devices, if requested.
+        async move {
+          if let Some(plan) = simulator_plan {
+            simulator::run_simulator(plan, notify_simulator).await;
+          }
+        },
+        // Lovense Connect discovery status, if the engine is looking for Lovense Connect.
+        async move {
+          if use_lovense_connect {
+            lovense_connect::run_lovense_connect_monitor(
+              bridge_options.lovense_connect,
+              notify_lovense_connect,
+            )
+            .await;
+          }
+        },
+        // Idle stop, if requested. Clients connect to us, so there's nothing to wait for in client
+        // mode.
+        async move {
+          if bridge_options.idle_stop.enabled && !client_mode {
+            idle_stop::run_idle_monitor(bridge_options.idle_stop, notify_idle_stop).await;
+          }
+        },
+        // Scanning for devices that dropped, if requested.
+        async move {
+          if bridge_options.auto_reconnect.enabled {
+            auto_reconnect::run_auto_reconnect(bridge_options.auto_reconnect, notify_auto_reconnect)
+              .await;
+          }
+        },
+        // Stops the engine's server when transports change, so the engine task can run it again
+        // with them.
+        async move {
+          let stopped = notify_transports.notified();
+          pin_mut!(stopped);
+          stopped.as_mut().enable();
+          loop {
+            select! {
+              _ = transports::change_requested() => {
+                info!("Transports changed, stopping engine server to apply them");
+                engine_transports.lock().unwrap().stop();
+              }
+              _ = &mut stopped => break,
+            }
+          }
         }
       );
-      RUN_STATUS.store(false, Ordering::Relaxed);
-      sink_clone.close();
+      clear_engine_state(&sink_clone);
       info!("Exiting main join.");
     }
     .instrument(info_span!("IC main engine task")),
   );
+  *ENGINE_TASK.lock().unwrap() = Some((engine_task, task_sink));
   *runtime_storage = Some(runtime);
   Ok(())
 }
 
-pub fn send(msg_json: String) {
-  let msg: IntifaceMessage = serde_json::from_str(&msg_json).unwrap();
+// Clears out everything that only lives as long as an engine run. Runs when the main engine task
+// exits, or from stop_engine if that task had to be cancelled.
+fn clear_engine_state(sink: &SharedMessageSink) {
+  ENGINE_METRICS.reset_gauges();
+  devices::clear();
+  device_list::clear();
+  allowlist::clear();
+  usage_metrics::clear_run();
+  sensor_stream::clear();
+  raw_endpoints::clear();
+  hardware_info::clear();
+  transports::engine_stopped();
+  clients::clear();
+  client_sessions::clear();
+  device_stats::clear();
+  simulator::clear();
+  patterns::clear();
+  lovense_connect::clear();
+  websocket_devices::clear();
+  app_lifecycle::clear();
+  auto_scan::clear();
+  scan_timeout::clear();
+  server_ready::clear();
+  port_mapping::clear();
+  backdoor_events::engine_stopped();
+  RUN_STATUS.store(false, Ordering::Relaxed);
+  *ENGINE_SINK.lock().unwrap() = None;
+  shutdown::engine_exited();
+  sink.close();
+}
+
+pub fn set_bridge_engine_options(options: BridgeEngineOptions) {
+  options::set_bridge_options(options);
+}
+
+pub fn generate_websocket_tls_certificate() -> Result<TlsCertificate> {
+  websocket_tls::generate_self_signed()
+}
+
+// Where each server for the running engine is listening, once they all are. Same as the last
+// ServerStarted event.
+pub fn get_server_addresses() -> Option<Vec<ServerAddresses>> {
+  server_ready::addresses()
+}
+
+// Ports the router is forwarding to the running engine, with port_mapping on.
+pub fn get_port_mappings() -> Vec<PortMapping> {
+  port_mapping::mappings()
+}
+
+// Fingerprint of the certificate the secure websocket server is using, if it's running.
+pub fn get_websocket_tls_fingerprint() -> Option<String> {
+  websocket_tls::active_fingerprint(ListeningServer::SecureWebsocket)
+}
+
+// Same for the secure device websocket server.
+pub fn get_device_websocket_tls_fingerprint() -> Option<String> {
+  websocket_tls::active_fingerprint(ListeningServer::SecureDeviceWebsocket)
+}
+
+// Compact blob with what a client needs to connect to the running server (addresses, ports, TLS
+// fingerprint, token), for showing as a QR code. Signed with the TLS key where possible (see
+// connection_payload).
+pub fn generate_connection_payload() -> Result<String> {
+  connection_payload::generate()
+}
+
+// Reads a payload from generate_connection_payload, checking its signature or checksum.
+pub fn parse_connection_payload(payload: String) -> Result<ConnectionPayload> {
+  connection_payload::parse(&payload)
+}
+
+// Devices currently connected to the device websocket server (plain or TLS), with the name they gave
+// and the protocol it maps to.
+pub fn get_websocket_device_connections() -> Vec<WebsocketDeviceConnection> {
+  websocket_devices::connections()
+}
+
+// For adding a websocket device. Stands in for the device websocket server (on the engine's default
+// port if none is given) until a device connects, and returns what to save for it. The engine
+// can't be running, as it would have the port. Blocks until a device connects or the timeout runs
+// out.
+pub fn discover_websocket_device(
+  port: Option<u16>,
+  protocol: Option<String>,
+  timeout_ms: u32,
+) -> Result<WebsocketDeviceDiscovery> {
+  websocket_devices::discover_device(
+    port.unwrap_or(websocket_devices::DEFAULT_DEVICE_WEBSOCKET_PORT),
+    protocol,
+    timeout_ms,
+  )
+}
+
+pub fn cancel_websocket_device_discovery() {
+  websocket_devices::cancel_discovery();
+}
+
+// Interfaces the websocket servers can be bound to with BridgeEngineOptions.websocket_bind_address.
+pub fn list_network_interfaces() -> Result<Vec<NetworkInterface>> {
+  network_interfaces::list_network_interfaces()
+}
+
+// Whether a server could listen on the port, on localhost, or on every interface as well with
+// all_interfaces. Tells apart ports the running engine has from ones something else has.
+pub fn check_port_available(port: u16, all_interfaces: bool) -> PortCheck {
+  network_interfaces::check_port(port, all_interfaces)
+}
+
+// Streams other Intiface instances found over mDNS, until stop_mdns_browse is called.
+pub fn browse_mdns_servers(sink: StreamSink<MdnsServerEvent>) -> Result<()> {
+  mdns_browse::browse(sink)
+}
+
+pub fn stop_mdns_browse() {
+  mdns_browse::stop();
+}
+
+// Clients currently connected to the engine. Only ever one, unless multiple_clients is on. Spec
+// version and address are only known for clients that connected through the bridge's websocket
+// proxy (see websocket_proxy).
+pub fn get_connected_clients() -> Vec<ConnectedClient> {
+  clients::connected_clients()
+}
+
+// Round trip time and time since the last message for each connected client, to tell a slow network
+// from a slow device. Only available for clients that connected through the bridge's websocket
+// proxy.
+pub fn get_client_latency() -> Vec<ClientLatency> {
+  clients::latency()
+}
+
+// Turns a device transport on or off on the running engine. The engine's server is run again with
+// the new set, so connected devices and clients drop for a moment (see transports). A
+// TransportChanged event goes out once it's applied.
+pub fn set_transport_enabled(transport: Transport, enabled: bool) -> Result<()> {
+  transports::set_enabled(transport, enabled)
+}
+
+// Transports the running engine has on. Changes show up here once they've been applied.
+pub fn get_enabled_transports() -> Vec<Transport> {
+  transports::enabled()
+}
+
+// Kicks a client by the id from get_connected_clients, leaving devices connected. Only works for
+// clients that connected through the bridge's websocket proxy.
+pub fn disconnect_client(client_id: u32) -> Result<()> {
+  clients::disconnect(client_id)
+}
+
+// Which clients have control of which devices, with multiple_clients on. DeviceControlChanged
+// events go out as this changes.
+pub fn get_device_control() -> Vec<DeviceControl> {
+  client_sessions::device_control()
+}
+
+// Latest repeater status, if the engine is running as a repeater.
+pub fn get_repeater_status() -> Option<RepeaterStatus> {
+  repeater_monitor::status()
+}
+
+// Latest tunnel status, if the engine is taking clients through a relay.
+pub fn get_tunnel_status() -> Option<TunnelStatus> {
+  tunnel::status()
+}
+
+// Latest result of the Lovense Connect discovery check, if the engine is looking for Lovense Connect.
+pub fn get_lovense_connect_status() -> Option<LovenseConnectStatus> {
+  lovense_connect::status()
+}
+
+pub fn get_metrics_text() -> String {
+  ENGINE_METRICS.render()
+}
+
+pub fn get_session_stats() -> SessionStats {
+  ENGINE_METRICS.session_stats()
+}
+
+// Starts recording Buttplug messages between clients and the engine, keeping the latest
+// max_messages. Starting again throws out the previous capture.
+pub fn start_traffic_capture(max_messages: u32) -> Result<()> {
+  traffic_capture::start(max_messages)
+}
+
+// Stops recording, keeping the capture for export.
+pub fn stop_traffic_capture() {
+  traffic_capture::stop();
+}
+
+// Captured messages as JSON, oldest first. Works while still capturing too.
+pub fn export_traffic_capture() -> String {
+  traffic_capture::export()
+}
+
+// Plays a looping pattern on a scalar actuator (index as in ScalarCmd), replacing whatever pattern
+// was already playing on it. Runs until stopped or the device disconnects, whether or not a client
+// is connected.
+pub fn start_pattern(
+  device_index: u32,
+  scalar_index: u32,
+  actuator_type: String,
+  pattern: Pattern,
+) -> Result<()> {
+  patterns::start(device_index, scalar_index, &actuator_type, pattern)
+}
+
+// Changes a playing pattern without restarting it.
+pub fn update_pattern(device_index: u32, scalar_index: u32, pattern: Pattern) -> Result<()> {
+  patterns::update(device_index, scalar_index, pattern)
+}
+
+pub fn stop_pattern(device_index: u32, scalar_index: u32) -> Result<()> {
+  patterns::stop(device_index, scalar_index)
+}
+
+pub fn stop_all_patterns() {
+  patterns::stop_all();
+}
+
+// Records device commands from clients and the frontend to a file, for replaying later.
+pub fn start_session_recording(path: String) -> Result<()> {
+  session_recording::start_recording(&path)
+}
+
+// Returns how many commands were recorded.
+pub fn stop_session_recording() -> Result<u32> {
+  session_recording::stop_recording()
+}
+
+// Plays a recording from start_session_recording back against the devices connected now, in the
+// background. A SessionReplayFinished bridge event is sent when it's done.
+pub fn start_session_replay(path: String) -> Result<()> {
+  session_recording::start_replay(&path)
+}
+
+pub fn stop_session_replay() {
+  session_recording::stop_replay();
+}
+
+// Command latency and error counts for each device that's had commands this session.
+pub fn get_device_command_stats() -> Vec<DeviceCommandStats> {
+  device_stats::stats()
+}
+
+// Runs all of the native side troubleshooting checks. Port and user config checks are skipped if
+// they're not passed in.
+pub fn run_system_check(
+  websocket_port: Option<u16>,
+  user_config: Option<String>,
+) -> SystemCheckReport {
+  system_check::run_system_check(
+    websocket_port,
+    user_config,
+    RUN_STATUS.load(Ordering::Relaxed),
+  )
+}
+
+pub fn check_bluetooth_state() -> Result<BluetoothState> {
+  bluetooth_state::check_bluetooth_state()
+}
+
+#[frb(mirror(IntifaceMessage))]
+pub enum _IntifaceMessage {
+  RequestEngineVersion { expected_version: u32 },
+  Stop {},
+}
+
+pub fn send(msg: IntifaceMessage) {
   if ENGINE_BROADCASTER.receiver_count() > 0 {
     ENGINE_BROADCASTER
       .send(msg)
@@ -239,33 +991,114 @@ pub fn send(msg_json: String) {
   }
 }
 
+// For the Android platform layer to call on doze, battery saver and foreground service changes, so
+// the engine can ease off (and save what it needs to) before the OS makes it.
+pub fn notify_android_power_event(event: AndroidPowerEvent) {
+  app_lifecycle::notify_android_power_event(event);
+}
+
+// For the iOS platform layer to call when the app goes to the background, before it can be
+// suspended, and when it comes back. Devices that dropped in between get scanned for.
+pub fn notify_app_background() {
+  app_lifecycle::notify_app_background();
+}
+
+pub fn notify_app_foreground() {
+  app_lifecycle::notify_app_foreground();
+}
+
 pub fn stop_engine() {
   info!("Stop engine called in rust.");
+  let shutdown_options = options::bridge_options().shutdown;
+  let running = RUNTIME.lock().unwrap().is_some();
+  shutdown::begin(devices::connected_devices());
+  let started_at = Instant::now();
   if let Some(notifier) = ENGINE_NOTIFIER.get() {
     notifier.notify_waiters();
   }
-  // Need to park ourselves real quick to let the other runtime threads finish out.
-  //
-  // HACK The android JNI drop calls (and sometimes windows UWP calls) are slow (100ms+) and need
-  // quite a while to get everything disconnected if there are currently connected devices. If they
-  // don't run to completion, the runtime won't shutdown properly and everything will stall. Running
-  // runtime_shutdown() doesn't work here because these are all tasks that may be stalled at the OS
-  // level so we don't have enough info. Waiting on this is not the optimal way to do it, but I also
-  // don't have a good way to know when shutdown is finished right now. So waiting it is. This isn't
-  // super noticable from an UX standpoint.
-  thread::sleep(Duration::from_millis(500));
-  let runtime;
-  {
-    runtime = RUNTIME.lock().unwrap().take();
-  }
+  // The android JNI drop calls (and sometimes windows UWP calls) are slow (100ms+) and need quite a
+  // while to get everything disconnected if there are currently connected devices. If they don't run
+  // to completion, the runtime won't shutdown properly and everything will stall, so wait for the
+  // engine to say they're all gone before taking the runtime down. If it never does, get more
+  // forceful a stage at a time.
+  let (completed, devices) = if running {
+    shutdown::wait(Duration::from_millis(
+      shutdown_options.graceful_timeout_ms as u64,
+    ))
+  } else {
+    (true, vec![])
+  };
+  let mut stage = ShutdownStage::Graceful;
+  let engine_task = ENGINE_TASK.lock().unwrap().take();
+  let runtime = RUNTIME.lock().unwrap().take();
+  let mut cancelled_sink = None;
   if let Some(rt) = runtime {
+    if let (false, Some((task, sink))) = (completed || shutdown::has_exited(), engine_task) {
+      warn!("Engine did not stop in time, cancelling engine task");
+      stage = ShutdownStage::TaskCancelled;
+      task.abort();
+      let cancel_timeout = Duration::from_millis(shutdown_options.cancel_timeout_ms as u64);
+      if rt
+        .block_on(async { tokio::time::timeout(cancel_timeout, task).await })
+        .is_err()
+      {
+        warn!("Engine task did not cancel in time, shutting down runtime under it");
+        stage = ShutdownStage::RuntimeShutdown;
+      }
+      cancelled_sink = Some(sink);
+    }
     info!("Shutting down runtime");
-    rt.shutdown_timeout(Duration::from_secs(1));
+    rt.shutdown_timeout(Duration::from_millis(
+      shutdown_options.runtime_timeout_ms as u64,
+    ));
     info!("Runtime shutdown complete");
   }
+  // A cancelled engine task never got to clean up after itself.
+  if let Some(sink) = cancelled_sink {
+    if !shutdown::has_exited() {
+      clear_engine_state(&sink);
+    }
+  }
+  if running {
+    events::emit(BridgeEvent::EngineShutdown {
+      stage,
+      elapsed_ms: started_at.elapsed().as_millis() as u64,
+      devices,
+    });
+  }
   RUN_STATUS.store(false, Ordering::Relaxed);
 }
 
+// For after a Dart hot restart, which leaves the engine running but the stream it was sending to
+// dead. Points the running engine at the new stream. Streams for logging and events are replaced
+// as usual by setting them up again, but BLE probes and mDNS browsing were sending to the old
+// isolate, so those are stopped.
+pub fn reattach(sink: StreamSink<String>) -> Result<()> {
+  let Some(engine_sink) = ENGINE_SINK.lock().unwrap().clone() else {
+    return Err(BridgeError::NotRunning.into());
+  };
+  info!("Reattaching engine to new message stream");
+  engine_sink.swap(Arc::new(sink));
+  ble_probe::stop();
+  mdns_browse::stop();
+  Ok(())
+}
+
+// For when there's nothing worth reattaching to, or reattaching didn't work. Stops the engine if
+// it's still going and drops every stream the bridge is holding, so the next run_engine starts
+// clean instead of failing on leftovers from before the restart.
+pub fn force_reset_bridge() {
+  warn!("Force resetting bridge");
+  stop_engine();
+  ble_probe::stop();
+  mdns_browse::stop();
+  events::clear_sink();
+  device_events::clear_sink();
+  binary_stream::clear_sink();
+  backdoor_events::disconnect();
+  shutdown_logging();
+}
+
 pub fn send_backend_server_message(msg: String) {
   if BACKDOOR_INCOMING_BROADCASTER.receiver_count() > 0 {
     BACKDOOR_INCOMING_BROADCASTER
@@ -274,6 +1107,145 @@ pub fn send_backend_server_message(msg: String) {
   }
 }
 
+// Same as send_backend_server_message, for messages the bridge builds itself. Returns false if
+// there's no backdoor server running to get it.
+pub(crate) fn send_bridge_backdoor_message(msg: String) -> bool {
+  BACKDOOR_INCOMING_BROADCASTER.send(msg).is_ok()
+}
+
+// Typed client for the running engine, in place of a Buttplug client on the backdoor. Sends every
+// connected device, then device and scanning events, until the engine stops. Commands go through the
+// backdoor_* calls below.
+pub fn backdoor_connect(sink: StreamSink<BackdoorEvent>) -> Result<()> {
+  backdoor_events::connect(sink)
+}
+
+pub fn backdoor_disconnect() {
+  backdoor_events::disconnect();
+}
+
+// Timeout overrides the scan_timeout_ms option for this scan, with 0 for none.
+pub fn backdoor_start_scanning(timeout_ms: Option<u32>) -> Result<()> {
+  backdoor_client::start_scanning(timeout_ms)
+}
+
+pub fn backdoor_stop_scanning() -> Result<()> {
+  backdoor_client::stop_scanning()
+}
+
+pub fn backdoor_request_device_list() -> Result<Vec<BackdoorDevice>> {
+  backdoor_client::request_device_list()
+}
+
+pub fn backdoor_stop_device(device_index: u32) -> Result<()> {
+  backdoor_client::stop_device(device_index)
+}
+
+// Pulses each actuator on the device in turn at a low level, for the settings page test button.
+// Results are per actuator, so one that doesn't respond doesn't hide the others.
+pub fn test_device(device_index: u32) -> Result<Vec<ActuatorTestResult>> {
+  backdoor_client::test_device(device_index)
+}
+
+pub fn backdoor_stop_all() -> Result<()> {
+  backdoor_client::stop_all()
+}
+
+// Streams readings from a sensor on a connected device to the sink, until unsubscribe_sensor is
+// called or the device disconnects. Sensor type is the Buttplug name, e.g. "Pressure" or "Button".
+pub fn subscribe_sensor(
+  sink: StreamSink<SensorReadingEvent>,
+  device_index: u32,
+  sensor_index: u32,
+  sensor_type: String,
+) -> Result<()> {
+  sensor_stream::subscribe(sink, device_index, sensor_index, sensor_type)
+}
+
+// Same as subscribe_sensor, with readings going out on the binary stream instead. The binary stream
+// needs to be set up first.
+pub fn subscribe_sensor_binary(
+  device_index: u32,
+  sensor_index: u32,
+  sensor_type: String,
+) -> Result<()> {
+  sensor_stream::subscribe_binary(device_index, sensor_index, sensor_type)
+}
+
+pub fn unsubscribe_sensor(device_index: u32, sensor_index: u32, sensor_type: String) -> Result<()> {
+  sensor_stream::unsubscribe(device_index, sensor_index, sensor_type)
+}
+
+fn check_raw_messages_allowed() -> Result<()> {
+  if !RAW_MESSAGES_ALLOWED.load(Ordering::Relaxed) {
+    return Err(anyhow::Error::msg(
+      "Raw endpoint access is only available when raw messages are allowed",
+    ));
+  }
+  Ok(())
+}
+
+// Writes hex encoded bytes straight to a device endpoint (e.g. "tx"), bypassing its protocol.
+pub fn raw_write(
+  device_index: u32,
+  endpoint: String,
+  hex_data: String,
+  write_with_response: bool,
+) -> Result<()> {
+  check_raw_messages_allowed()?;
+  raw_endpoints::write(device_index, &endpoint, &hex_data, write_with_response)
+}
+
+// Reads from a device endpoint, returning the data hex encoded.
+pub fn raw_read(
+  device_index: u32,
+  endpoint: String,
+  expected_length: u32,
+  timeout_ms: u32,
+) -> Result<String> {
+  check_raw_messages_allowed()?;
+  raw_endpoints::read(device_index, &endpoint, expected_length, timeout_ms)
+}
+
+pub fn raw_subscribe(
+  sink: StreamSink<RawReadingEvent>,
+  device_index: u32,
+  endpoint: String,
+) -> Result<()> {
+  check_raw_messages_allowed()?;
+  raw_endpoints::subscribe(sink, device_index, &endpoint)
+}
+
+pub fn raw_unsubscribe(device_index: u32, endpoint: String) -> Result<()> {
+  raw_endpoints::unsubscribe(device_index, &endpoint)
+}
+
+// Asks a connected device for its firmware version, hardware revision and serial number, where
+// its protocol supports it. Goes over raw endpoints, so needs raw messages allowed too. Once known,
+// the details are included in that device's events.
+pub fn query_device_hardware_info(device_index: u32) -> Result<DeviceHardwareInfo> {
+  check_raw_messages_allowed()?;
+  hardware_info::query(device_index)
+}
+
+pub fn backdoor_scalar(device_index: u32, scalars: Vec<BackdoorScalarCommand>) -> Result<()> {
+  backdoor_client::scalar(device_index, &scalars)
+}
+
+// Speeds for each of the device's vibrators, in order. If there are fewer speeds than vibrators,
+// the rest are left as they are.
+pub fn backdoor_vibrate(device_index: u32, speeds: Vec<f64>) -> Result<()> {
+  backdoor_client::vibrate(device_index, &speeds)
+}
+
+pub fn backdoor_rotate(device_index: u32, rotations: Vec<BackdoorRotateCommand>) -> Result<()> {
+  backdoor_client::rotate(device_index, &rotations)
+}
+
+pub fn backdoor_linear(device_index: u32, vectors: Vec<BackdoorLinearCommand>) -> Result<()> {
+  backdoor_client::linear(device_index, &vectors)
+}
+
 // "Exposed" types are mirrors of internal Buttplug types, but with all public members and typing
 // that's amiable to FlutterRustBridge translation. These types can't be directly mirrored because
 // the library itself has private members.
@@ -281,7 +1253,7 @@ pub fn send_backend_server_message(msg: String) {
 // "But don't you own the library also?" I mean, yes, I do, but I cannot emotionally handle bringing
 // myself to set the struct members public there just for this application. I hate having ethics.
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ExposedUserDeviceIdentifier {
   pub address: String,
   pub protocol: String,
@@ -304,6 +1276,63 @@ impl Into<UserDeviceIdentifier> for ExposedUserDeviceIdentifier {
   }
 }
 
+// The server side version of the identifier, which is what protocols and the device manager work
+// with. Same information as the user config identifier, but the protocol attributes identifier is
+// an enum there. A missing identifier here means the protocol default attributes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExposedServerDeviceIdentifier {
+  pub address: String,
+  pub protocol: String,
+  pub attributes_identifier: Option<String>,
+}
+
+impl From<ServerDeviceIdentifier> for ExposedServerDeviceIdentifier {
+  fn from(value: ServerDeviceIdentifier) -> Self {
+    Self {
+      address: value.address().clone(),
+      protocol: value.protocol().clone(),
+      attributes_identifier: match value.attributes_identifier() {
+        ProtocolAttributesType::Identifier(identifier) => Some(identifier.clone()),
+        ProtocolAttributesType::Default => None,
+      },
+    }
+  }
+}
+
+impl From<ExposedServerDeviceIdentifier> for ServerDeviceIdentifier {
+  fn from(value: ExposedServerDeviceIdentifier) -> Self {
+    ServerDeviceIdentifier::new(
+      &value.address,
+      &value.protocol,
+      &value
+        .attributes_identifier
+        .map_or(ProtocolAttributesType::Default, |identifier| {
+          ProtocolAttributesType::Identifier(identifier)
+        }),
+    )
+  }
+}
+
+impl From<ExposedServerDeviceIdentifier> for ExposedUserDeviceIdentifier {
+  fn from(value: ExposedServerDeviceIdentifier) -> Self {
+    Self {
+      address: value.address,
+      protocol: value.protocol,
+      identifier: value.attributes_identifier,
+    }
+  }
+}
+
+impl From<ExposedUserDeviceIdentifier> for ExposedServerDeviceIdentifier {
+  fn from(value: ExposedUserDeviceIdentifier) -> Self {
+    Self {
+      address: value.address,
+      protocol: value.protocol,
+      attributes_identifier: value.identifier,
+    }
+  }
+}
+
 #[derive(Debug, Clone)]
 pub struct ExposedSerialSpecifier {
   pub baud_rate: u32,
@@ -356,16 +1385,337 @@ impl Into<WebsocketSpecifier> for ExposedWebsocketSpecifier {
   }
 }
 
-#[derive(Debug, Clone)]
-pub struct ExposedDeviceFeatureActuator {
-  pub step_range: (u32, u32),
-  pub step_limit: (u32, u32),
-  pub messages: Vec<ButtplugActuatorFeatureMessageType>,
+#[derive(Debug, Error)]
+pub enum WebsocketSpecifierError {
+  #[error("Websocket device name cannot be empty")]
+  EmptyName,
+
+  // Devices are matched on name alone, so a name can only go to one protocol.
+  #[error("Websocket device name {name} is already used by protocol {protocol}")]
+  DuplicateName { name: String, protocol: String },
 }
 
-impl From<DeviceFeatureActuator> for ExposedDeviceFeatureActuator {
-  fn from(value: DeviceFeatureActuator) -> Self {
-    Self {
+impl ExposedWebsocketSpecifier {
+  fn validate(&self) -> Result<(), WebsocketSpecifierError> {
+    if self.name.trim().is_empty() {
+      return Err(WebsocketSpecifierError::EmptyName);
+    }
+    Ok(())
+  }
+}
+
+// Finds a protocol other than this one with a websocket specifier for the name, in either the base or
+// user config.
+fn websocket_name_owner(
+  dcm: &DeviceConfigurationManager,
+  protocol: &str,
+  name: &str,
+) -> Option<String> {
+  let has_name = |specifiers: &[ProtocolCommunicationSpecifier]| {
+    specifiers.iter().any(|x| match x {
+      ProtocolCommunicationSpecifier::Websocket(ws) => ws.name() == name,
+      _ => false,
+    })
+  };
+  dcm
+    .user_communication_specifiers()
+    .iter()
+    .find(|kv| kv.key() != protocol && has_name(kv.value()))
+    .map(|kv| kv.key().clone())
+    .or_else(|| {
+      dcm
+        .base_communication_specifiers()
+        .iter()
+        .find(|(other, specifiers)| *other != protocol && has_name(specifiers))
+        .map(|(other, _)| other.clone())
+    })
+}
+
+fn validate_websocket_specifier(
+  dcm: &DeviceConfigurationManager,
+  protocol: &str,
+  specifier: &ExposedWebsocketSpecifier,
+) -> Result<(), WebsocketSpecifierError> {
+  specifier.validate()?;
+  match websocket_name_owner(dcm, protocol, &specifier.name) {
+    Some(owner) => Err(WebsocketSpecifierError::DuplicateName {
+      name: specifier.name.clone(),
+      protocol: owner,
+    }),
+    None => Ok(()),
+  }
+}
+
+#[derive(Debug, Error)]
+pub enum SerialSpecifierError {
+  #[error("Serial port name cannot be empty")]
+  EmptyPort,
+
+  #[error("Serial baud rate must be greater than 0")]
+  ZeroBaudRate,
+
+  #[error("Serial data bits must be between 5 and 8")]
+  InvalidDataBits,
+
+  #[error("Serial stop bits must be 1 or 2")]
+  InvalidStopBits,
+
+  #[error("Serial parity must be one of N, E, or O")]
+  InvalidParity,
+}
+
+impl ExposedSerialSpecifier {
+  pub(crate) fn validate(&self) -> Result<(), SerialSpecifierError> {
+    if self.port.is_empty() {
+      return Err(SerialSpecifierError::EmptyPort);
+    }
+    if self.baud_rate == 0 {
+      return Err(SerialSpecifierError::ZeroBaudRate);
+    }
+    if !(5..=8).contains(&self.data_bits) {
+      return Err(SerialSpecifierError::InvalidDataBits);
+    }
+    if !(1..=2).contains(&self.stop_bits) {
+      return Err(SerialSpecifierError::InvalidStopBits);
+    }
+    if !matches!(self.parity.as_str(), "N" | "E" | "O") {
+      return Err(SerialSpecifierError::InvalidParity);
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExposedHIDSpecifier {
+  pub vendor_id: u16,
+  pub product_id: u16,
+}
+
+impl From<HIDSpecifier> for ExposedHIDSpecifier {
+  fn from(value: HIDSpecifier) -> Self {
+    Self {
+      vendor_id: *value.vendor_id(),
+      product_id: *value.product_id(),
+    }
+  }
+}
+
+impl From<ExposedHIDSpecifier> for HIDSpecifier {
+  fn from(value: ExposedHIDSpecifier) -> Self {
+    HIDSpecifier::new(value.vendor_id, value.product_id)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExposedUSBSpecifier {
+  pub vendor_id: u16,
+  pub product_id: u16,
+}
+
+impl From<USBSpecifier> for ExposedUSBSpecifier {
+  fn from(value: USBSpecifier) -> Self {
+    Self {
+      vendor_id: *value.vendor_id(),
+      product_id: *value.product_id(),
+    }
+  }
+}
+
+impl From<ExposedUSBSpecifier> for USBSpecifier {
+  fn from(value: ExposedUSBSpecifier) -> Self {
+    USBSpecifier::new(value.vendor_id, value.product_id)
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExposedBluetoothLEManufacturerData {
+  pub company: u16,
+  pub data: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExposedBluetoothLEEndpoint {
+  pub endpoint: Endpoint,
+  pub characteristic: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExposedBluetoothLEService {
+  pub service: String,
+  pub endpoints: Vec<ExposedBluetoothLEEndpoint>,
+}
+
+// User config Bluetooth LE specifiers are for matching devices that advertise differently from the
+// ones in the base config, like rebranded clones, or for wiring up hardware with its own service
+// layout to an existing protocol.
+#[derive(Debug, Clone, Default)]
+pub struct ExposedBluetoothLESpecifier {
+  // Advertised names to match. A trailing * matches any name starting with what's before it.
+  pub names: Vec<String>,
+  pub manufacturer_data: Vec<ExposedBluetoothLEManufacturerData>,
+  pub advertised_services: Vec<String>,
+  // Service and characteristic UUIDs for each endpoint the protocol uses. If empty, these get
+  // copied over from the base config for the protocol when the specifier is set.
+  pub services: Vec<ExposedBluetoothLEService>,
+}
+
+// The library doesn't give us a way to build a BLE specifier field by field, so conversions go
+// through its config file representation instead.
+impl ExposedBluetoothLESpecifier {
+  pub(crate) fn from_specifier(specifier: &BluetoothLESpecifier) -> Option<Self> {
+    let value = serde_json::to_value(specifier).ok()?;
+    let strings = |key: &str| -> Vec<String> {
+      value
+        .get(key)
+        .and_then(|x| x.as_array())
+        .map(|x| {
+          x.iter()
+            .filter_map(|x| x.as_str().map(|x| x.to_owned()))
+            .collect()
+        })
+        .unwrap_or_default()
+    };
+    let manufacturer_data = value
+      .get("manufacturer-data")
+      .and_then(|x| x.as_array())
+      .map(|entries| {
+        entries
+          .iter()
+          .filter_map(|entry| {
+            Some(ExposedBluetoothLEManufacturerData {
+              company: entry.get("company")?.as_u64()? as u16,
+              data: entry
+                .get("data")
+                .and_then(|x| serde_json::from_value(x.clone()).ok()),
+            })
+          })
+          .collect()
+      })
+      .unwrap_or_default();
+    let services = value
+      .get("services")
+      .and_then(|x| x.as_object())
+      .map(|services| {
+        services
+          .iter()
+          .map(|(service, endpoints)| ExposedBluetoothLEService {
+            service: service.clone(),
+            endpoints: endpoints
+              .as_object()
+              .map(|endpoints| {
+                endpoints
+                  .iter()
+                  .filter_map(|(endpoint, characteristic)| {
+                    Some(ExposedBluetoothLEEndpoint {
+                      endpoint: serde_json::from_value(json!(endpoint)).ok()?,
+                      characteristic: characteristic.as_str()?.to_owned(),
+                    })
+                  })
+                  .collect()
+              })
+              .unwrap_or_default(),
+          })
+          .collect()
+      })
+      .unwrap_or_default();
+    Some(Self {
+      names: strings("names"),
+      manufacturer_data,
+      advertised_services: strings("advertised-services"),
+      services,
+    })
+  }
+
+  // Default services are used if the specifier doesn't have any of its own.
+  pub(crate) fn into_specifier(
+    self,
+    default_services: Option<serde_json::Value>,
+  ) -> Result<BluetoothLESpecifier> {
+    let services = if self.services.is_empty() {
+      default_services.ok_or(anyhow::Error::msg(
+        "Bluetooth LE specifier has no services, and the protocol has no Bluetooth LE configuration to copy them from",
+      ))?
+    } else {
+      let mut services = serde_json::Map::new();
+      for service in self.services {
+        let mut endpoints = serde_json::Map::new();
+        for endpoint in service.endpoints {
+          let name = serde_json::to_value(endpoint.endpoint)?
+            .as_str()
+            .map(|x| x.to_owned())
+            .ok_or(anyhow::Error::msg("Cannot serialize endpoint name"))?;
+          endpoints.insert(name, json!(endpoint.characteristic));
+        }
+        services.insert(service.service, serde_json::Value::Object(endpoints));
+      }
+      serde_json::Value::Object(services)
+    };
+    let manufacturer_data: Vec<serde_json::Value> = self
+      .manufacturer_data
+      .into_iter()
+      .map(|entry| match entry.data {
+        Some(data) => json!({ "company": entry.company, "data": data }),
+        None => json!({ "company": entry.company }),
+      })
+      .collect();
+    Ok(serde_json::from_value(json!({
+      "names": self.names,
+      "manufacturer-data": manufacturer_data,
+      "advertised-services": self.advertised_services,
+      "services": services,
+    }))?)
+  }
+
+  pub(crate) fn validate(&self) -> Result<()> {
+    if self.names.is_empty()
+      && self.manufacturer_data.is_empty()
+      && self.advertised_services.is_empty()
+    {
+      return Err(anyhow::Error::msg(
+        "Bluetooth LE specifier needs at least one name, manufacturer data entry, or service",
+      ));
+    }
+    if self
+      .names
+      .iter()
+      .any(|name| name.trim_end_matches('*').is_empty())
+    {
+      return Err(anyhow::Error::msg("Bluetooth LE names cannot be empty"));
+    }
+    if self
+      .services
+      .iter()
+      .any(|service| service.endpoints.is_empty())
+    {
+      return Err(anyhow::Error::msg(
+        "Bluetooth LE services need at least one endpoint",
+      ));
+    }
+    Ok(())
+  }
+}
+
+// All of the user specifiers for a single protocol, so the frontend can edit them as one unit
+// instead of one call per type.
+#[derive(Debug, Clone, Default)]
+pub struct ExposedUserDeviceSpecifiers {
+  pub websocket: Vec<ExposedWebsocketSpecifier>,
+  pub serial: Vec<ExposedSerialSpecifier>,
+  pub hid: Vec<ExposedHIDSpecifier>,
+  pub usb: Vec<ExposedUSBSpecifier>,
+  pub bluetooth_le: Vec<ExposedBluetoothLESpecifier>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExposedDeviceFeatureActuator {
+  pub step_range: (u32, u32),
+  pub step_limit: (u32, u32),
+  pub messages: Vec<ButtplugActuatorFeatureMessageType>,
+}
+
+impl From<DeviceFeatureActuator> for ExposedDeviceFeatureActuator {
+  fn from(value: DeviceFeatureActuator) -> Self {
+    Self {
       step_range: (*value.step_range().start(), *value.step_range().end()),
       step_limit: (*value.step_limit().start(), *value.step_limit().end()),
       messages: value.messages().iter().cloned().collect(),
@@ -422,11 +1772,16 @@ pub struct ExposedDeviceFeature {
   pub actuator: Option<ExposedDeviceFeatureActuator>,
   pub sensor: Option<ExposedDeviceFeatureSensor>,
   // Leave out raw here, we'll never need it in the UI anyways
+  //
+  // Disabled features stay in the list, so feature indexes don't shift, but support no messages, so
+  // clients never see them.
+  pub enabled: bool,
 }
 
 impl From<DeviceFeature> for ExposedDeviceFeature {
   fn from(value: DeviceFeature) -> Self {
     Self {
+      enabled: feature_enabled(&value),
       description: value.description().clone(),
       feature_type: *value.feature_type(),
       actuator: value
@@ -443,12 +1798,17 @@ impl From<DeviceFeature> for ExposedDeviceFeature {
 
 impl Into<DeviceFeature> for ExposedDeviceFeature {
   fn into(self) -> DeviceFeature {
-    DeviceFeature::new(
+    let feature = DeviceFeature::new(
       &self.description,
       self.feature_type,
       &self.actuator.and_then(|x| Some(x.into())),
       &self.sensor.and_then(|x| Some(x.into())),
-    )
+    );
+    if self.enabled {
+      feature
+    } else {
+      feature_with_messages(&feature, None)
+    }
   }
 }
 
@@ -629,15 +1989,18 @@ pub enum _ButtplugDeviceMessageType {
 pub fn setup_device_configuration_manager(
   base_config: Option<String>,
   user_config: Option<String>,
-) {
+) -> Result<()> {
+  let new_dcm = load_protocol_configs(&base_config, &user_config, false)
+    .and_then(|mut builder| builder.finish())
+    .map_err(|e| {
+      let detail = format!("{:?}", e);
+      error_reporting::report(ErrorSource::ConfigLoad, None, &detail);
+      BridgeError::ConfigParse { detail }
+    })?;
   if let Ok(mut dcm) = DEVICE_CONFIG_MANAGER.try_write() {
-    *dcm = Arc::new(
-      load_protocol_configs(&base_config, &user_config, false)
-        .unwrap()
-        .finish()
-        .unwrap(),
-    );
+    *dcm = Arc::new(new_dcm);
   }
+  Ok(())
 }
 
 pub fn get_user_websocket_communication_specifiers() -> Vec<(String, ExposedWebsocketSpecifier)> {
@@ -676,6 +2039,184 @@ pub fn get_user_serial_communication_specifiers() -> Vec<(String, ExposedSerialS
   port_specs
 }
 
+pub fn get_user_device_specifiers() -> Vec<(String, ExposedUserDeviceSpecifiers)> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  user_device_specifiers(&dcm)
+}
+
+pub(crate) fn user_device_specifiers(
+  dcm: &DeviceConfigurationManager,
+) -> Vec<(String, ExposedUserDeviceSpecifiers)> {
+  let mut specifiers = vec![];
+  for kv in dcm.user_communication_specifiers() {
+    let mut protocol_specifiers = ExposedUserDeviceSpecifiers::default();
+    for comm_spec in kv.value() {
+      match comm_spec {
+        ProtocolCommunicationSpecifier::Websocket(ws) => {
+          protocol_specifiers.websocket.push(ws.clone().into())
+        }
+        ProtocolCommunicationSpecifier::Serial(port) => {
+          protocol_specifiers.serial.push(port.clone().into())
+        }
+        ProtocolCommunicationSpecifier::HID(hid) => {
+          protocol_specifiers.hid.push(hid.clone().into())
+        }
+        ProtocolCommunicationSpecifier::USB(usb) => {
+          protocol_specifiers.usb.push(usb.clone().into())
+        }
+        ProtocolCommunicationSpecifier::BluetoothLE(ble) => {
+          if let Some(ble) = ExposedBluetoothLESpecifier::from_specifier(ble) {
+            protocol_specifiers.bluetooth_le.push(ble);
+          }
+        }
+      }
+    }
+    specifiers.push((kv.key().to_owned(), protocol_specifiers));
+  }
+  specifiers
+}
+
+// Replaces all of the user specifiers for a protocol.
+// Everything is checked before anything is changed, and the old set is put back if the config
+// manager refuses one of the new ones, so a bad entry won't leave the protocol half updated.
+pub fn set_user_device_specifiers(
+  protocol: String,
+  specifiers: ExposedUserDeviceSpecifiers,
+) -> Result<()> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  apply_user_device_specifiers(&dcm, &protocol, specifiers)
+}
+
+pub(crate) fn apply_user_device_specifiers(
+  dcm: &DeviceConfigurationManager,
+  protocol: &str,
+  specifiers: ExposedUserDeviceSpecifiers,
+) -> Result<()> {
+  if !get_default_protocol_map().contains_key(protocol) {
+    return Err(anyhow::Error::msg(format!("Unknown protocol {}", protocol)));
+  }
+  for ws in &specifiers.websocket {
+    validate_websocket_specifier(dcm, protocol, ws)?;
+  }
+  for port in &specifiers.serial {
+    port.validate()?;
+  }
+  let vendor_ids = specifiers
+    .hid
+    .iter()
+    .map(|hid| hid.vendor_id)
+    .chain(specifiers.usb.iter().map(|usb| usb.vendor_id));
+  for vendor_id in vendor_ids {
+    if vendor_id == 0 {
+      return Err(anyhow::Error::msg("HID/USB vendor id cannot be 0"));
+    }
+  }
+  for ble in &specifiers.bluetooth_le {
+    ble.validate()?;
+  }
+  let default_services = base_bluetooth_le_services(dcm, protocol);
+  let mut bluetooth_le = vec![];
+  for ble in specifiers.bluetooth_le {
+    bluetooth_le.push(ProtocolCommunicationSpecifier::BluetoothLE(
+      ble.into_specifier(default_services.clone())?,
+    ));
+  }
+  let existing: Vec<ProtocolCommunicationSpecifier> = dcm
+    .user_communication_specifiers()
+    .get(protocol)
+    .map(|x| x.value().clone())
+    .unwrap_or_default();
+  for comm_spec in &existing {
+    dcm.remove_user_communication_specifier(protocol, comm_spec);
+  }
+  let new_specifiers: Vec<ProtocolCommunicationSpecifier> = specifiers
+    .websocket
+    .into_iter()
+    .map(|ws| ProtocolCommunicationSpecifier::Websocket(ws.into()))
+    .chain(
+      specifiers
+        .serial
+        .into_iter()
+        .map(|port| ProtocolCommunicationSpecifier::Serial(port.into())),
+    )
+    .chain(
+      specifiers
+        .hid
+        .into_iter()
+        .map(|hid| ProtocolCommunicationSpecifier::HID(hid.into())),
+    )
+    .chain(
+      specifiers
+        .usb
+        .into_iter()
+        .map(|usb| ProtocolCommunicationSpecifier::USB(usb.into())),
+    )
+    .chain(bluetooth_le)
+    .collect();
+  for (added, comm_spec) in new_specifiers.iter().enumerate() {
+    if let Err(e) = dcm.add_user_communication_specifier(protocol, comm_spec) {
+      // Put the protocol back the way it was, rather than leaving it with only some of the new set.
+      for comm_spec in &new_specifiers[..added] {
+        dcm.remove_user_communication_specifier(protocol, comm_spec);
+      }
+      for comm_spec in &existing {
+        if let Err(e) = dcm.add_user_communication_specifier(protocol, comm_spec) {
+          warn!("Cannot restore specifier for {}: {:?}", protocol, e);
+        }
+      }
+      return Err(anyhow::Error::msg(format!("{:?}", e)));
+    }
+  }
+  Ok(())
+}
+
+// Endpoint map from the first Bluetooth LE specifier in the base config for a protocol, in config
+// file form.
+pub(crate) fn base_bluetooth_le_services(
+  dcm: &DeviceConfigurationManager,
+  protocol: &str,
+) -> Option<serde_json::Value> {
+  dcm
+    .base_communication_specifiers()
+    .get(protocol)?
+    .iter()
+    .find_map(|comm_spec| match comm_spec {
+      ProtocolCommunicationSpecifier::BluetoothLE(ble) => {
+        serde_json::to_value(ble).ok()?.get("services").cloned()
+      }
+      _ => None,
+    })
+}
+
+// Wires up a Bluetooth LE device with its own service layout to an existing protocol, which handles
+// the commands. Protocol command handling is compiled into the library, so a protocol that speaks
+// the same commands as the hardware still has to exist.
+pub fn add_custom_bluetooth_le_device(
+  protocol: String,
+  specifier: ExposedBluetoothLESpecifier,
+) -> Result<()> {
+  if !get_default_protocol_map().contains_key(&protocol) {
+    return Err(anyhow::Error::msg(format!("Unknown protocol {}", protocol)));
+  }
+  if specifier.services.is_empty() {
+    return Err(anyhow::Error::msg(
+      "Custom Bluetooth LE devices need at least one service",
+    ));
+  }
+  specifier.validate()?;
+  let comm_spec = ProtocolCommunicationSpecifier::BluetoothLE(specifier.into_specifier(None)?);
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  dcm
+    .add_user_communication_specifier(&protocol, &comm_spec)
+    .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))
+}
+
 pub fn get_user_device_definitions(
 ) -> Vec<(ExposedUserDeviceIdentifier, ExposedUserDeviceDefinition)> {
   let dcm = DEVICE_CONFIG_MANAGER
@@ -688,6 +2229,20 @@ pub fn get_user_device_definitions(
     .collect()
 }
 
+// Definition for a single configured device, so the device settings page doesn't have to pull every
+// definition just to show one.
+pub fn get_user_device_definition(
+  identifier: ExposedUserDeviceIdentifier,
+) -> Option<ExposedUserDeviceDefinition> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  dcm
+    .user_device_definitions()
+    .get(&identifier.into())
+    .map(|x| x.value().clone().into())
+}
+
 pub fn get_protocol_names() -> Vec<String> {
   get_default_protocol_map()
     .keys()
@@ -696,14 +2251,29 @@ pub fn get_protocol_names() -> Vec<String> {
     .collect()
 }
 
-pub fn add_websocket_specifier(protocol: String, name: String) {
+// Protocol names along with what the UI needs to know to filter them, sorted by name.
+pub fn get_protocol_info() -> Vec<ProtocolInfo> {
+  protocol_info::protocol_info()
+}
+
+// Feature types devices of the protocol can have, going by its default configs, so the UI can show
+// what a device will be able to do before it's bound to the protocol.
+pub fn get_protocol_features(protocol_name: String) -> Result<Vec<FeatureType>> {
+  protocol_info::protocol_features(&protocol_name)
+}
+
+pub fn add_websocket_specifier(protocol: String, name: String) -> Result<()> {
+  let specifier = ExposedWebsocketSpecifier { name };
   let dcm = DEVICE_CONFIG_MANAGER
     .try_read()
     .expect("We should have a reader at this point");
-  dcm.add_user_communication_specifier(
-    &protocol,
-    &ProtocolCommunicationSpecifier::Websocket(WebsocketSpecifier::new(&name)),
-  );
+  validate_websocket_specifier(&dcm, &protocol, &specifier)?;
+  dcm
+    .add_user_communication_specifier(
+      &protocol,
+      &ProtocolCommunicationSpecifier::Websocket(specifier.into()),
+    )
+    .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))
 }
 
 pub fn remove_websocket_specifier(protocol: String, name: String) {
@@ -723,20 +2293,36 @@ pub fn add_serial_specifier(
   data_bits: u8,
   stop_bits: u8,
   parity: String,
-) {
+) -> Result<()> {
+  let specifier = ExposedSerialSpecifier {
+    baud_rate,
+    data_bits,
+    stop_bits,
+    parity,
+    port,
+  };
+  specifier.validate()?;
   let dcm = DEVICE_CONFIG_MANAGER
     .try_read()
     .expect("We should have a reader at this point");
-  dcm.add_user_communication_specifier(
-    &protocol,
-    &ProtocolCommunicationSpecifier::Serial(SerialSpecifier::new(
-      &port,
-      baud_rate,
-      data_bits,
-      stop_bits,
-      parity.chars().next().unwrap(),
-    )),
-  );
+  dcm
+    .add_user_communication_specifier(
+      &protocol,
+      &ProtocolCommunicationSpecifier::Serial(specifier.into()),
+    )
+    .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))
+}
+
+// Sets up a device on a serial port for a protocol, checking the port exists first. With hot_add, a
+// running engine scans for a few seconds to pick it up. Like the other specifier calls, this only
+// changes the loaded config, save it to keep the device.
+pub fn add_serial_device(
+  port: String,
+  baud_rate: u32,
+  protocol: String,
+  hot_add: bool,
+) -> Result<()> {
+  serial_devices::add_device(port, baud_rate, protocol, hot_add)
 }
 
 pub fn remove_serial_specifier(protocol: String, port: String) {
@@ -752,18 +2338,683 @@ pub fn remove_serial_specifier(protocol: String, port: String) {
 pub fn update_user_config(
   identifier: ExposedUserDeviceIdentifier,
   config: ExposedUserDeviceDefinition,
-) {
+) -> Result<()> {
+  let display_name = config.user_config.display_name.clone();
+  let enabled: Vec<bool> = config.features.iter().map(|x| x.enabled).collect();
+  {
+    let dcm = DEVICE_CONFIG_MANAGER
+      .try_read()
+      .expect("We should have a reader at this point");
+    let user_identifier: UserDeviceIdentifier = identifier.clone().into();
+    let definition: UserDeviceDefinition = config.into();
+    // Features being turned back on need their messages back, which only the base config has.
+    let features: Vec<DeviceFeature> = definition
+      .features()
+      .iter()
+      .zip(enabled)
+      .enumerate()
+      .map(|(index, (feature, enabled))| {
+        if !enabled || feature_enabled(feature) {
+          return feature.clone();
+        }
+        base_feature(&dcm, &user_identifier, index)
+          .map(|base| feature_with_messages(feature, Some(&base)))
+          .unwrap_or_else(|| feature.clone())
+      })
+      .collect();
+    dcm
+      .add_user_device_definition(
+        &user_identifier,
+        &UserDeviceDefinition::new(definition.name(), &features, definition.user_config()),
+      )
+      .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+  }
+  device_list::update_display_name(&identifier, display_name);
+  Ok(())
+}
+
+// Changes the index a device is reserved at, which is the index clients see it at, while the engine
+// runs. The engine keeps a connected device at the index it connected with, so a device connected
+// at another index is stopped (so nothing is left running under an index clients are about to lose)
+// and moves over when it next connects. Clients get that as the device being removed and added back,
+// so there's no need to restart the server or re-pair anything.
+pub fn set_device_index(identifier: ExposedUserDeviceIdentifier, index: u32) -> Result<()> {
+  let user_identifier: UserDeviceIdentifier = identifier.clone().into();
+  let old_index = {
+    let dcm = DEVICE_CONFIG_MANAGER
+      .try_read()
+      .expect("We should have a reader at this point");
+    let definition = dcm
+      .user_device_definitions()
+      .get(&user_identifier)
+      .map(|x| x.value().clone())
+      .ok_or(anyhow::Error::msg("No user config for that device"))?;
+    let reserved = dcm
+      .user_device_definitions()
+      .iter()
+      .any(|kv| kv.key() != &user_identifier && kv.value().user_config().index() == index);
+    let in_use = devices::connected_device(index).is_some_and(|x| x.identifier != identifier);
+    if reserved || in_use {
+      return Err(anyhow::Error::msg(format!(
+        "Index {} is already taken by another device",
+        index
+      )));
+    }
+    let config = definition.user_config();
+    if config.index() == index {
+      return Ok(());
+    }
+    dcm
+      .add_user_device_definition(
+        &user_identifier,
+        &UserDeviceDefinition::new(
+          definition.name(),
+          definition.features(),
+          &UserDeviceCustomization::new(
+            config.display_name(),
+            config.allow(),
+            config.deny(),
+            index,
+          ),
+        ),
+      )
+      .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+    config.index()
+  };
+  let connected_index = devices::device_index(&identifier);
+  if let Some(connected_index) = connected_index.filter(|x| *x != index) {
+    if let Err(e) = backdoor_client::stop_device(connected_index) {
+      warn!(
+        "Cannot stop device {} for index change: {:?}",
+        connected_index, e
+      );
+    }
+  }
+  events::emit(BridgeEvent::DeviceIndexChanged {
+    identifier,
+    old_index,
+    new_index: index,
+    connected_index,
+  });
+  Ok(())
+}
+
+pub fn remove_user_config(identifier: ExposedUserDeviceIdentifier) {
   let dcm = DEVICE_CONFIG_MANAGER
     .try_read()
     .expect("We should have a reader at this point");
-  dcm.add_user_device_definition(&identifier.into(), &config.into());
+  dcm.remove_user_device_definition(&identifier.into());
 }
 
-pub fn remove_user_config(identifier: ExposedUserDeviceIdentifier) {
+// Removes a single device from the user config file at config_path, as well as from the loaded
+// config. The file is read back in rather than written out from the loaded config, so anything
+// else the frontend has changed on disk isn't lost. Returns false if the device wasn't in the file.
+pub fn remove_user_device_config(
+  config_path: String,
+  identifier: ExposedUserDeviceIdentifier,
+) -> Result<bool> {
+  let user_identifier: UserDeviceIdentifier = identifier.into();
+  DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .remove_user_device_definition(&user_identifier);
+
+  let file_config = fs::read_to_string(&config_path)?;
+  let file_dcm = load_protocol_configs(&None, &Some(file_config), false)
+    .and_then(|mut builder| builder.finish())
+    .map_err(|e| BridgeError::ConfigParse {
+      detail: format!("{:?}", e),
+    })?;
+  if !file_dcm
+    .user_device_definitions()
+    .contains_key(&user_identifier)
+  {
+    return Ok(false);
+  }
+  file_dcm.remove_user_device_definition(&user_identifier);
+  let new_config = save_user_config(&file_dcm)
+    .map_err(|e| anyhow::Error::msg(format!("Cannot save user config: {:?}", e)))?;
+  device_config_updater::write_atomically(Path::new(&config_path), new_config.as_bytes())?;
+  Ok(true)
+}
+
+// Writes the user config to a file without ever leaving it half written, keeping a backup of what
+// was there before.
+pub fn save_user_config_file(path: String, config: ExposedUserConfig) -> Result<()> {
+  Ok(user_config_file::save(&path, config)?)
+}
+
+// Reads a user config file, migrating it if it's from an older version.
+pub fn load_user_config_file(path: String) -> Result<ExposedUserConfig> {
+  Ok(user_config_file::load(&path)?)
+}
+
+// Upgrades a user config file from an older version of the library. Should be run on the saved
+// config before it's handed to setup_device_configuration_manager, which will reject old versions.
+pub fn migrate_user_config(old_json: String) -> Result<String> {
+  Ok(user_config_migration::migrate_user_config(&old_json)?)
+}
+
+// Device and specifier level differences between two user configs. Both need to be the current
+// version, so run migrate_user_config on anything older first.
+pub fn diff_user_configs(old_json: String, new_json: String) -> Result<UserConfigDiff> {
+  Ok(user_config_diff::diff_user_configs(&old_json, &new_json)?)
+}
+
+// Returns every problem found in the user config, or an empty list if it's good to load.
+pub fn validate_user_config(user_config: String) -> Vec<UserConfigError> {
+  user_config_validation::validate_user_config(&user_config)
+}
+
+pub fn server_to_user_device_identifier(
+  identifier: ExposedServerDeviceIdentifier,
+) -> ExposedUserDeviceIdentifier {
+  identifier.into()
+}
+
+pub fn user_to_server_device_identifier(
+  identifier: ExposedUserDeviceIdentifier,
+) -> ExposedServerDeviceIdentifier {
+  identifier.into()
+}
+
+// Catches the frontend's device list up from the last version it saw, either with the deltas it
+// missed or, if it's too far behind, a full snapshot. Pass 0 to get the full list on startup.
+pub fn get_device_list(snapshot_version: u64) -> DeviceListSync {
+  device_list::sync(snapshot_version)
+}
+
+// Runtime index of a connected device, if a device with this identifier is currently connected.
+pub fn get_device_index(identifier: ExposedUserDeviceIdentifier) -> Option<u32> {
+  devices::device_index(&identifier)
+}
+
+// Identifier of the device currently connected at this runtime index.
+pub fn get_device_identifier(device_index: u32) -> Option<ExposedUserDeviceIdentifier> {
+  devices::connected_device(device_index).map(|device| device.identifier)
+}
+
+// Streams raw Bluetooth LE advertisements without starting the engine, to check whether a device is
+// advertising at all. Stops on stop_ble_probe, or when the engine starts.
+pub fn start_ble_probe(sink: StreamSink<BleAdvertisement>) -> Result<()> {
+  if RUN_STATUS.load(Ordering::Relaxed) {
+    return Err(anyhow::Error::msg(
+      "Cannot run Bluetooth probe while the engine is running",
+    ));
+  }
+  ble_probe::start(sink)
+}
+
+pub fn stop_ble_probe() {
+  ble_probe::stop();
+}
+
+// Services and characteristics the OS found on a connected Bluetooth LE device, with the endpoints
+// they're mapped to in the device config. This is for protocol development, so it's only available
+// while the engine is running with raw messages allowed.
+pub fn get_device_endpoint_map(device_index: u32) -> Result<Vec<DiscoveredService>> {
+  check_raw_messages_allowed()?;
+  let device = devices::connected_device(device_index)
+    .ok_or(anyhow::Error::msg("No device connected at that index"))?;
+  endpoint_map::endpoint_map(&device.identifier)
+}
+
+// Puts the bridge's clock into manual mode, where time only moves on advance_manual_clock. Only
+// meant for tests.
+pub fn set_manual_clock(enabled: bool) {
+  clock::set_manual(enabled);
+}
+
+pub fn advance_manual_clock(milliseconds: u64) {
+  clock::advance(Duration::from_millis(milliseconds));
+}
+
+pub fn get_device_settings_str() -> String {
+  device_settings::save()
+}
+
+pub fn load_device_settings(settings_json: String) -> Result<()> {
+  device_settings::load(&settings_json)
+}
+
+// Client permissions are saved separately from the user config, same as device settings.
+pub fn get_client_permissions_str() -> String {
+  client_permissions::save()
+}
+
+pub fn load_client_permissions(permissions_json: String) -> Result<()> {
+  client_permissions::load(&permissions_json)
+}
+
+// Address rules are saved separately from the user config too.
+pub fn get_address_rules_str() -> String {
+  address_rules::save()
+}
+
+pub fn load_address_rules(rules_json: String) -> Result<()> {
+  address_rules::load(&rules_json)
+}
+
+pub fn get_address_rules() -> Vec<AddressRule> {
+  address_rules::rules()
+}
+
+// Replaces the address rules, and applies them to every device in the user config. Patterns take *
+// and ? wildcards, e.g. "C4:8A:*". Deny rules win over allow rules.
+pub fn set_address_rules(rules: Vec<AddressRule>) -> Result<()> {
+  address_rules::set(rules)
+}
+
+pub fn get_client_permissions() -> Vec<ClientPermissions> {
+  client_permissions::permissions()
+}
+
+// Restricts a client, by the name it connects with, to the given devices. An empty list hides every
+// device from it. These are applied by the websocket proxy, which only gets put in front of the
+// engine if there were permissions set when it started.
+pub fn set_client_permissions(
+  client_name: String,
+  allowed_devices: Vec<ExposedUserDeviceIdentifier>,
+) -> Result<()> {
+  if client_name.is_empty() {
+    return Err(anyhow::Error::msg("Client name cannot be empty"));
+  }
+  client_permissions::set(&client_name, allowed_devices);
+  Ok(())
+}
+
+// Lets the client see every device again.
+pub fn remove_client_permissions(client_name: String) -> Result<()> {
+  client_permissions::remove(&client_name)
+}
+
+pub fn get_actuator_curves(identifier: ExposedUserDeviceIdentifier) -> Vec<ActuatorCurve> {
+  device_settings::settings(&identifier).curves
+}
+
+pub fn set_actuator_curve(
+  identifier: ExposedUserDeviceIdentifier,
+  curve: ActuatorCurve,
+) -> Result<()> {
+  curve.validate()?;
+  set_feature_step_limit(
+    &identifier,
+    curve.feature_index,
+    Some((curve.min, curve.max)),
+  )?;
+  device_settings::update(&identifier, |settings| {
+    settings
+      .curves
+      .retain(|x| x.feature_index != curve.feature_index);
+    settings.curves.push(curve);
+  });
+  Ok(())
+}
+
+pub fn get_device_ramp_rate(identifier: ExposedUserDeviceIdentifier) -> Option<f64> {
+  device_settings::settings(&identifier).max_rate_per_second
+}
+
+// Rate is in actuator units (0.0-1.0) per second, so 0.5 means going from off to full takes 2
+// seconds. None removes the limit.
+pub fn set_device_ramp_rate(
+  identifier: ExposedUserDeviceIdentifier,
+  max_rate_per_second: Option<f64>,
+) -> Result<()> {
+  if let Some(rate) = max_rate_per_second {
+    if !rate.is_finite() || rate <= 0.0 {
+      return Err(anyhow::Error::msg("Ramp rate must be greater than 0"));
+    }
+  }
+  device_settings::update(&identifier, |settings| {
+    settings.max_rate_per_second = max_rate_per_second;
+  });
+  Ok(())
+}
+
+pub fn get_device_auto_reconnect(identifier: ExposedUserDeviceIdentifier) -> bool {
+  !device_settings::settings(&identifier).no_auto_reconnect
+}
+
+// Whether to scan for the device when it drops, with auto reconnect on. On by default.
+pub fn set_device_auto_reconnect(identifier: ExposedUserDeviceIdentifier, enabled: bool) {
+  device_settings::update(&identifier, |settings| {
+    settings.no_auto_reconnect = !enabled;
+  });
+}
+
+pub fn remove_actuator_curve(
+  identifier: ExposedUserDeviceIdentifier,
+  feature_index: u32,
+) -> Result<()> {
+  set_feature_step_limit(&identifier, feature_index, None)?;
+  device_settings::update(&identifier, |settings| {
+    settings.curves.retain(|x| x.feature_index != feature_index);
+  });
+  Ok(())
+}
+
+// Stops a feature being advertised to clients, or starts it again. Disabling clears the messages the
+// feature supports, and enabling puts back the ones from the base config.
+pub fn set_device_feature_enabled(
+  identifier: ExposedUserDeviceIdentifier,
+  feature_index: u32,
+  enabled: bool,
+) -> Result<()> {
   let dcm = DEVICE_CONFIG_MANAGER
     .try_read()
     .expect("We should have a reader at this point");
-  dcm.remove_user_device_definition(&identifier.into());
+  let user_identifier: UserDeviceIdentifier = identifier.into();
+  let definition = dcm
+    .user_device_definitions()
+    .get(&user_identifier)
+    .map(|x| x.value().clone())
+    .ok_or(anyhow::Error::msg(
+      "No user configuration for device, it must connect at least once before features can be disabled.",
+    ))?;
+  let mut features = definition.features().clone();
+  let feature = features
+    .get_mut(feature_index as usize)
+    .ok_or(anyhow::Error::msg("Feature index out of range"))?;
+  if enabled {
+    let base = base_feature(&dcm, &user_identifier, feature_index as usize).ok_or(
+      anyhow::Error::msg("No base configuration for feature, cannot re-enable it"),
+    )?;
+    *feature = feature_with_messages(feature, Some(&base));
+  } else {
+    *feature = feature_with_messages(feature, None);
+  }
+  dcm
+    .add_user_device_definition(
+      &user_identifier,
+      &UserDeviceDefinition::new(definition.name(), &features, definition.user_config()),
+    )
+    .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))
+}
+
+// Whether a feature supports any messages. Features that are neither actuators nor sensors can't be
+// disabled, so count as enabled.
+fn feature_enabled(feature: &DeviceFeature) -> bool {
+  feature
+    .actuator()
+    .as_ref()
+    .is_none_or(|x| !x.messages().is_empty())
+    && feature
+      .sensor()
+      .as_ref()
+      .is_none_or(|x| !x.messages().is_empty())
+}
+
+// Copy of a feature with the messages from another (normally its base config), or with none.
+fn feature_with_messages(feature: &DeviceFeature, source: Option<&DeviceFeature>) -> DeviceFeature {
+  let actuator_messages = source
+    .and_then(|x| x.actuator().as_ref())
+    .map(|x| x.messages().clone())
+    .unwrap_or_default();
+  let sensor_messages = source
+    .and_then(|x| x.sensor().as_ref())
+    .map(|x| x.messages().clone())
+    .unwrap_or_default();
+  DeviceFeature::new(
+    feature.description(),
+    *feature.feature_type(),
+    &feature
+      .actuator()
+      .as_ref()
+      .map(|x| DeviceFeatureActuator::new(x.step_range(), x.step_limit(), &actuator_messages)),
+    &feature
+      .sensor()
+      .as_ref()
+      .map(|x| DeviceFeatureSensor::new(x.value_range(), &sensor_messages)),
+  )
+}
+
+fn base_feature(
+  dcm: &DeviceConfigurationManager,
+  identifier: &UserDeviceIdentifier,
+  feature_index: usize,
+) -> Option<DeviceFeature> {
+  user_config_migration::find_base_definition(dcm, identifier)
+    .ok()?
+    .1
+    .get(feature_index)
+    .cloned()
+}
+
+// Copy of an actuator feature with a new step limit, or None if the feature isn't an actuator or the
+// limit doesn't fit in its step range.
+pub(crate) fn feature_with_step_limit(
+  feature: &DeviceFeature,
+  step_limit: &RangeInclusive<u32>,
+) -> Option<DeviceFeature> {
+  let actuator = feature.actuator().clone()?;
+  let step_range = actuator.step_range();
+  if step_limit.start() < step_range.start() || step_limit.end() > step_range.end() {
+    return None;
+  }
+  Some(DeviceFeature::new(
+    feature.description(),
+    *feature.feature_type(),
+    &Some(DeviceFeatureActuator::new(
+      step_range,
+      step_limit,
+      actuator.messages(),
+    )),
+    feature.sensor(),
+  ))
+}
+
+// UserDeviceCustomization has no setters, and its constructor doesn't cover every field, so updates
+// go through its serialized form. Fails if the value doesn't survive the round trip, which means
+// the library doesn't know about the field.
+pub(crate) fn update_customization(
+  customization: &UserDeviceCustomization,
+  key: &str,
+  value: serde_json::Value,
+) -> Result<UserDeviceCustomization> {
+  let mut serialized = serde_json::to_value(customization)?;
+  serialized[key] = value.clone();
+  let updated: UserDeviceCustomization = serde_json::from_value(serialized)?;
+  if !value.is_null() && serde_json::to_value(&updated)?.get(key) != Some(&value) {
+    return Err(anyhow::Error::msg(format!(
+      "{} is not supported by this version of the device configuration library.",
+      key
+    )));
+  }
+  Ok(updated)
+}
+
+// Sets the step limit of an actuator feature to a fraction of its step range, or back to the full
+// range if no limit is given. This is what makes the curve output range apply server side.
+pub(crate) fn set_feature_step_limit(
+  identifier: &ExposedUserDeviceIdentifier,
+  feature_index: u32,
+  limit: Option<(f64, f64)>,
+) -> Result<()> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let user_identifier: UserDeviceIdentifier = identifier.clone().into();
+  let definition = dcm
+    .user_device_definitions()
+    .get(&user_identifier)
+    .map(|x| x.value().clone())
+    .ok_or(anyhow::Error::msg(
+      "No user configuration for device, it must connect at least once before curves can be set.",
+    ))?;
+  let mut features = definition.features().clone();
+  let feature = features
+    .get_mut(feature_index as usize)
+    .ok_or(anyhow::Error::msg("Feature index out of range"))?;
+  let actuator = feature
+    .actuator()
+    .clone()
+    .ok_or(anyhow::Error::msg("Feature is not an actuator"))?;
+  let step_range = actuator.step_range();
+  let step_limit = match limit {
+    Some((min, max)) => {
+      let span = (step_range.end() - step_range.start()) as f64;
+      RangeInclusive::new(
+        step_range.start() + (span * min).round() as u32,
+        step_range.start() + (span * max).round() as u32,
+      )
+    }
+    None => step_range.clone(),
+  };
+  *feature = feature_with_step_limit(feature, &step_limit).ok_or(anyhow::Error::msg(
+    "Step limit is outside of the feature step range",
+  ))?;
+  dcm
+    .add_user_device_definition(
+      &user_identifier,
+      &UserDeviceDefinition::new(definition.name(), &features, definition.user_config()),
+    )
+    .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))
+}
+
+// Minimum time between commands the server sends to a device. Helps with devices (or Bluetooth
+// stacks) that fall over if commands come in too quickly.
+pub fn get_device_message_gap(identifier: ExposedUserDeviceIdentifier) -> Option<u32> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let definition = dcm
+    .user_device_definitions()
+    .get(&identifier.into())?
+    .value()
+    .clone();
+  serde_json::to_value(definition.user_config())
+    .ok()?
+    .get("message-gap-ms")?
+    .as_u64()
+    .map(|gap| gap as u32)
+}
+
+pub fn set_device_message_gap(
+  identifier: ExposedUserDeviceIdentifier,
+  message_gap_ms: Option<u32>,
+) -> Result<()> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let user_identifier: UserDeviceIdentifier = identifier.into();
+  let definition = dcm
+    .user_device_definitions()
+    .get(&user_identifier)
+    .map(|x| x.value().clone())
+    .ok_or(anyhow::Error::msg(
+      "No user configuration for device, it must connect at least once before the message gap can be set.",
+    ))?;
+  let customization = update_customization(
+    definition.user_config(),
+    "message-gap-ms",
+    json!(message_gap_ms.filter(|gap| *gap > 0)),
+  )?;
+  dcm
+    .add_user_device_definition(
+      &user_identifier,
+      &UserDeviceDefinition::new(definition.name(), definition.features(), &customization),
+    )
+    .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))
+}
+
+// All four XInput controller slots, with whatever has connected in them and how they're set up.
+pub fn get_xinput_slots() -> Vec<XInputSlot> {
+  xinput::slots()
+}
+
+pub fn set_xinput_slot_enabled(slot: u32, enabled: bool) -> Result<()> {
+  xinput::set_enabled(slot, enabled)
+}
+
+// Picks which rumble motors a slot's controller uses. Works through step limits, so it replaces any
+// output range set on the motors with curves.
+pub fn set_xinput_slot_motors(slot: u32, motors: XInputMotors) -> Result<()> {
+  xinput::set_motors(slot, motors)
+}
+
+// Packs a device's setup (display name, limits, message gap, curves and ramps) into a string that
+// can be pasted into chat.
+pub fn get_device_share_code(identifier: ExposedUserDeviceIdentifier) -> Result<String> {
+  share_code::encode(&identifier)
+}
+
+pub fn get_device_share_code_info(code: String) -> Result<DeviceShareCodeInfo> {
+  share_code::info(&code)
+}
+
+// Applies a share code to a device. The device has to be the same kind as the one the code was made
+// from, and must have connected at least once.
+pub fn import_device_share_code(
+  code: String,
+  identifier: ExposedUserDeviceIdentifier,
+) -> Result<()> {
+  share_code::import(&code, &identifier)
+}
+
+// Turns a device the user has mapped locally into a snippet for the upstream device config repo,
+// which they can attach to an issue or PR to get the device supported for everyone.
+pub fn package_device_submission(capture: DeviceCapture, protocol_guess: String) -> Result<String> {
+  device_submission::package(capture, &protocol_guess)
+}
+
+// Anonymous usage counts (engine starts, transports used, protocols connected), off until turned on
+// here. Turning them off throws away anything not yet uploaded. See usage_metrics.
+pub fn set_usage_metrics_enabled(enabled: bool) {
+  usage_metrics::set_enabled(enabled);
+}
+
+pub fn get_usage_metrics_enabled() -> bool {
+  usage_metrics::enabled()
+}
+
+// Uploads only happen while this is set, so the frontend needs to keep it up to date.
+pub fn set_usage_metrics_on_wifi(on_wifi: bool) {
+  usage_metrics::set_on_wifi(on_wifi);
+}
+
+// The batch of counts not uploaded yet, to be saved between app runs.
+pub fn get_usage_metrics_str() -> String {
+  usage_metrics::save()
+}
+
+pub fn load_usage_metrics(metrics_json: String) -> Result<()> {
+  usage_metrics::load(&metrics_json)
+}
+
+// Returns whether anything was sent. Nothing is sent with metrics off, nothing counted, or off Wi-Fi.
+pub fn upload_usage_metrics(url: String) -> Result<bool> {
+  usage_metrics::upload(&url)
+}
+
+pub fn update_device_config_file(
+  path: String,
+  current_version: Option<String>,
+  expected_sha256: String,
+  url: Option<String>,
+) -> Result<Option<DeviceConfigUpdate>> {
+  device_config_updater::update_device_config(&path, current_version, expected_sha256, url)
+}
+
+// Sends ConfigChanged events when either file is changed by something other than the bridge. With
+// reload on, changes are also applied to the loaded config, see config_watcher for what that
+// covers while the engine is running. Calling this again replaces the previous watch.
+pub fn watch_config_files(
+  device_config_path: Option<String>,
+  user_config_path: Option<String>,
+  reload: bool,
+) -> Result<()> {
+  config_watcher::watch(device_config_path, user_config_path, reload)
+}
+
+pub fn stop_config_watch() {
+  config_watcher::stop();
+}
+
+// The device config built into the library, for diffing downloaded configs against, and falling
+// back to when a downloaded one won't load.
+pub fn get_default_device_config_json() -> String {
+  DEVICE_CONFIGURATION_JSON.to_owned()
 }
 
 pub fn get_user_config_str() -> String {
@@ -773,17 +3024,104 @@ pub fn get_user_config_str() -> String {
   save_user_config(&dcm).unwrap()
 }
 
+// Encrypts the current user config, device settings and client permissions with a passphrase, for
+// moving a setup to another machine.
+pub fn export_user_config(passphrase: String) -> Result<String> {
+  config_archive::export(
+    &UserConfigArchive {
+      user_config: get_user_config_str(),
+      device_settings: device_settings::save(),
+      client_permissions: client_permissions::save(),
+    },
+    &passphrase,
+  )
+}
+
+// Decrypts an export from export_user_config. Nothing is loaded, so the frontend can show what will
+// change (see diff_user_configs) before handing the results to setup_device_configuration_manager,
+// load_device_settings and load_client_permissions. User configs from older versions are migrated
+// on the way out.
+pub fn import_user_config(blob: String, passphrase: String) -> Result<UserConfigArchive> {
+  let mut archive = config_archive::import(&blob, &passphrase)?;
+  archive.user_config = user_config_migration::migrate_user_config(&archive.user_config)?;
+  if let Some(error) = user_config_validation::validate_user_config(&archive.user_config).first() {
+    return Err(anyhow::Error::msg(format!(
+      "Imported user config is invalid: {}",
+      error.message
+    )));
+  }
+  Ok(archive)
+}
+
 pub fn setup_logging(sink: StreamSink<String>) {
   // Default log to debug, we'll filter in UI if we need it.
   std::env::set_var(
     "RUST_LOG",
     format!("debug,h2=warn,reqwest=warn,rustls=warn,hyper=warn"),
   );
-  *LOGGER.lock().unwrap() = Some(FlutterTracingWriter::new(sink));
+  setup_log_sink(sink, "default".to_owned(), LogSinkOptions::default());
+}
+
+// Adds another consumer for log output, with its own filtering. Setting up a sink with the same
+// name as an existing one replaces it.
+pub fn setup_log_sink(sink: StreamSink<String>, name: String, options: LogSinkOptions) {
+  logging::add_sink(&name, sink, options);
+  LOGGER
+    .lock()
+    .unwrap()
+    .get_or_insert_with(FlutterTracingWriter::new);
+}
+
+pub fn set_log_sink_options(name: String, options: LogSinkOptions) -> bool {
+  logging::set_sink_options(&name, options)
+}
+
+// Hashes or truncates device addresses and serials and IP addresses in log output and traffic
+// capture exports, for sharing them publicly. Applies to all log sinks. See log_sanitizer.
+pub fn set_log_sanitization(mode: LogSanitization) {
+  log_sanitizer::set_mode(mode);
+}
+
+pub fn get_log_sanitization() -> LogSanitization {
+  log_sanitizer::mode()
+}
+
+pub fn shutdown_log_sink(name: String) {
+  logging::remove_sink(&name);
 }
 
 pub fn shutdown_logging() {
+  // Stop the writer first so everything it has queued still makes it out.
   *LOGGER.lock().unwrap() = None;
+  logging::clear_sinks();
+}
+
+pub fn setup_bridge_events(sink: StreamSink<String>) {
+  events::set_sink(sink);
+}
+
+pub fn shutdown_bridge_events() {
+  events::clear_sink();
+}
+
+// Typed device connects and disconnects. Devices that are already connected when this is called
+// aren't sent, use get_device_list to get those first.
+pub fn setup_device_events(sink: StreamSink<DeviceEvent>) {
+  device_events::set_sink(sink);
+}
+
+pub fn shutdown_device_events() {
+  device_events::clear_sink();
+}
+
+// CBOR encoded device events and sensor readings, one event per buffer. See binary_stream for
+// what's sent.
+pub fn setup_binary_stream(sink: StreamSink<ZeroCopyBuffer<Vec<u8>>>) {
+  binary_stream::set_sink(sink);
+}
+
+pub fn shutdown_binary_stream() {
+  binary_stream::clear_sink();
 }
 
 pub fn crash_reporting(sentry_api_key: String) {
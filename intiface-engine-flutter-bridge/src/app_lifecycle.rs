@@ -0,0 +1,272 @@
+use crate::{
+  api::{self, ExposedUserDeviceIdentifier},
+  backdoor, client_permissions, device_settings, devices,
+  events::{self, BridgeEvent},
+  session_recording,
+};
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::{
+  sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Mutex,
+  },
+  thread,
+  time::Duration,
+};
+
+// Hooks for the platform layer to tell us what the OS is doing to the app. Android throttles
+// network and timers in doze and battery saver, and may kill the process outright once the
+// foreground service goes, and iOS suspends backgrounded apps, so the engine needs to get ahead of
+// that rather than find out halfway through a write.
+//
+// While throttled, we pause the frontend's device scanning (picking it back up afterwards), and ping
+// the engine on behalf of proxied clients, whose own pings are likely stuck in a throttled network,
+// so the engine doesn't drop them and stop their devices. Before things get worse, we flush what
+// we're writing and hand the frontend a checkpoint of everything it would need to save.
+//
+// In the background, bridge timers that only feed the UI (battery levels, command stats, Lovense
+// Connect checks) skip their ticks. Devices that dropped while iOS had us suspended won't come back
+// by themselves, so coming back to the foreground scans for a while to pick them back up.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AndroidPowerEvent {
+  DozeEntered,
+  DozeExited,
+  BatterySaverEnabled,
+  BatterySaverDisabled,
+  ForegroundServiceStarted,
+  // Sent when the service is about to go, which is the last chance to save anything.
+  ForegroundServiceStopping,
+}
+
+// How long to scan for devices that dropped while we were in the background, if the frontend wasn't
+// scanning anyways.
+const RECONNECT_SCAN_DURATION: Duration = Duration::from_secs(15);
+
+#[derive(Default)]
+struct LifecycleState {
+  doze: bool,
+  battery_saver: bool,
+  background: bool,
+  // Scanning that was running when we got throttled, to restart when we're not.
+  scanning_paused: bool,
+  // Devices connected when we went into the background.
+  background_devices: Vec<ExposedUserDeviceIdentifier>,
+}
+
+impl LifecycleState {
+  fn throttled(&self) -> bool {
+    self.doze || self.battery_saver || self.background
+  }
+}
+
+// Whether the frontend has scanning running, going by what it's sent and what the engine has said.
+static FRONTEND_SCANNING: AtomicBool = AtomicBool::new(false);
+static KEEP_ALIVE: AtomicBool = AtomicBool::new(false);
+static IN_BACKGROUND: AtomicBool = AtomicBool::new(false);
+// From the engine options, 0 if pings are off.
+static MAX_PING_TIME_MS: AtomicU32 = AtomicU32::new(0);
+
+lazy_static! {
+  static ref LIFECYCLE_STATE: Mutex<LifecycleState> = Mutex::new(LifecycleState::default());
+}
+
+pub fn set_max_ping_time(max_ping_time_ms: u32) {
+  MAX_PING_TIME_MS.store(max_ping_time_ms, Ordering::Relaxed);
+}
+
+// How often proxied clients should be pinged for, if they need it right now.
+pub fn keep_alive_interval() -> Option<Duration> {
+  let max_ping_time = MAX_PING_TIME_MS.load(Ordering::Relaxed);
+  if !KEEP_ALIVE.load(Ordering::Relaxed) || max_ping_time == 0 {
+    return None;
+  }
+  // Half the limit, so a late tick still gets there in time.
+  Some(Duration::from_millis((max_ping_time / 2).max(100) as u64))
+}
+
+pub fn in_background() -> bool {
+  IN_BACKGROUND.load(Ordering::Relaxed)
+}
+
+pub fn frontend_scanning() -> bool {
+  FRONTEND_SCANNING.load(Ordering::Relaxed)
+}
+
+pub fn ping_message() -> Value {
+  json!([{ "Ping": { "Id": backdoor::next_message_id() } }])
+}
+
+// (type, id) for each message in a serialized message array.
+fn message_types(msg: &str) -> Vec<(String, Option<u64>)> {
+  serde_json::from_str::<Vec<Value>>(msg)
+    .unwrap_or_default()
+    .iter()
+    .filter_map(|x| x.as_object()?.iter().next())
+    .map(|(message_type, fields)| {
+      (
+        message_type.clone(),
+        fields.get("Id").and_then(|x| x.as_u64()),
+      )
+    })
+    .collect()
+}
+
+// Keeps track of whether the frontend is scanning, from messages it sends through the backdoor. Our
+// own scanning messages go through there too, but they don't change what the frontend asked for.
+pub fn track_frontend_message(msg: &str) {
+  for (message_type, id) in message_types(msg) {
+    if id.is_some_and(|id| id >= backdoor::BRIDGE_MESSAGE_ID_START as u64) {
+      continue;
+    }
+    match message_type.as_str() {
+      "StartScanning" => frontend_scanning_changed(true),
+      "StopScanning" => frontend_scanning_changed(false),
+      _ => {}
+    }
+  }
+}
+
+// For scanning the frontend asks for through typed calls, which go out as bridge messages.
+pub(crate) fn frontend_scanning_changed(scanning: bool) {
+  FRONTEND_SCANNING.store(scanning, Ordering::Relaxed);
+  if !scanning {
+    // Nothing to resume anymore.
+    LIFECYCLE_STATE.lock().unwrap().scanning_paused = false;
+  }
+}
+
+// Scanning finishes by itself for some device types. Finishing because we paused it doesn't
+// matter, as scanning_paused remembers that.
+pub fn track_server_message(msg: &str) {
+  if message_types(msg)
+    .iter()
+    .any(|(message_type, _)| message_type == "ScanningFinished")
+  {
+    FRONTEND_SCANNING.store(false, Ordering::Relaxed);
+  }
+}
+
+pub(crate) fn send_scanning(start: bool) {
+  let message_type = if start {
+    "StartScanning"
+  } else {
+    "StopScanning"
+  };
+  let msg = json!([{ message_type: { "Id": backdoor::next_message_id() } }]);
+  api::send_bridge_backdoor_message(msg.to_string());
+}
+
+// Flushes anything we're in the middle of writing, and gives the frontend everything it would need
+// to save to pick up where it left off.
+fn checkpoint() {
+  session_recording::flush();
+  events::emit(BridgeEvent::StateCheckpoint {
+    user_config: api::get_user_config_str(),
+    device_settings: device_settings::save(),
+    client_permissions: client_permissions::save(),
+  });
+}
+
+// Makes a state change, and starts or stops throttling if that changes whether we should be.
+fn update_state(f: impl FnOnce(&mut LifecycleState)) {
+  let mut state = LIFECYCLE_STATE.lock().unwrap();
+  let was_throttled = state.throttled();
+  f(&mut state);
+  IN_BACKGROUND.store(state.background, Ordering::Relaxed);
+  let running = api::runtime_started();
+  match (was_throttled, state.throttled()) {
+    (false, true) => {
+      if running && FRONTEND_SCANNING.load(Ordering::Relaxed) {
+        info!("Pausing device scanning while throttled");
+        send_scanning(false);
+        state.scanning_paused = true;
+      }
+      KEEP_ALIVE.store(true, Ordering::Relaxed);
+    }
+    (true, false) => {
+      KEEP_ALIVE.store(false, Ordering::Relaxed);
+      if state.scanning_paused && running {
+        info!("Resuming device scanning");
+        send_scanning(true);
+      }
+      state.scanning_paused = false;
+    }
+    _ => {}
+  }
+}
+
+pub fn notify_android_power_event(event: AndroidPowerEvent) {
+  info!("Android power event: {:?}", event);
+  update_state(|state| match event {
+    AndroidPowerEvent::DozeEntered => state.doze = true,
+    AndroidPowerEvent::DozeExited => state.doze = false,
+    AndroidPowerEvent::BatterySaverEnabled => state.battery_saver = true,
+    AndroidPowerEvent::BatterySaverDisabled => state.battery_saver = false,
+    AndroidPowerEvent::ForegroundServiceStarted | AndroidPowerEvent::ForegroundServiceStopping => {}
+  });
+  // Doze is where Android starts killing things off, and a stopping service is the last we'll hear.
+  if matches!(
+    event,
+    AndroidPowerEvent::DozeEntered | AndroidPowerEvent::ForegroundServiceStopping
+  ) {
+    checkpoint();
+  }
+}
+
+pub fn notify_app_background() {
+  info!("App moved to background");
+  update_state(|state| {
+    state.background = true;
+    state.background_devices = devices::connected_identifiers();
+  });
+  // iOS can suspend us any time from here on.
+  checkpoint();
+}
+
+pub fn notify_app_foreground() {
+  info!("App moved to foreground");
+  let mut lost_devices = vec![];
+  update_state(|state| {
+    state.background = false;
+    let connected = devices::connected_identifiers();
+    lost_devices = std::mem::take(&mut state.background_devices)
+      .into_iter()
+      .filter(|x| !connected.contains(x))
+      .collect();
+  });
+  if lost_devices.is_empty() || !api::runtime_started() || FRONTEND_SCANNING.load(Ordering::Relaxed)
+  {
+    return;
+  }
+  info!(
+    "{} devices dropped while in the background, scanning for them",
+    lost_devices.len()
+  );
+  scan_for(RECONNECT_SCAN_DURATION);
+}
+
+// Scans on the frontend's behalf for a while, to pick up devices it's expecting. Does nothing if the
+// frontend is already scanning.
+pub(crate) fn scan_for(duration: Duration) {
+  if !api::runtime_started() || FRONTEND_SCANNING.load(Ordering::Relaxed) {
+    return;
+  }
+  send_scanning(true);
+  thread::spawn(move || {
+    thread::sleep(duration);
+    // Leave it running if the frontend has started scanning since.
+    if api::runtime_started() && !FRONTEND_SCANNING.load(Ordering::Relaxed) {
+      send_scanning(false);
+    }
+  });
+}
+
+// Scanning state goes with the engine, but what the OS is doing to us doesn't.
+pub fn clear() {
+  FRONTEND_SCANNING.store(false, Ordering::Relaxed);
+  let mut state = LIFECYCLE_STATE.lock().unwrap();
+  state.scanning_paused = false;
+  state.background_devices.clear();
+}
@@ -0,0 +1,80 @@
+use crate::{
+  api::{ExposedUserDeviceIdentifier, DEVICE_CONFIG_MANAGER},
+  app_lifecycle, clock, device_settings, devices,
+  options::AutoReconnectOptions,
+};
+use buttplug::server::device::configuration::UserDeviceIdentifier;
+use futures::pin_mut;
+use lazy_static::lazy_static;
+use std::{
+  collections::HashSet,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{select, sync::Notify};
+
+// Gets devices back after they drop (walking out of range, turning off for a charge) without anyone
+// having to hit scan again. The engine connects anything it finds while scanning, so all this needs
+// to do is scan for a bit every so often while a device we've had connected is missing.
+//
+// Devices are remembered from when they first connect until the app closes, across engine runs.
+// Devices denied in the user config are skipped, as the engine won't connect them anyways, and
+// devices can be opted out in their device settings.
+
+const MIN_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+  static ref KNOWN_DEVICES: Mutex<HashSet<ExposedUserDeviceIdentifier>> =
+    Mutex::new(HashSet::new());
+}
+
+pub fn remember(identifier: &ExposedUserDeviceIdentifier) {
+  KNOWN_DEVICES.lock().unwrap().insert(identifier.clone());
+}
+
+fn denied(identifier: &ExposedUserDeviceIdentifier) -> bool {
+  let user_identifier: UserDeviceIdentifier = identifier.clone().into();
+  DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .user_device_definitions()
+    .get(&user_identifier)
+    .is_some_and(|x| x.user_config().deny())
+}
+
+fn missing_devices() -> Vec<ExposedUserDeviceIdentifier> {
+  let connected = devices::connected_identifiers();
+  KNOWN_DEVICES
+    .lock()
+    .unwrap()
+    .iter()
+    .filter(|x| !connected.contains(x))
+    .filter(|x| !device_settings::settings(x).no_auto_reconnect && !denied(x))
+    .cloned()
+    .collect()
+}
+
+pub async fn run_auto_reconnect(options: AutoReconnectOptions, stop: Arc<Notify>) {
+  let interval = Duration::from_millis(options.check_interval_ms as u64).max(MIN_CHECK_INTERVAL);
+  // Scans shouldn't run into each other.
+  let scan_duration = Duration::from_millis(options.scan_duration_ms as u64).min(interval / 2);
+  let stopped = stop.notified();
+  pin_mut!(stopped);
+  let mut ticker = clock::Ticker::new(interval);
+  loop {
+    select! {
+      _ = ticker.tick() => {}
+      _ = &mut stopped => break,
+    }
+    let missing = missing_devices();
+    if missing.is_empty() {
+      continue;
+    }
+    debug!(
+      "{} known devices missing, scanning to reconnect",
+      missing.len()
+    );
+    // Does nothing if the frontend is already scanning, which will find them just the same.
+    app_lifecycle::scan_for(scan_duration);
+  }
+}
@@ -0,0 +1,44 @@
+use crate::app_lifecycle;
+use intiface_engine::EngineMessage;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Plenty of simple clients never send StartScanning, and expect devices to just show up. With this
+// on, we scan whenever a client connects, and stop again when it goes. If the frontend was already
+// scanning, it's left alone both ways.
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// Whether the scanning that's running is ours to stop.
+static SCANNING: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+  ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn client_connected() {
+  if !app_lifecycle::frontend_scanning() && !SCANNING.swap(true, Ordering::Relaxed) {
+    info!("Client connected, starting scanning");
+    app_lifecycle::send_scanning(true);
+  }
+}
+
+fn client_disconnected() {
+  if SCANNING.swap(false, Ordering::Relaxed) && !app_lifecycle::frontend_scanning() {
+    info!("Client disconnected, stopping scanning");
+    app_lifecycle::send_scanning(false);
+  }
+}
+
+pub fn update_from_engine_message(msg: &EngineMessage) {
+  if !ENABLED.load(Ordering::Relaxed) {
+    return;
+  }
+  match msg {
+    EngineMessage::ClientConnected { .. } => client_connected(),
+    EngineMessage::ClientDisconnected {} => client_disconnected(),
+    _ => {}
+  }
+}
+
+pub fn clear() {
+  SCANNING.store(false, Ordering::Relaxed);
+}
@@ -0,0 +1,130 @@
+use crate::{actuator_curve, api, device_settings, ramp::RampLimiter};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    mpsc::{channel, Sender},
+    Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+// How often in-progress ramps get moved along.
+pub const RAMP_STEP_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long request() waits for the server to reply before giving up.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Message ids at or above this are reserved for messages the bridge sends to the backdoor server on
+// its own. Responses to them get consumed in the bridge and never forwarded to the frontend, as its
+// client would have no idea what they're for. The frontend client counts up from 1, so it'd take a
+// very long session to ever collide with this.
+pub const BRIDGE_MESSAGE_ID_START: u32 = 0x4000_0000;
+
+static NEXT_MESSAGE_ID: AtomicU32 = AtomicU32::new(BRIDGE_MESSAGE_ID_START);
+
+lazy_static! {
+  // Message id -> whoever is waiting on the reply to it.
+  static ref PENDING_REPLIES: Mutex<HashMap<u32, Sender<Value>>> = Mutex::new(HashMap::new());
+}
+
+pub fn next_message_id() -> u32 {
+  let id = NEXT_MESSAGE_ID.fetch_add(1, Ordering::Relaxed);
+  if id == u32::MAX {
+    NEXT_MESSAGE_ID.store(BRIDGE_MESSAGE_ID_START, Ordering::Relaxed);
+  }
+  id
+}
+
+// Pulls the id out of each message in a serialized message array. Buttplug messages are always
+// objects with a single key (the message type), with the id in the message body.
+pub fn message_ids(msg: &str) -> Vec<u32> {
+  let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(msg) else {
+    return vec![];
+  };
+  messages
+    .iter()
+    .filter_map(|message| {
+      message
+        .as_object()?
+        .values()
+        .next()?
+        .get("Id")?
+        .as_u64()
+        .map(|id| id as u32)
+    })
+    .collect()
+}
+
+pub fn is_bridge_response(msg: &str) -> bool {
+  let ids = message_ids(msg);
+  !ids.is_empty() && ids.iter().all(|id| *id >= BRIDGE_MESSAGE_ID_START)
+}
+
+// Hands replies to bridge messages over to whoever's waiting on them in request(). Replies nobody is
+// waiting on (like the ones to ramp steps) are dropped.
+pub fn route_bridge_response(msg: &str) {
+  let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(msg) else {
+    return;
+  };
+  let mut pending = PENDING_REPLIES.lock().unwrap();
+  for message in messages {
+    let Some(id) = message
+      .as_object()
+      .and_then(|x| x.values().next())
+      .and_then(|x| x.get("Id"))
+      .and_then(|x| x.as_u64())
+    else {
+      continue;
+    };
+    if let Some(sender) = pending.remove(&(id as u32)) {
+      let _ = sender.send(message);
+    }
+  }
+}
+
+// Sends a message to the backdoor server as the bridge, and blocks until the server replies. The id
+// gets filled in here, so fields should leave it out. Error replies come back as errors, anything
+// else is returned whole, e.g. {"Ok": {"Id": 1073741830}}.
+pub fn request(msg_type: &str, fields: Value) -> Result<Value> {
+  request_with_timeout(msg_type, fields, REQUEST_TIMEOUT)
+}
+
+// For messages where the server itself may take a while to reply, like reads from hardware.
+pub fn request_with_timeout(msg_type: &str, mut fields: Value, timeout: Duration) -> Result<Value> {
+  let id = next_message_id();
+  fields["Id"] = json!(id);
+  let (sender, receiver) = channel();
+  PENDING_REPLIES.lock().unwrap().insert(id, sender);
+  if !api::send_bridge_backdoor_message(json!([{ msg_type: fields }]).to_string()) {
+    PENDING_REPLIES.lock().unwrap().remove(&id);
+    return Err(anyhow::Error::msg("Engine is not running"));
+  }
+  let reply = receiver.recv_timeout(timeout);
+  PENDING_REPLIES.lock().unwrap().remove(&id);
+  let reply = reply
+    .map_err(|_| anyhow::Error::msg(format!("Timed out waiting for reply to {}", msg_type)))?;
+  if let Some(error) = reply.get("Error") {
+    return Err(anyhow::Error::msg(
+      error
+        .get("ErrorMessage")
+        .and_then(|x| x.as_str())
+        .unwrap_or("Server returned an error")
+        .to_owned(),
+    ));
+  }
+  Ok(reply)
+}
+
+// Runs a message headed for the backdoor server through everything the bridge applies to device
+// commands. Ramps go first, as they work on what the user asked for, then curves reshape the result.
+pub fn apply_command_filters(msg: String, ramps: &mut RampLimiter, now: Instant) -> String {
+  let msg = ramps
+    .limit_message(&msg, device_settings::ramp_rate_for_device, now)
+    .unwrap_or(msg);
+  actuator_curve::shape_scalar_commands(&msg, device_settings::curve_shape_for_scalar)
+    .unwrap_or(msg)
+}
@@ -0,0 +1,436 @@
+use crate::{
+  api::{ExposedDeviceFeature, ExposedUserDeviceIdentifier},
+  app_lifecycle, backdoor, devices, scan_timeout,
+};
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::HashMap, thread, time::Duration};
+
+// Typed calls for the handful of Buttplug messages the app itself needs, so the frontend doesn't
+// need its own client implementation just to list, test and stop devices. These go over the
+// backdoor as bridge messages, so they work whether or not the frontend's own backdoor client is
+// connected. Commands go through the same ramps and curves as anything else sent to the backdoor.
+
+#[derive(Debug, Clone)]
+pub struct BackdoorActuator {
+  // Index within the actuators for the same command, which is what commands address them by.
+  pub index: u32,
+  pub description: String,
+  pub actuator_type: String,
+  pub step_count: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackdoorSensor {
+  pub index: u32,
+  pub description: String,
+  pub sensor_type: String,
+  pub ranges: Vec<(i32, i32)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackdoorDevice {
+  pub index: u32,
+  pub name: String,
+  pub display_name: Option<String>,
+  pub message_gap_ms: Option<u32>,
+  pub scalar_actuators: Vec<BackdoorActuator>,
+  pub rotate_actuators: Vec<BackdoorActuator>,
+  pub linear_actuators: Vec<BackdoorActuator>,
+  pub sensors: Vec<BackdoorSensor>,
+  // Where the device is in the user config, and the full feature list from there (step ranges,
+  // sensor ranges and all), so the frontend doesn't have to look them up. Empty if we can't tell
+  // which device it is.
+  pub identifier: Option<ExposedUserDeviceIdentifier>,
+  pub features: Vec<ExposedDeviceFeature>,
+}
+
+impl BackdoorDevice {
+  pub fn set_identifier(&mut self, identifier: ExposedUserDeviceIdentifier) {
+    self.features = devices::features(&identifier);
+    self.identifier = Some(identifier);
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct BackdoorScalarCommand {
+  pub index: u32,
+  // 0.0-1.0
+  pub scalar: f64,
+  pub actuator_type: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackdoorRotateCommand {
+  pub index: u32,
+  // 0.0-1.0
+  pub speed: f64,
+  pub clockwise: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackdoorLinearCommand {
+  pub index: u32,
+  pub duration_ms: u32,
+  // 0.0-1.0
+  pub position: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ActuatorTestResult {
+  // ScalarCmd, RotateCmd or LinearCmd, and the actuator's index within those.
+  pub command: String,
+  pub index: u32,
+  pub actuator_type: String,
+  // None if the device took the commands.
+  pub error: Option<String>,
+}
+
+// Device tests run each actuator at a low level for a moment, one at a time, so it's clear which one
+// is moving. Linear actuators move a short way either side of the middle instead.
+const TEST_INTENSITY: f64 = 0.2;
+const TEST_PULSE_TIME: Duration = Duration::from_millis(500);
+const TEST_GAP_TIME: Duration = Duration::from_millis(250);
+const TEST_LINEAR_POSITIONS: [f64; 2] = [0.4, 0.6];
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeviceListFields {
+  devices: Vec<DeviceFields>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeviceFields {
+  device_name: String,
+  device_index: u32,
+  device_display_name: Option<String>,
+  device_message_timing_gap: Option<u32>,
+  #[serde(default)]
+  device_messages: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ActuatorFields {
+  #[serde(default)]
+  feature_descriptor: String,
+  actuator_type: String,
+  step_count: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SensorFields {
+  #[serde(default)]
+  feature_descriptor: String,
+  sensor_type: String,
+  sensor_range: Vec<(i32, i32)>,
+}
+
+fn actuators(device: &DeviceFields, msg_type: &str) -> Vec<BackdoorActuator> {
+  device
+    .device_messages
+    .get(msg_type)
+    .and_then(|x| serde_json::from_value::<Vec<ActuatorFields>>(x.clone()).ok())
+    .unwrap_or_default()
+    .into_iter()
+    .enumerate()
+    .map(|(index, actuator)| BackdoorActuator {
+      index: index as u32,
+      description: actuator.feature_descriptor,
+      actuator_type: actuator.actuator_type,
+      step_count: actuator.step_count,
+    })
+    .collect()
+}
+
+fn sensors(device: &DeviceFields) -> Vec<BackdoorSensor> {
+  device
+    .device_messages
+    .get("SensorReadCmd")
+    .and_then(|x| serde_json::from_value::<Vec<SensorFields>>(x.clone()).ok())
+    .unwrap_or_default()
+    .into_iter()
+    .enumerate()
+    .map(|(index, sensor)| BackdoorSensor {
+      index: index as u32,
+      description: sensor.feature_descriptor,
+      sensor_type: sensor.sensor_type,
+      ranges: sensor.sensor_range,
+    })
+    .collect()
+}
+
+fn device(fields: &DeviceFields) -> BackdoorDevice {
+  let mut device = BackdoorDevice {
+    index: fields.device_index,
+    name: fields.device_name.clone(),
+    display_name: fields.device_display_name.clone(),
+    message_gap_ms: fields.device_message_timing_gap,
+    scalar_actuators: actuators(fields, "ScalarCmd"),
+    rotate_actuators: actuators(fields, "RotateCmd"),
+    linear_actuators: actuators(fields, "LinearCmd"),
+    sensors: sensors(fields),
+    identifier: None,
+    features: vec![],
+  };
+  if let Some(connected) = devices::connected_device(fields.device_index) {
+    device.set_identifier(connected.identifier);
+  }
+  device
+}
+
+// From the body of a DeviceAdded message, which has the same fields as a device list entry.
+pub fn parse_device(fields: Value) -> Option<BackdoorDevice> {
+  Some(device(&serde_json::from_value(fields).ok()?))
+}
+
+pub fn request_device_list() -> Result<Vec<BackdoorDevice>> {
+  let reply = backdoor::request("RequestDeviceList", json!({}))?;
+  let list: DeviceListFields = serde_json::from_value(
+    reply
+      .get("DeviceList")
+      .cloned()
+      .ok_or(anyhow::Error::msg("Unexpected reply to RequestDeviceList"))?,
+  )?;
+  Ok(list.devices.iter().map(device).collect())
+}
+
+// Raw values from a sensor, in the sensor's own range.
+pub fn read_sensor(device_index: u32, sensor_index: u32, sensor_type: &str) -> Result<Vec<i32>> {
+  let reply = backdoor::request(
+    "SensorReadCmd",
+    json!({
+      "DeviceIndex": device_index,
+      "SensorIndex": sensor_index,
+      "SensorType": sensor_type,
+    }),
+  )?;
+  let data = reply
+    .get("SensorReading")
+    .and_then(|x| x.get("Data"))
+    .cloned()
+    .ok_or(anyhow::Error::msg("Unexpected reply to SensorReadCmd"))?;
+  Ok(serde_json::from_value(data)?)
+}
+
+pub fn stop_device(device_index: u32) -> Result<()> {
+  backdoor::request("StopDeviceCmd", json!({ "DeviceIndex": device_index }))?;
+  Ok(())
+}
+
+pub fn stop_all() -> Result<()> {
+  backdoor::request("StopAllDevices", json!({}))?;
+  Ok(())
+}
+
+// Scanning asked for here is the frontend's, even though it goes out as a bridge message, so app
+// lifecycle handling pauses and resumes it like any other. Timeout overrides the scan timeout option
+// for this scan, with 0 for none.
+pub fn start_scanning(timeout_ms: Option<u32>) -> Result<()> {
+  if let Some(timeout_ms) = timeout_ms {
+    scan_timeout::set_next_timeout(timeout_ms);
+  }
+  backdoor::request("StartScanning", json!({}))?;
+  app_lifecycle::frontend_scanning_changed(true);
+  Ok(())
+}
+
+pub fn stop_scanning() -> Result<()> {
+  backdoor::request("StopScanning", json!({}))?;
+  app_lifecycle::frontend_scanning_changed(false);
+  Ok(())
+}
+
+fn check_unit_range(name: &str, value: f64) -> Result<()> {
+  if !(0.0..=1.0).contains(&value) {
+    return Err(anyhow::Error::msg(format!(
+      "{} must be between 0.0 and 1.0, got {}",
+      name, value
+    )));
+  }
+  Ok(())
+}
+
+pub fn scalar(device_index: u32, scalars: &[BackdoorScalarCommand]) -> Result<()> {
+  for command in scalars {
+    check_unit_range("Scalar", command.scalar)?;
+  }
+  let scalars: Vec<_> = scalars
+    .iter()
+    .map(|command| {
+      json!({
+        "Index": command.index,
+        "Scalar": command.scalar,
+        "ActuatorType": command.actuator_type,
+      })
+    })
+    .collect();
+  backdoor::request(
+    "ScalarCmd",
+    json!({ "DeviceIndex": device_index, "Scalars": scalars }),
+  )?;
+  Ok(())
+}
+
+// Speeds are for the device's vibrators in order, skipping over any other scalar actuators, so a
+// test slider doesn't need to know the device's actuator layout.
+pub fn vibrate(device_index: u32, speeds: &[f64]) -> Result<()> {
+  let device = request_device_list()?
+    .into_iter()
+    .find(|device| device.index == device_index)
+    .ok_or(anyhow::Error::msg("No device connected at that index"))?;
+  let vibrators: Vec<BackdoorActuator> = device
+    .scalar_actuators
+    .into_iter()
+    .filter(|actuator| actuator.actuator_type == "Vibrate")
+    .collect();
+  if speeds.len() > vibrators.len() {
+    return Err(anyhow::Error::msg(format!(
+      "Got {} speeds, but device only has {} vibrators",
+      speeds.len(),
+      vibrators.len()
+    )));
+  }
+  let commands: Vec<BackdoorScalarCommand> = vibrators
+    .iter()
+    .zip(speeds)
+    .map(|(actuator, speed)| BackdoorScalarCommand {
+      index: actuator.index,
+      scalar: *speed,
+      actuator_type: actuator.actuator_type.clone(),
+    })
+    .collect();
+  scalar(device_index, &commands)
+}
+
+pub fn rotate(device_index: u32, rotations: &[BackdoorRotateCommand]) -> Result<()> {
+  for command in rotations {
+    check_unit_range("Speed", command.speed)?;
+  }
+  let rotations: Vec<_> = rotations
+    .iter()
+    .map(|command| {
+      json!({
+        "Index": command.index,
+        "Speed": command.speed,
+        "Clockwise": command.clockwise,
+      })
+    })
+    .collect();
+  backdoor::request(
+    "RotateCmd",
+    json!({ "DeviceIndex": device_index, "Rotations": rotations }),
+  )?;
+  Ok(())
+}
+
+pub fn linear(device_index: u32, vectors: &[BackdoorLinearCommand]) -> Result<()> {
+  for command in vectors {
+    check_unit_range("Position", command.position)?;
+  }
+  let vectors: Vec<_> = vectors
+    .iter()
+    .map(|command| {
+      json!({
+        "Index": command.index,
+        "Duration": command.duration_ms,
+        "Position": command.position,
+      })
+    })
+    .collect();
+  backdoor::request(
+    "LinearCmd",
+    json!({ "DeviceIndex": device_index, "Vectors": vectors }),
+  )?;
+  Ok(())
+}
+
+fn test_actuator(device_index: u32, command: &str, actuator: &BackdoorActuator) -> Result<()> {
+  match command {
+    "ScalarCmd" => {
+      let pulse = |level| {
+        scalar(
+          device_index,
+          &[BackdoorScalarCommand {
+            index: actuator.index,
+            scalar: level,
+            actuator_type: actuator.actuator_type.clone(),
+          }],
+        )
+      };
+      pulse(TEST_INTENSITY)?;
+      thread::sleep(TEST_PULSE_TIME);
+      pulse(0.0)
+    }
+    "RotateCmd" => {
+      let pulse = |speed| {
+        rotate(
+          device_index,
+          &[BackdoorRotateCommand {
+            index: actuator.index,
+            speed,
+            clockwise: true,
+          }],
+        )
+      };
+      pulse(TEST_INTENSITY)?;
+      thread::sleep(TEST_PULSE_TIME);
+      pulse(0.0)
+    }
+    _ => {
+      for position in TEST_LINEAR_POSITIONS {
+        linear(
+          device_index,
+          &[BackdoorLinearCommand {
+            index: actuator.index,
+            duration_ms: TEST_PULSE_TIME.as_millis() as u32,
+            position,
+          }],
+        )?;
+        thread::sleep(TEST_PULSE_TIME);
+      }
+      Ok(())
+    }
+  }
+}
+
+// Runs a short, low level pulse on each of the device's actuators in turn. Blocks until it's done,
+// which is around a second per actuator. The device is stopped afterwards either way.
+pub fn test_device(device_index: u32) -> Result<Vec<ActuatorTestResult>> {
+  let device = request_device_list()?
+    .into_iter()
+    .find(|device| device.index == device_index)
+    .ok_or(anyhow::Error::msg("No device connected at that index"))?;
+  let actuators = device
+    .scalar_actuators
+    .iter()
+    .map(|x| ("ScalarCmd", x))
+    .chain(device.rotate_actuators.iter().map(|x| ("RotateCmd", x)))
+    .chain(device.linear_actuators.iter().map(|x| ("LinearCmd", x)));
+  let mut results = vec![];
+  for (command, actuator) in actuators {
+    let error = test_actuator(device_index, command, actuator)
+      .err()
+      .map(|e| e.to_string());
+    if let Some(error) = &error {
+      warn!(
+        "Testing {} {} on device {} failed: {}",
+        command, actuator.index, device_index, error
+      );
+    }
+    results.push(ActuatorTestResult {
+      command: command.to_owned(),
+      index: actuator.index,
+      actuator_type: actuator.actuator_type.clone(),
+      error,
+    });
+    thread::sleep(TEST_GAP_TIME);
+  }
+  if let Err(e) = stop_device(device_index) {
+    warn!("Cannot stop device {} after test: {:?}", device_index, e);
+  }
+  Ok(results)
+}
@@ -0,0 +1,105 @@
+use crate::{
+  api,
+  backdoor_client::{self, BackdoorDevice},
+  bridge_error::BridgeError,
+  devices,
+};
+use anyhow::Result;
+use flutter_rust_bridge::StreamSink;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::sync::Mutex;
+
+// The event half of the typed backdoor client (see backdoor_client), so the app's own device
+// controls can work entirely through typed calls instead of running a Buttplug client over the
+// backdoor's message strings. Connecting sends every device that's already there, then follows the
+// backdoor server's device and scanning events until the engine stops or the frontend disconnects.
+//
+// Devices are described the way the backdoor server sees them, so indexes match what backdoor
+// commands take, along with their features from the user config.
+
+#[derive(Debug, Clone)]
+pub enum BackdoorEvent {
+  DeviceAdded(Box<BackdoorDevice>),
+  DeviceRemoved { index: u32 },
+  ScanningFinished,
+  // Last event before the stream closes, sent when the engine stops.
+  EngineStopped,
+}
+
+lazy_static! {
+  static ref BACKDOOR_EVENT_SINK: Mutex<Option<StreamSink<BackdoorEvent>>> = Mutex::new(None);
+}
+
+fn emit(event: BackdoorEvent) {
+  if let Some(sink) = BACKDOOR_EVENT_SINK.lock().unwrap().as_ref() {
+    sink.add(event);
+  }
+}
+
+pub fn connect(sink: StreamSink<BackdoorEvent>) -> Result<()> {
+  if !api::runtime_started() {
+    return Err(BridgeError::NotRunning.into());
+  }
+  if let Some(old_sink) = BACKDOOR_EVENT_SINK.lock().unwrap().replace(sink) {
+    old_sink.close();
+  }
+  // Anything added while we're asking will show up twice, which is fine, as adds replace.
+  for device in backdoor_client::request_device_list()? {
+    emit(BackdoorEvent::DeviceAdded(Box::new(device)));
+  }
+  Ok(())
+}
+
+pub fn disconnect() {
+  if let Some(sink) = BACKDOOR_EVENT_SINK.lock().unwrap().take() {
+    sink.close();
+  }
+}
+
+pub fn is_connected() -> bool {
+  BACKDOOR_EVENT_SINK.lock().unwrap().is_some()
+}
+
+// Picks the events out of messages coming from the backdoor server. Replies to requests are left to
+// whoever sent them.
+pub async fn route_server_message(msg: &str) {
+  if !is_connected() {
+    return;
+  }
+  for message in serde_json::from_str::<Vec<Value>>(msg).unwrap_or_default() {
+    let Some((message_type, fields)) = message.as_object().and_then(|x| x.iter().next()) else {
+      continue;
+    };
+    let event = match message_type.as_str() {
+      "DeviceAdded" => match backdoor_client::parse_device(fields.clone()) {
+        // The engine may not have told us about the device yet.
+        Some(mut device) if device.identifier.is_none() => {
+          if let Some(identifier) = devices::wait_for_device(device.index).await {
+            device.set_identifier(identifier);
+          }
+          Some(BackdoorEvent::DeviceAdded(Box::new(device)))
+        }
+        device => device.map(|x| BackdoorEvent::DeviceAdded(Box::new(x))),
+      },
+      "DeviceRemoved" => fields
+        .get("DeviceIndex")
+        .and_then(|x| x.as_u64())
+        .map(|index| BackdoorEvent::DeviceRemoved {
+          index: index as u32,
+        }),
+      "ScanningFinished" => Some(BackdoorEvent::ScanningFinished),
+      _ => None,
+    };
+    if let Some(event) = event {
+      emit(event);
+    }
+  }
+}
+
+pub fn engine_stopped() {
+  if let Some(sink) = BACKDOOR_EVENT_SINK.lock().unwrap().take() {
+    sink.add(BackdoorEvent::EngineStopped);
+    sink.close();
+  }
+}
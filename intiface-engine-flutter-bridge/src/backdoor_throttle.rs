@@ -0,0 +1,91 @@
+use crate::{
+  backdoor, backdoor_validation,
+  events::{self, BridgeEvent},
+  options::BackdoorRateLimitOptions,
+};
+use std::time::{Duration, Instant};
+
+// Flood protection for messages from the frontend to the backdoor server. A UI loop gone wrong
+// (e.g. a slider sending on every frame with no debounce) can send far faster than devices can take
+// commands, and once the incoming channel backs up far enough the backdoor task falls behind and
+// loses messages anyways. Instead we keep to a steady rate with some room for bursts, and turn
+// away whatever's over it with error replies, so the client still hears back on each message.
+//
+// Messages the bridge sends itself are never held back, as it already paces those.
+
+// How often throttled events go out while messages are being dropped.
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct BackdoorThrottle {
+  options: BackdoorRateLimitOptions,
+  tokens: f64,
+  last_refill: Instant,
+  // Dropped since the last throttled event.
+  dropped: u64,
+  last_event: Option<Instant>,
+}
+
+impl BackdoorThrottle {
+  pub fn new(options: BackdoorRateLimitOptions, now: Instant) -> Self {
+    Self {
+      tokens: options.burst as f64,
+      options,
+      last_refill: now,
+      dropped: 0,
+      last_event: None,
+    }
+  }
+
+  // Returns the message if it can go through, otherwise error replies for the client.
+  pub fn check(&mut self, msg: String, now: Instant) -> Result<String, String> {
+    // Same id check as for bridge responses, as the bridge uses its own id range both ways.
+    if !self.options.enabled || backdoor::is_bridge_response(&msg) {
+      return Ok(msg);
+    }
+    let elapsed = now
+      .saturating_duration_since(self.last_refill)
+      .as_secs_f64();
+    self.last_refill = now;
+    self.tokens = (self.tokens + elapsed * self.options.max_messages_per_second as f64)
+      .min(self.options.burst as f64);
+    // Each message in the array counts, so batching doesn't get around the limit.
+    let count = backdoor::message_ids(&msg).len().max(1) as f64;
+    if self.tokens >= count {
+      self.tokens -= count;
+      return Ok(msg);
+    }
+    self.dropped += count as u64;
+    self.flush(now);
+    Err(backdoor_validation::message_error_replies(&msg, |_| {
+      "Too many messages, slow down".to_owned()
+    }))
+  }
+
+  // Messages we never saw, because the incoming channel overflowed before we got to them.
+  pub fn lagged(&mut self, count: u64, now: Instant) {
+    self.dropped += count;
+    self.flush(now);
+  }
+
+  // Sends out what's been dropped since the last event, if it's been long enough. Also needs
+  // calling every so often, so the last of a flood doesn't go unreported.
+  pub fn flush(&mut self, now: Instant) {
+    if self.dropped == 0
+      || self
+        .last_event
+        .is_some_and(|x| now.saturating_duration_since(x) < EVENT_INTERVAL)
+    {
+      return;
+    }
+    warn!(
+      "Dropped {} backdoor messages over the rate limit",
+      self.dropped
+    );
+    events::emit(BridgeEvent::BackdoorThrottled {
+      dropped_messages: self.dropped,
+      max_messages_per_second: self.options.max_messages_per_second,
+    });
+    self.dropped = 0;
+    self.last_event = Some(now);
+  }
+}
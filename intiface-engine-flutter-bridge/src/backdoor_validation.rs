@@ -0,0 +1,501 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+// Checks messages from the frontend against the Buttplug v3 message spec before they go to the
+// backdoor server. The server turns anything malformed into a generic error reply that doesn't say
+// what was wrong with it. Here we collect everything that's wrong, with a path to where it is, so
+// the frontend can show it while someone's working on their client code.
+//
+// The library's own schema validator isn't public, so the spec is written out here. It only needs
+// to cover client messages, as that's all a client can send.
+
+// Buttplug error code for malformed messages.
+const ERROR_MSG: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum BackdoorValidationErrorKind {
+  InvalidJson,
+  // Messages always come in an array, even if there's only one.
+  NotAnArray,
+  // Each message is an object with a single key, the message type.
+  NotAMessage,
+  UnknownMessageType,
+  MissingField,
+  UnknownField,
+  WrongType,
+  OutOfRange,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackdoorValidationError {
+  pub kind: BackdoorValidationErrorKind,
+  // Position of the message in the array, if the error is in one.
+  pub message_index: Option<u32>,
+  // Dotted path to the offending field, e.g. "[0].ScalarCmd.Scalars[1].Scalar". Empty if the error
+  // applies to the whole array.
+  pub field: String,
+  pub message: String,
+}
+
+#[derive(Clone, Copy)]
+enum FieldType {
+  U32,
+  Text,
+  Bool,
+  // 0.0 to 1.0, for speeds and positions.
+  Unit,
+  // Array of byte values.
+  Bytes,
+  // Non-empty array of objects with these fields.
+  Objects(&'static [(&'static str, FieldType)]),
+}
+
+use FieldType::*;
+
+const SCALARS: &[(&str, FieldType)] = &[("Index", U32), ("Scalar", Unit), ("ActuatorType", Text)];
+const VECTORS: &[(&str, FieldType)] = &[("Index", U32), ("Duration", U32), ("Position", Unit)];
+const ROTATIONS: &[(&str, FieldType)] = &[("Index", U32), ("Speed", Unit), ("Clockwise", Bool)];
+
+// Fields for each client message type, apart from the id, which they all have.
+fn message_fields(message_type: &str) -> Option<&'static [(&'static str, FieldType)]> {
+  Some(match message_type {
+    "RequestServerInfo" => &[("ClientName", Text), ("MessageVersion", U32)],
+    "Ping" | "StartScanning" | "StopScanning" | "RequestDeviceList" | "StopAllDevices" => &[],
+    "StopDeviceCmd" => &[("DeviceIndex", U32)],
+    "ScalarCmd" => &[("DeviceIndex", U32), ("Scalars", Objects(SCALARS))],
+    "LinearCmd" => &[("DeviceIndex", U32), ("Vectors", Objects(VECTORS))],
+    "RotateCmd" => &[("DeviceIndex", U32), ("Rotations", Objects(ROTATIONS))],
+    "SensorReadCmd" | "SensorSubscribeCmd" | "SensorUnsubscribeCmd" => &[
+      ("DeviceIndex", U32),
+      ("SensorIndex", U32),
+      ("SensorType", Text),
+    ],
+    "RawWriteCmd" => &[
+      ("DeviceIndex", U32),
+      ("Endpoint", Text),
+      ("Data", Bytes),
+      ("WriteWithResponse", Bool),
+    ],
+    "RawReadCmd" => &[
+      ("DeviceIndex", U32),
+      ("Endpoint", Text),
+      ("ExpectedLength", U32),
+      ("Timeout", U32),
+    ],
+    "RawSubscribeCmd" | "RawUnsubscribeCmd" => &[("DeviceIndex", U32), ("Endpoint", Text)],
+    _ => return None,
+  })
+}
+
+struct Validator {
+  message_index: Option<u32>,
+  errors: Vec<BackdoorValidationError>,
+}
+
+impl Validator {
+  fn error(&mut self, kind: BackdoorValidationErrorKind, field: &str, message: String) {
+    self.errors.push(BackdoorValidationError {
+      kind,
+      message_index: self.message_index,
+      field: field.to_owned(),
+      message,
+    });
+  }
+
+  fn check_value(&mut self, path: &str, value: &Value, field_type: FieldType) {
+    let valid = match field_type {
+      U32 => value.as_u64().is_some_and(|x| x <= u32::MAX as u64),
+      Text => value.is_string(),
+      Bool => value.is_boolean(),
+      Unit => {
+        if let Some(x) = value.as_f64() {
+          if !(0.0..=1.0).contains(&x) {
+            self.error(
+              BackdoorValidationErrorKind::OutOfRange,
+              path,
+              format!("{} must be between 0.0 and 1.0, got {}", path, x),
+            );
+          }
+          true
+        } else {
+          false
+        }
+      }
+      Bytes => value
+        .as_array()
+        .is_some_and(|x| x.iter().all(|x| x.as_u64().is_some_and(|x| x <= 255))),
+      Objects(fields) => match value.as_array() {
+        Some(items) if items.is_empty() => {
+          self.error(
+            BackdoorValidationErrorKind::OutOfRange,
+            path,
+            format!("{} needs at least one entry", path),
+          );
+          true
+        }
+        Some(items) => {
+          for (i, item) in items.iter().enumerate() {
+            let item_path = format!("{}[{}]", path, i);
+            match item.as_object() {
+              Some(item) => self.check_fields(&item_path, item, fields),
+              None => self.error(
+                BackdoorValidationErrorKind::WrongType,
+                &item_path,
+                format!("{} must be an object", item_path),
+              ),
+            }
+          }
+          true
+        }
+        None => false,
+      },
+    };
+    if !valid {
+      let expected = match field_type {
+        U32 => "an unsigned 32 bit integer",
+        Text => "a string",
+        Bool => "a boolean",
+        Unit => "a number",
+        Bytes => "an array of byte values",
+        Objects(_) => "an array",
+      };
+      self.error(
+        BackdoorValidationErrorKind::WrongType,
+        path,
+        format!("{} must be {}", path, expected),
+      );
+    }
+  }
+
+  fn check_fields(
+    &mut self,
+    path: &str,
+    object: &Map<String, Value>,
+    fields: &[(&str, FieldType)],
+  ) {
+    for (name, field_type) in fields {
+      let field_path = format!("{}.{}", path, name);
+      match object.get(*name) {
+        Some(value) => self.check_value(&field_path, value, *field_type),
+        None => self.error(
+          BackdoorValidationErrorKind::MissingField,
+          &field_path,
+          format!("{} is missing {}", path, name),
+        ),
+      }
+    }
+    for name in object.keys() {
+      if name != "Id" && !fields.iter().any(|(x, _)| x == name) {
+        let field_path = format!("{}.{}", path, name);
+        self.error(
+          BackdoorValidationErrorKind::UnknownField,
+          &field_path,
+          format!("{} has unknown field {}", path, name),
+        );
+      }
+    }
+  }
+
+  fn check_message(&mut self, path: &str, message: &Value) {
+    let Some((message_type, fields)) = message
+      .as_object()
+      .filter(|x| x.len() == 1)
+      .and_then(|x| x.iter().next())
+    else {
+      self.error(
+        BackdoorValidationErrorKind::NotAMessage,
+        path,
+        format!(
+          "{} must be an object with the message type as its only key",
+          path
+        ),
+      );
+      return;
+    };
+    let path = format!("{}.{}", path, message_type);
+    let Some(spec) = message_fields(message_type) else {
+      self.error(
+        BackdoorValidationErrorKind::UnknownMessageType,
+        &path,
+        format!("{} isn't a client message type", message_type),
+      );
+      return;
+    };
+    let Some(fields) = fields.as_object() else {
+      self.error(
+        BackdoorValidationErrorKind::WrongType,
+        &path,
+        format!("{} must be an object", path),
+      );
+      return;
+    };
+    // Id 0 is for messages the server sends on its own.
+    let id_path = format!("{}.Id", path);
+    match fields.get("Id").and_then(|x| x.as_u64()) {
+      Some(id) if id > 0 && id <= u32::MAX as u64 => {}
+      Some(_) => self.error(
+        BackdoorValidationErrorKind::OutOfRange,
+        &id_path,
+        format!("{} must be between 1 and {}", id_path, u32::MAX),
+      ),
+      None if fields.contains_key("Id") => self.error(
+        BackdoorValidationErrorKind::WrongType,
+        &id_path,
+        format!("{} must be an unsigned 32 bit integer", id_path),
+      ),
+      None => self.error(
+        BackdoorValidationErrorKind::MissingField,
+        &id_path,
+        format!("{} is missing Id", path),
+      ),
+    }
+    self.check_fields(&path, fields, spec);
+  }
+}
+
+// Returns everything wrong with a serialized message array, or nothing if it's all valid.
+pub fn validate(msg: &str) -> Vec<BackdoorValidationError> {
+  let mut validator = Validator {
+    message_index: None,
+    errors: vec![],
+  };
+  let messages = match serde_json::from_str::<Value>(msg) {
+    Ok(Value::Array(messages)) => messages,
+    Ok(_) => {
+      validator.error(
+        BackdoorValidationErrorKind::NotAnArray,
+        "",
+        "Messages must be sent as an array".to_owned(),
+      );
+      return validator.errors;
+    }
+    Err(e) => {
+      validator.error(BackdoorValidationErrorKind::InvalidJson, "", e.to_string());
+      return validator.errors;
+    }
+  };
+  for (i, message) in messages.iter().enumerate() {
+    validator.message_index = Some(i as u32);
+    validator.check_message(&format!("[{}]", i), message);
+  }
+  validator.errors
+}
+
+// Error replies for a message array that didn't validate, so the client isn't left waiting on
+// them. The server would reject the whole array, so every message in it gets one, with the first
+// thing wrong with it (or the array) as the error message. Anything without a usable id gets id 0,
+// same as the server does.
+pub fn error_replies(msg: &str, errors: &[BackdoorValidationError]) -> String {
+  message_error_replies(msg, |i| {
+    errors
+      .iter()
+      .find(|x| x.message_index == Some(i as u32))
+      .or(errors.first())
+      .map(|x| x.message.clone())
+      .map(|x| format!("Invalid message: {}", x))
+      .unwrap_or_default()
+  })
+}
+
+// An error reply for each message in the array, with error giving the error message for the
+// message at each position.
+pub fn message_error_replies(msg: &str, error: impl Fn(usize) -> String) -> String {
+  let messages = match serde_json::from_str::<Value>(msg) {
+    Ok(Value::Array(messages)) if !messages.is_empty() => messages,
+    _ => vec![Value::Null],
+  };
+  let replies: Vec<Value> = messages
+    .iter()
+    .enumerate()
+    .map(|(i, message)| {
+      let id = message
+        .as_object()
+        .and_then(|x| x.values().next())
+        .and_then(|x| x.get("Id"))
+        .and_then(|x| x.as_u64())
+        .filter(|x| *x <= u32::MAX as u64)
+        .unwrap_or(0);
+      json!({
+        "Error": {
+          "Id": id,
+          "ErrorMessage": error(i),
+          "ErrorCode": ERROR_MSG,
+        }
+      })
+    })
+    .collect();
+  Value::Array(replies).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn kinds(errors: &[BackdoorValidationError]) -> Vec<BackdoorValidationErrorKind> {
+    errors.iter().map(|x| x.kind).collect()
+  }
+
+  #[test]
+  fn valid_messages_pass() {
+    let msg = json!([
+      {"RequestServerInfo": {"Id": 1, "ClientName": "Test", "MessageVersion": 3}},
+      {"ScalarCmd": {"Id": 2, "DeviceIndex": 0, "Scalars": [
+        {"Index": 0, "Scalar": 0.5, "ActuatorType": "Vibrate"}
+      ]}},
+      {"RawWriteCmd": {"Id": 3, "DeviceIndex": 1, "Endpoint": "tx", "Data": [0, 255],
+        "WriteWithResponse": false}},
+      {"StopAllDevices": {"Id": 4}},
+    ]);
+    assert!(validate(&msg.to_string()).is_empty());
+  }
+
+  #[test]
+  fn whole_array_errors() {
+    assert_eq!(
+      kinds(&validate("[{")),
+      vec![BackdoorValidationErrorKind::InvalidJson]
+    );
+    let errors = validate(&json!({"Ping": {"Id": 1}}).to_string());
+    assert_eq!(
+      kinds(&errors),
+      vec![BackdoorValidationErrorKind::NotAnArray]
+    );
+    assert_eq!(errors[0].message_index, None);
+    assert_eq!(errors[0].field, "");
+  }
+
+  #[test]
+  fn message_shape_errors() {
+    let msg = json!([
+      {"Ping": {"Id": 1}, "StopAllDevices": {"Id": 2}},
+      {"VibrateCmd": {"Id": 3}},
+      {"Ping": 4},
+    ]);
+    let errors = validate(&msg.to_string());
+    assert_eq!(
+      kinds(&errors),
+      vec![
+        BackdoorValidationErrorKind::NotAMessage,
+        BackdoorValidationErrorKind::UnknownMessageType,
+        BackdoorValidationErrorKind::WrongType,
+      ]
+    );
+    assert_eq!(
+      errors.iter().map(|x| x.message_index).collect::<Vec<_>>(),
+      vec![Some(0), Some(1), Some(2)]
+    );
+    assert_eq!(errors[1].field, "[1].VibrateCmd");
+  }
+
+  #[test]
+  fn ids_are_checked() {
+    let msg = json!([
+      {"Ping": {}},
+      {"Ping": {"Id": 0}},
+      {"Ping": {"Id": "1"}},
+      {"Ping": {"Id": 4294967296u64}},
+    ]);
+    let errors = validate(&msg.to_string());
+    assert_eq!(
+      kinds(&errors),
+      vec![
+        BackdoorValidationErrorKind::MissingField,
+        BackdoorValidationErrorKind::OutOfRange,
+        BackdoorValidationErrorKind::WrongType,
+        BackdoorValidationErrorKind::OutOfRange,
+      ]
+    );
+    assert!(errors.iter().all(|x| x.field.ends_with(".Ping.Id")));
+  }
+
+  #[test]
+  fn fields_are_checked_with_paths() {
+    let msg = json!([{"ScalarCmd": {"Id": 1, "DeviceIndex": -1, "Extra": true, "Scalars": [
+      {"Index": 0, "Scalar": 1.5, "ActuatorType": "Vibrate"},
+      {"Index": 1, "ActuatorType": 7},
+      "nope",
+    ]}}]);
+    let errors = validate(&msg.to_string());
+    let found: Vec<(BackdoorValidationErrorKind, &str)> =
+      errors.iter().map(|x| (x.kind, x.field.as_str())).collect();
+    assert_eq!(
+      found,
+      vec![
+        (
+          BackdoorValidationErrorKind::WrongType,
+          "[0].ScalarCmd.DeviceIndex"
+        ),
+        (
+          BackdoorValidationErrorKind::OutOfRange,
+          "[0].ScalarCmd.Scalars[0].Scalar"
+        ),
+        (
+          BackdoorValidationErrorKind::MissingField,
+          "[0].ScalarCmd.Scalars[1].Scalar"
+        ),
+        (
+          BackdoorValidationErrorKind::WrongType,
+          "[0].ScalarCmd.Scalars[1].ActuatorType"
+        ),
+        (
+          BackdoorValidationErrorKind::WrongType,
+          "[0].ScalarCmd.Scalars[2]"
+        ),
+        (
+          BackdoorValidationErrorKind::UnknownField,
+          "[0].ScalarCmd.Extra"
+        ),
+      ]
+    );
+  }
+
+  #[test]
+  fn empty_and_bad_arrays() {
+    let msg = json!([
+      {"LinearCmd": {"Id": 1, "DeviceIndex": 0, "Vectors": []}},
+      {"RawWriteCmd": {"Id": 2, "DeviceIndex": 0, "Endpoint": "tx", "Data": [256],
+        "WriteWithResponse": false}},
+    ]);
+    let errors = validate(&msg.to_string());
+    assert_eq!(
+      kinds(&errors),
+      vec![
+        BackdoorValidationErrorKind::OutOfRange,
+        BackdoorValidationErrorKind::WrongType,
+      ]
+    );
+    assert_eq!(errors[1].field, "[1].RawWriteCmd.Data");
+  }
+
+  #[test]
+  fn error_replies_cover_every_message() {
+    let msg = json!([
+      {"Ping": {"Id": 5}},
+      {"StopDeviceCmd": {"Id": 6}},
+    ])
+    .to_string();
+    let errors = validate(&msg);
+    assert_eq!(errors.len(), 1);
+    let replies: Value = serde_json::from_str(&error_replies(&msg, &errors)).unwrap();
+    let replies = replies.as_array().unwrap();
+    assert_eq!(replies.len(), 2);
+    // The valid message gets the array's first error, as the server would reject it along with
+    // the rest.
+    for (reply, id) in replies.iter().zip([5, 6]) {
+      assert_eq!(reply["Error"]["Id"], id);
+      assert_eq!(reply["Error"]["ErrorCode"], ERROR_MSG);
+      assert_eq!(
+        reply["Error"]["ErrorMessage"],
+        format!("Invalid message: {}", errors[0].message)
+      );
+    }
+  }
+
+  #[test]
+  fn error_replies_without_ids() {
+    let errors = validate("not json");
+    let replies: Value = serde_json::from_str(&error_replies("not json", &errors)).unwrap();
+    assert_eq!(replies.as_array().unwrap().len(), 1);
+    assert_eq!(replies[0]["Error"]["Id"], 0);
+  }
+}
@@ -0,0 +1,86 @@
+use crate::{
+  app_lifecycle, backdoor_client, clock, devices,
+  events::{self, BridgeEvent},
+};
+use futures::pin_mut;
+use lazy_static::lazy_static;
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{select, sync::Notify};
+
+// Polls battery levels on connected devices while the engine is running, so the frontend can show
+// them without having to schedule sensor reads itself. Levels are only sent out when they change,
+// as most devices only report in steps of 5-10% anyways.
+
+lazy_static! {
+  // Device index -> last level we sent out.
+  static ref LAST_LEVELS: Mutex<HashMap<u32, f64>> = Mutex::new(HashMap::new());
+}
+
+pub async fn run_battery_monitor(interval: Duration, stop: Arc<Notify>) {
+  let stopped = stop.notified();
+  pin_mut!(stopped);
+  let mut ticker = clock::Ticker::new(interval);
+  loop {
+    select! {
+      _ = ticker.tick() => {}
+      _ = &mut stopped => break,
+    }
+    if app_lifecycle::in_background() {
+      continue;
+    }
+    // Reads block on the backdoor server replying, so they can't run on the runtime threads.
+    select! {
+      _ = tokio::task::spawn_blocking(poll_batteries) => {}
+      _ = &mut stopped => break,
+    }
+  }
+  LAST_LEVELS.lock().unwrap().clear();
+}
+
+fn poll_batteries() {
+  let devices = match backdoor_client::request_device_list() {
+    Ok(devices) => devices,
+    Err(e) => {
+      debug!("Cannot get device list for battery check: {:?}", e);
+      return;
+    }
+  };
+  let mut last_levels = LAST_LEVELS.lock().unwrap();
+  last_levels.retain(|index, _| devices.iter().any(|device| device.index == *index));
+  for device in devices {
+    // Devices with more than one battery sensor aren't a thing yet, so just use the first.
+    let Some(sensor) = device
+      .sensors
+      .iter()
+      .find(|sensor| sensor.sensor_type == "Battery")
+    else {
+      continue;
+    };
+    let reading = match backdoor_client::read_sensor(device.index, sensor.index, "Battery") {
+      Ok(reading) => reading,
+      Err(e) => {
+        debug!("Cannot read battery for device {}: {:?}", device.index, e);
+        continue;
+      }
+    };
+    let (Some(value), Some((min, max))) = (reading.first(), sensor.ranges.first()) else {
+      continue;
+    };
+    if max <= min {
+      continue;
+    }
+    let level = ((value - min) as f64 / (max - min) as f64).clamp(0.0, 1.0);
+    if last_levels.insert(device.index, level) == Some(level) {
+      continue;
+    }
+    events::emit(BridgeEvent::BatteryLevel {
+      device_index: device.index,
+      identifier: devices::connected_device(device.index).map(|x| x.identifier),
+      level,
+    });
+  }
+}
@@ -0,0 +1,63 @@
+use flutter_rust_bridge::{StreamSink, ZeroCopyBuffer};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::sync::Mutex;
+
+// Binary version of the high rate streams. Every typed event that crosses the bridge turns into a
+// pile of Dart objects, which adds up when a sensor is reporting at 100Hz. Events on this stream are
+// CBOR encoded into a single byte buffer each, handed over without copying, so the frontend can
+// decode just the fields it needs.
+//
+// Carries device connects and disconnects (see device_events) while it's set up, and readings for
+// sensors subscribed with subscribe_sensor_binary. Device features aren't included, as they don't
+// change at any rate worth optimizing for, and get_device_list has them.
+
+#[derive(Debug, Clone, Serialize)]
+pub enum BinaryEvent {
+  DeviceAdded {
+    index: u32,
+    name: String,
+    display_name: Option<String>,
+    protocol: String,
+    address: String,
+    identifier: Option<String>,
+  },
+  DeviceRemoved {
+    index: u32,
+  },
+  SensorReading {
+    device_index: u32,
+    sensor_index: u32,
+    sensor_type: String,
+    data: Vec<i32>,
+    timestamp_ms: u64,
+  },
+}
+
+lazy_static! {
+  static ref BINARY_SINK: Mutex<Option<StreamSink<ZeroCopyBuffer<Vec<u8>>>>> = Mutex::new(None);
+}
+
+pub fn set_sink(sink: StreamSink<ZeroCopyBuffer<Vec<u8>>>) {
+  *BINARY_SINK.lock().unwrap() = Some(sink);
+}
+
+pub fn clear_sink() {
+  if let Some(sink) = BINARY_SINK.lock().unwrap().take() {
+    sink.close();
+  }
+}
+
+pub fn is_set() -> bool {
+  BINARY_SINK.lock().unwrap().is_some()
+}
+
+pub fn emit(event: &BinaryEvent) {
+  let sink = BINARY_SINK.lock().unwrap();
+  let Some(sink) = sink.as_ref() else {
+    return;
+  };
+  let mut buffer = Vec::with_capacity(64);
+  ciborium::into_writer(event, &mut buffer).expect("Binary events should always encode");
+  sink.add(ZeroCopyBuffer(buffer));
+}
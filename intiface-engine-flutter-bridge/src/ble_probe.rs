@@ -0,0 +1,131 @@
+use crate::mobile_init;
+use anyhow::Result;
+use btleplug::api::{Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter};
+use flutter_rust_bridge::StreamSink;
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use std::{
+  sync::{Arc, Mutex},
+  thread,
+};
+use tokio::{select, sync::Notify};
+
+// Scans for Bluetooth LE advertisements and passes them along as-is, without starting the engine.
+// Mostly so we can answer "is my toy even showing up?" without having to go through device config
+// matching, which is where most people get stuck. Can't run alongside the engine, as the engine
+// needs the adapter for its own scanning.
+
+#[derive(Debug, Clone)]
+pub struct BleManufacturerData {
+  pub company_id: u16,
+  pub hex_data: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BleAdvertisement {
+  // OS assigned id for the peripheral. Same as address on most platforms, but Apple platforms hide
+  // addresses and only give us this.
+  pub id: String,
+  pub address: String,
+  pub name: Option<String>,
+  pub rssi: Option<i16>,
+  pub manufacturer_data: Vec<BleManufacturerData>,
+  pub services: Vec<String>,
+}
+
+lazy_static! {
+  static ref PROBE_STOP: Mutex<Option<Arc<Notify>>> = Mutex::new(None);
+}
+
+pub fn start(sink: StreamSink<BleAdvertisement>) -> Result<()> {
+  let stop = {
+    let mut probe_stop = PROBE_STOP.lock().unwrap();
+    if probe_stop.is_some() {
+      return Err(anyhow::Error::msg("Bluetooth probe already running"));
+    }
+    let stop = Arc::new(Notify::new());
+    *probe_stop = Some(stop.clone());
+    stop
+  };
+  let runtime = match mobile_init::create_runtime() {
+    Ok(runtime) => runtime,
+    Err(e) => {
+      *PROBE_STOP.lock().unwrap() = None;
+      return Err(e.into());
+    }
+  };
+  thread::spawn(move || {
+    if let Err(e) = runtime.block_on(scan(&sink, stop.clone())) {
+      warn!("Bluetooth probe stopped with error: {:?}", e);
+    }
+    sink.close();
+    // Only clear our own handle, a new probe may have been started after a stop.
+    let mut probe_stop = PROBE_STOP.lock().unwrap();
+    if probe_stop.as_ref().is_some_and(|x| Arc::ptr_eq(x, &stop)) {
+      *probe_stop = None;
+    }
+  });
+  Ok(())
+}
+
+pub fn stop() {
+  if let Some(stop) = PROBE_STOP.lock().unwrap().take() {
+    // notify_one, so the stop sticks even if the scan task isn't waiting yet.
+    stop.notify_one();
+  }
+}
+
+async fn scan(sink: &StreamSink<BleAdvertisement>, stop: Arc<Notify>) -> Result<()> {
+  let manager = btleplug::platform::Manager::new().await?;
+  let adapter = manager
+    .adapters()
+    .await?
+    .into_iter()
+    .next()
+    .ok_or(anyhow::Error::msg("No Bluetooth adapter found"))?;
+  let mut events = adapter.events().await?;
+  adapter.start_scan(ScanFilter::default()).await?;
+  info!("Bluetooth probe started");
+  loop {
+    let event = select! {
+      event = events.next() => event,
+      _ = stop.notified() => break,
+    };
+    let id = match event {
+      Some(CentralEvent::DeviceDiscovered(id))
+      | Some(CentralEvent::DeviceUpdated(id))
+      | Some(CentralEvent::ManufacturerDataAdvertisement { id, .. })
+      | Some(CentralEvent::ServicesAdvertisement { id, .. }) => id,
+      Some(_) => continue,
+      None => break,
+    };
+    let Ok(peripheral) = adapter.peripheral(&id).await else {
+      continue;
+    };
+    let Ok(Some(properties)) = peripheral.properties().await else {
+      continue;
+    };
+    let mut manufacturer_data: Vec<BleManufacturerData> = properties
+      .manufacturer_data
+      .into_iter()
+      .map(|(company_id, data)| BleManufacturerData {
+        company_id,
+        hex_data: hex::encode(data),
+      })
+      .collect();
+    manufacturer_data.sort_by_key(|x| x.company_id);
+    sink.add(BleAdvertisement {
+      id: id.to_string(),
+      address: properties.address.to_string(),
+      name: properties.local_name,
+      rssi: properties.rssi,
+      manufacturer_data,
+      services: properties.services.iter().map(|x| x.to_string()).collect(),
+    });
+  }
+  if let Err(e) = adapter.stop_scan().await {
+    debug!("Cannot stop Bluetooth probe scan: {:?}", e);
+  }
+  info!("Bluetooth probe stopped");
+  Ok(())
+}
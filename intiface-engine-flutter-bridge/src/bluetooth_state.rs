@@ -0,0 +1,37 @@
+use crate::mobile_init;
+use anyhow::Result;
+use btleplug::api::{Central, CentralState, Manager as _};
+
+// Quick check of whether the engine will be able to use Bluetooth, so the frontend can ask the user
+// to turn their radio on or grant permissions before starting the engine, instead of the engine
+// coming up and just never finding anything.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BluetoothState {
+  Ready,
+  PoweredOff,
+  Unauthorized,
+  // No adapter at all, or the platform doesn't do Bluetooth LE.
+  Unsupported,
+}
+
+pub fn check_bluetooth_state() -> Result<BluetoothState> {
+  let runtime = mobile_init::create_runtime()?;
+  let result = runtime.block_on(async {
+    let manager = btleplug::platform::Manager::new().await?;
+    let Some(adapter) = manager.adapters().await?.into_iter().next() else {
+      return Ok(BluetoothState::Unsupported);
+    };
+    Ok(match adapter.adapter_state().await? {
+      CentralState::PoweredOff => BluetoothState::PoweredOff,
+      // Some platforms can't tell us the radio state, in which case we'd rather let the engine
+      // try than tell the user something that may be wrong.
+      CentralState::PoweredOn | CentralState::Unknown => BluetoothState::Ready,
+    })
+  });
+  match result {
+    Err(btleplug::Error::PermissionDenied) => Ok(BluetoothState::Unauthorized),
+    Err(btleplug::Error::NotSupported(_)) => Ok(BluetoothState::Unsupported),
+    result => Ok(result?),
+  }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Errors the frontend may want to do something specific about, rather than just show.
+//
+// Bridge calls can only fail with a message string, so these are sent as JSON (the variant as
+// "kind", its fields, and an English "message" for logs and anything that doesn't know the kind).
+// parse_bridge_error turns the string from a failed call back into one of these, or None for errors
+// that don't have a kind yet.
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum BridgeError {
+  AlreadyRunning,
+  NotRunning,
+  RuntimeInitFailed { detail: String },
+  ConfigParse { detail: String },
+  PortInUse { port: u16 },
+}
+
+impl BridgeError {
+  pub fn message(&self) -> String {
+    match self {
+      BridgeError::AlreadyRunning => "Server already running".to_owned(),
+      BridgeError::NotRunning => "No engine running".to_owned(),
+      BridgeError::RuntimeInitFailed { detail } => {
+        format!("Cannot create engine runtime: {}", detail)
+      }
+      BridgeError::ConfigParse { detail } => format!("Cannot parse config: {}", detail),
+      BridgeError::PortInUse { port } => format!("Port {} is already in use", port),
+    }
+  }
+
+  pub fn parse(message: &str) -> Option<BridgeError> {
+    serde_json::from_str(message).ok()
+  }
+}
+
+impl fmt::Display for BridgeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut value = serde_json::to_value(self).map_err(|_| fmt::Error)?;
+    value["message"] = self.message().into();
+    write!(f, "{}", value)
+  }
+}
+
+impl std::error::Error for BridgeError {}
@@ -12,8 +12,8 @@ pub extern "C" fn wire_run_engine(port_: i64, args: *mut wire_EngineOptionsExter
 }
 
 #[no_mangle]
-pub extern "C" fn wire_send(port_: i64, msg_json: *mut wire_uint_8_list) {
-  wire_send_impl(port_, msg_json)
+pub extern "C" fn wire_send(port_: i64, msg: *mut wire_IntifaceMessage) {
+  wire_send_impl(port_, msg)
 }
 
 #[no_mangle]
@@ -165,6 +165,11 @@ pub extern "C" fn new_box_autoadd_exposed_user_device_identifier_0(
   support::new_leak_box_ptr(wire_ExposedUserDeviceIdentifier::new_with_null_ptr())
 }
 
+#[no_mangle]
+pub extern "C" fn new_box_autoadd_intiface_message_0() -> *mut wire_IntifaceMessage {
+  support::new_leak_box_ptr(wire_IntifaceMessage::new_with_null_ptr())
+}
+
 #[no_mangle]
 pub extern "C" fn new_box_autoadd_u16_0(value: u16) -> *mut u16 {
   support::new_leak_box_ptr(value)
@@ -272,6 +277,12 @@ impl Wire2Api<ExposedUserDeviceIdentifier> for *mut wire_ExposedUserDeviceIdenti
     Wire2Api::<ExposedUserDeviceIdentifier>::wire2api(*wrap).into()
   }
 }
+impl Wire2Api<IntifaceMessage> for *mut wire_IntifaceMessage {
+  fn wire2api(self) -> IntifaceMessage {
+    let wrap = unsafe { support::box_from_leak_ptr(self) };
+    Wire2Api::<IntifaceMessage>::wire2api(*wrap).into()
+  }
+}
 impl Wire2Api<u16> for *mut u16 {
   fn wire2api(self) -> u16 {
     unsafe { *support::box_from_leak_ptr(self) }
@@ -318,6 +329,7 @@ impl Wire2Api<ExposedDeviceFeature> for wire_ExposedDeviceFeature {
       feature_type: self.feature_type.wire2api(),
       actuator: self.actuator.wire2api(),
       sensor: self.sensor.wire2api(),
+      enabled: self.enabled.wire2api(),
     }
   }
 }
@@ -366,6 +378,21 @@ impl Wire2Api<ExposedUserDeviceIdentifier> for wire_ExposedUserDeviceIdentifier
     }
   }
 }
+impl Wire2Api<IntifaceMessage> for wire_IntifaceMessage {
+  fn wire2api(self) -> IntifaceMessage {
+    match self.tag {
+      0 => unsafe {
+        let ans = support::box_from_leak_ptr(self.kind);
+        let ans = support::box_from_leak_ptr(ans.RequestEngineVersion);
+        IntifaceMessage::RequestEngineVersion {
+          expected_version: ans.expected_version.wire2api(),
+        }
+      },
+      1 => IntifaceMessage::Stop {},
+      _ => unreachable!(),
+    }
+  }
+}
 
 impl Wire2Api<Vec<(i32, i32)>> for *mut wire_list___record__i32_i32 {
   fn wire2api(self) -> Vec<(i32, i32)> {
@@ -471,6 +498,7 @@ pub struct wire_ExposedDeviceFeature {
   feature_type: i32,
   actuator: *mut wire_ExposedDeviceFeatureActuator,
   sensor: *mut wire_ExposedDeviceFeatureSensor,
+  enabled: bool,
 }
 
 #[repr(C)]
@@ -513,6 +541,29 @@ pub struct wire_ExposedUserDeviceIdentifier {
   identifier: *mut wire_uint_8_list,
 }
 
+#[repr(C)]
+#[derive(Clone)]
+pub struct wire_IntifaceMessage {
+  tag: i32,
+  kind: *mut IntifaceMessageKind,
+}
+
+#[repr(C)]
+pub union IntifaceMessageKind {
+  RequestEngineVersion: *mut wire_IntifaceMessage_RequestEngineVersion,
+  Stop: *mut wire_IntifaceMessage_Stop,
+}
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct wire_IntifaceMessage_RequestEngineVersion {
+  expected_version: u32,
+}
+
+#[repr(C)]
+#[derive(Clone)]
+pub struct wire_IntifaceMessage_Stop {}
+
 #[repr(C)]
 #[derive(Clone)]
 pub struct wire_list___record__i32_i32 {
@@ -637,6 +688,7 @@ impl NewWithNullPtr for wire_ExposedDeviceFeature {
       feature_type: Default::default(),
       actuator: core::ptr::null_mut(),
       sensor: core::ptr::null_mut(),
+      enabled: Default::default(),
     }
   }
 }
@@ -727,6 +779,37 @@ impl Default for wire_ExposedUserDeviceIdentifier {
   }
 }
 
+impl Default for wire_IntifaceMessage {
+  fn default() -> Self {
+    Self::new_with_null_ptr()
+  }
+}
+
+impl NewWithNullPtr for wire_IntifaceMessage {
+  fn new_with_null_ptr() -> Self {
+    Self {
+      tag: -1,
+      kind: core::ptr::null_mut(),
+    }
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn inflate_IntifaceMessage_RequestEngineVersion() -> *mut IntifaceMessageKind {
+  support::new_leak_box_ptr(IntifaceMessageKind {
+    RequestEngineVersion: support::new_leak_box_ptr(wire_IntifaceMessage_RequestEngineVersion {
+      expected_version: Default::default(),
+    }),
+  })
+}
+
+#[no_mangle]
+pub extern "C" fn inflate_IntifaceMessage_Stop() -> *mut IntifaceMessageKind {
+  support::new_leak_box_ptr(IntifaceMessageKind {
+    Stop: support::new_leak_box_ptr(wire_IntifaceMessage_Stop {}),
+  })
+}
+
 // Section: sync execution mode utility
 
 #[no_mangle]
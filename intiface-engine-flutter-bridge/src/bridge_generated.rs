@@ -48,7 +48,7 @@ fn wire_run_engine_impl(
     },
   )
 }
-fn wire_send_impl(port_: MessagePort, msg_json: impl Wire2Api<String> + UnwindSafe) {
+fn wire_send_impl(port_: MessagePort, msg: impl Wire2Api<IntifaceMessage> + UnwindSafe) {
   FLUTTER_RUST_BRIDGE_HANDLER.wrap::<_, _, _, (), _>(
     WrapInfo {
       debug_name: "send",
@@ -56,8 +56,8 @@ fn wire_send_impl(port_: MessagePort, msg_json: impl Wire2Api<String> + UnwindSa
       mode: FfiCallMode::Normal,
     },
     move || {
-      let api_msg_json = msg_json.wire2api();
-      move |task_callback| Result::<_, ()>::Ok(send(api_msg_json))
+      let api_msg = msg.wire2api();
+      move |task_callback| Result::<_, ()>::Ok(send(api_msg))
     },
   )
 }
@@ -101,12 +101,7 @@ fn wire_setup_device_configuration_manager_impl(
     move || {
       let api_base_config = base_config.wire2api();
       let api_user_config = user_config.wire2api();
-      move |task_callback| {
-        Result::<_, ()>::Ok(setup_device_configuration_manager(
-          api_base_config,
-          api_user_config,
-        ))
-      }
+      move |task_callback| setup_device_configuration_manager(api_base_config, api_user_config)
     },
   )
 }
@@ -165,7 +160,7 @@ fn wire_add_websocket_specifier_impl(
     move || {
       let api_protocol = protocol.wire2api();
       let api_name = name.wire2api();
-      move |task_callback| Result::<_, ()>::Ok(add_websocket_specifier(api_protocol, api_name))
+      move |task_callback| add_websocket_specifier(api_protocol, api_name)
     },
   )
 }
@@ -210,14 +205,14 @@ fn wire_add_serial_specifier_impl(
       let api_stop_bits = stop_bits.wire2api();
       let api_parity = parity.wire2api();
       move |task_callback| {
-        Result::<_, ()>::Ok(add_serial_specifier(
+        add_serial_specifier(
           api_protocol,
           api_port,
           api_baud_rate,
           api_data_bits,
           api_stop_bits,
           api_parity,
-        ))
+        )
       }
     },
   )
@@ -254,7 +249,7 @@ fn wire_update_user_config_impl(
     move || {
       let api_identifier = identifier.wire2api();
       let api_config = config.wire2api();
-      move |task_callback| Result::<_, ()>::Ok(update_user_config(api_identifier, api_config))
+      move |task_callback| update_user_config(api_identifier, api_config)
     },
   )
 }
@@ -500,6 +495,7 @@ impl support::IntoDart for ExposedDeviceFeature {
       self.feature_type.into_into_dart().into_dart(),
       self.actuator.into_dart(),
       self.sensor.into_dart(),
+      self.enabled.into_into_dart().into_dart(),
     ]
     .into_dart()
   }
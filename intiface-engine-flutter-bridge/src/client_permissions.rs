@@ -0,0 +1,240 @@
+use crate::{api::ExposedUserDeviceIdentifier, devices};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::{
+  collections::{HashMap, HashSet},
+  sync::RwLock,
+};
+
+// Limits which devices a client can see and control, keyed by the name the client gives in its
+// RequestServerInfo. Clients without an entry get every device, same as without this. Like device
+// settings, these can't go in the Buttplug user config file, as the library rejects anything it
+// doesn't know about, so they're saved as their own JSON document.
+//
+// The engine doesn't know about any of this, so it's applied to the Buttplug messages going through
+// the bridge's websocket proxy. Having any permissions set makes the proxy front the websocket
+// server, so clients can't get around it by connecting to the engine directly.
+
+const CLIENT_PERMISSIONS_VERSION: u32 = 1;
+
+// Buttplug spec error code for device errors.
+const ERROR_DEVICE: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientPermissions {
+  pub client_name: String,
+  pub allowed_devices: Vec<ExposedUserDeviceIdentifier>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ClientPermissionsFile {
+  version: u32,
+  #[serde(default)]
+  clients: Vec<ClientPermissions>,
+}
+
+lazy_static! {
+  static ref CLIENT_PERMISSIONS: RwLock<HashMap<String, HashSet<ExposedUserDeviceIdentifier>>> =
+    RwLock::new(HashMap::new());
+}
+
+pub fn load(permissions_json: &str) -> Result<()> {
+  let file: ClientPermissionsFile = serde_json::from_str(permissions_json)?;
+  if file.version > CLIENT_PERMISSIONS_VERSION {
+    return Err(anyhow::Error::msg(format!(
+      "Client permissions version {} is newer than supported version {}",
+      file.version, CLIENT_PERMISSIONS_VERSION
+    )));
+  }
+  *CLIENT_PERMISSIONS.write().unwrap() = file
+    .clients
+    .into_iter()
+    .map(|x| (x.client_name, x.allowed_devices.into_iter().collect()))
+    .collect();
+  Ok(())
+}
+
+pub fn save() -> String {
+  let file = ClientPermissionsFile {
+    version: CLIENT_PERMISSIONS_VERSION,
+    clients: permissions(),
+  };
+  serde_json::to_string(&file).expect("Client permissions should always serialize")
+}
+
+pub fn permissions() -> Vec<ClientPermissions> {
+  let mut permissions: Vec<ClientPermissions> = CLIENT_PERMISSIONS
+    .read()
+    .unwrap()
+    .iter()
+    .map(|(client_name, allowed_devices)| ClientPermissions {
+      client_name: client_name.clone(),
+      allowed_devices: allowed_devices.iter().cloned().collect(),
+    })
+    .collect();
+  permissions.sort_by(|a, b| a.client_name.cmp(&b.client_name));
+  permissions
+}
+
+pub fn set(client_name: &str, allowed_devices: Vec<ExposedUserDeviceIdentifier>) {
+  CLIENT_PERMISSIONS.write().unwrap().insert(
+    client_name.to_owned(),
+    allowed_devices.into_iter().collect(),
+  );
+}
+
+pub fn remove(client_name: &str) -> Result<()> {
+  CLIENT_PERMISSIONS
+    .write()
+    .unwrap()
+    .remove(client_name)
+    .map(|_| ())
+    .ok_or(anyhow::Error::msg("No permissions set for that client"))
+}
+
+pub fn has_permissions() -> bool {
+  !CLIENT_PERMISSIONS.read().unwrap().is_empty()
+}
+
+fn device_allowed(client_name: Option<&str>, identifier: &ExposedUserDeviceIdentifier) -> bool {
+  let Some(client_name) = client_name else {
+    return true;
+  };
+  CLIENT_PERMISSIONS
+    .read()
+    .unwrap()
+    .get(client_name)
+    .is_none_or(|allowed| allowed.contains(identifier))
+}
+
+// Splits a message into its type and fields, e.g. {"DeviceAdded": {...}}.
+fn message_parts(message: &mut Value) -> Option<(&String, &mut Map<String, Value>)> {
+  let (message_type, fields) = message.as_object_mut()?.iter_mut().next()?;
+  Some((message_type, fields.as_object_mut()?))
+}
+
+fn device_index(fields: &Map<String, Value>) -> Option<u32> {
+  fields
+    .get("DeviceIndex")
+    .and_then(|x| x.as_u64())
+    .map(|x| x as u32)
+}
+
+// Permission state for one proxied client connection. Permissions are looked up by name every time,
+// so changes apply to clients that are already connected, though they won't see devices appear or
+// disappear until they next get a device list.
+#[derive(Default)]
+pub struct ClientFilter {
+  client_name: Option<String>,
+  // Devices the client has been told about.
+  visible: HashSet<u32>,
+}
+
+impl ClientFilter {
+  pub fn set_client_name(&mut self, client_name: &str) {
+    self.client_name = Some(client_name.to_owned());
+  }
+
+  fn restricted(&self) -> bool {
+    self
+      .client_name
+      .as_ref()
+      .is_some_and(|x| CLIENT_PERMISSIONS.read().unwrap().contains_key(x))
+  }
+
+  fn index_allowed(&self, index: u32) -> bool {
+    if !self.restricted() {
+      return true;
+    }
+    devices::connected_device(index)
+      .is_some_and(|x| device_allowed(self.client_name.as_deref(), &x.identifier))
+  }
+
+  // Messages from the client. Returns the messages to pass on to the engine, and error replies for
+  // anything aimed at a device the client isn't allowed to use.
+  pub fn filter_from_client(&self, messages: Vec<Value>) -> (Vec<Value>, Vec<Value>) {
+    let mut allowed = vec![];
+    let mut rejected = vec![];
+    for mut message in messages {
+      let denied = match message_parts(&mut message) {
+        Some((_, fields)) => device_index(fields)
+          .filter(|x| !self.index_allowed(*x))
+          .map(|_| fields.get("Id").cloned().unwrap_or(json!(0))),
+        None => None,
+      };
+      match denied {
+        Some(id) => rejected.push(json!({
+          "Error": {
+            "Id": id,
+            "ErrorMessage": "Client is not permitted to use this device",
+            "ErrorCode": ERROR_DEVICE,
+          }
+        })),
+        None => allowed.push(message),
+      }
+    }
+    (allowed, rejected)
+  }
+
+  // Messages from the engine. Drops anything about devices the client isn't allowed to see.
+  pub async fn filter_from_engine(&mut self, messages: Vec<Value>) -> Vec<Value> {
+    let mut allowed = vec![];
+    for mut message in messages {
+      let Some((message_type, fields)) = message_parts(&mut message) else {
+        allowed.push(message);
+        continue;
+      };
+      match message_type.as_str() {
+        "DeviceList" => {
+          if let Some(list) = fields.get_mut("Devices").and_then(|x| x.as_array_mut()) {
+            self.visible.clear();
+            let mut kept = vec![];
+            for device in list.drain(..) {
+              if let Some(index) = device.as_object().and_then(device_index) {
+                if !self.check_new_device(index).await {
+                  continue;
+                }
+              }
+              kept.push(device);
+            }
+            *list = kept;
+          }
+        }
+        "DeviceAdded" => {
+          if let Some(index) = device_index(fields) {
+            if !self.check_new_device(index).await {
+              continue;
+            }
+          }
+        }
+        "DeviceRemoved" => {
+          if let Some(index) = device_index(fields) {
+            if !self.visible.remove(&index) {
+              continue;
+            }
+          }
+        }
+        _ => {}
+      }
+      allowed.push(message);
+    }
+    allowed
+  }
+
+  async fn check_new_device(&mut self, index: u32) -> bool {
+    if !self.restricted() {
+      self.visible.insert(index);
+      return true;
+    }
+    // If we can't tell what it is, err on the side of hiding it.
+    let allowed = devices::wait_for_device(index)
+      .await
+      .is_some_and(|x| device_allowed(self.client_name.as_deref(), &x));
+    if allowed {
+      self.visible.insert(index);
+    }
+    allowed
+  }
+}
@@ -0,0 +1,71 @@
+use crate::{
+  clock,
+  events::{self, BridgeEvent},
+  options::ClientReconnectOptions,
+};
+use serde::Serialize;
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{select, sync::Notify};
+
+// When the engine is connecting out to a server (websocket_client_address), it stops as soon as the
+// remote goes away, which on phones happens every time the network hiccups. With reconnects on, we
+// run the engine again after a backoff instead of letting the whole thing stop.
+
+#[derive(Debug, Clone, Serialize)]
+pub enum WebsocketClientState {
+  // Attempt is 0 for the first connection.
+  Connecting { attempt: u32 },
+  Disconnected { error: Option<String> },
+  Reconnecting { attempt: u32, delay_ms: u32 },
+  GaveUp { attempts: u32 },
+}
+
+pub fn emit_state(state: WebsocketClientState) {
+  events::emit(BridgeEvent::WebsocketClientState(state));
+}
+
+// Doesn't need to be good randomness, just enough that a room full of clients don't all come back
+// at once.
+fn jitter_fraction() -> f64 {
+  let nanos = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|x| x.subsec_nanos())
+    .unwrap_or_default();
+  (nanos % 1000) as f64 / 1000.0
+}
+
+// Delay before reconnect attempt number `attempt` (starting at 1), or None if we've run out of
+// retries.
+pub fn reconnect_delay(options: &ClientReconnectOptions, attempt: u32) -> Option<Duration> {
+  if !options.enabled || options.max_retries.is_some_and(|max| attempt > max) {
+    return None;
+  }
+  let backoff =
+    (options.initial_backoff_ms as f64) * 2f64.powi(attempt.saturating_sub(1).min(16) as i32);
+  let backoff = backoff.min(options.max_backoff_ms as f64);
+  let jitter = options.jitter.clamp(0.0, 1.0);
+  // Jitter spreads the delay out by up to that fraction either side.
+  let backoff = backoff * (1.0 + jitter * (jitter_fraction() * 2.0 - 1.0));
+  Some(Duration::from_millis(backoff.max(0.0) as u64))
+}
+
+// Waits out a reconnect delay. Returns false if the engine was stopped in the meantime.
+pub async fn wait(delay: Duration, stopping: &AtomicBool, stop: &Arc<Notify>) -> bool {
+  let stopped = stop.notified();
+  tokio::pin!(stopped);
+  // Register before checking the flag, so a stop in between can't be missed.
+  stopped.as_mut().enable();
+  if stopping.load(Ordering::Relaxed) {
+    return false;
+  }
+  select! {
+    _ = clock::sleep_until(clock::now() + delay) => !stopping.load(Ordering::Relaxed),
+    _ = stopped => false,
+  }
+}
@@ -0,0 +1,911 @@
+use crate::{
+  backdoor, clients,
+  events::{self, BridgeEvent},
+  options::{self, DeviceArbitration},
+};
+use anyhow::Result;
+use futures::{
+  channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+  Sink, SinkExt, Stream, StreamExt,
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::{
+  collections::HashMap,
+  pin::Pin,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Mutex,
+  },
+  task::{Context, Poll},
+};
+use tokio::{net::TcpStream, select};
+use tokio_tungstenite::{
+  connect_async,
+  tungstenite::{Error as WsError, Message},
+  MaybeTlsStream, WebSocketStream,
+};
+
+// Lets more than one Buttplug client use the engine at once. The engine's server only takes one
+// client connection, so with multiple_clients on, proxied clients (see websocket_proxy) don't get a
+// connection to the engine each. They share one, and the hub here sorts out who's who: message ids
+// are swapped for ones of our own on the way in and put back on the way out, so replies go to
+// whoever asked, and events (devices coming and going, scanning finishing, sensor readings) go to
+// everyone.
+//
+// The first client's handshake is the one the engine sees, and later clients get its ServerInfo.
+// The engine only speaks one spec version per connection, so they have to ask for the same one.
+//
+// Who can command which devices is up to the arbitration policy. Exclusive gives every device to
+// the first client that commands one, Shared lets everyone command everything, and PerDevice gives
+// each device to the first client that commands it. A client keeps control until it disconnects, at
+// which point whatever it had is stopped. Anything that doesn't make a device do something (device
+// lists, sensor reads, scanning) is open to everyone. Relays ask before ramping or shaping a
+// command (see arbitrate), and the keep alives and ramp steps they send on a client's behalf go
+// through the same checks as the client's own messages.
+//
+// Ids from backdoor::BRIDGE_MESSAGE_ID_START up are the bridge's, so clients can't use them.
+//
+// Once the last client leaves, the shared connection is closed, so the engine sees a normal client
+// disconnect.
+
+// Buttplug error codes.
+const ERROR_INIT: u32 = 1;
+const ERROR_MSG: u32 = 3;
+const ERROR_DEVICE: u32 = 4;
+
+// Messages that make a device do something, and so need control of it.
+const DEVICE_COMMANDS: [&str; 5] = [
+  "ScalarCmd",
+  "LinearCmd",
+  "RotateCmd",
+  "RawWriteCmd",
+  "StopDeviceCmd",
+];
+
+// What a relay sends towards the engine.
+pub enum RelayMessage {
+  // From the client.
+  Client(Message),
+  // Sent on the client's behalf (keep alives, ramp steps), with bridge ids, so the replies stop at
+  // the hub.
+  Relay(Message),
+}
+
+impl From<RelayMessage> for Message {
+  fn from(msg: RelayMessage) -> Self {
+    match msg {
+      RelayMessage::Client(msg) | RelayMessage::Relay(msg) => msg,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceControl {
+  // None when the client has all of them (exclusive arbitration).
+  pub device_index: Option<u32>,
+  // As in get_connected_clients.
+  pub client_id: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Claim {
+  // Session can command the device, and already could.
+  Allowed,
+  // Session can command the device, and has just been given control of it.
+  Taken(Option<u32>),
+  // Someone else has it.
+  Refused,
+}
+
+// Who's controlling what. Sessions are websocket_proxy connection ids.
+#[derive(Default)]
+struct DeviceArbiter {
+  policy: DeviceArbitration,
+  // Session with every device, for Exclusive.
+  controller: Option<u32>,
+  // Device index -> session with it, for PerDevice.
+  owners: HashMap<u32, u32>,
+}
+
+impl DeviceArbiter {
+  fn new(policy: DeviceArbitration) -> Self {
+    Self {
+      policy,
+      ..Default::default()
+    }
+  }
+
+  fn claim(&mut self, session: u32, device_index: u32) -> Claim {
+    match self.policy {
+      DeviceArbitration::Shared => Claim::Allowed,
+      DeviceArbitration::Exclusive => match self.controller {
+        Some(x) if x == session => Claim::Allowed,
+        Some(_) => Claim::Refused,
+        None => {
+          self.controller = Some(session);
+          Claim::Taken(None)
+        }
+      },
+      DeviceArbitration::PerDevice => match self.owners.get(&device_index) {
+        Some(x) if *x == session => Claim::Allowed,
+        Some(_) => Claim::Refused,
+        None => {
+          self.owners.insert(device_index, session);
+          Claim::Taken(Some(device_index))
+        }
+      },
+    }
+  }
+
+  // Whether the session can stop everything, which is only a problem if someone else has control of
+  // everything.
+  fn can_stop_all(&self, session: u32) -> bool {
+    self.controller.is_none_or(|x| x == session)
+  }
+
+  // Devices the session has control of, for PerDevice.
+  fn owned(&self, session: u32) -> Vec<u32> {
+    let mut owned: Vec<u32> = self
+      .owners
+      .iter()
+      .filter(|(_, x)| **x == session)
+      .map(|(index, _)| *index)
+      .collect();
+    owned.sort();
+    owned
+  }
+
+  // Gives up whatever the session had, returning it the same way as Claim::Taken.
+  fn release(&mut self, session: u32) -> Vec<Option<u32>> {
+    if self.controller.take_if(|x| *x == session).is_some() {
+      return vec![None];
+    }
+    let owned = self.owned(session);
+    for index in &owned {
+      self.owners.remove(index);
+    }
+    owned.into_iter().map(Some).collect()
+  }
+
+  // Whether the device had anyone controlling it.
+  fn device_removed(&mut self, device_index: u32) -> bool {
+    self.owners.remove(&device_index).is_some()
+  }
+
+  fn controls(&self) -> Vec<(Option<u32>, u32)> {
+    let mut controls: Vec<(Option<u32>, u32)> = self
+      .controller
+      .map(|x| (None, x))
+      .into_iter()
+      .chain(self.owners.iter().map(|(index, x)| (Some(*index), *x)))
+      .collect();
+    controls.sort();
+    controls
+  }
+}
+
+struct Session {
+  to_client: UnboundedSender<Message>,
+  // Whether the session's handshake has been answered, so it can send anything else and gets
+  // events.
+  handshake_done: bool,
+}
+
+// RequestServerInfo waiting on the one the engine's answering.
+struct WaitingHandshake {
+  session: u32,
+  message: Value,
+}
+
+struct Hub {
+  // Tells the upstream task which hub it belongs to, in case it's been replaced by the time the
+  // engine connection goes.
+  generation: u32,
+  upstream: UnboundedSender<Message>,
+  sessions: HashMap<u32, Session>,
+  // Id we sent a message to the engine with -> session it came from, and the id it had.
+  pending: HashMap<u32, (u32, Value)>,
+  next_id: u32,
+  // The engine's ServerInfo, and the spec version it was asked for, once the first handshake's
+  // done.
+  server_info: Option<(Map<String, Value>, u32)>,
+  // Id and spec version of the handshake the engine's answering.
+  handshake_in_flight: Option<(u32, u32)>,
+  waiting_handshakes: Vec<WaitingHandshake>,
+  arbiter: DeviceArbiter,
+}
+
+type EngineConnection = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+static NEXT_GENERATION: AtomicU32 = AtomicU32::new(1);
+
+lazy_static! {
+  static ref HUB: Mutex<Option<Hub>> = Mutex::new(None);
+}
+
+// Message type, id and device index of a message in a Buttplug message array.
+fn message_parts(message: &Value) -> Option<(String, Value, Option<u32>)> {
+  let (message_type, fields) = message.as_object()?.iter().next()?;
+  let id = fields.get("Id").cloned().unwrap_or(json!(0));
+  let device_index = fields
+    .get("DeviceIndex")
+    .and_then(|x| x.as_u64())
+    .map(|x| x as u32);
+  Some((message_type.clone(), id, device_index))
+}
+
+// Device index of a message that makes a device do something.
+fn device_command(message_type: &str, device_index: Option<u32>) -> Option<u32> {
+  device_index.filter(|_| DEVICE_COMMANDS.contains(&message_type))
+}
+
+fn is_reserved_id(id: &Value) -> bool {
+  id.as_u64()
+    .is_some_and(|x| x >= backdoor::BRIDGE_MESSAGE_ID_START as u64)
+}
+
+fn set_id(message: &mut Value, id: Value) {
+  if let Some(fields) = message
+    .as_object_mut()
+    .and_then(|x| x.values_mut().next())
+    .and_then(|x| x.as_object_mut())
+  {
+    fields.insert("Id".to_owned(), id);
+  }
+}
+
+fn error_reply(id: Value, code: u32, message: &str) -> Value {
+  json!({
+    "Error": {
+      "Id": id,
+      "ErrorMessage": message,
+      "ErrorCode": code,
+    }
+  })
+}
+
+fn to_ws(messages: Vec<Value>) -> Message {
+  Message::text(Value::Array(messages).to_string())
+}
+
+fn control_changed(device_index: Option<u32>, session: Option<u32>) {
+  events::emit(BridgeEvent::DeviceControlChanged {
+    device_index,
+    client_id: session.and_then(clients::client_id),
+  });
+}
+
+impl Hub {
+  fn new(generation: u32, upstream: UnboundedSender<Message>) -> Self {
+    Self {
+      generation,
+      upstream,
+      sessions: HashMap::new(),
+      pending: HashMap::new(),
+      next_id: 1,
+      server_info: None,
+      handshake_in_flight: None,
+      waiting_handshakes: vec![],
+      arbiter: DeviceArbiter::new(options::bridge_options().client_sessions.arbitration),
+    }
+  }
+
+  fn send_upstream(&self, messages: Vec<Value>) {
+    if !messages.is_empty() {
+      let _ = self.upstream.unbounded_send(to_ws(messages));
+    }
+  }
+
+  fn send_to_session(&self, session: u32, messages: Vec<Value>) {
+    if messages.is_empty() {
+      return;
+    }
+    if let Some(session) = self.sessions.get(&session) {
+      let _ = session.to_client.unbounded_send(to_ws(messages));
+    }
+  }
+
+  // Swaps the message's id for one of ours, below the bridge's own ids, remembering whose it was.
+  fn remap(&mut self, session: u32, mut message: Value, id: Value) -> (Value, u32) {
+    while self.pending.contains_key(&self.next_id) {
+      self.next_id = self.next_id % (backdoor::BRIDGE_MESSAGE_ID_START - 1) + 1;
+    }
+    let upstream_id = self.next_id;
+    self.next_id = self.next_id % (backdoor::BRIDGE_MESSAGE_ID_START - 1) + 1;
+    self.pending.insert(upstream_id, (session, id));
+    set_id(&mut message, json!(upstream_id));
+    (message, upstream_id)
+  }
+
+  fn finish_handshake(&mut self, session: u32) {
+    if let Some(x) = self
+      .sessions
+      .get_mut(&session)
+      .filter(|x| !x.handshake_done)
+    {
+      x.handshake_done = true;
+      clients::session_connected(session);
+    }
+  }
+
+  // Answers the handshake from the engine's ServerInfo if we have it, otherwise sends it on, or
+  // holds it until the one that's already been sent on is answered.
+  fn handshake(
+    &mut self,
+    session: u32,
+    message: Value,
+    upstream: &mut Vec<Value>,
+  ) -> Option<Value> {
+    let (_, id, _) = message_parts(&message)?;
+    let spec_version = clients::parse_handshake(std::slice::from_ref(&message))
+      .map(|(_, x)| x)
+      .unwrap_or_default();
+    if let Some((info, server_spec_version)) = &self.server_info {
+      if spec_version != *server_spec_version {
+        return Some(error_reply(
+          id,
+          ERROR_INIT,
+          &format!(
+            "Another client is connected with spec version {}, and the server can only speak one \
+             at a time",
+            server_spec_version
+          ),
+        ));
+      }
+      let mut info = info.clone();
+      info.insert("Id".to_owned(), id);
+      self.finish_handshake(session);
+      return Some(json!({ "ServerInfo": info }));
+    }
+    if self.handshake_in_flight.is_some() {
+      self
+        .waiting_handshakes
+        .push(WaitingHandshake { session, message });
+      return None;
+    }
+    let (message, upstream_id) = self.remap(session, message, id);
+    self.handshake_in_flight = Some((upstream_id, spec_version));
+    upstream.push(message);
+    None
+  }
+
+  // Error reply for anything but a handshake from a session that hasn't finished its own.
+  fn handshake_check(&self, session: u32, message_type: &str, id: &Value) -> Option<Value> {
+    if message_type == "RequestServerInfo"
+      || self
+        .sessions
+        .get(&session)
+        .is_some_and(|x| x.handshake_done)
+    {
+      return None;
+    }
+    Some(error_reply(
+      id.clone(),
+      ERROR_INIT,
+      "RequestServerInfo must be sent first",
+    ))
+  }
+
+  // Gives the session control of the device if nobody has it, or an error reply if someone else
+  // does.
+  fn device_check(&mut self, session: u32, device_index: u32, id: &Value) -> Option<Value> {
+    match self.arbiter.claim(session, device_index) {
+      Claim::Allowed => None,
+      Claim::Taken(device_index) => {
+        control_changed(device_index, Some(session));
+        None
+      }
+      Claim::Refused => Some(error_reply(
+        id.clone(),
+        ERROR_DEVICE,
+        "Another client has control of this device",
+      )),
+    }
+  }
+
+  // Splits out the device commands the session can't send, answering them with errors.
+  fn arbitrate(&mut self, session: u32, messages: Vec<Value>) -> (Vec<Value>, Vec<Value>) {
+    let mut allowed = vec![];
+    let mut refused = vec![];
+    for message in messages {
+      let reply = message_parts(&message).and_then(|(message_type, id, device_index)| {
+        let device_index = device_command(&message_type, device_index)?;
+        if is_reserved_id(&id) {
+          // Left for route_from_session to turn away.
+          return None;
+        }
+        self
+          .handshake_check(session, &message_type, &id)
+          .or_else(|| self.device_check(session, device_index, &id))
+      });
+      match reply {
+        Some(reply) => refused.push(reply),
+        None => allowed.push(message),
+      }
+    }
+    (allowed, refused)
+  }
+
+  fn route_from_session(&mut self, session: u32, messages: Vec<Value>) {
+    let mut upstream = vec![];
+    let mut replies = vec![];
+    for message in messages {
+      let Some((message_type, id, device_index)) = message_parts(&message) else {
+        replies.push(error_reply(json!(0), ERROR_MSG, "Invalid message"));
+        continue;
+      };
+      // Replies to these would be taken for the bridge's own, and dropped.
+      if is_reserved_id(&id) {
+        replies.push(error_reply(
+          id,
+          ERROR_MSG,
+          &format!(
+            "Message ids from {} up are reserved",
+            backdoor::BRIDGE_MESSAGE_ID_START
+          ),
+        ));
+        continue;
+      }
+      if let Some(reply) = self.handshake_check(session, &message_type, &id) {
+        replies.push(reply);
+        continue;
+      }
+      if message_type == "RequestServerInfo" {
+        replies.extend(self.handshake(session, message, &mut upstream));
+        continue;
+      }
+      if message_type == "StopAllDevices" {
+        if !self.arbiter.can_stop_all(session) {
+          replies.push(error_reply(
+            id,
+            ERROR_DEVICE,
+            "Another client has control of the devices",
+          ));
+          continue;
+        }
+        // Only the session's own devices get stopped.
+        if self.arbiter.policy == DeviceArbitration::PerDevice {
+          for index in self.arbiter.owned(session) {
+            upstream.push(json!({
+              "StopDeviceCmd": { "Id": backdoor::next_message_id(), "DeviceIndex": index }
+            }));
+          }
+          replies.push(json!({ "Ok": { "Id": id } }));
+          continue;
+        }
+      }
+      if let Some(index) = device_command(&message_type, device_index) {
+        if let Some(reply) = self.device_check(session, index, &id) {
+          replies.push(reply);
+          continue;
+        }
+      }
+      upstream.push(self.remap(session, message, id).0);
+    }
+    self.send_upstream(upstream);
+    self.send_to_session(session, replies);
+  }
+
+  // Keep alives and ramp steps get the same checks as the client's own messages, but keep their
+  // bridge ids, as nobody's waiting on the replies. Anything refused is dropped, since the client
+  // never sent it.
+  fn route_from_relay(&mut self, session: u32, messages: Vec<Value>) {
+    let mut upstream = vec![];
+    for message in messages {
+      let Some((message_type, id, device_index)) = message_parts(&message) else {
+        continue;
+      };
+      if !is_reserved_id(&id) || self.handshake_check(session, &message_type, &id).is_some() {
+        continue;
+      }
+      if let Some(index) = device_command(&message_type, device_index) {
+        if self.device_check(session, index, &id).is_some() {
+          continue;
+        }
+      }
+      upstream.push(message);
+    }
+    self.send_upstream(upstream);
+  }
+
+  fn route_from_engine(&mut self, messages: Vec<Value>) {
+    let mut outgoing: HashMap<u32, Vec<Value>> = HashMap::new();
+    let mut upstream = vec![];
+    for mut message in messages {
+      let Some((message_type, id, device_index)) = message_parts(&message) else {
+        continue;
+      };
+      let Some(id) = id.as_u64().map(|x| x as u32) else {
+        continue;
+      };
+      // Events go to everyone.
+      if id == 0 {
+        if message_type == "DeviceRemoved" {
+          if let Some(index) = device_index.filter(|x| self.arbiter.device_removed(*x)) {
+            control_changed(Some(index), None);
+          }
+        }
+        for (session, _) in self.sessions.iter().filter(|(_, x)| x.handshake_done) {
+          outgoing.entry(*session).or_default().push(message.clone());
+        }
+        continue;
+      }
+      // Replies to bridge ids (and anyone who's since left) stop here.
+      let Some((session, original_id)) = self.pending.remove(&id) else {
+        continue;
+      };
+      set_id(&mut message, original_id);
+      if let Some((_, spec_version)) = self.handshake_in_flight.take_if(|(x, _)| *x == id) {
+        if let Some(info) = message.get("ServerInfo").and_then(|x| x.as_object()) {
+          self.server_info = Some((info.clone(), spec_version));
+          self.finish_handshake(session);
+        }
+        // Anyone waiting gets answered now, or has their go at it if this one failed.
+        for waiting in std::mem::take(&mut self.waiting_handshakes) {
+          if let Some(reply) = self.handshake(waiting.session, waiting.message, &mut upstream) {
+            outgoing.entry(waiting.session).or_default().push(reply);
+          }
+        }
+      }
+      outgoing.entry(session).or_default().push(message);
+    }
+    self.send_upstream(upstream);
+    for (session, messages) in outgoing {
+      self.send_to_session(session, messages);
+    }
+  }
+}
+
+// Our end of the shared engine connection, for a session's relay to send to. Dropping it (when the
+// relay ends) takes the session out of the hub.
+pub struct SessionSink {
+  session: u32,
+}
+
+impl Sink<RelayMessage> for SessionSink {
+  type Error = WsError;
+
+  fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn start_send(self: Pin<&mut Self>, msg: RelayMessage) -> Result<(), WsError> {
+    let from_relay = matches!(msg, RelayMessage::Relay(_));
+    // Closes are handled by the relay ending, and there's nothing else worth passing on.
+    let Message::Text(text) = Message::from(msg) else {
+      return Ok(());
+    };
+    let mut hub = HUB.lock().unwrap();
+    let Some(hub) = hub
+      .as_mut()
+      .filter(|x| x.sessions.contains_key(&self.session))
+    else {
+      return Err(WsError::ConnectionClosed);
+    };
+    match serde_json::from_str::<Vec<Value>>(text.as_str()) {
+      Ok(messages) if from_relay => hub.route_from_relay(self.session, messages),
+      Ok(messages) => hub.route_from_session(self.session, messages),
+      Err(_) if from_relay => {}
+      Err(_) => hub.send_to_session(
+        self.session,
+        vec![error_reply(json!(0), ERROR_MSG, "Invalid message")],
+      ),
+    }
+    Ok(())
+  }
+
+  fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl Drop for SessionSink {
+  fn drop(&mut self) {
+    leave(self.session);
+  }
+}
+
+// Adds a proxied connection to the hub, connecting it to the engine if it's the first. Returns the
+// connection's ends of the engine connection, to relay to and from.
+pub async fn join(
+  engine_port: u16,
+  session: u32,
+) -> Result<(
+  SessionSink,
+  impl Stream<Item = Result<Message, WsError>> + Unpin,
+)> {
+  let (to_client, from_hub) = unbounded();
+  let sink = SessionSink { session };
+  let mut to_client = Some(to_client);
+  if let Some(hub) = HUB.lock().unwrap().as_mut() {
+    hub.sessions.insert(
+      session,
+      Session {
+        to_client: to_client.take().unwrap(),
+        handshake_done: false,
+      },
+    );
+  }
+  if let Some(to_client) = to_client {
+    let (engine_ws, _) = connect_async(format!("ws://127.0.0.1:{}", engine_port)).await?;
+    let mut hub = HUB.lock().unwrap();
+    // Someone else may have got there while we were connecting, in which case ours is dropped.
+    let hub = match hub.as_mut() {
+      Some(hub) => hub,
+      None => {
+        let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        let (upstream, outgoing) = unbounded();
+        tokio::spawn(run_upstream(engine_ws, outgoing, generation));
+        hub.insert(Hub::new(generation, upstream))
+      }
+    };
+    hub.sessions.insert(
+      session,
+      Session {
+        to_client,
+        handshake_done: false,
+      },
+    );
+  }
+  Ok((sink, from_hub.map(Ok)))
+}
+
+async fn run_upstream(
+  engine_ws: EngineConnection,
+  mut outgoing: UnboundedReceiver<Message>,
+  generation: u32,
+) {
+  let (mut engine_sink, mut engine_stream) = engine_ws.split();
+  loop {
+    select! {
+      msg = outgoing.next() => {
+        // Hub's gone, as the last session left.
+        let Some(msg) = msg else {
+          break;
+        };
+        if engine_sink.send(msg).await.is_err() {
+          break;
+        }
+      }
+      msg = engine_stream.next() => {
+        let Some(Ok(msg)) = msg else {
+          break;
+        };
+        let Message::Text(text) = msg else {
+          continue;
+        };
+        let Ok(messages) = serde_json::from_str::<Vec<Value>>(text.as_str()) else {
+          continue;
+        };
+        if let Some(hub) = HUB
+          .lock()
+          .unwrap()
+          .as_mut()
+          .filter(|x| x.generation == generation)
+        {
+          hub.route_from_engine(messages);
+        }
+      }
+    }
+  }
+  let _ = engine_sink.close().await;
+  // If the engine went away, everyone goes with it. Dropping the hub ends each session's stream,
+  // which ends its relay.
+  let mut hub = HUB.lock().unwrap();
+  if hub.as_ref().is_some_and(|x| x.generation == generation) {
+    hub.take();
+  }
+}
+
+pub fn leave(session: u32) {
+  clients::session_disconnected(session);
+  let mut hub_lock = HUB.lock().unwrap();
+  let Some(hub) = hub_lock.as_mut() else {
+    return;
+  };
+  if hub.sessions.remove(&session).is_none() {
+    return;
+  }
+  hub.pending.retain(|_, (x, _)| *x != session);
+  hub.waiting_handshakes.retain(|x| x.session != session);
+  let mut stops = vec![];
+  for device_index in hub.arbiter.release(session) {
+    control_changed(device_index, None);
+    stops.push(match device_index {
+      Some(index) => json!({
+        "StopDeviceCmd": { "Id": backdoor::next_message_id(), "DeviceIndex": index }
+      }),
+      None => json!({ "StopAllDevices": { "Id": backdoor::next_message_id() } }),
+    });
+  }
+  hub.send_upstream(stops);
+  if hub.sessions.is_empty() {
+    // Upstream task sends what's left (the stops above), then closes the engine connection.
+    hub_lock.take();
+  }
+}
+
+// Turns away device commands the session can't have control of, before the relay ramps or shapes
+// them, so a refused command leaves nothing behind to be sent later. Returns the messages that can
+// go on, and error replies for the rest. Connections that aren't sharing the engine get everything
+// back.
+pub fn arbitrate(session: u32, messages: Vec<Value>) -> (Vec<Value>, Vec<Value>) {
+  let mut hub = HUB.lock().unwrap();
+  match hub.as_mut().filter(|x| x.sessions.contains_key(&session)) {
+    Some(hub) => hub.arbitrate(session, messages),
+    None => (messages, vec![]),
+  }
+}
+
+pub fn device_control() -> Vec<DeviceControl> {
+  let hub = HUB.lock().unwrap();
+  let Some(hub) = hub.as_ref() else {
+    return vec![];
+  };
+  hub
+    .arbiter
+    .controls()
+    .into_iter()
+    .filter_map(|(device_index, session)| {
+      Some(DeviceControl {
+        device_index,
+        client_id: clients::client_id(session)?,
+      })
+    })
+    .collect()
+}
+
+pub fn clear() {
+  HUB.lock().unwrap().take();
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ramp::RampLimiter;
+  use std::time::{Duration, Instant};
+
+  fn hub_with_sessions(
+    policy: DeviceArbitration,
+    handshake_done: bool,
+  ) -> (Hub, UnboundedReceiver<Message>) {
+    let (upstream, outgoing) = unbounded();
+    let mut hub = Hub::new(0, upstream);
+    hub.arbiter = DeviceArbiter::new(policy);
+    for session in [1, 2] {
+      let (to_client, _) = unbounded();
+      hub.sessions.insert(
+        session,
+        Session {
+          to_client,
+          handshake_done,
+        },
+      );
+    }
+    (hub, outgoing)
+  }
+
+  fn scalar_cmd(id: u32, value: f64) -> Value {
+    json!({
+      "ScalarCmd": {
+        "Id": id,
+        "DeviceIndex": 0,
+        "Scalars": [{ "Index": 0, "Scalar": value, "ActuatorType": "Vibrate" }],
+      }
+    })
+  }
+
+  #[test]
+  fn exclusive_gives_everything_to_the_first_client() {
+    let mut arbiter = DeviceArbiter::new(DeviceArbitration::Exclusive);
+    assert_eq!(arbiter.claim(1, 0), Claim::Taken(None));
+    assert_eq!(arbiter.claim(1, 1), Claim::Allowed);
+    assert_eq!(arbiter.claim(2, 0), Claim::Refused);
+    assert_eq!(arbiter.claim(2, 2), Claim::Refused);
+    assert!(arbiter.can_stop_all(1));
+    assert!(!arbiter.can_stop_all(2));
+    assert_eq!(arbiter.controls(), vec![(None, 1)]);
+  }
+
+  #[test]
+  fn exclusive_control_passes_on_after_release() {
+    let mut arbiter = DeviceArbiter::new(DeviceArbitration::Exclusive);
+    arbiter.claim(1, 0);
+    assert_eq!(arbiter.release(2), vec![]);
+    assert_eq!(arbiter.release(1), vec![None]);
+    assert_eq!(arbiter.claim(2, 0), Claim::Taken(None));
+  }
+
+  #[test]
+  fn shared_lets_everyone_in() {
+    let mut arbiter = DeviceArbiter::new(DeviceArbitration::Shared);
+    assert_eq!(arbiter.claim(1, 0), Claim::Allowed);
+    assert_eq!(arbiter.claim(2, 0), Claim::Allowed);
+    assert!(arbiter.can_stop_all(2));
+    assert_eq!(arbiter.release(1), vec![]);
+    assert_eq!(arbiter.controls(), vec![]);
+  }
+
+  #[test]
+  fn per_device_gives_each_device_to_its_first_client() {
+    let mut arbiter = DeviceArbiter::new(DeviceArbitration::PerDevice);
+    assert_eq!(arbiter.claim(1, 0), Claim::Taken(Some(0)));
+    assert_eq!(arbiter.claim(2, 1), Claim::Taken(Some(1)));
+    assert_eq!(arbiter.claim(1, 0), Claim::Allowed);
+    assert_eq!(arbiter.claim(1, 1), Claim::Refused);
+    assert_eq!(arbiter.claim(1, 2), Claim::Taken(Some(2)));
+    assert!(arbiter.can_stop_all(2));
+    assert_eq!(arbiter.owned(1), vec![0, 2]);
+    assert_eq!(
+      arbiter.controls(),
+      vec![(Some(0), 1), (Some(1), 2), (Some(2), 1)]
+    );
+    assert_eq!(arbiter.release(1), vec![Some(0), Some(2)]);
+    assert_eq!(arbiter.claim(2, 0), Claim::Taken(Some(0)));
+  }
+
+  #[test]
+  fn removed_devices_are_given_up() {
+    let mut arbiter = DeviceArbiter::new(DeviceArbitration::PerDevice);
+    arbiter.claim(1, 0);
+    assert!(arbiter.device_removed(0));
+    assert!(!arbiter.device_removed(0));
+    assert_eq!(arbiter.claim(2, 0), Claim::Taken(Some(0)));
+  }
+
+  #[test]
+  fn message_ids_are_swapped_and_put_back() {
+    let (upstream, _outgoing) = unbounded();
+    let mut hub = Hub::new(0, upstream);
+    let (message, upstream_id) = hub.remap(7, json!({ "StartScanning": { "Id": 3 } }), json!(3));
+    assert_eq!(message, json!({ "StartScanning": { "Id": upstream_id } }));
+    assert_eq!(hub.pending.get(&upstream_id), Some(&(7, json!(3))));
+    let (_, next_id) = hub.remap(8, json!({ "StopScanning": { "Id": 3 } }), json!(3));
+    assert_ne!(next_id, upstream_id);
+    assert!(next_id < backdoor::BRIDGE_MESSAGE_ID_START);
+  }
+
+  #[test]
+  fn ramped_commands_from_another_client_never_reach_the_device() {
+    let (mut hub, mut outgoing) = hub_with_sessions(DeviceArbitration::Exclusive, true);
+    hub.route_from_session(1, vec![scalar_cmd(1, 0.5)]);
+    assert!(outgoing.try_recv().is_ok());
+
+    // Same order as the relay: arbitration, then ramps.
+    let start = Instant::now();
+    let mut ramps = RampLimiter::default();
+    let (allowed, refused) = hub.arbitrate(2, vec![scalar_cmd(1, 1.0)]);
+    assert!(allowed.is_empty());
+    assert_eq!(refused[0]["Error"]["ErrorCode"], ERROR_DEVICE);
+    let allowed = serde_json::to_string(&allowed).unwrap();
+    assert!(ramps
+      .limit_message(&allowed, |_| Some(1.0), start)
+      .is_none());
+    for step in ramps.step(start + Duration::from_millis(250)) {
+      hub.route_from_relay(2, serde_json::from_str(&step).unwrap());
+    }
+    // Ramp steps are arbitrated too.
+    hub.route_from_relay(2, vec![scalar_cmd(backdoor::next_message_id(), 1.0)]);
+    // And the client can't pass its own commands off as the relay's.
+    hub.route_from_session(2, vec![scalar_cmd(backdoor::BRIDGE_MESSAGE_ID_START, 1.0)]);
+    assert!(outgoing.try_recv().is_err());
+  }
+
+  #[test]
+  fn relay_messages_wait_for_the_handshake() {
+    let (mut hub, mut outgoing) = hub_with_sessions(DeviceArbitration::Shared, false);
+    let ping = json!({ "Ping": { "Id": backdoor::next_message_id() } });
+    hub.route_from_relay(1, vec![ping.clone()]);
+    assert!(outgoing.try_recv().is_err());
+    hub.sessions.get_mut(&1).unwrap().handshake_done = true;
+    hub.route_from_relay(1, vec![ping.clone()]);
+    // Keeps its bridge id, so the reply stops at the hub.
+    assert_eq!(outgoing.try_recv().unwrap(), to_ws(vec![ping]));
+  }
+}
@@ -0,0 +1,340 @@
+use crate::{
+  events::{self, BridgeEvent},
+  options,
+};
+use anyhow::Result;
+use intiface_engine::EngineMessage;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+  collections::HashMap,
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Notify;
+
+// Tracks the Buttplug client connected to the engine. The engine only tells us the client's name,
+// so for connections that come through our websocket proxy, we also pick the client's
+// RequestServerInfo out of the messages going past, which has the spec version it speaks, and match
+// it up by name when the engine says a client connected. Clients connecting to the engine directly
+// only get a name.
+//
+// Proxied connections also get websocket pings from us every few seconds, so the round trip to the
+// client can be told apart from time spent talking to devices, and we note when the client last
+// sent anything.
+//
+// The engine has no setting for the newest spec version it takes, so max_spec_version is applied to
+// proxied connections here too. Clients asking for newer get the same error an older server would
+// give them, and ServerInfo replies are capped so they don't advertise past it. Anything at or
+// below it gets spoken as asked, so the spec version a client connected with is the one in use.
+//
+// The engine also has no way of kicking a client, so disconnect() only works for clients that came
+// through the proxy, where we can just close the connection on them. The engine sees that as a
+// normal client disconnect, and devices stay connected.
+//
+// With multiple_clients on, the engine only ever sees the one connection client_sessions shares
+// between everyone, so clients are tracked from there instead, as their handshakes are answered.
+
+// Buttplug error code for handshake failures.
+const ERROR_INIT: u32 = 1;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectedClient {
+  // Unique per client session, for disconnect_client.
+  pub id: u32,
+  pub name: String,
+  // Spec version the client and server are speaking.
+  pub spec_version: Option<u32>,
+  // ip:port the client connected from.
+  pub address: Option<String>,
+  // Milliseconds since the unix epoch.
+  pub connected_at_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientLatency {
+  pub client_id: u32,
+  // Latest websocket ping round trip, None until the client has answered one.
+  pub round_trip_ms: Option<u32>,
+  // None if the client hasn't sent a message since connecting.
+  pub since_last_message_ms: Option<u64>,
+}
+
+#[derive(Default)]
+struct ConnectionActivity {
+  round_trip: Option<Duration>,
+  last_message: Option<Instant>,
+}
+
+// What we saw in a RequestServerInfo on its way through the proxy.
+#[derive(Debug, Clone)]
+struct HandshakeInfo {
+  name: String,
+  spec_version: u32,
+  address: SocketAddr,
+  connection_id: u32,
+}
+
+// Client, plus the id of the proxied connection it came in on, if it did.
+type ClientSession = (ConnectedClient, Option<u32>);
+
+static NEXT_CLIENT_ID: AtomicU32 = AtomicU32::new(1);
+static NEXT_CONNECTION_ID: AtomicU32 = AtomicU32::new(1);
+
+lazy_static! {
+  static ref CONNECTED_CLIENTS: Mutex<Vec<ClientSession>> = Mutex::new(vec![]);
+  static ref PENDING_HANDSHAKES: Mutex<Vec<HandshakeInfo>> = Mutex::new(vec![]);
+  // Proxied connection id -> notifier that closes it.
+  static ref PROXIED_CONNECTIONS: Mutex<HashMap<u32, Arc<Notify>>> = Mutex::new(HashMap::new());
+  static ref CONNECTION_ACTIVITY: Mutex<HashMap<u32, ConnectionActivity>> =
+    Mutex::new(HashMap::new());
+}
+
+pub fn connected_clients() -> Vec<ConnectedClient> {
+  // Buttplug servers only take one client at a time, unless multiple_clients is on.
+  CONNECTED_CLIENTS
+    .lock()
+    .unwrap()
+    .iter()
+    .map(|(client, _)| client.clone())
+    .collect()
+}
+
+pub fn register_connection() -> (u32, Arc<Notify>) {
+  let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+  let close = Arc::new(Notify::new());
+  PROXIED_CONNECTIONS
+    .lock()
+    .unwrap()
+    .insert(id, close.clone());
+  (id, close)
+}
+
+pub fn unregister_connection(id: u32) {
+  PROXIED_CONNECTIONS.lock().unwrap().remove(&id);
+  CONNECTION_ACTIVITY.lock().unwrap().remove(&id);
+}
+
+pub fn record_message(connection_id: u32) {
+  CONNECTION_ACTIVITY
+    .lock()
+    .unwrap()
+    .entry(connection_id)
+    .or_default()
+    .last_message = Some(Instant::now());
+}
+
+pub fn record_round_trip(connection_id: u32, round_trip: Duration) {
+  CONNECTION_ACTIVITY
+    .lock()
+    .unwrap()
+    .entry(connection_id)
+    .or_default()
+    .round_trip = Some(round_trip);
+}
+
+// Only has anything for clients that came through the proxy.
+pub fn latency() -> Vec<ClientLatency> {
+  let clients = CONNECTED_CLIENTS.lock().unwrap();
+  let activity = CONNECTION_ACTIVITY.lock().unwrap();
+  clients
+    .iter()
+    .filter_map(|(client, connection_id)| {
+      let activity = activity.get(connection_id.as_ref()?)?;
+      Some(ClientLatency {
+        client_id: client.id,
+        round_trip_ms: activity.round_trip.map(|x| x.as_millis() as u32),
+        since_last_message_ms: activity
+          .last_message
+          .map(|x| x.elapsed().as_millis() as u64),
+      })
+    })
+    .collect()
+}
+
+pub fn disconnect(client_id: u32) -> Result<()> {
+  let connection_id = match CONNECTED_CLIENTS
+    .lock()
+    .unwrap()
+    .iter()
+    .find(|(client, _)| client.id == client_id)
+  {
+    Some((_, connection_id)) => *connection_id,
+    None => return Err(anyhow::Error::msg("No client connected with that id")),
+  };
+  let close = connection_id
+    .and_then(|x| PROXIED_CONNECTIONS.lock().unwrap().get(&x).cloned())
+    .ok_or(anyhow::Error::msg(
+      "Client didn't connect through the bridge's websocket proxy, so it can't be disconnected",
+    ))?;
+  info!("Disconnecting client {}", client_id);
+  // notify_one, so it sticks even if the connection task is between polls.
+  close.notify_one();
+  Ok(())
+}
+
+fn add_client(name: String, handshake: Option<HandshakeInfo>) {
+  let connection_id = handshake.as_ref().map(|x| x.connection_id);
+  let client = ConnectedClient {
+    id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+    name,
+    spec_version: handshake.as_ref().map(|x| x.spec_version),
+    address: handshake.map(|x| x.address.to_string()),
+    connected_at_ms: SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|x| x.as_millis() as u64)
+      .unwrap_or_default(),
+  };
+  CONNECTED_CLIENTS
+    .lock()
+    .unwrap()
+    .push((client.clone(), connection_id));
+  events::emit(BridgeEvent::ClientConnected(client));
+}
+
+pub fn update_from_engine_message(msg: &EngineMessage) {
+  // That's the shared connection coming and going, client_sessions has the actual clients.
+  if options::bridge_options().client_sessions.multiple_clients {
+    return;
+  }
+  match msg {
+    EngineMessage::ClientConnected { client_name } => {
+      let handshake = {
+        let mut pending = PENDING_HANDSHAKES.lock().unwrap();
+        // Latest matching one, in case a client has had a few goes at connecting.
+        let position = pending.iter().rposition(|x| x.name == *client_name);
+        let handshake = position.map(|x| pending.remove(x));
+        pending.clear();
+        handshake
+      };
+      add_client(client_name.clone(), handshake);
+    }
+    EngineMessage::ClientDisconnected {} => {
+      let clients: Vec<ClientSession> = CONNECTED_CLIENTS.lock().unwrap().drain(..).collect();
+      for (client, _) in clients {
+        events::emit(BridgeEvent::ClientDisconnected(client));
+      }
+    }
+    _ => {}
+  }
+}
+
+// Proxied connection's handshake was answered by client_sessions.
+pub fn session_connected(connection_id: u32) {
+  let handshake = {
+    let mut pending = PENDING_HANDSHAKES.lock().unwrap();
+    let position = pending
+      .iter()
+      .rposition(|x| x.connection_id == connection_id);
+    let handshake = position.map(|x| pending.remove(x));
+    pending.retain(|x| x.connection_id != connection_id);
+    handshake
+  };
+  if let Some(handshake) = handshake {
+    add_client(handshake.name.clone(), Some(handshake));
+  }
+}
+
+pub fn session_disconnected(connection_id: u32) {
+  PENDING_HANDSHAKES
+    .lock()
+    .unwrap()
+    .retain(|x| x.connection_id != connection_id);
+  let client = {
+    let mut clients = CONNECTED_CLIENTS.lock().unwrap();
+    let position = clients.iter().position(|(_, x)| *x == Some(connection_id));
+    position.map(|x| clients.remove(x))
+  };
+  if let Some((client, _)) = client {
+    events::emit(BridgeEvent::ClientDisconnected(client));
+  }
+}
+
+// Client id for a proxied connection, if its client has finished connecting.
+pub fn client_id(connection_id: u32) -> Option<u32> {
+  CONNECTED_CLIENTS
+    .lock()
+    .unwrap()
+    .iter()
+    .find(|(_, x)| *x == Some(connection_id))
+    .map(|(client, _)| client.id)
+}
+
+pub fn clear() {
+  CONNECTED_CLIENTS.lock().unwrap().clear();
+  PENDING_HANDSHAKES.lock().unwrap().clear();
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RequestServerInfo {
+  client_name: String,
+  message_version: u32,
+}
+
+// Client name and spec version, if this batch of messages from a client has its RequestServerInfo.
+pub fn parse_handshake(messages: &[Value]) -> Option<(String, u32)> {
+  let info: RequestServerInfo =
+    serde_json::from_value(messages.first()?.get("RequestServerInfo")?.clone()).ok()?;
+  Some((info.client_name, info.message_version))
+}
+
+pub fn record_handshake(name: String, spec_version: u32, address: SocketAddr, connection_id: u32) {
+  PENDING_HANDSHAKES.lock().unwrap().push(HandshakeInfo {
+    name,
+    spec_version,
+    address,
+    connection_id,
+  });
+}
+
+// Error reply for a handshake asking for a newer spec version than max_spec_version allows, if it
+// is one.
+pub fn spec_version_rejection(messages: &[Value], spec_version: u32) -> Option<Value> {
+  let max_spec_version = options::bridge_options().max_spec_version?;
+  if spec_version <= max_spec_version {
+    return None;
+  }
+  let id = messages
+    .first()?
+    .get("RequestServerInfo")?
+    .get("Id")
+    .cloned()
+    .unwrap_or(json!(1));
+  Some(json!({
+    "Error": {
+      "Id": id,
+      "ErrorMessage": format!(
+        "Client spec version {} is newer than server spec version {}",
+        spec_version, max_spec_version
+      ),
+      "ErrorCode": ERROR_INIT,
+    }
+  }))
+}
+
+// Caps the spec version in ServerInfo replies to max_spec_version.
+pub fn cap_server_info(messages: &mut [Value]) {
+  let Some(max_spec_version) = options::bridge_options().max_spec_version else {
+    return;
+  };
+  for message in messages {
+    let Some(version) = message
+      .get_mut("ServerInfo")
+      .and_then(|x| x.get_mut("MessageVersion"))
+    else {
+      continue;
+    };
+    if version
+      .as_u64()
+      .is_some_and(|x| x > max_spec_version as u64)
+    {
+      *version = json!(max_spec_version);
+    }
+  }
+}
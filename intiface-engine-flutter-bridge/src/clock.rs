@@ -0,0 +1,111 @@
+use lazy_static::lazy_static;
+use std::{
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+// Time source for everything the bridge schedules itself (ramps, timers, backoff, playback). Normally
+// this is just the system clock, but it can be switched into manual mode, where time only moves
+// when advance() is called. That lets tests step through timing-sensitive code deterministically
+// instead of sleeping and hoping.
+//
+// Anything that waits on bridge time should use now()/sleep_until()/Ticker from here rather than
+// going to std or tokio directly, otherwise it'll ignore manual mode.
+
+struct ManualTime {
+  start: Instant,
+  elapsed: Duration,
+}
+
+lazy_static! {
+  static ref MANUAL_TIME: Mutex<Option<ManualTime>> = Mutex::new(None);
+  static ref CLOCK_CHANGED: Notify = Notify::new();
+}
+
+pub fn now() -> Instant {
+  match MANUAL_TIME.lock().unwrap().as_ref() {
+    Some(manual) => manual.start + manual.elapsed,
+    None => Instant::now(),
+  }
+}
+
+// Switches manual mode on or off. Manual time starts at the current real time, so values from
+// now() stay monotonic going in, though they'll jump forward again coming back out.
+pub fn set_manual(enabled: bool) {
+  {
+    let mut manual_time = MANUAL_TIME.lock().unwrap();
+    if enabled == manual_time.is_some() {
+      return;
+    }
+    *manual_time = enabled.then(|| ManualTime {
+      start: Instant::now(),
+      elapsed: Duration::ZERO,
+    });
+  }
+  // Anything sleeping needs to recheck which clock it should be waiting on.
+  CLOCK_CHANGED.notify_waiters();
+}
+
+// Moves manual time forward, waking anything whose deadline has now passed. Does nothing if the
+// clock isn't in manual mode.
+pub fn advance(duration: Duration) {
+  {
+    let mut manual_time = MANUAL_TIME.lock().unwrap();
+    let Some(manual) = manual_time.as_mut() else {
+      warn!("Tried to advance clock while not in manual mode, ignoring");
+      return;
+    };
+    manual.elapsed += duration;
+  }
+  CLOCK_CHANGED.notify_waiters();
+}
+
+fn is_manual() -> bool {
+  MANUAL_TIME.lock().unwrap().is_some()
+}
+
+pub async fn sleep_until(deadline: Instant) {
+  loop {
+    // Register for notifications before checking the time, so we can't miss an advance that
+    // happens in between.
+    let changed = CLOCK_CHANGED.notified();
+    if now() >= deadline {
+      return;
+    }
+    if is_manual() {
+      changed.await;
+    } else {
+      tokio::select! {
+        _ = tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)) => return,
+        _ = changed => {}
+      }
+    }
+  }
+}
+
+// Fixed rate ticker. If ticks are missed (because whatever's handling them took too long, or manual
+// time jumped a long way), they're skipped rather than fired in a burst to catch up.
+pub struct Ticker {
+  period: Duration,
+  next: Instant,
+}
+
+impl Ticker {
+  pub fn new(period: Duration) -> Self {
+    Self {
+      period,
+      next: now() + period,
+    }
+  }
+
+  // Cancel safe, so it can be used as a select! branch.
+  pub async fn tick(&mut self) {
+    sleep_until(self.next).await;
+    let now = now();
+    self.next += self.period;
+    if self.next <= now {
+      self.next = now + self.period;
+    }
+  }
+}
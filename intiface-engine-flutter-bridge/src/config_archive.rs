@@ -0,0 +1,209 @@
+use anyhow::Result;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+  aead::{rand_core::RngCore, Aead, OsRng, Payload},
+  AeadCore, ChaCha20Poly1305, Key, KeyInit, Nonce,
+};
+use serde::{Deserialize, Serialize};
+
+// Passphrase protected archives of everything the user has set up for their devices, for moving
+// setups between machines. Configs are full of device addresses, which can be used to pick someone
+// out (or at least their toys) from Bluetooth scans, so they shouldn't be sitting around in plain
+// text in someone's cloud drive or chat history.
+//
+// Layout is magic, format version, salt, nonce, then the encrypted contents, all base64'd. The
+// header goes in as associated data, so it can't be changed without failing decryption. Key
+// derivation parameters are fixed per format version, so changing them means a new version.
+
+const ARCHIVE_MAGIC: &[u8; 4] = b"ICUA";
+const ARCHIVE_VERSION: u8 = 1;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const HEADER_LENGTH: usize = ARCHIVE_MAGIC.len() + 1 + SALT_LENGTH + NONCE_LENGTH;
+
+// Argon2id, with the OWASP recommended minimums. Still runs in well under a second on phones.
+const KDF_MEMORY_KIB: u32 = 19 * 1024;
+const KDF_ITERATIONS: u32 = 2;
+const KDF_PARALLELISM: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserConfigArchive {
+  #[serde(rename = "user-config")]
+  pub user_config: String,
+  #[serde(rename = "device-settings")]
+  pub device_settings: String,
+  // Empty for archives made before client permissions existed.
+  #[serde(rename = "client-permissions", default)]
+  pub client_permissions: String,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key> {
+  let params = Params::new(KDF_MEMORY_KIB, KDF_ITERATIONS, KDF_PARALLELISM, Some(32))
+    .map_err(|e| anyhow::Error::msg(format!("Invalid key derivation parameters: {}", e)))?;
+  let mut key = Key::default();
+  Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+    .map_err(|e| anyhow::Error::msg(format!("Cannot derive key from passphrase: {}", e)))?;
+  Ok(key)
+}
+
+pub fn export(archive: &UserConfigArchive, passphrase: &str) -> Result<String> {
+  if passphrase.is_empty() {
+    return Err(anyhow::Error::msg("Config exports need a passphrase"));
+  }
+  let mut salt = [0u8; SALT_LENGTH];
+  OsRng.fill_bytes(&mut salt);
+  let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+  let mut header = Vec::with_capacity(HEADER_LENGTH);
+  header.extend_from_slice(ARCHIVE_MAGIC);
+  header.push(ARCHIVE_VERSION);
+  header.extend_from_slice(&salt);
+  header.extend_from_slice(&nonce);
+
+  let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt)?);
+  let ciphertext = cipher
+    .encrypt(
+      &nonce,
+      Payload {
+        msg: &serde_json::to_vec(archive)?,
+        aad: &header,
+      },
+    )
+    .map_err(|_| anyhow::Error::msg("Cannot encrypt config export"))?;
+  header.extend_from_slice(&ciphertext);
+  Ok(STANDARD.encode(header))
+}
+
+pub fn import(blob: &str, passphrase: &str) -> Result<UserConfigArchive> {
+  let data = STANDARD
+    .decode(blob.trim())
+    .map_err(|_| anyhow::Error::msg("Not a config export"))?;
+  if data.len() < HEADER_LENGTH || !data.starts_with(ARCHIVE_MAGIC) {
+    return Err(anyhow::Error::msg("Not a config export"));
+  }
+  let version = data[ARCHIVE_MAGIC.len()];
+  if version != ARCHIVE_VERSION {
+    return Err(anyhow::Error::msg(format!(
+      "Config export format version {} is not supported by this version of Intiface",
+      version
+    )));
+  }
+  let (header, ciphertext) = data.split_at(HEADER_LENGTH);
+  let salt_start = ARCHIVE_MAGIC.len() + 1;
+  let salt = &header[salt_start..salt_start + SALT_LENGTH];
+  let nonce = Nonce::from_slice(&header[salt_start + SALT_LENGTH..]);
+
+  let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, salt)?);
+  // Wrong passphrases and damaged data look the same from here.
+  let plaintext = cipher
+    .decrypt(
+      nonce,
+      Payload {
+        msg: ciphertext,
+        aad: header,
+      },
+    )
+    .map_err(|_| anyhow::Error::msg("Wrong passphrase, or the export is damaged"))?;
+  Ok(serde_json::from_slice(&plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn archive() -> UserConfigArchive {
+    UserConfigArchive {
+      user_config: "{\"version\":{\"major\":3,\"minor\":0}}".to_owned(),
+      device_settings: "{\"version\":1,\"devices\":[]}".to_owned(),
+      client_permissions: "{}".to_owned(),
+    }
+  }
+
+  fn reencode(blob: &str, change: impl FnOnce(&mut Vec<u8>)) -> String {
+    let mut data = STANDARD.decode(blob).unwrap();
+    change(&mut data);
+    STANDARD.encode(data)
+  }
+
+  #[test]
+  fn round_trip() {
+    let blob = export(&archive(), "correct horse").unwrap();
+    // Whitespace from copy and paste is fine.
+    let imported = import(&format!("  {}\n", blob), "correct horse").unwrap();
+    assert_eq!(imported.user_config, archive().user_config);
+    assert_eq!(imported.device_settings, archive().device_settings);
+    assert_eq!(imported.client_permissions, archive().client_permissions);
+  }
+
+  #[test]
+  fn exports_are_salted() {
+    assert_ne!(
+      export(&archive(), "correct horse").unwrap(),
+      export(&archive(), "correct horse").unwrap()
+    );
+  }
+
+  #[test]
+  fn needs_a_passphrase() {
+    assert!(export(&archive(), "").is_err());
+  }
+
+  #[test]
+  fn wrong_passphrase() {
+    let blob = export(&archive(), "correct horse").unwrap();
+    let error = import(&blob, "battery staple").unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "Wrong passphrase, or the export is damaged"
+    );
+  }
+
+  #[test]
+  fn damaged_exports() {
+    let blob = export(&archive(), "correct horse").unwrap();
+    // Contents, and the header, which goes in as associated data.
+    let damaged_contents = reencode(&blob, |data| *data.last_mut().unwrap() ^= 1);
+    assert!(import(&damaged_contents, "correct horse").is_err());
+    let damaged_salt = reencode(&blob, |data| data[ARCHIVE_MAGIC.len() + 1] ^= 1);
+    assert!(import(&damaged_salt, "correct horse").is_err());
+    let truncated = reencode(&blob, |data| data.truncate(HEADER_LENGTH - 1));
+    assert_eq!(
+      import(&truncated, "correct horse").unwrap_err().to_string(),
+      "Not a config export"
+    );
+  }
+
+  #[test]
+  fn not_an_export() {
+    for blob in [
+      "",
+      "not base64!",
+      &STANDARD.encode([0u8; HEADER_LENGTH + 16]),
+    ] {
+      assert_eq!(
+        import(blob, "correct horse").unwrap_err().to_string(),
+        "Not a config export"
+      );
+    }
+  }
+
+  #[test]
+  fn newer_versions_are_refused() {
+    let blob = export(&archive(), "correct horse").unwrap();
+    let newer = reencode(&blob, |data| {
+      data[ARCHIVE_MAGIC.len()] = ARCHIVE_VERSION + 1
+    });
+    assert!(import(&newer, "correct horse")
+      .unwrap_err()
+      .to_string()
+      .contains("version 2 is not supported"));
+  }
+
+  #[test]
+  fn archives_from_before_client_permissions() {
+    let archive: UserConfigArchive =
+      serde_json::from_str(r#"{"user-config": "a", "device-settings": "b"}"#).unwrap();
+    assert_eq!(archive.client_permissions, "");
+  }
+}
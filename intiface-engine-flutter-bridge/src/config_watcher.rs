@@ -0,0 +1,228 @@
+use crate::{
+  api::{
+    self, apply_user_device_specifiers, user_device_specifiers, ExposedUserDeviceDefinition,
+    ExposedUserDeviceIdentifier, DEVICE_CONFIG_MANAGER,
+  },
+  device_list,
+  events::{self, BridgeEvent},
+  user_config_migration,
+};
+use anyhow::Result;
+use buttplug::{
+  server::device::configuration::{DeviceConfigurationManager, UserDeviceIdentifier},
+  util::device_configuration::load_protocol_configs,
+};
+use lazy_static::lazy_static;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::{
+  collections::{hash_map::DefaultHasher, HashMap, HashSet},
+  fs,
+  hash::{Hash, Hasher},
+  path::{Path, PathBuf},
+  sync::{mpsc, Arc, Mutex},
+  thread,
+  time::Duration,
+};
+
+// Watches the device and user config files for changes from outside the app, like sync services or
+// someone with a text editor, so they get picked up without a restart.
+//
+// Directories are watched rather than the files, as most editors and sync tools (and our own
+// write_atomically) replace the file instead of writing to it. Writes made by the bridge itself are
+// skipped by checking contents against what we last wrote.
+//
+// With reloading on, a changed user config is merged into the loaded config, which the running
+// engine shares, so new specifiers and device settings apply right away. A changed device config
+// replaces the loaded one when the engine isn't running. The engine holds on to the base config it
+// started with, so while it's running, device config changes wait for the next start.
+
+// Editors and sync tools tend to write in a few steps, so wait for things to settle.
+const DEBOUNCE_TIME: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ConfigFileKind {
+  DeviceConfig,
+  UserConfig,
+}
+
+lazy_static! {
+  static ref CONFIG_WATCHER: Mutex<Option<RecommendedWatcher>> = Mutex::new(None);
+  // Hashes of the contents we last saw or wrote, by path.
+  static ref KNOWN_CONTENTS: Mutex<HashMap<PathBuf, u64>> = Mutex::new(HashMap::new());
+}
+
+fn content_hash(contents: &[u8]) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  contents.hash(&mut hasher);
+  hasher.finish()
+}
+
+// Called on every bridge write to a config file, so the watcher doesn't report our own changes.
+pub fn note_written(path: &Path, contents: &[u8]) {
+  KNOWN_CONTENTS
+    .lock()
+    .unwrap()
+    .insert(path.to_path_buf(), content_hash(contents));
+}
+
+// Replaces the user specifiers and device definitions in the loaded config with the ones from the
+// file.
+fn reload_user_config(contents: &str) -> Result<()> {
+  let contents = user_config_migration::migrate_user_config(contents)?;
+  let file_dcm = load_protocol_configs(&None, &Some(contents), false)
+    .and_then(|mut builder| builder.finish())
+    .map_err(|e| anyhow::Error::msg(format!("Cannot load user config: {:?}", e)))?;
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+
+  // Clear everything out first, so names moving between protocols don't look like duplicates.
+  for (protocol, _) in user_device_specifiers(&dcm) {
+    apply_user_device_specifiers(&dcm, &protocol, Default::default())?;
+  }
+  for (protocol, specifiers) in user_device_specifiers(&file_dcm) {
+    apply_user_device_specifiers(&dcm, &protocol, specifiers)?;
+  }
+
+  let file_devices: HashSet<UserDeviceIdentifier> = file_dcm
+    .user_device_definitions()
+    .iter()
+    .map(|kv| kv.key().clone())
+    .collect();
+  let removed: Vec<UserDeviceIdentifier> = dcm
+    .user_device_definitions()
+    .iter()
+    .map(|kv| kv.key().clone())
+    .filter(|x| !file_devices.contains(x))
+    .collect();
+  for identifier in removed {
+    dcm.remove_user_device_definition(&identifier);
+  }
+  for kv in file_dcm.user_device_definitions().iter() {
+    let _ = dcm.add_user_device_definition(kv.key(), kv.value());
+    let identifier: ExposedUserDeviceIdentifier = kv.key().clone().into();
+    let definition: ExposedUserDeviceDefinition = kv.value().clone().into();
+    device_list::update_display_name(&identifier, definition.user_config.display_name);
+  }
+  Ok(())
+}
+
+fn reload_device_config(contents: &str) -> Result<bool> {
+  if api::runtime_started() {
+    info!("Engine is running, device config changes will be used on next start");
+    return Ok(false);
+  }
+  // Keep the user config that's loaded now, rather than whatever's on disk.
+  let user_config = api::get_user_config_str();
+  let dcm: DeviceConfigurationManager =
+    load_protocol_configs(&Some(contents.to_owned()), &Some(user_config), false)
+      .and_then(|mut builder| builder.finish())
+      .map_err(|e| anyhow::Error::msg(format!("Cannot load device config: {:?}", e)))?;
+  let Ok(mut current) = DEVICE_CONFIG_MANAGER.try_write() else {
+    return Err(anyhow::Error::msg("Device config is in use, cannot reload"));
+  };
+  *current = Arc::new(dcm);
+  Ok(true)
+}
+
+fn handle_change(kind: ConfigFileKind, path: &Path, reload: bool) {
+  // Gone or mid-replace. If it's coming back, we'll hear about it.
+  let Ok(contents) = fs::read_to_string(path) else {
+    return;
+  };
+  let hash = content_hash(contents.as_bytes());
+  if KNOWN_CONTENTS
+    .lock()
+    .unwrap()
+    .insert(path.to_path_buf(), hash)
+    == Some(hash)
+  {
+    return;
+  }
+  info!("{:?} changed on disk: {:?}", kind, path);
+  let reloaded = reload
+    && match kind {
+      ConfigFileKind::UserConfig => reload_user_config(&contents).map(|_| true),
+      ConfigFileKind::DeviceConfig => reload_device_config(&contents),
+    }
+    .unwrap_or_else(|e| {
+      warn!("Cannot reload {:?}: {:?}", kind, e);
+      false
+    });
+  events::emit(BridgeEvent::ConfigChanged {
+    kind,
+    path: path.to_string_lossy().into_owned(),
+    reloaded,
+  });
+}
+
+pub fn watch(
+  device_config_path: Option<String>,
+  user_config_path: Option<String>,
+  reload: bool,
+) -> Result<()> {
+  let files: Vec<(ConfigFileKind, PathBuf)> = [
+    (ConfigFileKind::DeviceConfig, device_config_path),
+    (ConfigFileKind::UserConfig, user_config_path),
+  ]
+  .into_iter()
+  .filter_map(|(kind, path)| Some((kind, PathBuf::from(path?))))
+  .collect();
+  if files.is_empty() {
+    return Err(anyhow::Error::msg("No config files to watch"));
+  }
+  for (_, path) in &files {
+    if let Ok(contents) = fs::read(path) {
+      note_written(path, &contents);
+    }
+  }
+
+  let (sender, receiver) = mpsc::channel::<PathBuf>();
+  let mut watcher =
+    notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+      Ok(event) => {
+        for path in event.paths {
+          let _ = sender.send(path);
+        }
+      }
+      Err(e) => warn!("Config watcher error: {:?}", e),
+    })?;
+  let directories: HashSet<PathBuf> = files
+    .iter()
+    .map(|(_, path)| match path.parent() {
+      Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+      _ => PathBuf::from("."),
+    })
+    .collect();
+  for directory in directories {
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+  }
+
+  // Runs until the watcher, and with it the sender, is dropped.
+  thread::spawn(move || {
+    while let Ok(path) = receiver.recv() {
+      let mut changed = HashSet::from([path]);
+      while let Ok(path) = receiver.recv_timeout(DEBOUNCE_TIME) {
+        changed.insert(path);
+      }
+      for (kind, path) in &files {
+        if changed
+          .iter()
+          .any(|x| x.file_name().is_some() && x.file_name() == path.file_name())
+        {
+          handle_change(*kind, path, reload);
+        }
+      }
+    }
+  });
+  info!("Watching config files");
+  *CONFIG_WATCHER.lock().unwrap() = Some(watcher);
+  Ok(())
+}
+
+pub fn stop() {
+  if CONFIG_WATCHER.lock().unwrap().take().is_some() {
+    info!("Stopped watching config files");
+  }
+}
@@ -0,0 +1,376 @@
+use crate::{
+  network_interfaces, options, server_ready, websocket_proxy::ListeningServer, websocket_tls,
+};
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+  rand::SystemRandom,
+  signature::{
+    EcdsaKeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_FIXED,
+    ECDSA_P256_SHA256_FIXED_SIGNING,
+  },
+};
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashSet,
+  net::{IpAddr, SocketAddr},
+};
+
+// Everything a client needs to connect to the running server, packed small enough for a QR code,
+// so pairing a phone with a desktop is one scan instead of typing addresses.
+//
+// With the secure websocket server running on an ECDSA P-256 certificate (which the generated ones
+// are), payloads are signed with the certificate's key, and carry the public key. A client that
+// then connects over wss can check the server's certificate has that key, so it knows it reached
+// the server that made the payload and not something the payload was edited to point at. Without
+// that there's nothing to sign with, and payloads just get a short checksum to catch bad scans.
+
+const PAYLOAD_PREFIX: &str = "ICP1-";
+const UNSIGNED: u8 = 0;
+const SIGNED: u8 = 1;
+const CHECKSUM_LENGTH: usize = 4;
+// Uncompressed P-256 point.
+const PUBLIC_KEY_LENGTH: usize = 65;
+const SIGNATURE_LENGTH: usize = 64;
+
+// Keys are kept short, as every byte makes the QR code denser.
+#[derive(Serialize, Deserialize)]
+struct PayloadFields {
+  #[serde(rename = "a")]
+  addresses: Vec<String>,
+  #[serde(rename = "p")]
+  port: u16,
+  #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+  tls_port: Option<u16>,
+  // Hex, without the colons.
+  #[serde(rename = "f", default, skip_serializing_if = "Option::is_none")]
+  tls_fingerprint: Option<String>,
+  #[serde(rename = "t", default, skip_serializing_if = "Option::is_none")]
+  auth_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionPayload {
+  // IPs the server can be reached on, without ports.
+  pub addresses: Vec<String>,
+  pub port: u16,
+  pub tls_port: Option<u16>,
+  // Same colon separated format as get_websocket_tls_fingerprint.
+  pub tls_fingerprint: Option<String>,
+  pub auth_token: Option<String>,
+  // Hex encoded key the payload was signed with, if it was. The signature has been checked against
+  // it.
+  pub signing_key: Option<String>,
+}
+
+fn server_addresses(server: ListeningServer) -> Vec<SocketAddr> {
+  server_ready::addresses()
+    .unwrap_or_default()
+    .into_iter()
+    .filter(|x| x.server == server)
+    .flat_map(|x| x.addresses)
+    .filter_map(|x| x.parse().ok())
+    .collect()
+}
+
+// Addresses someone else could reach a listening address on. Listening everywhere means every
+// interface we have.
+fn reachable_ips(listening: &[SocketAddr]) -> Result<Vec<String>> {
+  let mut ips = vec![];
+  for address in listening {
+    match address.ip() {
+      ip if ip.is_loopback() => {}
+      ip if ip.is_unspecified() => {
+        for interface in network_interfaces::list_network_interfaces()? {
+          if !interface.loopback && (ip.is_ipv6() || !interface.ipv6) {
+            ips.push(interface.address);
+          }
+        }
+      }
+      ip => ips.push(ip.to_string()),
+    }
+  }
+  let mut seen = HashSet::new();
+  ips.retain(|x| seen.insert(x.clone()));
+  Ok(ips)
+}
+
+fn compact_fingerprint(fingerprint: &str) -> String {
+  fingerprint.replace(':', "").to_ascii_lowercase()
+}
+
+fn expand_fingerprint(fingerprint: &str) -> Result<String> {
+  let bytes =
+    hex::decode(fingerprint).map_err(|_| anyhow::Error::msg("Invalid TLS fingerprint"))?;
+  Ok(
+    bytes
+      .iter()
+      .map(|x| format!("{:02X}", x))
+      .collect::<Vec<String>>()
+      .join(":"),
+  )
+}
+
+fn signing_key() -> Option<EcdsaKeyPair> {
+  let pem = websocket_tls::active_private_key_pem(ListeningServer::SecureWebsocket)?;
+  let Ok(Some(Item::Pkcs8Key(key))) = rustls_pemfile::read_one(&mut pem.as_bytes()) else {
+    debug!("TLS key isn't PKCS#8, not signing connection payload");
+    return None;
+  };
+  EcdsaKeyPair::from_pkcs8(
+    &ECDSA_P256_SHA256_FIXED_SIGNING,
+    key.secret_pkcs8_der(),
+    &SystemRandom::new(),
+  )
+  .map_err(|_| debug!("TLS key isn't ECDSA P-256, not signing connection payload"))
+  .ok()
+}
+
+pub fn generate() -> Result<String> {
+  let websocket = server_addresses(ListeningServer::Websocket);
+  let port = websocket
+    .first()
+    .map(|x| x.port())
+    .ok_or(anyhow::Error::msg(
+      "No websocket server is running to connect to",
+    ))?;
+  let addresses = reachable_ips(&websocket)?;
+  if addresses.is_empty() {
+    return Err(anyhow::Error::msg(
+      "Websocket server only listens on localhost, nothing else can connect to it",
+    ));
+  }
+  let tls_port = server_addresses(ListeningServer::SecureWebsocket)
+    .first()
+    .map(|x| x.port());
+  let fields = PayloadFields {
+    addresses,
+    port,
+    tls_port,
+    tls_fingerprint: websocket_tls::active_fingerprint(ListeningServer::SecureWebsocket)
+      .map(|x| compact_fingerprint(&x)),
+    auth_token: options::bridge_options()
+      .websocket_auth_token
+      .filter(|x| !x.is_empty()),
+  };
+  encode(&fields, signing_key().as_ref())
+}
+
+fn encode(fields: &PayloadFields, key: Option<&EcdsaKeyPair>) -> Result<String> {
+  let mut payload = serde_json::to_vec(fields)?;
+  let mut body = vec![];
+  match key {
+    Some(key) => {
+      let signature = key
+        .sign(&SystemRandom::new(), &payload)
+        .map_err(|_| anyhow::Error::msg("Cannot sign connection payload"))?;
+      body.push(SIGNED);
+      body.append(&mut payload);
+      body.extend_from_slice(key.public_key().as_ref());
+      body.extend_from_slice(signature.as_ref());
+    }
+    None => {
+      let checksum = Sha256::digest(&payload);
+      body.push(UNSIGNED);
+      body.append(&mut payload);
+      body.extend_from_slice(&checksum[..CHECKSUM_LENGTH]);
+    }
+  }
+  Ok(format!(
+    "{}{}",
+    PAYLOAD_PREFIX,
+    URL_SAFE_NO_PAD.encode(body)
+  ))
+}
+
+fn damaged() -> anyhow::Error {
+  anyhow::Error::msg("Connection payload is damaged or incomplete")
+}
+
+pub fn parse(payload: &str) -> Result<ConnectionPayload> {
+  let encoded = payload
+    .trim()
+    .strip_prefix(PAYLOAD_PREFIX)
+    .ok_or(anyhow::Error::msg("Not an Intiface connection payload"))?;
+  let body = URL_SAFE_NO_PAD.decode(encoded).map_err(|_| damaged())?;
+  let (kind, body) = body.split_first().ok_or_else(damaged)?;
+  let (fields, signing_key) = match *kind {
+    UNSIGNED => {
+      let split = body
+        .len()
+        .checked_sub(CHECKSUM_LENGTH)
+        .ok_or_else(damaged)?;
+      let (fields, checksum) = body.split_at(split);
+      if Sha256::digest(fields)[..CHECKSUM_LENGTH] != *checksum {
+        return Err(damaged());
+      }
+      (fields, None)
+    }
+    SIGNED => {
+      let split = body
+        .len()
+        .checked_sub(PUBLIC_KEY_LENGTH + SIGNATURE_LENGTH)
+        .ok_or_else(damaged)?;
+      let (fields, trailer) = body.split_at(split);
+      let (public_key, signature) = trailer.split_at(PUBLIC_KEY_LENGTH);
+      UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, public_key)
+        .verify(fields, signature)
+        .map_err(|_| anyhow::Error::msg("Connection payload signature doesn't match"))?;
+      (fields, Some(hex::encode(public_key)))
+    }
+    _ => {
+      return Err(anyhow::Error::msg(
+        "Connection payload is from a newer version",
+      ))
+    }
+  };
+  let fields: PayloadFields = serde_json::from_slice(fields).map_err(|_| damaged())?;
+  if fields.addresses.is_empty() || fields.port == 0 {
+    return Err(damaged());
+  }
+  for address in &fields.addresses {
+    address
+      .parse::<IpAddr>()
+      .map_err(|_| anyhow::Error::msg(format!("Invalid address {} in payload", address)))?;
+  }
+  Ok(ConnectionPayload {
+    addresses: fields.addresses,
+    port: fields.port,
+    tls_port: fields.tls_port,
+    tls_fingerprint: fields
+      .tls_fingerprint
+      .as_deref()
+      .map(expand_fingerprint)
+      .transpose()?,
+    auth_token: fields.auth_token,
+    signing_key,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn fields() -> PayloadFields {
+    PayloadFields {
+      addresses: vec!["192.168.1.20".to_owned(), "fe80::1".to_owned()],
+      port: 12345,
+      tls_port: Some(12346),
+      tls_fingerprint: Some("ab01ff".to_owned()),
+      auth_token: Some("secret".to_owned()),
+    }
+  }
+
+  fn key() -> EcdsaKeyPair {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+  }
+
+  // Decodes a payload, changes its body, and encodes it again.
+  fn edit(payload: &str, change: impl FnOnce(&mut Vec<u8>)) -> String {
+    let mut body = URL_SAFE_NO_PAD
+      .decode(payload.strip_prefix(PAYLOAD_PREFIX).unwrap())
+      .unwrap();
+    change(&mut body);
+    format!("{}{}", PAYLOAD_PREFIX, URL_SAFE_NO_PAD.encode(body))
+  }
+
+  #[test]
+  fn unsigned_round_trip() {
+    let payload = encode(&fields(), None).unwrap();
+    let parsed = parse(&format!(" {} ", payload)).unwrap();
+    assert_eq!(parsed.addresses, fields().addresses);
+    assert_eq!(parsed.port, 12345);
+    assert_eq!(parsed.tls_port, Some(12346));
+    assert_eq!(parsed.tls_fingerprint.as_deref(), Some("AB:01:FF"));
+    assert_eq!(parsed.auth_token.as_deref(), Some("secret"));
+    assert!(parsed.signing_key.is_none());
+  }
+
+  #[test]
+  fn signed_round_trip() {
+    let key = key();
+    let payload = encode(&fields(), Some(&key)).unwrap();
+    let parsed = parse(&payload).unwrap();
+    assert_eq!(parsed.addresses, fields().addresses);
+    assert_eq!(
+      parsed.signing_key,
+      Some(hex::encode(key.public_key().as_ref()))
+    );
+  }
+
+  #[test]
+  fn optional_fields_can_be_left_out() {
+    let fields = PayloadFields {
+      addresses: vec!["10.0.0.2".to_owned()],
+      port: 12345,
+      tls_port: None,
+      tls_fingerprint: None,
+      auth_token: None,
+    };
+    let parsed = parse(&encode(&fields, None).unwrap()).unwrap();
+    assert!(parsed.tls_port.is_none());
+    assert!(parsed.tls_fingerprint.is_none());
+    assert!(parsed.auth_token.is_none());
+  }
+
+  #[test]
+  fn edited_payloads_are_caught() {
+    let unsigned = encode(&fields(), None).unwrap();
+    let edited = edit(&unsigned, |body| body[10] ^= 1);
+    assert_eq!(
+      parse(&edited).unwrap_err().to_string(),
+      damaged().to_string()
+    );
+    let signed = encode(&fields(), Some(&key())).unwrap();
+    let edited = edit(&signed, |body| body[10] ^= 1);
+    assert_eq!(
+      parse(&edited).unwrap_err().to_string(),
+      "Connection payload signature doesn't match"
+    );
+  }
+
+  #[test]
+  fn truncated_payloads_are_caught() {
+    let signed = encode(&fields(), Some(&key())).unwrap();
+    for length in [0, 1, 20] {
+      let truncated = edit(&signed, |body| body.truncate(length));
+      assert!(parse(&truncated).is_err());
+    }
+  }
+
+  #[test]
+  fn not_a_payload() {
+    assert_eq!(
+      parse("ws://192.168.1.20:12345").unwrap_err().to_string(),
+      "Not an Intiface connection payload"
+    );
+    let newer = edit(&encode(&fields(), None).unwrap(), |body| body[0] = 2);
+    assert_eq!(
+      parse(&newer).unwrap_err().to_string(),
+      "Connection payload is from a newer version"
+    );
+  }
+
+  #[test]
+  fn bad_fields_are_refused() {
+    let mut bad_address = fields();
+    bad_address.addresses.push("example.com".to_owned());
+    assert!(parse(&encode(&bad_address, None).unwrap()).is_err());
+    let mut no_addresses = fields();
+    no_addresses.addresses.clear();
+    assert!(parse(&encode(&no_addresses, None).unwrap()).is_err());
+    let mut bad_fingerprint = fields();
+    bad_fingerprint.tls_fingerprint = Some("xyz".to_owned());
+    assert!(parse(&encode(&bad_fingerprint, None).unwrap()).is_err());
+  }
+
+  #[test]
+  fn fingerprints() {
+    assert_eq!(compact_fingerprint("AB:01:FF"), "ab01ff");
+    assert_eq!(expand_fingerprint("ab01ff").unwrap(), "AB:01:FF");
+  }
+}
@@ -0,0 +1,118 @@
+use crate::{
+  config_watcher,
+  events::{self, BridgeEvent},
+};
+use anyhow::Result;
+use buttplug::util::device_configuration::load_protocol_configs;
+use reqwest::{
+  blocking::Client,
+  header::{ETAG, IF_NONE_MATCH},
+  StatusCode,
+};
+use sha2::{Digest, Sha256};
+use std::{
+  fs::{self, File},
+  io::{self, Write},
+  path::Path,
+  time::Duration,
+};
+
+pub const DEFAULT_DEVICE_CONFIG_URL: &str =
+  "https://intiface-engine-device-config.intiface.com/buttplug-device-config-v3.json";
+
+#[derive(Debug, Clone)]
+pub struct DeviceConfigUpdate {
+  // ETag of the new file if the server gave us one, otherwise the SHA-256 of the contents.
+  pub version: String,
+  pub sha256: String,
+}
+
+// Checks for a new version of the device config file, and replaces the local copy if there is one.
+//
+// The file is only replaced if it matches the expected checksum AND the library can actually load
+// it, so a truncated or tampered download or a broken upstream push can't leave us with a config the
+// engine chokes on next start. The checksum is required, without one nothing is downloaded.
+pub fn update_device_config(
+  path: &str,
+  current_version: Option<String>,
+  expected_sha256: String,
+  url: Option<String>,
+) -> Result<Option<DeviceConfigUpdate>> {
+  if expected_sha256.trim().is_empty() {
+    return Err(anyhow::Error::msg(
+      "No checksum given for device config update, not downloading it",
+    ));
+  }
+  let url = url.unwrap_or(DEFAULT_DEVICE_CONFIG_URL.to_owned());
+  info!("Checking for device config update at {}", url);
+  let client = Client::builder().timeout(Duration::from_secs(30)).build()?;
+  let mut request = client.get(&url);
+  // If we don't have a copy of the file locally, skip our version check and always download it.
+  if let Some(version) = &current_version {
+    if Path::new(path).exists() {
+      request = request.header(IF_NONE_MATCH, version);
+    }
+  }
+  let response = request.send()?;
+  if response.status() == StatusCode::NOT_MODIFIED {
+    info!("No new device config version found");
+    return Ok(None);
+  }
+  if !response.status().is_success() {
+    return Err(anyhow::Error::msg(format!(
+      "Device config update request failed with status {}",
+      response.status()
+    )));
+  }
+  let etag = response
+    .headers()
+    .get(ETAG)
+    .and_then(|x| x.to_str().ok())
+    .map(|x| x.to_owned());
+  let contents = response.text()?;
+
+  let sha256 = hex::encode(Sha256::digest(contents.as_bytes()));
+  if !expected_sha256.trim().eq_ignore_ascii_case(&sha256) {
+    return Err(anyhow::Error::msg(format!(
+      "Device config checksum mismatch, expected {} but got {}",
+      expected_sha256, sha256
+    )));
+  }
+  load_protocol_configs(&Some(contents.clone()), &None, false).map_err(|e| {
+    anyhow::Error::msg(format!(
+      "Downloaded device config failed to load, not replacing local copy: {:?}",
+      e
+    ))
+  })?;
+
+  write_atomically(Path::new(path), contents.as_bytes())?;
+  let version = etag.unwrap_or(sha256.clone());
+  info!("Device config updated to version {}", version);
+  events::emit(BridgeEvent::DeviceConfigUpdateAvailable {
+    path: path.to_owned(),
+    version: version.clone(),
+    sha256: sha256.clone(),
+  });
+  Ok(Some(DeviceConfigUpdate { version, sha256 }))
+}
+
+// Write to a temp file next to the destination, sync it, then rename over the original. Rename is
+// atomic on all the platforms we care about, so anything reading the file either gets the old
+// version or the new one, never half of one.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+  let tmp_path = path.with_extension("tmp");
+  {
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+  }
+  config_watcher::note_written(path, contents);
+  fs::rename(&tmp_path, path)?;
+  // The rename itself isn't on disk until the directory is synced. Windows has no way to do that,
+  // but also doesn't need it.
+  #[cfg(unix)]
+  if let Some(parent) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+    File::open(parent)?.sync_all()?;
+  }
+  Ok(())
+}
@@ -0,0 +1,82 @@
+use crate::{
+  api::ExposedDeviceFeature,
+  binary_stream::{self, BinaryEvent},
+  device_list::DeviceListEntry,
+  devices,
+  hardware_info::{self, DeviceHardwareInfo},
+};
+use flutter_rust_bridge::StreamSink;
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+// Device connects and disconnects as typed values on their own stream, for frontend code that just
+// wants to know what's connected without parsing engine messages. Follows the device list, so it
+// sees the same devices the frontend does. Also sent on the binary stream, if that's set up.
+
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+  pub index: u32,
+  pub name: String,
+  pub display_name: Option<String>,
+  pub protocol: String,
+  pub address: String,
+  pub identifier: Option<String>,
+  pub features: Vec<ExposedDeviceFeature>,
+  // Only once it's been asked for with query_device_hardware_info.
+  pub hardware_info: Option<DeviceHardwareInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub enum DeviceEvent {
+  // Also sent again for devices that are already connected if their details change, e.g. when
+  // they're renamed.
+  Added(Box<DeviceInfo>),
+  Removed { index: u32 },
+}
+
+lazy_static! {
+  static ref DEVICE_EVENT_SINK: Mutex<Option<StreamSink<DeviceEvent>>> = Mutex::new(None);
+}
+
+pub fn set_sink(sink: StreamSink<DeviceEvent>) {
+  *DEVICE_EVENT_SINK.lock().unwrap() = Some(sink);
+}
+
+pub fn clear_sink() {
+  if let Some(sink) = DEVICE_EVENT_SINK.lock().unwrap().take() {
+    sink.close();
+  }
+}
+
+fn emit(event: DeviceEvent) {
+  if let Some(sink) = DEVICE_EVENT_SINK.lock().unwrap().as_ref() {
+    sink.add(event);
+  }
+}
+
+pub fn device_added(entry: &DeviceListEntry) {
+  binary_stream::emit(&BinaryEvent::DeviceAdded {
+    index: entry.index,
+    name: entry.name.clone(),
+    display_name: entry.display_name.clone(),
+    protocol: entry.identifier.protocol.clone(),
+    address: entry.identifier.address.clone(),
+    identifier: entry.identifier.identifier.clone(),
+  });
+  let features = devices::features(&entry.identifier);
+  emit(DeviceEvent::Added(Box::new(DeviceInfo {
+    index: entry.index,
+    name: entry.name.clone(),
+    display_name: entry.display_name.clone(),
+    protocol: entry.identifier.protocol.clone(),
+    address: entry.identifier.address.clone(),
+    identifier: entry.identifier.identifier.clone(),
+    features,
+    hardware_info: hardware_info::cached(&entry.identifier),
+  })));
+}
+
+pub fn device_removed(index: u32) {
+  binary_stream::emit(&BinaryEvent::DeviceRemoved { index });
+  emit(DeviceEvent::Removed { index });
+}
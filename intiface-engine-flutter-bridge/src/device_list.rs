@@ -0,0 +1,201 @@
+use crate::{
+  api::ExposedUserDeviceIdentifier,
+  device_events,
+  events::{self, BridgeEvent},
+};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::VecDeque, sync::Mutex};
+
+// The device list as the frontend should show it, kept on the bridge side so the frontend doesn't
+// have to rebuild its list from scratch every time something changes. Every change bumps the list
+// version and goes out as a delta on the bridge event stream. If the frontend misses deltas (or is
+// just starting up), it can catch up with sync() from the last version it saw.
+//
+// This only follows messages that actually made it to the frontend, so devices held back by
+// reconnect storm protection don't show up here either.
+
+// How many deltas we keep around for catching up. Anyone further behind than this gets a snapshot.
+const MAX_DELTA_HISTORY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceListEntry {
+  pub index: u32,
+  pub name: String,
+  pub display_name: Option<String>,
+  pub identifier: ExposedUserDeviceIdentifier,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum DeviceListChange {
+  Added(DeviceListEntry),
+  Updated(DeviceListEntry),
+  Removed { index: u32 },
+  // Engine stopped, so every device is gone.
+  Cleared,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceListDelta {
+  pub version: u64,
+  pub change: DeviceListChange,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceListSync {
+  pub version: u64,
+  // Full list, if the caller was too far behind to catch up with deltas. If this is set, the
+  // caller should replace its list with it, and deltas will be empty.
+  pub snapshot: Option<Vec<DeviceListEntry>>,
+  pub deltas: Vec<DeviceListDelta>,
+}
+
+#[derive(Default)]
+struct DeviceListState {
+  version: u64,
+  devices: Vec<DeviceListEntry>,
+  history: VecDeque<DeviceListDelta>,
+}
+
+impl DeviceListState {
+  fn push(&mut self, change: DeviceListChange) {
+    self.version += 1;
+    let delta = DeviceListDelta {
+      version: self.version,
+      change,
+    };
+    if self.history.len() == MAX_DELTA_HISTORY {
+      self.history.pop_front();
+    }
+    self.history.push_back(delta.clone());
+    events::emit(BridgeEvent::DeviceListChanged(delta));
+  }
+
+  fn upsert(&mut self, entry: DeviceListEntry) {
+    match self.devices.iter_mut().find(|x| x.index == entry.index) {
+      Some(existing) if *existing == entry => {}
+      Some(existing) => {
+        *existing = entry.clone();
+        device_events::device_added(&entry);
+        self.push(DeviceListChange::Updated(entry));
+      }
+      None => {
+        self.devices.push(entry.clone());
+        device_events::device_added(&entry);
+        self.push(DeviceListChange::Added(entry));
+      }
+    }
+  }
+
+  fn remove(&mut self, index: u32) {
+    let count = self.devices.len();
+    self.devices.retain(|x| x.index != index);
+    if self.devices.len() != count {
+      device_events::device_removed(index);
+      self.push(DeviceListChange::Removed { index });
+    }
+  }
+
+  fn clear(&mut self) {
+    if !self.devices.is_empty() {
+      for device in self.devices.drain(..) {
+        device_events::device_removed(device.index);
+      }
+      self.push(DeviceListChange::Cleared);
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct DeviceDisconnectedFields {
+  index: u32,
+}
+
+lazy_static! {
+  static ref DEVICE_LIST: Mutex<DeviceListState> = Mutex::new(DeviceListState::default());
+}
+
+// Takes serialized EngineMessages, as that's the form held messages are kept in, and it's what the
+// frontend gets anyways.
+pub fn apply_engine_message(serialized: &str) {
+  let Ok(Value::Object(msg)) = serde_json::from_str::<Value>(serialized) else {
+    return;
+  };
+  let Some((msg_type, fields)) = msg.into_iter().next() else {
+    return;
+  };
+  let mut state = DEVICE_LIST.lock().unwrap();
+  match msg_type.as_str() {
+    "DeviceConnected" => match serde_json::from_value::<DeviceListEntry>(fields) {
+      Ok(entry) => state.upsert(entry),
+      Err(e) => warn!(
+        "Cannot parse DeviceConnected message for device list: {}",
+        e
+      ),
+    },
+    "DeviceDisconnected" => {
+      if let Ok(fields) = serde_json::from_value::<DeviceDisconnectedFields>(fields) {
+        state.remove(fields.index);
+      }
+    }
+    "EngineStopped" => state.clear(),
+    _ => {}
+  }
+}
+
+// Display names can change without the device reconnecting, when the user renames it.
+pub fn update_display_name(identifier: &ExposedUserDeviceIdentifier, display_name: Option<String>) {
+  let mut state = DEVICE_LIST.lock().unwrap();
+  let Some(entry) = state
+    .devices
+    .iter()
+    .find(|x| x.identifier == *identifier)
+    .cloned()
+  else {
+    return;
+  };
+  state.upsert(DeviceListEntry {
+    display_name,
+    ..entry
+  });
+}
+
+// Sends a device out on the device event stream again, for when something kept outside the list,
+// like hardware info, changes.
+pub fn resend(index: u32) {
+  let state = DEVICE_LIST.lock().unwrap();
+  if let Some(entry) = state.devices.iter().find(|x| x.index == index) {
+    device_events::device_added(entry);
+  }
+}
+
+pub fn clear() {
+  DEVICE_LIST.lock().unwrap().clear();
+}
+
+pub fn sync(snapshot_version: u64) -> DeviceListSync {
+  let state = DEVICE_LIST.lock().unwrap();
+  let oldest_available = state
+    .history
+    .front()
+    .map(|x| x.version - 1)
+    .unwrap_or(state.version);
+  if snapshot_version > state.version || snapshot_version < oldest_available {
+    return DeviceListSync {
+      version: state.version,
+      snapshot: Some(state.devices.clone()),
+      deltas: vec![],
+    };
+  }
+  DeviceListSync {
+    version: state.version,
+    snapshot: None,
+    deltas: state
+      .history
+      .iter()
+      .filter(|x| x.version > snapshot_version)
+      .cloned()
+      .collect(),
+  }
+}
@@ -0,0 +1,150 @@
+use crate::{
+  actuator_curve::{ActuatorCurve, ActuatorCurveShape},
+  api::{ExposedUserDeviceIdentifier, DEVICE_CONFIG_MANAGER},
+  devices,
+};
+use anyhow::Result;
+use buttplug::core::message::ButtplugActuatorFeatureMessageType;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::RwLock};
+
+// Per-device settings that the bridge handles itself. These can't go into the Buttplug user config
+// file, as that's schema checked by the library and will reject anything it doesn't know about, so
+// they're saved as their own JSON document, handled the same way as the user config string.
+
+const DEVICE_SETTINGS_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceSettings {
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub curves: Vec<ActuatorCurve>,
+  // Maximum change in actuator value per second, 0.0-1.0 scale.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max_rate_per_second: Option<f64>,
+  // Leaves the device out of auto reconnect (see auto_reconnect).
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub no_auto_reconnect: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeviceSettingsEntry {
+  identifier: ExposedUserDeviceIdentifier,
+  settings: DeviceSettings,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeviceSettingsFile {
+  version: u32,
+  #[serde(default)]
+  devices: Vec<DeviceSettingsEntry>,
+}
+
+lazy_static! {
+  static ref DEVICE_SETTINGS: RwLock<HashMap<ExposedUserDeviceIdentifier, DeviceSettings>> =
+    RwLock::new(HashMap::new());
+}
+
+pub fn load(settings_json: &str) -> Result<()> {
+  let file: DeviceSettingsFile = serde_json::from_str(settings_json)?;
+  if file.version > DEVICE_SETTINGS_VERSION {
+    return Err(anyhow::Error::msg(format!(
+      "Device settings version {} is newer than supported version {}",
+      file.version, DEVICE_SETTINGS_VERSION
+    )));
+  }
+  *DEVICE_SETTINGS.write().unwrap() = file
+    .devices
+    .into_iter()
+    .map(|entry| (entry.identifier, entry.settings))
+    .collect();
+  Ok(())
+}
+
+pub fn save() -> String {
+  let settings = DEVICE_SETTINGS.read().unwrap();
+  let file = DeviceSettingsFile {
+    version: DEVICE_SETTINGS_VERSION,
+    devices: settings
+      .iter()
+      .map(|(identifier, settings)| DeviceSettingsEntry {
+        identifier: identifier.clone(),
+        settings: settings.clone(),
+      })
+      .collect(),
+  };
+  serde_json::to_string(&file).expect("Device settings should always serialize")
+}
+
+pub fn settings(identifier: &ExposedUserDeviceIdentifier) -> DeviceSettings {
+  DEVICE_SETTINGS
+    .read()
+    .unwrap()
+    .get(identifier)
+    .cloned()
+    .unwrap_or_default()
+}
+
+// Runs the update function against the settings for a device, creating them if needed, and drops
+// the entry again if it ends up back at defaults so we don't accumulate empty records.
+pub fn update<F>(identifier: &ExposedUserDeviceIdentifier, update_fn: F)
+where
+  F: FnOnce(&mut DeviceSettings),
+{
+  let mut all_settings = DEVICE_SETTINGS.write().unwrap();
+  let settings = all_settings.entry(identifier.clone()).or_default();
+  update_fn(settings);
+  if *settings == DeviceSettings::default() {
+    all_settings.remove(identifier);
+  }
+}
+
+// Finds the curve shape for a ScalarCmd actuator on a connected device. Scalar indexes only count
+// features that accept ScalarCmd, so we have to walk the device definition to get back to the
+// feature index that curves are keyed on.
+pub fn curve_shape_for_scalar(device_index: u32, scalar_index: u32) -> Option<ActuatorCurveShape> {
+  let device = devices::connected_device(device_index)?;
+  let settings = settings(&device.identifier);
+  if settings.curves.is_empty() {
+    return None;
+  }
+  let dcm = DEVICE_CONFIG_MANAGER.try_read().ok()?;
+  let definition = dcm
+    .user_device_definitions()
+    .get(&device.identifier.clone().into())?
+    .value()
+    .clone();
+  let feature_index = definition
+    .features()
+    .iter()
+    .enumerate()
+    .filter(|(_, feature)| {
+      feature.actuator().as_ref().is_some_and(|actuator| {
+        actuator
+          .messages()
+          .contains(&ButtplugActuatorFeatureMessageType::ScalarCmd)
+      })
+    })
+    .nth(scalar_index as usize)?
+    .0;
+  settings
+    .curves
+    .iter()
+    .find(|curve| curve.feature_index as usize == feature_index)
+    .map(|curve| curve.shape.clone())
+}
+
+// Whether any device has settings that change the commands sent to it, so client connections need
+// to go through the bridge for them to apply.
+pub fn has_command_filters() -> bool {
+  DEVICE_SETTINGS
+    .read()
+    .unwrap()
+    .values()
+    .any(|settings| !settings.curves.is_empty() || settings.max_rate_per_second.is_some())
+}
+
+pub fn ramp_rate_for_device(device_index: u32) -> Option<f64> {
+  let device = devices::connected_device(device_index)?;
+  settings(&device.identifier).max_rate_per_second
+}
@@ -0,0 +1,168 @@
+use crate::{
+  api::ExposedUserDeviceIdentifier,
+  app_lifecycle, clock, devices,
+  events::{self, BridgeEvent},
+};
+use futures::pin_mut;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+use tokio::{select, sync::Notify};
+
+// Per-device command latency and failures, so people can work out which toy is holding things up.
+// Latency is from when a command is handed to the engine to when its reply comes back, so it covers
+// the engine and the device write, but not the client's own network. We can only time commands we
+// see, which means clients on the websocket proxy and the frontend through the backdoor.
+
+// Engines always answer, but in case one doesn't, don't hold on to every command forever.
+const MAX_PENDING_COMMANDS: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceCommandStats {
+  pub device_index: u32,
+  pub identifier: Option<ExposedUserDeviceIdentifier>,
+  pub commands: u64,
+  // Commands the engine answered with an error, usually a failed write to the device.
+  pub errors: u64,
+  pub last_latency_ms: Option<f64>,
+  pub average_latency_ms: Option<f64>,
+  pub max_latency_ms: Option<f64>,
+}
+
+#[derive(Default)]
+struct DeviceTotals {
+  identifier: Option<ExposedUserDeviceIdentifier>,
+  commands: u64,
+  errors: u64,
+  total_latency: Duration,
+  last_latency: Option<Duration>,
+  max_latency: Option<Duration>,
+}
+
+lazy_static! {
+  static ref DEVICE_STATS: Mutex<HashMap<u32, DeviceTotals>> = Mutex::new(HashMap::new());
+}
+
+fn to_ms(duration: Duration) -> f64 {
+  duration.as_secs_f64() * 1000.0
+}
+
+fn record(device_index: u32, latency: Duration, error: bool) {
+  let mut stats = DEVICE_STATS.lock().unwrap();
+  let totals = stats.entry(device_index).or_default();
+  if totals.identifier.is_none() {
+    totals.identifier = devices::connected_device(device_index).map(|x| x.identifier);
+  }
+  totals.commands += 1;
+  if error {
+    totals.errors += 1;
+  }
+  totals.total_latency += latency;
+  totals.last_latency = Some(latency);
+  totals.max_latency = totals.max_latency.max(Some(latency));
+}
+
+pub fn stats() -> Vec<DeviceCommandStats> {
+  let mut stats: Vec<DeviceCommandStats> = DEVICE_STATS
+    .lock()
+    .unwrap()
+    .iter()
+    .map(|(device_index, totals)| DeviceCommandStats {
+      device_index: *device_index,
+      identifier: totals.identifier.clone(),
+      commands: totals.commands,
+      errors: totals.errors,
+      last_latency_ms: totals.last_latency.map(to_ms),
+      average_latency_ms: (totals.commands > 0)
+        .then(|| to_ms(totals.total_latency) / totals.commands as f64),
+      max_latency_ms: totals.max_latency.map(to_ms),
+    })
+    .collect();
+  stats.sort_by_key(|x| x.device_index);
+  stats
+}
+
+pub fn clear() {
+  DEVICE_STATS.lock().unwrap().clear();
+}
+
+// Type and fields of a message, e.g. {"ScalarCmd": {...}}.
+fn message_parts(message: &Value) -> Option<(&String, &serde_json::Map<String, Value>)> {
+  let (message_type, fields) = message.as_object()?.iter().next()?;
+  Some((message_type, fields.as_object()?))
+}
+
+// Device commands on one connection that are still waiting on a reply. Message ids are only
+// unique per connection, so each one needs its own.
+#[derive(Default)]
+pub struct CommandTracker {
+  // Message id -> (device index, when it went to the engine)
+  pending: HashMap<u64, (u32, Instant)>,
+}
+
+impl CommandTracker {
+  pub fn track_sent(&mut self, messages: &[Value]) {
+    let now = clock::now();
+    for message in messages {
+      let Some((_, fields)) = message_parts(message) else {
+        continue;
+      };
+      let (Some(id), Some(device_index)) = (
+        fields.get("Id").and_then(|x| x.as_u64()),
+        fields.get("DeviceIndex").and_then(|x| x.as_u64()),
+      ) else {
+        continue;
+      };
+      if self.pending.len() >= MAX_PENDING_COMMANDS {
+        continue;
+      }
+      self.pending.insert(id, (device_index as u32, now));
+    }
+  }
+
+  pub fn track_replies(&mut self, messages: &[Value]) {
+    let now = clock::now();
+    for message in messages {
+      let Some((message_type, fields)) = message_parts(message) else {
+        continue;
+      };
+      let Some((device_index, sent_at)) = fields
+        .get("Id")
+        .and_then(|x| x.as_u64())
+        .and_then(|x| self.pending.remove(&x))
+      else {
+        continue;
+      };
+      record(
+        device_index,
+        now.saturating_duration_since(sent_at),
+        message_type == "Error",
+      );
+    }
+  }
+}
+
+// Sends the stats out as bridge events every interval while the engine is running.
+pub async fn run_stats_reporter(interval: Duration, stop: Arc<Notify>) {
+  let stopped = stop.notified();
+  pin_mut!(stopped);
+  let mut ticker = clock::Ticker::new(interval);
+  loop {
+    select! {
+      _ = ticker.tick() => {}
+      _ = &mut stopped => break,
+    }
+    if app_lifecycle::in_background() {
+      continue;
+    }
+    let devices = stats();
+    if !devices.is_empty() {
+      events::emit(BridgeEvent::DeviceCommandStats { devices });
+    }
+  }
+}
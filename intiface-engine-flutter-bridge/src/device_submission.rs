@@ -0,0 +1,113 @@
+use crate::api::{
+  base_bluetooth_le_services, ExposedBluetoothLESpecifier, ExposedDeviceFeature,
+  DEVICE_CONFIG_MANAGER,
+};
+use anyhow::Result;
+use buttplug::server::device::protocol::get_default_protocol_map;
+use serde_json::{json, Value};
+
+// Builds device support submissions for the upstream device config file, so people who get a new
+// toy working locally can send it back without having to learn the config format. The output is a
+// partial config, with just the protocol entry for the device, laid out the same way as the
+// protocols section of buttplug-device-config.json so it can be merged in directly.
+
+// Everything we know about a device that's been mapped locally.
+#[derive(Debug, Clone)]
+pub struct DeviceCapture {
+  // Product name, as it should show up in device lists.
+  pub name: String,
+  pub specifier: ExposedBluetoothLESpecifier,
+  // If empty, the device is assumed to use the protocol's default features.
+  pub features: Vec<ExposedDeviceFeature>,
+}
+
+fn feature_json(feature: &ExposedDeviceFeature) -> Result<Value> {
+  let mut value = json!({ "feature-type": feature.feature_type });
+  if !feature.description.is_empty() {
+    value["description"] = json!(feature.description);
+  }
+  // Step limits are the user's own preference, so only the ranges the hardware supports go in.
+  if let Some(actuator) = &feature.actuator {
+    if actuator.step_range.0 > actuator.step_range.1 || actuator.messages.is_empty() {
+      return Err(anyhow::Error::msg(format!(
+        "Actuator for feature {:?} needs a valid step range and at least one message",
+        feature.feature_type
+      )));
+    }
+    value["actuator"] = json!({
+      "step-range": [actuator.step_range.0, actuator.step_range.1],
+      "messages": actuator.messages,
+    });
+  }
+  if let Some(sensor) = &feature.sensor {
+    if sensor.value_range.is_empty() || sensor.messages.is_empty() {
+      return Err(anyhow::Error::msg(format!(
+        "Sensor for feature {:?} needs at least one value range and message",
+        feature.feature_type
+      )));
+    }
+    let value_range: Vec<[i32; 2]> = sensor.value_range.iter().map(|x| [x.0, x.1]).collect();
+    value["sensor"] = json!({
+      "value-range": value_range,
+      "messages": sensor.messages,
+    });
+  }
+  Ok(value)
+}
+
+pub fn package(capture: DeviceCapture, protocol_guess: &str) -> Result<String> {
+  if !get_default_protocol_map().contains_key(protocol_guess) {
+    return Err(anyhow::Error::msg(format!(
+      "Unknown protocol {}, new protocols need code changes and can't be submitted as config",
+      protocol_guess
+    )));
+  }
+  if capture.name.trim().is_empty() {
+    return Err(anyhow::Error::msg("Device submissions need a product name"));
+  }
+  capture.specifier.validate()?;
+  // Configurations are matched on exact names, so wildcards can only go in the communication block.
+  let identifiers: Vec<String> = capture
+    .specifier
+    .names
+    .iter()
+    .filter(|name| !name.ends_with('*'))
+    .cloned()
+    .collect();
+  if identifiers.is_empty() {
+    return Err(anyhow::Error::msg(
+      "Device submissions need at least one exact advertised name to identify the device",
+    ));
+  }
+  let features = capture
+    .features
+    .iter()
+    .map(feature_json)
+    .collect::<Result<Vec<Value>>>()?;
+
+  let default_services = {
+    let dcm = DEVICE_CONFIG_MANAGER
+      .try_read()
+      .expect("We should have a reader at this point");
+    base_bluetooth_le_services(&dcm, protocol_guess)
+  };
+  // Run the specifier through the library so we only ever produce something it'll load.
+  let specifier = capture.specifier.into_specifier(default_services)?;
+
+  let mut configuration = json!({
+    "identifier": identifiers,
+    "name": capture.name.trim(),
+  });
+  if !features.is_empty() {
+    configuration["features"] = json!(features);
+  }
+  let submission = json!({
+    "protocols": {
+      protocol_guess: {
+        "communication": [{ "btle": specifier }],
+        "configurations": [configuration],
+      }
+    }
+  });
+  Ok(serde_json::to_string_pretty(&submission)?)
+}
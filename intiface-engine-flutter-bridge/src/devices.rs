@@ -0,0 +1,138 @@
+use crate::{
+  api::{ExposedDeviceFeature, ExposedUserDeviceIdentifier, DEVICE_CONFIG_MANAGER},
+  auto_reconnect, log_sanitizer, shutdown, usage_metrics,
+};
+use buttplug::server::device::configuration::UserDeviceIdentifier;
+use intiface_engine::EngineMessage;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::{collections::HashMap, sync::RwLock, time::Duration};
+
+// Tracks what the engine has told the frontend about connected devices, so other parts of the
+// bridge can map between runtime device indexes and configuration identifiers.
+
+// Buttplug servers send DeviceAdded to their clients and the engine sends DeviceConnected to us
+// separately, so we may see the former first. This is how long we'll wait on the latter before
+// deciding we can't tell what the device is.
+const DEVICE_LOOKUP_TIMEOUT: Duration = Duration::from_millis(500);
+const DEVICE_LOOKUP_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone)]
+pub struct ConnectedDevice {
+  pub identifier: ExposedUserDeviceIdentifier,
+}
+
+#[derive(Deserialize)]
+struct DeviceConnectedFields {
+  index: u32,
+  identifier: ExposedUserDeviceIdentifier,
+}
+
+lazy_static! {
+  static ref CONNECTED_DEVICES: RwLock<HashMap<u32, ConnectedDevice>> = RwLock::new(HashMap::new());
+}
+
+// Index and identifier out of a DeviceConnected message. Goes through serde rather than
+// destructuring, so we get the identifier in the same shape the frontend sees it.
+pub fn connected_message_identifier(
+  msg: &EngineMessage,
+) -> Option<(u32, ExposedUserDeviceIdentifier)> {
+  let fields = serde_json::to_value(msg)
+    .ok()
+    .and_then(|value| value.get("DeviceConnected").cloned())
+    .and_then(|value| serde_json::from_value::<DeviceConnectedFields>(value).ok())?;
+  Some((fields.index, fields.identifier))
+}
+
+pub fn update_from_engine_message(msg: &EngineMessage) {
+  match msg {
+    EngineMessage::DeviceConnected { .. } => {
+      let Some((index, identifier)) = connected_message_identifier(msg) else {
+        warn!("Cannot parse DeviceConnected message for device tracking.");
+        return;
+      };
+      auto_reconnect::remember(&identifier);
+      log_sanitizer::remember(&identifier);
+      usage_metrics::device_connected(&identifier);
+      CONNECTED_DEVICES
+        .write()
+        .unwrap()
+        .insert(index, ConnectedDevice { identifier });
+    }
+    EngineMessage::DeviceDisconnected { index } => {
+      CONNECTED_DEVICES.write().unwrap().remove(index);
+      shutdown::device_disconnected(*index);
+    }
+    EngineMessage::EngineStopped { .. } => clear(),
+    _ => {}
+  }
+}
+
+pub fn connected_device(index: u32) -> Option<ConnectedDevice> {
+  CONNECTED_DEVICES.read().unwrap().get(&index).cloned()
+}
+
+pub async fn wait_for_device(index: u32) -> Option<ExposedUserDeviceIdentifier> {
+  let mut waited = Duration::ZERO;
+  loop {
+    if let Some(device) = connected_device(index) {
+      return Some(device.identifier);
+    }
+    if waited >= DEVICE_LOOKUP_TIMEOUT {
+      return None;
+    }
+    tokio::time::sleep(DEVICE_LOOKUP_INTERVAL).await;
+    waited += DEVICE_LOOKUP_INTERVAL;
+  }
+}
+
+// Features the engine is using for a device. The engine writes a user definition for every device
+// it connects, so that's where they live.
+pub fn features(identifier: &ExposedUserDeviceIdentifier) -> Vec<ExposedDeviceFeature> {
+  let identifier: UserDeviceIdentifier = identifier.clone().into();
+  DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .user_device_definitions()
+    .get(&identifier)
+    .map(|definition| {
+      definition
+        .features()
+        .iter()
+        .cloned()
+        .map(ExposedDeviceFeature::from)
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+pub fn device_index(identifier: &ExposedUserDeviceIdentifier) -> Option<u32> {
+  CONNECTED_DEVICES
+    .read()
+    .unwrap()
+    .iter()
+    .find(|(_, device)| device.identifier == *identifier)
+    .map(|(index, _)| *index)
+}
+
+pub fn connected_devices() -> Vec<(u32, ExposedUserDeviceIdentifier)> {
+  CONNECTED_DEVICES
+    .read()
+    .unwrap()
+    .iter()
+    .map(|(index, device)| (*index, device.identifier.clone()))
+    .collect()
+}
+
+pub fn connected_identifiers() -> Vec<ExposedUserDeviceIdentifier> {
+  CONNECTED_DEVICES
+    .read()
+    .unwrap()
+    .values()
+    .map(|device| device.identifier.clone())
+    .collect()
+}
+
+pub fn clear() {
+  CONNECTED_DEVICES.write().unwrap().clear();
+}
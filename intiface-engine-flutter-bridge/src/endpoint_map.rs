@@ -0,0 +1,144 @@
+use crate::{
+  api::{ExposedBluetoothLESpecifier, ExposedUserDeviceIdentifier, DEVICE_CONFIG_MANAGER},
+  mobile_init,
+};
+use anyhow::Result;
+use btleplug::api::{Central, CharPropFlags, Manager as _, Peripheral as _};
+use buttplug::{
+  core::message::Endpoint, server::device::configuration::ProtocolCommunicationSpecifier,
+};
+use std::collections::HashMap;
+
+// Buttplug only keeps the characteristics a protocol's config tells it about, so to see everything
+// a device has, we go to the OS through btleplug. The engine already has the device connected, and
+// the OS shares that connection, so we can read the service layout without connecting again.
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredCharacteristic {
+  pub uuid: String,
+  // Property names, e.g. "read", "write", "notify".
+  pub properties: Vec<String>,
+  // Endpoint this characteristic is mapped to in the device config, if any.
+  pub endpoint: Option<Endpoint>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredService {
+  pub uuid: String,
+  pub primary: bool,
+  pub characteristics: Vec<DiscoveredCharacteristic>,
+}
+
+const PROPERTY_NAMES: [(CharPropFlags, &str); 8] = [
+  (CharPropFlags::BROADCAST, "broadcast"),
+  (CharPropFlags::READ, "read"),
+  (
+    CharPropFlags::WRITE_WITHOUT_RESPONSE,
+    "write-without-response",
+  ),
+  (CharPropFlags::WRITE, "write"),
+  (CharPropFlags::NOTIFY, "notify"),
+  (CharPropFlags::INDICATE, "indicate"),
+  (
+    CharPropFlags::AUTHENTICATED_SIGNED_WRITES,
+    "authenticated-signed-writes",
+  ),
+  (CharPropFlags::EXTENDED_PROPERTIES, "extended-properties"),
+];
+
+// (service uuid, characteristic uuid) -> endpoint, out of every Bluetooth LE specifier the protocol
+// has, user and base.
+fn configured_endpoints(protocol: &str) -> HashMap<(String, String), Endpoint> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let mut comm_specs = dcm
+    .base_communication_specifiers()
+    .get(protocol)
+    .cloned()
+    .unwrap_or_default();
+  if let Some(user_specs) = dcm.user_communication_specifiers().get(protocol) {
+    comm_specs.extend(user_specs.value().iter().cloned());
+  }
+  let mut endpoints = HashMap::new();
+  for comm_spec in comm_specs {
+    let ProtocolCommunicationSpecifier::BluetoothLE(ble) = comm_spec else {
+      continue;
+    };
+    let Some(ble) = ExposedBluetoothLESpecifier::from_specifier(&ble) else {
+      continue;
+    };
+    for service in ble.services {
+      for endpoint in service.endpoints {
+        endpoints.insert(
+          (
+            service.service.to_lowercase(),
+            endpoint.characteristic.to_lowercase(),
+          ),
+          endpoint.endpoint,
+        );
+      }
+    }
+  }
+  endpoints
+}
+
+pub fn endpoint_map(identifier: &ExposedUserDeviceIdentifier) -> Result<Vec<DiscoveredService>> {
+  let configured = configured_endpoints(&identifier.protocol);
+  // Depending on platform, the address Buttplug uses is either the peripheral's Bluetooth address
+  // or its OS assigned id.
+  let address = identifier.address.to_lowercase();
+  let runtime = mobile_init::create_runtime()?;
+  runtime.block_on(async {
+    let manager = btleplug::platform::Manager::new().await?;
+    for adapter in manager.adapters().await? {
+      for peripheral in adapter.peripherals().await? {
+        if peripheral.address().to_string().to_lowercase() != address
+          && peripheral.id().to_string().to_lowercase() != address
+        {
+          continue;
+        }
+        if !peripheral.is_connected().await? {
+          return Err(anyhow::Error::msg(
+            "Device is not connected at the Bluetooth level",
+          ));
+        }
+        peripheral.discover_services().await?;
+        return Ok(
+          peripheral
+            .services()
+            .into_iter()
+            .map(|service| {
+              let service_uuid = service.uuid.to_string();
+              DiscoveredService {
+                characteristics: service
+                  .characteristics
+                  .into_iter()
+                  .map(|characteristic| {
+                    let uuid = characteristic.uuid.to_string();
+                    DiscoveredCharacteristic {
+                      endpoint: configured
+                        .get(&(service_uuid.clone(), uuid.clone()))
+                        .copied(),
+                      properties: PROPERTY_NAMES
+                        .iter()
+                        .filter(|(flag, _)| characteristic.properties.contains(*flag))
+                        .map(|(_, name)| name.to_string())
+                        .collect(),
+                      uuid,
+                    }
+                  })
+                  .collect(),
+                uuid: service_uuid,
+                primary: service.primary,
+              }
+            })
+            .collect(),
+        );
+      }
+    }
+    Err(anyhow::Error::msg(
+      "Cannot find device among the system's Bluetooth devices",
+    ))
+  })
+}
@@ -0,0 +1,45 @@
+use crate::{
+  events::{self, BridgeEvent},
+  options::EngineRestartOptions,
+};
+use serde::Serialize;
+use std::time::Duration;
+
+// For long unattended sessions, where nobody's around to notice the engine fell over. With restarts
+// on, if the engine stops with an error without being asked to, it's run again with the same options
+// after a backoff. Client mode engines have their own reconnect handling (see client_reconnect), so
+// this only covers server mode.
+
+// A run that lasted this long counts as having worked, so later failures start the backoff over.
+pub const STABLE_RUN_TIME: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize)]
+pub enum EngineRestartState {
+  // Attempt starts at 1.
+  Restarting {
+    attempt: u32,
+    delay_ms: u32,
+    error: String,
+  },
+  GaveUp {
+    attempts: u32,
+    error: String,
+  },
+}
+
+pub fn emit_state(state: EngineRestartState) {
+  events::emit(BridgeEvent::EngineRestart(state));
+}
+
+// Delay before restart attempt number `attempt` (starting at 1), or None if we've run out of
+// retries.
+pub fn restart_delay(options: &EngineRestartOptions, attempt: u32) -> Option<Duration> {
+  if !options.enabled || attempt > options.max_retries {
+    return None;
+  }
+  let backoff =
+    (options.initial_backoff_ms as f64) * 2f64.powi(attempt.saturating_sub(1).min(16) as i32);
+  Some(Duration::from_millis(
+    backoff.min(options.max_backoff_ms as f64) as u64,
+  ))
+}
@@ -0,0 +1,96 @@
+use crate::{log_sanitizer, options};
+use lazy_static::lazy_static;
+use sentry::protocol::{Event, Level};
+use std::{
+  collections::{BTreeMap, HashSet},
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+// Sends errors that don't crash anything (the engine giving up on a device, a config that won't
+// load) to Sentry along with crashes, so we hear about failures people otherwise just live with.
+// Only does anything with the error_reporting option on and crash reporting set up.
+//
+// Reports always go through the log sanitizer's hashing, whatever it's set to for logs, as they
+// leave the machine. At most max_events_per_hour go out an hour, and the same error only once.
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+// Targets of engine logs that get reported at error level.
+const ENGINE_LOG_TARGETS: [&str; 2] = ["buttplug", "intiface_engine"];
+
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorSource {
+  EngineError,
+  EngineLog,
+  ConfigLoad,
+}
+
+impl ErrorSource {
+  fn tag(&self) -> &'static str {
+    match self {
+      ErrorSource::EngineError => "engine_error",
+      ErrorSource::EngineLog => "engine_log",
+      ErrorSource::ConfigLoad => "config_load",
+    }
+  }
+}
+
+struct RateLimit {
+  window_start: Instant,
+  sent: u32,
+  // Errors already sent this window.
+  seen: HashSet<String>,
+}
+
+lazy_static! {
+  static ref RATE_LIMIT: Mutex<RateLimit> = Mutex::new(RateLimit {
+    window_start: Instant::now(),
+    sent: 0,
+    seen: HashSet::new(),
+  });
+}
+
+fn allowed(message: &str, max_events: u32) -> bool {
+  let mut limit = RATE_LIMIT.lock().unwrap();
+  if limit.window_start.elapsed() >= RATE_LIMIT_WINDOW {
+    limit.window_start = Instant::now();
+    limit.sent = 0;
+    limit.seen.clear();
+  }
+  if limit.sent >= max_events || !limit.seen.insert(message.to_owned()) {
+    return false;
+  }
+  limit.sent += 1;
+  true
+}
+
+pub fn report(source: ErrorSource, logger: Option<&str>, message: &str) {
+  let options = options::bridge_options().error_reporting;
+  if !options.enabled || sentry::Hub::current().client().is_none() {
+    return;
+  }
+  let message = log_sanitizer::scrub(message.to_owned());
+  if !allowed(&message, options.max_events_per_hour) {
+    return;
+  }
+  let mut tags = BTreeMap::new();
+  tags.insert("source".to_owned(), source.tag().to_owned());
+  sentry::capture_event(Event {
+    level: Level::Error,
+    message: Some(message),
+    logger: logger.map(|x| x.to_owned()),
+    tags,
+    ..Default::default()
+  });
+}
+
+// Error level log lines from the engine, which is where device connect failures end up.
+pub fn report_log(target: &str, message: &str) {
+  let engine_log = ENGINE_LOG_TARGETS
+    .iter()
+    .any(|x| target == *x || target.starts_with(&format!("{}::", x)));
+  if engine_log {
+    report(ErrorSource::EngineLog, Some(target), message);
+  }
+}
@@ -0,0 +1,212 @@
+use crate::{
+  address_rules::BlockReason,
+  api::ExposedUserDeviceIdentifier,
+  backdoor_validation::BackdoorValidationError,
+  client_reconnect::WebsocketClientState,
+  clients::ConnectedClient,
+  config_watcher::ConfigFileKind,
+  device_list::DeviceListDelta,
+  device_stats::DeviceCommandStats,
+  engine_supervisor::EngineRestartState,
+  idle_stop::IdleStopState,
+  lovense_connect::LovenseConnectStatus,
+  port_mapping::PortMapping,
+  repeater_monitor::RepeaterStatus,
+  server_ready::ServerAddresses,
+  shutdown::{DeviceShutdownResult, ShutdownStage},
+  simulator::SimulatedDeviceKind,
+  transports::Transport,
+  tunnel::TunnelStatus,
+  websocket_devices::WebsocketDeviceConnection,
+  websocket_proxy::ListeningServer,
+};
+use flutter_rust_bridge::StreamSink;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::sync::Mutex;
+
+// Events for things the bridge does on its own, as opposed to EngineMessages, which come from the
+// engine and only exist while it's running. These go out on their own stream, which the frontend
+// sets up once at startup (same as logging), and are serialized the same way as EngineMessages so
+// the frontend can parse them the same way.
+#[derive(Debug, Clone, Serialize)]
+pub enum BridgeEvent {
+  DeviceConfigUpdateAvailable {
+    path: String,
+    version: String,
+    sha256: String,
+  },
+  // Device kept reconnecting, so its connection events are being held back for a while.
+  ReconnectStormQuarantineStarted {
+    identifier: ExposedUserDeviceIdentifier,
+    connections: u32,
+    quarantine_ms: u32,
+  },
+  ReconnectStormQuarantineEnded {
+    identifier: ExposedUserDeviceIdentifier,
+  },
+  DeviceListChanged(DeviceListDelta),
+  // Level is 0.0-1.0.
+  BatteryLevel {
+    device_index: u32,
+    identifier: Option<ExposedUserDeviceIdentifier>,
+    level: f64,
+  },
+  // Websocket client didn't have the right token. Address is the client's ip:port.
+  WebsocketConnectionRejected {
+    address: String,
+    reason: String,
+  },
+  // Addresses (ip:port) a server is actually listening on, once it's up.
+  ServerListening {
+    server: ListeningServer,
+    addresses: Vec<String>,
+  },
+  // Sent once every server for this engine run is listening, so connection details will work.
+  ServerStarted {
+    servers: Vec<ServerAddresses>,
+  },
+  // Sent on repeater connection state changes, and every few seconds while connected.
+  RepeaterStatus(RepeaterStatus),
+  // Peer connecting to the repeater didn't have the right token. Address is the peer's ip:port.
+  RepeaterConnectionRejected {
+    address: String,
+    reason: String,
+  },
+  // Only sent when the engine is connecting out to a server.
+  WebsocketClientState(WebsocketClientState),
+  ClientConnected(ConnectedClient),
+  // Client asked for a newer spec version than max_spec_version allows.
+  ClientSpecVersionRejected {
+    name: String,
+    address: String,
+    spec_version: u32,
+    max_spec_version: u32,
+  },
+  ClientDisconnected(ConnectedClient),
+  // Every device_stats_interval_ms, for devices that have had commands this session.
+  DeviceCommandStats {
+    devices: Vec<DeviceCommandStats>,
+  },
+  // Raw Lovense command the engine sent a simulated device, e.g. "Vibrate1:10;".
+  SimulatedDeviceCommand {
+    kind: SimulatedDeviceKind,
+    address: String,
+    command: String,
+  },
+  // Completed is false if the replay was stopped, or the engine stopped under it.
+  SessionReplayFinished {
+    path: String,
+    commands_sent: u32,
+    completed: bool,
+  },
+  // Result of the Lovense Connect discovery check changed.
+  LovenseConnectStatus(LovenseConnectStatus),
+  // Sent once a device on the device websocket server has sent its handshake.
+  WebsocketDeviceConnected(WebsocketDeviceConnection),
+  // Sent for every device websocket connection when it closes, handshake or not.
+  WebsocketDeviceDisconnected(WebsocketDeviceConnection),
+  // Everything the frontend needs to save, sent when the OS is about to get in the way of it (see
+  // app_lifecycle). Same formats as get_user_config_str, get_device_settings_str and
+  // get_client_permissions_str.
+  StateCheckpoint {
+    user_config: String,
+    device_settings: String,
+    client_permissions: String,
+  },
+  // Only sent with engine restarts on.
+  EngineRestart(EngineRestartState),
+  // Device was turned away by the user's rules after connecting (see address_rules and allowlist).
+  DeviceBlocked {
+    identifier: ExposedUserDeviceIdentifier,
+    reason: BlockReason,
+  },
+  // Device's reserved index was changed with set_device_index. Connected index is where it is right
+  // now if it's connected, which it keeps until it reconnects if that's not the new index.
+  DeviceIndexChanged {
+    identifier: ExposedUserDeviceIdentifier,
+    old_index: u32,
+    new_index: u32,
+    connected_index: Option<u32>,
+  },
+  // Scanning stopped, whoever started it. Timed out is whether it was the scan timeout that stopped
+  // it.
+  ScanFinished {
+    timed_out: bool,
+  },
+  // Only sent with idle stop on.
+  IdleStop(IdleStopState),
+  // Sent when stop_engine is done. Stage is how forceful it had to get, and devices that never
+  // disconnected are marked as such.
+  EngineShutdown {
+    stage: ShutdownStage,
+    elapsed_ms: u64,
+    devices: Vec<DeviceShutdownResult>,
+  },
+  // A watched config file was changed by something other than the bridge. Reloaded is whether the
+  // change has been applied to the loaded config.
+  ConfigChanged {
+    kind: ConfigFileKind,
+    path: String,
+    reloaded: bool,
+  },
+  // Router is forwarding a websocket port to us, sent when it's mapped and each time it's renewed.
+  PortMapped(PortMapping),
+  PortMappingFailed {
+    internal_port: u16,
+    error: String,
+  },
+  // Sent on tunnel state changes, and every few seconds while a remote client is connected.
+  TunnelStatus(TunnelStatus),
+  // Sent for each mapping taken down when the engine stops.
+  PortMappingRemoved {
+    internal_port: u16,
+    external_port: u16,
+  },
+  // Messages from the frontend didn't match the message spec, so none of them went to the server.
+  // The client gets error replies as usual.
+  BackdoorMessageRejected {
+    errors: Vec<BackdoorValidationError>,
+  },
+  // Frontend is sending messages faster than the backdoor rate limit allows. Sent at most once a
+  // second while messages are being dropped, with how many were dropped since the last one.
+  BackdoorThrottled {
+    dropped_messages: u64,
+    max_messages_per_second: u32,
+  },
+  // Transport was brought up or torn down with set_transport_enabled. Sent as the engine's server is
+  // run again with it.
+  TransportChanged {
+    transport: Transport,
+    enabled: bool,
+  },
+  // A client took control of a device, or gave it up, with multiple_clients on. device_index is
+  // None when the client has all of them (exclusive arbitration), and client_id is None when it was
+  // given up.
+  DeviceControlChanged {
+    device_index: Option<u32>,
+    client_id: Option<u32>,
+  },
+}
+
+lazy_static! {
+  static ref EVENT_SINK: Mutex<Option<StreamSink<String>>> = Mutex::new(None);
+}
+
+pub fn set_sink(sink: StreamSink<String>) {
+  *EVENT_SINK.lock().unwrap() = Some(sink);
+}
+
+pub fn clear_sink() {
+  if let Some(sink) = EVENT_SINK.lock().unwrap().take() {
+    sink.close();
+  }
+}
+
+pub fn emit(event: BridgeEvent) {
+  if let Some(sink) = EVENT_SINK.lock().unwrap().as_ref() {
+    sink.add(serde_json::to_string(&event).expect("Bridge events should always serialize"));
+  } else {
+    debug!("No bridge event sink set up, dropping event {:?}", event);
+  }
+}
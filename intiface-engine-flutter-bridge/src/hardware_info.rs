@@ -0,0 +1,91 @@
+use crate::{api::ExposedUserDeviceIdentifier, device_list, devices, raw_endpoints};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+// Firmware and hardware details for connected devices, for support requests and for telling which
+// hardware revision someone has when a protocol changed between them. Buttplug doesn't ask devices
+// for any of this, so we send the queries ourselves over raw endpoints, which means the engine has
+// to be running with raw messages allowed.
+//
+// Only protocols that answer such a query over endpoints Buttplug exposes are supported, which for
+// now is Lovense. Answers are kept per device for the rest of the engine run, and show up in device
+// events from then on.
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DeviceHardwareInfo {
+  pub firmware_version: Option<String>,
+  pub hardware_revision: Option<String>,
+  pub serial_number: Option<String>,
+}
+
+lazy_static! {
+  static ref HARDWARE_INFO: Mutex<HashMap<ExposedUserDeviceIdentifier, DeviceHardwareInfo>> =
+    Mutex::new(HashMap::new());
+}
+
+pub fn cached(identifier: &ExposedUserDeviceIdentifier) -> Option<DeviceHardwareInfo> {
+  HARDWARE_INFO.lock().unwrap().get(identifier).cloned()
+}
+
+pub fn clear() {
+  HARDWARE_INFO.lock().unwrap().clear();
+}
+
+fn non_empty(value: &str) -> Option<String> {
+  let value = value.trim();
+  (!value.is_empty()).then(|| value.to_owned())
+}
+
+// Lovense answers "DeviceType;" with e.g. "C:11:0082059AD3BD;", the device type letter, firmware
+// version and the device's id (its MAC, on most of them).
+fn query_lovense(index: u32) -> Result<DeviceHardwareInfo> {
+  let reply =
+    raw_endpoints::write_and_wait(index, "tx", b"DeviceType;", "rx", QUERY_TIMEOUT, |x| {
+      x.contains(&b';')
+    })?;
+  let reply = String::from_utf8_lossy(&reply);
+  let reply = reply.split(';').next().unwrap_or_default();
+  let mut fields = reply.split(':');
+  let (Some(device_type), Some(firmware_version)) = (fields.next(), fields.next()) else {
+    return Err(anyhow::Error::msg(format!(
+      "Unexpected Lovense device type reply: {}",
+      reply
+    )));
+  };
+  Ok(DeviceHardwareInfo {
+    hardware_revision: non_empty(device_type),
+    firmware_version: non_empty(firmware_version),
+    serial_number: fields.next().and_then(non_empty),
+  })
+}
+
+// Blocks while the device answers, so callers on an async runtime need to spawn_blocking it.
+pub fn query(index: u32) -> Result<DeviceHardwareInfo> {
+  let device = devices::connected_device(index).ok_or(anyhow::Error::msg(format!(
+    "No device connected at index {}",
+    index
+  )))?;
+  let info = match device.identifier.protocol.as_str() {
+    "lovense" => query_lovense(index)?,
+    protocol => {
+      return Err(anyhow::Error::msg(format!(
+        "Protocol {} doesn't support hardware info queries",
+        protocol
+      )))
+    }
+  };
+  let changed = HARDWARE_INFO
+    .lock()
+    .unwrap()
+    .insert(device.identifier, info.clone())
+    .as_ref()
+    != Some(&info);
+  if changed {
+    device_list::resend(index);
+  }
+  Ok(info)
+}
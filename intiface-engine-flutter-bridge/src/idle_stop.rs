@@ -0,0 +1,97 @@
+use crate::{
+  api, backdoor_client, clients, clock,
+  events::{self, BridgeEvent},
+  options::{IdleStopAction, IdleStopOptions},
+};
+use futures::pin_mut;
+use serde::Serialize;
+use std::{sync::Arc, thread, time::Duration};
+use tokio::{select, sync::Notify};
+
+// For when someone leaves the server running with nothing connected to it, which on a phone means
+// the radio and the foreground service keep draining the battery for nothing. Once no client has
+// been connected for the idle time, the engine is stopped, or just its devices are, depending on
+// the options. The frontend gets a warning event ahead of time, so it can offer to keep things
+// going, which for now means connecting a client or turning the option off and restarting.
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize)]
+pub enum IdleStopState {
+  Warning {
+    action: IdleStopAction,
+    remaining_ms: u32,
+  },
+  // A client connected after the warning.
+  Cancelled,
+  Stopped {
+    action: IdleStopAction,
+  },
+}
+
+fn emit_state(state: IdleStopState) {
+  events::emit(BridgeEvent::IdleStop(state));
+}
+
+fn stop(action: IdleStopAction) {
+  info!("Nothing connected for a while, idle stop: {:?}", action);
+  emit_state(IdleStopState::Stopped { action });
+  match action {
+    // stop_engine takes the runtime we're on down, so it can't be called from in here.
+    IdleStopAction::StopEngine => {
+      thread::spawn(api::stop_engine);
+    }
+    IdleStopAction::StopDevices => {
+      // Requests block on the backdoor server replying.
+      thread::spawn(|| {
+        if let Err(e) = backdoor_client::stop_scanning() {
+          debug!("Cannot stop scanning for idle stop: {:?}", e);
+        }
+        if let Err(e) = backdoor_client::stop_all() {
+          warn!("Cannot stop devices for idle stop: {:?}", e);
+        }
+      });
+    }
+  }
+}
+
+pub async fn run_idle_monitor(options: IdleStopOptions, stop_notify: Arc<Notify>) {
+  let idle_time = Duration::from_secs(options.idle_minutes.max(1) as u64 * 60);
+  let warning_time = Duration::from_millis(options.warning_ms as u64).min(idle_time);
+  let stopped = stop_notify.notified();
+  pin_mut!(stopped);
+  let mut ticker = clock::Ticker::new(CHECK_INTERVAL);
+  let mut idle_since = Some(clock::now());
+  let mut warned = false;
+  loop {
+    select! {
+      _ = ticker.tick() => {}
+      _ = &mut stopped => break,
+    }
+    if !clients::connected_clients().is_empty() {
+      if warned {
+        emit_state(IdleStopState::Cancelled);
+      }
+      idle_since = None;
+      warned = false;
+      continue;
+    }
+    let since = *idle_since.get_or_insert_with(clock::now);
+    let idle = clock::now().saturating_duration_since(since);
+    if idle >= idle_time {
+      stop(options.action);
+      if options.action == IdleStopAction::StopEngine {
+        break;
+      }
+      // Devices are stopped, so start over rather than stopping them again every second.
+      idle_since = Some(clock::now());
+      warned = false;
+    } else if !warned && idle + warning_time >= idle_time {
+      warned = true;
+      emit_state(IdleStopState::Warning {
+        action: options.action,
+        remaining_ms: (idle_time - idle).as_millis() as u32,
+      });
+    }
+  }
+}
@@ -1,5 +1,13 @@
+use crate::{
+  address_rules, allowlist, auto_scan, clients, device_list, devices,
+  error_reporting::{self, ErrorSource},
+  message_sink::SharedMessageSink,
+  metrics::ENGINE_METRICS,
+  raw_endpoints,
+  reconnect_storm::{self, StormCheck},
+  sensor_stream, server_ready,
+};
 use async_trait::async_trait;
-use flutter_rust_bridge::StreamSink;
 use futures::FutureExt;
 use intiface_engine::{EngineMessage, Frontend, IntifaceError, IntifaceMessage};
 use std::{future::Future, sync::Arc};
@@ -7,13 +15,13 @@ use tokio::sync::{broadcast, Notify};
 
 pub struct FlutterIntifaceEngineFrontend {
   sender: Arc<broadcast::Sender<IntifaceMessage>>,
-  sink: StreamSink<String>,
+  sink: SharedMessageSink,
   notify: Arc<Notify>,
   disconnect_notifier: Arc<Notify>,
 }
 
 impl FlutterIntifaceEngineFrontend {
-  pub fn new(sink: StreamSink<String>, sender: Arc<broadcast::Sender<IntifaceMessage>>) -> Self {
+  pub fn new(sink: SharedMessageSink, sender: Arc<broadcast::Sender<IntifaceMessage>>) -> Self {
     Self {
       sink,
       sender,
@@ -22,7 +30,7 @@ impl FlutterIntifaceEngineFrontend {
     }
   }
 
-  pub fn notify_on_creation(&self) -> impl Future {
+  pub fn notify_on_creation(&self) -> impl Future + Unpin {
     let notify = self.notify.clone();
     async move { notify.notified().await }.boxed()
   }
@@ -43,9 +51,44 @@ impl Frontend for FlutterIntifaceEngineFrontend {
     self.sender.subscribe()
   }
   async fn send(&self, msg: EngineMessage) {
+    ENGINE_METRICS.record_engine_message(&msg);
+    let serialized = serde_json::to_string(&msg).unwrap();
+    // Has to run before the device list update, as disconnects need the index still mapped.
+    let storm_check = reconnect_storm::check(&msg, &serialized);
+    devices::update_from_engine_message(&msg);
+    clients::update_from_engine_message(&msg);
+    auto_scan::update_from_engine_message(&msg);
+    if let EngineMessage::DeviceConnected { .. } = msg {
+      if let Some((index, identifier)) = devices::connected_message_identifier(&msg) {
+        if !address_rules::device_connected(index, &identifier) {
+          allowlist::device_connected(index, &identifier);
+        }
+      }
+    }
+    if let EngineMessage::EngineError { error } = &msg {
+      error_reporting::report(ErrorSource::EngineError, None, error);
+    }
+    if let EngineMessage::DeviceDisconnected { index } = msg {
+      sensor_stream::remove_device(index);
+      raw_endpoints::remove_device(index);
+    }
     if let EngineMessage::EngineServerCreated {} = msg {
+      server_ready::engine_server_created();
       self.notify.notify_waiters();
     }
-    self.sink.add(serde_json::to_string(&msg).unwrap());
+    match storm_check {
+      StormCheck::Forward => {
+        device_list::apply_engine_message(&serialized);
+        self.sink.add(serialized);
+      }
+      StormCheck::Hold => {}
+      StormCheck::Quarantined { identifier, until } => {
+        tokio::spawn(reconnect_storm::release_at(
+          identifier,
+          until,
+          self.sink.clone(),
+        ));
+      }
+    }
   }
 }
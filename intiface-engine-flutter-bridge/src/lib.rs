@@ -1,3 +1,4 @@
+#[cfg(feature = "native")]
 mod bridge_generated; /* AUTO INJECTED BY flutter_rust_bridge. This line may not be accurate, and you can change it according to your needs. */
 /// command line interface for intiface/buttplug.
 ///
@@ -5,9 +6,163 @@ extern crate log;
 #[macro_use]
 extern crate tracing;
 
+#[cfg(feature = "native")]
+mod actuator_curve;
+#[cfg(feature = "native")]
+mod address_rules;
+#[cfg(feature = "native")]
+mod allowlist;
+#[cfg(feature = "native")]
 mod api;
+#[cfg(feature = "native")]
+mod app_lifecycle;
+#[cfg(feature = "native")]
+mod auto_reconnect;
+#[cfg(feature = "native")]
+mod auto_scan;
+#[cfg(feature = "native")]
+mod backdoor;
+#[cfg(feature = "native")]
+mod backdoor_client;
+#[cfg(feature = "native")]
+mod backdoor_events;
+#[cfg(feature = "native")]
+mod backdoor_throttle;
+#[cfg(feature = "native")]
+mod backdoor_validation;
+#[cfg(feature = "native")]
+mod battery;
+#[cfg(feature = "native")]
+mod binary_stream;
+#[cfg(feature = "native")]
+mod ble_probe;
+#[cfg(feature = "native")]
+mod bluetooth_state;
+#[cfg(feature = "native")]
+mod bridge_error;
+#[cfg(feature = "native")]
+mod client_permissions;
+#[cfg(feature = "native")]
+mod client_reconnect;
+#[cfg(feature = "native")]
+mod client_sessions;
+#[cfg(feature = "native")]
+mod clients;
+#[cfg(feature = "native")]
+mod clock;
+#[cfg(feature = "native")]
+mod config_archive;
+#[cfg(feature = "native")]
+mod config_watcher;
+#[cfg(feature = "native")]
+mod connection_payload;
+#[cfg(feature = "native")]
+mod device_config_updater;
+#[cfg(feature = "native")]
+mod device_events;
+#[cfg(feature = "native")]
+mod device_list;
+#[cfg(feature = "native")]
+mod device_settings;
+#[cfg(feature = "native")]
+mod device_stats;
+#[cfg(feature = "native")]
+mod device_submission;
+#[cfg(feature = "native")]
+mod devices;
+#[cfg(feature = "native")]
+mod endpoint_map;
+#[cfg(feature = "native")]
+mod engine_supervisor;
+#[cfg(feature = "native")]
+mod error_reporting;
+#[cfg(feature = "native")]
+mod events;
+#[cfg(feature = "native")]
+mod hardware_info;
+#[cfg(feature = "native")]
+mod idle_stop;
+#[cfg(feature = "native")]
 mod in_process_frontend;
+#[cfg(feature = "native")]
+mod log_sanitizer;
+#[cfg(feature = "native")]
 mod logging;
+#[cfg(feature = "native")]
+mod lovense_connect;
+#[cfg(feature = "native")]
+mod mdns_broadcast;
+#[cfg(feature = "native")]
+mod mdns_browse;
+#[cfg(feature = "native")]
+mod message_sink;
+#[cfg(feature = "native")]
+mod metrics;
+#[cfg(feature = "native")]
 mod mobile_init;
+#[cfg(feature = "native")]
+mod network_interfaces;
+#[cfg(feature = "native")]
+mod options;
+#[cfg(feature = "native")]
+mod options_validation;
+#[cfg(feature = "native")]
+mod patterns;
+#[cfg(feature = "native")]
+mod port_mapping;
+#[cfg(feature = "native")]
+mod protocol_info;
+#[cfg(feature = "native")]
+mod ramp;
+#[cfg(feature = "native")]
+mod raw_endpoints;
+#[cfg(feature = "native")]
+mod reconnect_storm;
+#[cfg(feature = "native")]
+mod repeater_monitor;
+#[cfg(feature = "native")]
+mod scan_timeout;
+#[cfg(feature = "native")]
+mod sensor_stream;
+#[cfg(feature = "native")]
+mod serial_devices;
+#[cfg(feature = "native")]
+mod server_ready;
+#[cfg(feature = "native")]
+mod session_recording;
+#[cfg(feature = "native")]
+mod share_code;
+#[cfg(feature = "native")]
+mod shutdown;
+#[cfg(feature = "native")]
+mod simulator;
+#[cfg(feature = "native")]
+mod system_check;
+#[cfg(feature = "test-harness")]
+pub mod test_harness;
+#[cfg(feature = "native")]
+mod traffic_capture;
+#[cfg(feature = "native")]
+mod transports;
+#[cfg(feature = "native")]
+mod tunnel;
+#[cfg(feature = "native")]
+mod usage_metrics;
+mod user_config_diff;
+#[cfg(feature = "native")]
+mod user_config_file;
+mod user_config_migration;
+mod user_config_validation;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "native")]
+mod websocket_devices;
+#[cfg(feature = "native")]
+mod websocket_proxy;
+#[cfg(feature = "native")]
+mod websocket_tls;
+#[cfg(feature = "native")]
+mod xinput;
 
+#[cfg(feature = "native")]
 pub use api::*;
@@ -0,0 +1,252 @@
+use crate::api::{ExposedUserDeviceIdentifier, DEVICE_CONFIG_MANAGER};
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use std::{
+  collections::{hash_map::RandomState, HashSet},
+  hash::{BuildHasher, Hasher},
+  net::{Ipv4Addr, Ipv6Addr},
+  sync::RwLock,
+};
+
+// Privacy mode for logs and exports people want to share in public, e.g. attaching a debug log to a
+// bug report. Bluetooth addresses (MAC or, on Apple platforms, UUID), IP addresses and the addresses
+// and serials of devices we know about are either hashed or cut down to a prefix.
+//
+// Hashes are salted per run of the app, so the same device or client gets the same hash all through
+// a log, but hashes can't be matched up across runs or looked up. Truncating keeps enough to tell
+// what kind of thing it was (MAC vendor prefix, IP network), which is usually what debugging needs.
+// Loopback and unspecified IPs are left alone.
+//
+// Addresses are found by their shape, so anything else that happens to look like one gets replaced
+// too, which is fine for logs.
+
+// Known device strings shorter than this are left alone, so short ones (e.g. "COM3") don't chew up
+// ordinary words.
+const MIN_KNOWN_VALUE_LEN: usize = 6;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogSanitization {
+  #[default]
+  Off,
+  Hash,
+  Truncate,
+}
+
+enum Found {
+  Mac(u8),
+  Uuid,
+  Ipv4(Ipv4Addr),
+  Ipv6(Ipv6Addr),
+}
+
+lazy_static! {
+  static ref MODE: RwLock<LogSanitization> = RwLock::new(LogSanitization::Off);
+  // Device addresses and serials seen this run, which don't all have a recognizable shape.
+  static ref KNOWN_VALUES: RwLock<HashSet<String>> = RwLock::new(HashSet::new());
+  static ref SALT: [u8; 8] = RandomState::new().build_hasher().finish().to_le_bytes();
+}
+
+pub fn set_mode(mode: LogSanitization) {
+  *MODE.write().unwrap() = mode;
+  if mode == LogSanitization::Off {
+    return;
+  }
+  // Pick up everything in the user config, for devices that haven't connected yet this run.
+  let identifiers: Vec<ExposedUserDeviceIdentifier> = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .user_device_definitions()
+    .iter()
+    .map(|kv| kv.key().clone().into())
+    .collect();
+  for identifier in identifiers {
+    remember(&identifier);
+  }
+}
+
+pub fn mode() -> LogSanitization {
+  *MODE.read().unwrap()
+}
+
+pub fn remember(identifier: &ExposedUserDeviceIdentifier) {
+  let mut known = KNOWN_VALUES.write().unwrap();
+  for value in [Some(&identifier.address), identifier.identifier.as_ref()]
+    .into_iter()
+    .flatten()
+  {
+    if value.len() >= MIN_KNOWN_VALUE_LEN {
+      known.insert(value.clone());
+    }
+  }
+}
+
+fn hash(value: &str) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(*SALT);
+  hasher.update(value.to_ascii_lowercase().as_bytes());
+  format!("hash-{}", &hex::encode(hasher.finalize())[..8])
+}
+
+fn truncate(found: &Found, value: &str) -> String {
+  match found {
+    // Vendor prefix.
+    Found::Mac(separator) => {
+      let separator = *separator as char;
+      format!(
+        "{}{}xx{}xx{}xx",
+        &value[..8],
+        separator,
+        separator,
+        separator
+      )
+    }
+    Found::Uuid => format!("{}-xxxx-xxxx-xxxx-xxxxxxxxxxxx", &value[..8]),
+    Found::Ipv4(ip) => {
+      let octets = ip.octets();
+      format!("{}.{}.x.x", octets[0], octets[1])
+    }
+    Found::Ipv6(ip) => {
+      let segments = ip.segments();
+      format!("{:x}:{:x}:x::x", segments[0], segments[1])
+    }
+  }
+}
+
+fn is_word(byte: u8) -> bool {
+  byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+fn ends_at(bytes: &[u8], end: usize) -> bool {
+  bytes.get(end).is_none_or(|x| !is_word(*x))
+}
+
+fn match_mac(bytes: &[u8]) -> Option<(usize, Found)> {
+  let separator = *bytes.get(2)?;
+  if separator != b':' && separator != b'-' {
+    return None;
+  }
+  for group in 0..6 {
+    let start = group * 3;
+    if !bytes
+      .get(start..start + 2)?
+      .iter()
+      .all(u8::is_ascii_hexdigit)
+    {
+      return None;
+    }
+    if group < 5 && bytes.get(start + 2) != Some(&separator) {
+      return None;
+    }
+  }
+  ends_at(bytes, 17).then_some((17, Found::Mac(separator)))
+}
+
+fn match_uuid(bytes: &[u8]) -> Option<(usize, Found)> {
+  let uuid = bytes.get(..36)?;
+  let matches = uuid.iter().enumerate().all(|(index, byte)| match index {
+    8 | 13 | 18 | 23 => *byte == b'-',
+    _ => byte.is_ascii_hexdigit(),
+  });
+  (matches && ends_at(bytes, 36)).then_some((36, Found::Uuid))
+}
+
+fn match_ipv4(bytes: &[u8]) -> Option<(usize, Found)> {
+  let len = bytes
+    .iter()
+    .take_while(|x| x.is_ascii_digit() || **x == b'.')
+    .count();
+  // Leave a trailing full stop where it is.
+  let len = len
+    - bytes[..len]
+      .iter()
+      .rev()
+      .take_while(|x| **x == b'.')
+      .count();
+  if !ends_at(bytes, len) {
+    return None;
+  }
+  let ip: Ipv4Addr = std::str::from_utf8(&bytes[..len]).ok()?.parse().ok()?;
+  (!ip.is_loopback() && !ip.is_unspecified()).then_some((len, Found::Ipv4(ip)))
+}
+
+fn match_ipv6(bytes: &[u8]) -> Option<(usize, Found)> {
+  let len = bytes
+    .iter()
+    .take_while(|x| x.is_ascii_hexdigit() || **x == b':' || **x == b'.')
+    .count();
+  let len = len
+    - bytes[..len]
+      .iter()
+      .rev()
+      .take_while(|x| **x == b'.')
+      .count();
+  if !ends_at(bytes, len) || bytes[..len].iter().filter(|x| **x == b':').count() < 2 {
+    return None;
+  }
+  let ip: Ipv6Addr = std::str::from_utf8(&bytes[..len]).ok()?.parse().ok()?;
+  (!ip.is_loopback() && !ip.is_unspecified()).then_some((len, Found::Ipv6(ip)))
+}
+
+fn replace_addresses(line: &str, mode: LogSanitization) -> String {
+  let bytes = line.as_bytes();
+  let mut output = String::with_capacity(line.len());
+  let mut copied = 0;
+  let mut position = 0;
+  while position < bytes.len() {
+    let at_boundary = position == 0 || !is_word(bytes[position - 1]);
+    let found = if at_boundary && (bytes[position].is_ascii_hexdigit() || bytes[position] == b':') {
+      let rest = &bytes[position..];
+      match_mac(rest)
+        .or_else(|| match_uuid(rest))
+        .or_else(|| match_ipv4(rest))
+        .or_else(|| match_ipv6(rest))
+    } else {
+      None
+    };
+    match found {
+      Some((len, found)) => {
+        let value = &line[position..position + len];
+        output.push_str(&line[copied..position]);
+        output.push_str(&match mode {
+          LogSanitization::Truncate => truncate(&found, value),
+          _ => hash(value),
+        });
+        position += len;
+        copied = position;
+      }
+      None => position += 1,
+    }
+  }
+  output.push_str(&line[copied..]);
+  output
+}
+
+fn replace_known_values(mut line: String, mode: LogSanitization) -> String {
+  for value in KNOWN_VALUES.read().unwrap().iter() {
+    if line.contains(value.as_str()) {
+      let replacement = match mode {
+        LogSanitization::Truncate => format!("{}****", value.chars().take(4).collect::<String>()),
+        _ => hash(value),
+      };
+      line = line.replace(value.as_str(), &replacement);
+    }
+  }
+  line
+}
+
+pub fn sanitize(line: String) -> String {
+  let mode = mode();
+  if mode == LogSanitization::Off {
+    return line;
+  }
+  replace_known_values(replace_addresses(&line, mode), mode)
+}
+
+// Hashes whatever the mode is set to, for things leaving the machine without anyone looking at them
+// first.
+pub fn scrub(line: String) -> String {
+  replace_known_values(
+    replace_addresses(&line, LogSanitization::Hash),
+    LogSanitization::Hash,
+  )
+}
@@ -1,7 +1,12 @@
-use crossbeam_channel::{bounded, Sender};
+use crate::{error_reporting, log_sanitizer};
+use crossbeam_channel::{bounded, Receiver, Sender};
 use flutter_rust_bridge::StreamSink;
+use lazy_static::lazy_static;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
 use std::{
-  sync::{atomic::AtomicBool, Arc},
+  collections::HashMap,
+  sync::{atomic::AtomicBool, Arc, RwLock},
   thread::JoinHandle,
   time::Duration,
 };
@@ -9,7 +14,9 @@ use tracing::Level;
 use tracing_subscriber::{
   filter::{EnvFilter, LevelFilter},
   layer::SubscriberExt,
+  reload,
   util::SubscriberInitExt,
+  Registry,
 };
 
 use tracing_subscriber::fmt::MakeWriter;
@@ -47,49 +54,234 @@ impl MakeWriter<'_> for BroadcastWriter {
   }
 }
 
+// Log output can go to multiple sinks (the UI log view, file logging, etc), each with its own level
+// and module filter. The subscriber itself only lets through the most verbose level any sink wants,
+// so nothing gets formatted that no one will read, and the dispatch thread sorts out the rest.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+  Error,
+  Warn,
+  Info,
+  Debug,
+  Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+  fn from(value: LogLevel) -> Self {
+    match value {
+      LogLevel::Error => LevelFilter::ERROR,
+      LogLevel::Warn => LevelFilter::WARN,
+      LogLevel::Info => LevelFilter::INFO,
+      LogLevel::Debug => LevelFilter::DEBUG,
+      LogLevel::Trace => LevelFilter::TRACE,
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogSinkOptions {
+  pub max_level: LogLevel,
+  // Module paths to forward logs from (e.g. "buttplug::server"), including their submodules. If
+  // empty, logs from all modules are forwarded.
+  pub modules: Vec<String>,
+}
+
+impl Default for LogSinkOptions {
+  fn default() -> Self {
+    Self {
+      max_level: LogLevel::Debug,
+      modules: vec![],
+    }
+  }
+}
+
+impl LogSinkOptions {
+  fn accepts(&self, line: &LogLineHeader) -> bool {
+    let Some(level) = line.level() else {
+      // If we can't tell, let it through rather than lose something important.
+      return true;
+    };
+    level <= self.max_level
+      && (self.modules.is_empty()
+        || self.modules.iter().any(|module| {
+          line.target == *module || line.target.starts_with(&format!("{}::", module))
+        }))
+  }
+}
+
+// The parts of a JSON formatted log line we need for filtering, and error reporting.
+#[derive(Deserialize)]
+struct LogLineHeader {
+  #[serde(default)]
+  level: String,
+  #[serde(default)]
+  target: String,
+  #[serde(default)]
+  fields: LogLineFields,
+}
+
+#[derive(Default, Deserialize)]
+struct LogLineFields {
+  #[serde(default)]
+  message: String,
+}
+
+impl LogLineHeader {
+  fn level(&self) -> Option<LogLevel> {
+    match self.level.as_str() {
+      "ERROR" => Some(LogLevel::Error),
+      "WARN" => Some(LogLevel::Warn),
+      "INFO" => Some(LogLevel::Info),
+      "DEBUG" => Some(LogLevel::Debug),
+      "TRACE" => Some(LogLevel::Trace),
+      _ => None,
+    }
+  }
+}
+
+struct LogSink {
+  sink: StreamSink<String>,
+  options: LogSinkOptions,
+}
+
+lazy_static! {
+  static ref LOG_SINKS: RwLock<HashMap<String, LogSink>> = RwLock::new(HashMap::new());
+  // The subscriber can only be set up once per process, so the channel it writes into has to live
+  // as long as it does.
+  static ref LOG_CHANNEL: (Sender<String>, Receiver<String>) = bounded(255);
+}
+
+static LEVEL_FILTER_HANDLE: OnceCell<reload::Handle<LevelFilter, Registry>> = OnceCell::new();
+
+pub fn add_sink(name: &str, sink: StreamSink<String>, options: LogSinkOptions) {
+  if let Some(old_sink) = LOG_SINKS
+    .write()
+    .unwrap()
+    .insert(name.to_owned(), LogSink { sink, options })
+  {
+    old_sink.sink.close();
+  }
+  update_level_filter();
+}
+
+pub fn remove_sink(name: &str) {
+  if let Some(old_sink) = LOG_SINKS.write().unwrap().remove(name) {
+    old_sink.sink.close();
+  }
+  update_level_filter();
+}
+
+// Returns false if there's no sink with this name.
+pub fn set_sink_options(name: &str, options: LogSinkOptions) -> bool {
+  let updated = match LOG_SINKS.write().unwrap().get_mut(name) {
+    Some(log_sink) => {
+      log_sink.options = options;
+      true
+    }
+    None => false,
+  };
+  update_level_filter();
+  updated
+}
+
+pub fn clear_sinks() {
+  for (_, log_sink) in LOG_SINKS.write().unwrap().drain() {
+    log_sink.sink.close();
+  }
+  update_level_filter();
+}
+
+fn update_level_filter() {
+  let Some(handle) = LEVEL_FILTER_HANDLE.get() else {
+    return;
+  };
+  let max_level = LOG_SINKS
+    .read()
+    .unwrap()
+    .values()
+    .map(|log_sink| LevelFilter::from(log_sink.options.max_level))
+    .max()
+    .unwrap_or(LevelFilter::OFF);
+  if let Err(e) = handle.reload(max_level) {
+    // If the reload failed, the old filter's still in place, so this goes wherever logging was going.
+    error!("Cannot update log level filter: {:?}", e);
+  }
+}
+
+fn dispatch(line: String) {
+  let line = log_sanitizer::sanitize(line);
+  let sinks = LOG_SINKS.read().unwrap();
+  let Ok(header) = serde_json::from_str::<LogLineHeader>(&line) else {
+    for log_sink in sinks.values() {
+      log_sink.sink.add(line.clone());
+    }
+    return;
+  };
+  for log_sink in sinks.values() {
+    if log_sink.options.accepts(&header) {
+      log_sink.sink.add(line.clone());
+    }
+  }
+  if header.level() == Some(LogLevel::Error) {
+    error_reporting::report_log(&header.target, &header.fields.message);
+  }
+}
+
+fn init_subscriber() -> reload::Handle<LevelFilter, Registry> {
+  // Add panic hook for emitting backtraces through the logging system.
+  log_panics::init();
+  let external_sender = LOG_CHANNEL.0.clone();
+  let (level_filter, handle) = reload::Layer::new(LevelFilter::OFF);
+  if std::env::var("RUST_LOG").is_ok() {
+    tracing_subscriber::registry()
+      .with(level_filter)
+      .with(
+        EnvFilter::try_from_default_env()
+          .or_else(|_| EnvFilter::try_new("info"))
+          .unwrap(),
+      )
+      .with(
+        tracing_subscriber::fmt::layer()
+          .json()
+          //.with_max_level(log_level)
+          .with_ansi(false)
+          .with_writer(move || BroadcastWriter::new(external_sender.clone())),
+      )
+      //.with(sentry_tracing::layer())
+      .try_init()
+      .unwrap();
+  } else {
+    tracing_subscriber::registry()
+      .with(level_filter)
+      .with(LevelFilter::from(Level::DEBUG))
+      .with(
+        tracing_subscriber::fmt::layer()
+          .json()
+          //.with_max_level(log_level)
+          .with_ansi(false)
+          .with_writer(move || BroadcastWriter::new(external_sender.clone())),
+      )
+      //.with(sentry_tracing::layer())
+      .try_init()
+      .unwrap();
+  }
+  handle
+}
+
 pub struct FlutterTracingWriter {
   thread_handle: Option<JoinHandle<()>>,
   cancel: Arc<AtomicBool>,
 }
 
 impl FlutterTracingWriter {
-  pub fn new(sink: StreamSink<String>) -> Self {
-    // Add panic hook for emitting backtraces through the logging system.
-    log_panics::init();
-    let (external_sender, external_receiver) = bounded(255);
-    let external_sender_clone = external_sender.clone();
-    if std::env::var("RUST_LOG").is_ok() {
-      tracing_subscriber::registry()
-        .with(
-          EnvFilter::try_from_default_env()
-            .or_else(|_| EnvFilter::try_new("info"))
-            .unwrap(),
-        )
-        .with(
-          tracing_subscriber::fmt::layer()
-            .json()
-            //.with_max_level(log_level)
-            .with_ansi(false)
-            .with_writer(move || BroadcastWriter::new(external_sender_clone.clone())),
-        )
-        //.with(sentry_tracing::layer())
-        .try_init()
-        .unwrap();
-    } else {
-      tracing_subscriber::registry()
-        .with(LevelFilter::from(Level::DEBUG))
-        .with(
-          tracing_subscriber::fmt::layer()
-            .json()
-            //.with_max_level(log_level)
-            .with_ansi(false)
-            .with_writer(move || BroadcastWriter::new(external_sender_clone.clone())),
-        )
-        //.with(sentry_tracing::layer())
-        .try_init()
-        .unwrap();
+  pub fn new() -> Self {
+    if LEVEL_FILTER_HANDLE.get().is_none() {
+      let _ = LEVEL_FILTER_HANDLE.set(init_subscriber());
+      update_level_filter();
+      info!("Logging subscriber added to registry");
     }
-    info!("Logging subscriber added to registry");
+    let external_receiver = LOG_CHANNEL.1.clone();
     let cancel = Arc::new(AtomicBool::new(false));
     let cancel_clone = cancel.clone();
     let handle = std::thread::spawn(move || {
@@ -99,14 +291,14 @@ impl FlutterTracingWriter {
           info!("Breaking out of logging loop.");
           // Exhaust all waiting messages.
           while let Ok(msg) = external_receiver.try_recv() {
-            sink.add(msg);
+            dispatch(msg);
           }
           break;
         }
         // Wait on the receiver, as while getting 255 messages in the time between our quit calls is
         // unlikely, backpressure locks are worse than waiting 10ms.
         if let Ok(msg) = external_receiver.recv_timeout(Duration::from_millis(10)) {
-          sink.add(msg);
+          dispatch(msg);
         }
       }
     });
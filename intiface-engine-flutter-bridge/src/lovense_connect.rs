@@ -0,0 +1,179 @@
+use crate::{
+  app_lifecycle, clock,
+  events::{self, BridgeEvent},
+  options::LovenseConnectOptions,
+};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{select, sync::Notify};
+
+// The engine finds Lovense Connect apps by polling Lovense's lookup server over HTTPS, and says
+// nothing when that doesn't work, so users are left guessing at why their toys never show up. While
+// the engine runs with use_lovense_connect on, we do the same lookup ourselves, then check that the
+// app it points at actually answers on the local network, and send out a status event whenever the
+// result changes.
+//
+// The engine's own discovery isn't configurable, so the local address and token only apply to our
+// check. With a local address set, we skip the lookup and ask the app there directly, which tells
+// users whether the app is reachable at all when the lookup server can't find it.
+
+const LOVENSE_LOOKUP_URL: &str = "https://api.lovense.com/api/lan/getToys";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum LovenseConnectStatus {
+  // Before the first check finishes.
+  Searching,
+  // Couldn't get an answer out of Lovense's lookup server.
+  LookupFailed { error: String },
+  // Lookup server answered, but has no Lovense Connect app registered from this network.
+  NoAppFound,
+  // Found an app, but it didn't answer at its local address.
+  AppUnreachable { address: String, error: String },
+  Connected { address: String, toys: u32 },
+}
+
+lazy_static! {
+  static ref LOVENSE_CONNECT_STATUS: Mutex<Option<LovenseConnectStatus>> = Mutex::new(None);
+}
+
+pub fn status() -> Option<LovenseConnectStatus> {
+  LOVENSE_CONNECT_STATUS.lock().unwrap().clone()
+}
+
+fn update_status(status: LovenseConnectStatus) {
+  {
+    let mut current = LOVENSE_CONNECT_STATUS.lock().unwrap();
+    if current.as_ref() == Some(&status) {
+      return;
+    }
+    *current = Some(status.clone());
+  }
+  info!("Lovense Connect status: {:?}", status);
+  events::emit(BridgeEvent::LovenseConnectStatus(status));
+}
+
+pub fn clear() {
+  *LOVENSE_CONNECT_STATUS.lock().unwrap() = None;
+}
+
+async fn get_json(client: &Client, url: &str, token: &Option<String>) -> Result<Value> {
+  let mut request = client.get(url);
+  if let Some(token) = token {
+    request = request.query(&[("token", token)]);
+  }
+  let response = request.send().await?;
+  if !response.status().is_success() {
+    return Err(anyhow::Error::msg(format!(
+      "Request failed with status {}",
+      response.status()
+    )));
+  }
+  Ok(serde_json::from_str(&response.text().await?)?)
+}
+
+// Local addresses of the apps the lookup server knows about. The response is an object keyed by
+// app id, with each app's address info inside.
+fn app_urls(lookup: &Value) -> Vec<String> {
+  let Some(apps) = lookup.as_object() else {
+    return vec![];
+  };
+  apps
+    .values()
+    .filter_map(|app| {
+      if let (Some(domain), Some(port)) = (
+        app.get("domain").and_then(|x| x.as_str()),
+        app.get("httpsPort").and_then(|x| x.as_u64()),
+      ) {
+        Some(format!("https://{}:{}", domain, port))
+      } else {
+        let ip = app.get("ip").and_then(|x| x.as_str())?;
+        let port = app.get("httpPort").and_then(|x| x.as_u64())?;
+        Some(format!("http://{}:{}", ip, port))
+      }
+    })
+    .collect()
+}
+
+async fn check_app(client: &Client, url: &str, token: &Option<String>) -> LovenseConnectStatus {
+  match get_json(client, &format!("{}/GetToys", url), token).await {
+    Ok(response) => LovenseConnectStatus::Connected {
+      address: url.to_owned(),
+      toys: response
+        .get("data")
+        .and_then(|x| x.as_object())
+        .map(|x| x.len() as u32)
+        .unwrap_or_default(),
+    },
+    Err(e) => LovenseConnectStatus::AppUnreachable {
+      address: url.to_owned(),
+      error: e.to_string(),
+    },
+  }
+}
+
+async fn check(client: &Client, options: &LovenseConnectOptions) -> LovenseConnectStatus {
+  if let Some(address) = &options.local_address {
+    let url = if address.contains("://") {
+      address.clone()
+    } else {
+      format!("http://{}", address)
+    };
+    return check_app(client, &url, &options.token).await;
+  }
+  let lookup = match get_json(client, LOVENSE_LOOKUP_URL, &options.token).await {
+    Ok(lookup) => lookup,
+    Err(e) => {
+      return LovenseConnectStatus::LookupFailed {
+        error: e.to_string(),
+      }
+    }
+  };
+  let mut status = LovenseConnectStatus::NoAppFound;
+  // Report the first app that answers, or the last one that didn't if none do.
+  for url in app_urls(&lookup) {
+    status = check_app(client, &url, &options.token).await;
+    if matches!(status, LovenseConnectStatus::Connected { .. }) {
+      break;
+    }
+  }
+  status
+}
+
+pub async fn run_lovense_connect_monitor(options: LovenseConnectOptions, stop: Arc<Notify>) {
+  let client = match Client::builder().timeout(REQUEST_TIMEOUT).build() {
+    Ok(client) => client,
+    Err(e) => {
+      warn!("Cannot create Lovense Connect status client: {:?}", e);
+      return;
+    }
+  };
+  update_status(LovenseConnectStatus::Searching);
+  let interval = Duration::from_millis(options.poll_interval_ms as u64).max(MIN_POLL_INTERVAL);
+  let mut ticker = clock::Ticker::new(interval);
+  loop {
+    if app_lifecycle::in_background() {
+      select! {
+        _ = ticker.tick() => continue,
+        _ = stop.notified() => break,
+      }
+    }
+    let status = select! {
+      status = check(&client, &options) => status,
+      _ = stop.notified() => break,
+    };
+    update_status(status);
+    select! {
+      _ = ticker.tick() => {}
+      _ = stop.notified() => break,
+    }
+  }
+}
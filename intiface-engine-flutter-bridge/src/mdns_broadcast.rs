@@ -0,0 +1,148 @@
+use crate::{
+  api::EngineOptionsExternal,
+  mdns_browse::INTIFACE_MDNS_SERVICE_TYPE,
+  options::{BridgeEngineOptions, MdnsTxtRecord},
+  server_ready,
+};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashMap,
+  net::IpAddr,
+  sync::Arc,
+  time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Notify;
+
+// The engine's mDNS broadcast only carries the address and port, which isn't enough for someone
+// browsing to know whether they'll need TLS or a token to connect. So when broadcasting is on, we
+// turn the engine's off and broadcast the same service ourselves, with TXT records describing the
+// server.
+//
+// Standard TXT keys, which frontends browsing for servers can rely on:
+// - name: server name
+// - version: bridge version
+// - tls: "1" if wss is available, with the port in tls_port
+// - auth: "1" if clients need a token
+
+#[derive(Debug, Clone)]
+pub struct MdnsBroadcast {
+  instance_name: String,
+  port: u16,
+  bind_address: Option<IpAddr>,
+  txt_records: HashMap<String, String>,
+}
+
+// Same naming as the engine, so instances look the same to anything browsing either way.
+fn instance_name(suffix: &Option<String>) -> String {
+  let suffix = suffix.clone().unwrap_or_else(|| {
+    let nanos = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|x| x.as_nanos())
+      .unwrap_or_default();
+    let seed = format!("{}{}", nanos, std::process::id());
+    hex::encode(&Sha256::digest(seed.as_bytes())[..4])
+  });
+  format!("intiface_engine_{}", suffix)
+}
+
+// Takes over broadcasting from the engine, if it was going to. Needs to see args before the
+// websocket proxy moves the engine's ports around, since we broadcast the public port.
+pub fn plan(
+  options: &BridgeEngineOptions,
+  args: &mut EngineOptionsExternal,
+) -> Option<MdnsBroadcast> {
+  if !args.broadcast_server_mdns {
+    return None;
+  }
+  let Some(port) = args.websocket_port else {
+    warn!("mDNS broadcast requested without a websocket port, not broadcasting");
+    return None;
+  };
+  args.broadcast_server_mdns = false;
+  let mut txt_records: HashMap<String, String> = HashMap::new();
+  txt_records.insert("name".to_owned(), args.server_name.clone());
+  txt_records.insert("version".to_owned(), env!("CARGO_PKG_VERSION").to_owned());
+  match &options.websocket_tls {
+    Some(tls_options) => {
+      txt_records.insert("tls".to_owned(), "1".to_owned());
+      txt_records.insert("tls_port".to_owned(), tls_options.port.to_string());
+    }
+    None => {
+      txt_records.insert("tls".to_owned(), "0".to_owned());
+    }
+  }
+  let auth_required = options
+    .websocket_auth_token
+    .as_ref()
+    .is_some_and(|x| !x.is_empty());
+  txt_records.insert(
+    "auth".to_owned(),
+    if auth_required { "1" } else { "0" }.to_owned(),
+  );
+  // Extra records can't override the standard ones, since browsers need to be able to trust them.
+  for MdnsTxtRecord { key, value } in &options.mdns_txt_records {
+    if !txt_records.contains_key(key) {
+      txt_records.insert(key.clone(), value.clone());
+    }
+  }
+  Some(MdnsBroadcast {
+    instance_name: instance_name(&args.mdns_suffix),
+    port,
+    bind_address: options
+      .websocket_bind_address
+      .as_deref()
+      .and_then(|x| x.trim().parse().ok()),
+    txt_records,
+  })
+}
+
+pub async fn run_mdns_broadcast(broadcast: MdnsBroadcast, stop: Arc<Notify>) {
+  // No point telling anyone about a server they can't connect to yet.
+  tokio::select! {
+    _ = server_ready::wait() => {}
+    _ = stop.notified() => return,
+  }
+  let host_name = format!("{}.local.", broadcast.instance_name);
+  let service_info = match broadcast.bind_address {
+    Some(ip) => ServiceInfo::new(
+      INTIFACE_MDNS_SERVICE_TYPE,
+      &broadcast.instance_name,
+      &host_name,
+      ip,
+      broadcast.port,
+      broadcast.txt_records,
+    ),
+    None => ServiceInfo::new(
+      INTIFACE_MDNS_SERVICE_TYPE,
+      &broadcast.instance_name,
+      &host_name,
+      (),
+      broadcast.port,
+      broadcast.txt_records,
+    )
+    .map(|x| x.enable_addr_auto()),
+  };
+  let daemon = ServiceDaemon::new().and_then(|daemon| {
+    let service_info = service_info?;
+    let fullname = service_info.get_fullname().to_owned();
+    daemon.register(service_info)?;
+    Ok((daemon, fullname))
+  });
+  let (daemon, fullname) = match daemon {
+    Ok(daemon) => daemon,
+    Err(e) => {
+      error!("Cannot start mDNS broadcast: {:?}", e);
+      return;
+    }
+  };
+  info!("Broadcasting mDNS as {}", fullname);
+  stop.notified().await;
+  if let Err(e) = daemon.unregister(&fullname) {
+    debug!("Cannot unregister mDNS service: {:?}", e);
+  }
+  if let Err(e) = daemon.shutdown() {
+    debug!("Cannot shut down mDNS daemon: {:?}", e);
+  }
+  info!("mDNS broadcast stopped");
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use flutter_rust_bridge::StreamSink;
+use lazy_static::lazy_static;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use std::{sync::Mutex, thread};
+
+// Looks for other Intiface instances broadcasting over mDNS (broadcast_server_mdns), so the
+// repeater and remote connection settings can offer a list instead of making people type addresses.
+// Doesn't need the engine, and can run alongside it.
+
+// Service type Intiface Engine broadcasts under.
+pub const INTIFACE_MDNS_SERVICE_TYPE: &str = "_intiface_engine._tcp.local.";
+
+#[derive(Debug, Clone)]
+pub struct MdnsServer {
+  // mDNS instance name, e.g. "intiface_engine_abcd". Unique per broadcasting instance.
+  pub name: String,
+  pub hostname: String,
+  pub addresses: Vec<String>,
+  pub port: u16,
+  // From TXT records. Instances broadcast by the engine itself (rather than through the bridge)
+  // won't have these.
+  pub server_name: Option<String>,
+  pub version: Option<String>,
+  pub tls_port: Option<u16>,
+  pub auth_required: bool,
+  // Every TXT record, standard ones included, as (key, value).
+  pub txt_records: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum MdnsServerEvent {
+  Found(MdnsServer),
+  Lost { name: String },
+}
+
+lazy_static! {
+  static ref BROWSE_DAEMON: Mutex<Option<ServiceDaemon>> = Mutex::new(None);
+}
+
+fn instance_name(fullname: &str) -> String {
+  fullname
+    .strip_suffix(INTIFACE_MDNS_SERVICE_TYPE)
+    .map(|x| x.trim_end_matches('.'))
+    .unwrap_or(fullname)
+    .to_owned()
+}
+
+pub fn browse(sink: StreamSink<MdnsServerEvent>) -> Result<()> {
+  let mut browse_daemon = BROWSE_DAEMON.lock().unwrap();
+  if browse_daemon.is_some() {
+    return Err(anyhow::Error::msg("mDNS browse already running"));
+  }
+  let daemon = ServiceDaemon::new()?;
+  let receiver = daemon.browse(INTIFACE_MDNS_SERVICE_TYPE)?;
+  *browse_daemon = Some(daemon);
+  thread::spawn(move || {
+    // Ends when the browse is stopped, as the daemon drops its side of the channel.
+    while let Ok(event) = receiver.recv() {
+      match event {
+        ServiceEvent::ServiceResolved(info) => {
+          let mut addresses: Vec<String> =
+            info.get_addresses().iter().map(|x| x.to_string()).collect();
+          addresses.sort();
+          sink.add(MdnsServerEvent::Found(MdnsServer {
+            name: instance_name(info.get_fullname()),
+            hostname: info.get_hostname().trim_end_matches('.').to_owned(),
+            addresses,
+            port: info.get_port(),
+            server_name: info.get_property_val_str("name").map(|x| x.to_owned()),
+            version: info.get_property_val_str("version").map(|x| x.to_owned()),
+            tls_port: (info.get_property_val_str("tls") == Some("1"))
+              .then(|| info.get_property_val_str("tls_port"))
+              .flatten()
+              .and_then(|x| x.parse().ok()),
+            auth_required: info.get_property_val_str("auth") == Some("1"),
+            txt_records: info
+              .get_properties()
+              .iter()
+              .map(|x| (x.key().to_owned(), x.val_str().to_owned()))
+              .collect(),
+          }));
+        }
+        ServiceEvent::ServiceRemoved(_, fullname) => {
+          sink.add(MdnsServerEvent::Lost {
+            name: instance_name(&fullname),
+          });
+        }
+        ServiceEvent::SearchStopped(_) => break,
+        _ => {}
+      }
+    }
+    sink.close();
+  });
+  Ok(())
+}
+
+pub fn stop() {
+  let Some(daemon) = BROWSE_DAEMON.lock().unwrap().take() else {
+    return;
+  };
+  if let Err(e) = daemon.stop_browse(INTIFACE_MDNS_SERVICE_TYPE) {
+    debug!("Cannot stop mDNS browse: {:?}", e);
+  }
+  if let Err(e) = daemon.shutdown() {
+    debug!("Cannot shut down mDNS daemon: {:?}", e);
+  }
+}
@@ -0,0 +1,51 @@
+use flutter_rust_bridge::StreamSink;
+use std::sync::{Arc, RwLock};
+
+// Where engine messages end up. In the app that's a Flutter stream, but the engine side only needs
+// to add and close, so it's kept behind a trait that other consumers (like the test harness) can
+// implement too.
+pub trait MessageSink: Send + Sync {
+  fn add(&self, msg: String) -> bool;
+  fn close(&self) -> bool;
+}
+
+pub type SharedMessageSink = Arc<dyn MessageSink>;
+
+impl MessageSink for StreamSink<String> {
+  fn add(&self, msg: String) -> bool {
+    StreamSink::add(self, msg)
+  }
+
+  fn close(&self) -> bool {
+    StreamSink::close(self)
+  }
+}
+
+// Sink that can be pointed at a new stream while the engine is using it. A Dart hot restart kills
+// the stream a running engine was sending to, and this lets the restarted frontend pick it back up.
+pub struct SwappableSink {
+  inner: RwLock<SharedMessageSink>,
+}
+
+impl SwappableSink {
+  pub fn new(sink: SharedMessageSink) -> Self {
+    Self {
+      inner: RwLock::new(sink),
+    }
+  }
+
+  // Returns the sink that was replaced.
+  pub fn swap(&self, sink: SharedMessageSink) -> SharedMessageSink {
+    std::mem::replace(&mut *self.inner.write().unwrap(), sink)
+  }
+}
+
+impl MessageSink for SwappableSink {
+  fn add(&self, msg: String) -> bool {
+    self.inner.read().unwrap().add(msg)
+  }
+
+  fn close(&self) -> bool {
+    self.inner.read().unwrap().close()
+  }
+}
@@ -0,0 +1,303 @@
+use crate::{
+  clock,
+  websocket_proxy::{read_request_header, REQUEST_HEADER_TIMEOUT},
+};
+use intiface_engine::EngineMessage;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+  collections::VecDeque,
+  fmt::Write,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+use tokio::{io::AsyncWriteExt, net::TcpListener, select, sync::Notify};
+
+// Messages per second is averaged over roughly this long, going by when stats were asked for.
+const MESSAGE_RATE_WINDOW: Duration = Duration::from_secs(10);
+
+lazy_static! {
+  pub static ref ENGINE_METRICS: EngineMetrics = EngineMetrics::default();
+}
+
+// Snapshot of the current engine session for the status page.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionStats {
+  // None if the engine isn't running.
+  pub uptime_ms: Option<u64>,
+  // Buttplug messages seen this session, see EngineMetrics::total_messages.
+  pub total_messages: u64,
+  pub messages_per_second: f64,
+  pub connected_clients: u32,
+  pub connected_devices: u32,
+}
+
+#[derive(Default)]
+struct Session {
+  started_at: Option<Instant>,
+  // Total message count when the session started.
+  message_baseline: u64,
+  // (time, total message count), oldest first.
+  rate_samples: VecDeque<(Instant, u64)>,
+}
+
+// Counters for everything that passes through the bridge. We only see what the engine tells the
+// frontend and what goes over the backdoor, so this isn't a full view of server traffic, but it's
+// enough to tell whether things are alive and how busy they are.
+#[derive(Default)]
+pub struct EngineMetrics {
+  engine_running: AtomicU64,
+  clients_connected: AtomicU64,
+  devices_connected: AtomicU64,
+  client_connections: AtomicU64,
+  client_rejections: AtomicU64,
+  device_connections: AtomicU64,
+  engine_errors: AtomicU64,
+  engine_messages: AtomicU64,
+  backdoor_messages_incoming: AtomicU64,
+  backdoor_messages_outgoing: AtomicU64,
+  client_messages: AtomicU64,
+  session: Mutex<Session>,
+}
+
+fn saturating_decrement(counter: &AtomicU64) {
+  let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+    Some(x.saturating_sub(1))
+  });
+}
+
+impl EngineMetrics {
+  pub fn record_engine_message(&self, msg: &EngineMessage) {
+    self.engine_messages.fetch_add(1, Ordering::Relaxed);
+    match msg {
+      EngineMessage::EngineStarted { .. } => {
+        self.engine_running.store(1, Ordering::Relaxed);
+        *self.session.lock().unwrap() = Session {
+          started_at: Some(clock::now()),
+          message_baseline: self.total_messages(),
+          rate_samples: VecDeque::new(),
+        };
+      }
+      EngineMessage::EngineStopped { .. } => {
+        self.engine_running.store(0, Ordering::Relaxed);
+        self.clients_connected.store(0, Ordering::Relaxed);
+        self.devices_connected.store(0, Ordering::Relaxed);
+        self.session.lock().unwrap().started_at = None;
+      }
+      EngineMessage::EngineError { .. } => {
+        self.engine_errors.fetch_add(1, Ordering::Relaxed);
+      }
+      EngineMessage::ClientConnected { .. } => {
+        self.clients_connected.fetch_add(1, Ordering::Relaxed);
+        self.client_connections.fetch_add(1, Ordering::Relaxed);
+      }
+      EngineMessage::ClientDisconnected { .. } => saturating_decrement(&self.clients_connected),
+      EngineMessage::ClientRejected { .. } => {
+        self.client_rejections.fetch_add(1, Ordering::Relaxed);
+      }
+      EngineMessage::DeviceConnected { .. } => {
+        self.devices_connected.fetch_add(1, Ordering::Relaxed);
+        self.device_connections.fetch_add(1, Ordering::Relaxed);
+      }
+      EngineMessage::DeviceDisconnected { .. } => saturating_decrement(&self.devices_connected),
+      _ => {}
+    }
+  }
+
+  pub fn record_backdoor_incoming(&self) {
+    self
+      .backdoor_messages_incoming
+      .fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn record_backdoor_outgoing(&self) {
+    self
+      .backdoor_messages_outgoing
+      .fetch_add(1, Ordering::Relaxed);
+  }
+
+  // Buttplug messages in either direction on a client connection through the websocket proxy.
+  pub fn record_client_messages(&self, count: usize) {
+    self
+      .client_messages
+      .fetch_add(count as u64, Ordering::Relaxed);
+  }
+
+  // Every Buttplug message we've seen. The engine doesn't count messages itself, so this only
+  // covers clients that came through the websocket proxy, plus the backdoor.
+  fn total_messages(&self) -> u64 {
+    self.client_messages.load(Ordering::Relaxed)
+      + self.backdoor_messages_incoming.load(Ordering::Relaxed)
+      + self.backdoor_messages_outgoing.load(Ordering::Relaxed)
+  }
+
+  pub fn session_stats(&self) -> SessionStats {
+    let now = clock::now();
+    let total = self.total_messages();
+    let mut session = self.session.lock().unwrap();
+    let (uptime_ms, messages_per_second) = match session.started_at {
+      Some(started_at) => {
+        while session.rate_samples.len() > 1
+          && now.duration_since(session.rate_samples[1].0) >= MESSAGE_RATE_WINDOW
+        {
+          session.rate_samples.pop_front();
+        }
+        // Until there's an earlier sample to go on, average over the whole session.
+        let (since, since_total) = session
+          .rate_samples
+          .front()
+          .copied()
+          .unwrap_or((started_at, session.message_baseline));
+        session.rate_samples.push_back((now, total));
+        let elapsed = now.duration_since(since).as_secs_f64();
+        let rate = if elapsed > 0.0 {
+          total.saturating_sub(since_total) as f64 / elapsed
+        } else {
+          0.0
+        };
+        (
+          Some(now.duration_since(started_at).as_millis() as u64),
+          rate,
+        )
+      }
+      None => (None, 0.0),
+    };
+    SessionStats {
+      uptime_ms,
+      total_messages: total.saturating_sub(session.message_baseline),
+      messages_per_second,
+      connected_clients: self.clients_connected.load(Ordering::Relaxed) as u32,
+      connected_devices: self.devices_connected.load(Ordering::Relaxed) as u32,
+    }
+  }
+
+  // Called when the engine task exits, in case we never got an EngineStopped (i.e. on panic).
+  pub fn reset_gauges(&self) {
+    self.engine_running.store(0, Ordering::Relaxed);
+    self.clients_connected.store(0, Ordering::Relaxed);
+    self.devices_connected.store(0, Ordering::Relaxed);
+    self.session.lock().unwrap().started_at = None;
+  }
+
+  // Renders all metrics in Prometheus text exposition format (version 0.0.4).
+  pub fn render(&self) -> String {
+    let metrics: [(&str, &str, &str, &AtomicU64); 11] = [
+      (
+        "intiface_engine_running",
+        "gauge",
+        "Whether the engine is currently running.",
+        &self.engine_running,
+      ),
+      (
+        "intiface_clients_connected",
+        "gauge",
+        "Number of Buttplug clients currently connected.",
+        &self.clients_connected,
+      ),
+      (
+        "intiface_devices_connected",
+        "gauge",
+        "Number of devices currently connected.",
+        &self.devices_connected,
+      ),
+      (
+        "intiface_client_connections_total",
+        "counter",
+        "Total number of client connections accepted.",
+        &self.client_connections,
+      ),
+      (
+        "intiface_client_rejections_total",
+        "counter",
+        "Total number of client connections rejected.",
+        &self.client_rejections,
+      ),
+      (
+        "intiface_device_connections_total",
+        "counter",
+        "Total number of device connections.",
+        &self.device_connections,
+      ),
+      (
+        "intiface_engine_errors_total",
+        "counter",
+        "Total number of errors reported by the engine.",
+        &self.engine_errors,
+      ),
+      (
+        "intiface_engine_messages_total",
+        "counter",
+        "Total number of messages sent from the engine to the frontend.",
+        &self.engine_messages,
+      ),
+      (
+        "intiface_backdoor_messages_incoming_total",
+        "counter",
+        "Total number of messages sent to the backdoor server.",
+        &self.backdoor_messages_incoming,
+      ),
+      (
+        "intiface_backdoor_messages_outgoing_total",
+        "counter",
+        "Total number of messages emitted by the backdoor server.",
+        &self.backdoor_messages_outgoing,
+      ),
+      (
+        "intiface_client_messages_total",
+        "counter",
+        "Total number of Buttplug messages to and from clients on the websocket proxy.",
+        &self.client_messages,
+      ),
+    ];
+    let mut out = String::new();
+    for (name, metric_type, help, value) in metrics {
+      let _ = writeln!(out, "# HELP {name} {help}");
+      let _ = writeln!(out, "# TYPE {name} {metric_type}");
+      let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+    }
+    out
+  }
+}
+
+// Bare minimum HTTP responder. Every request gets the metrics page regardless of path or method,
+// which is all a Prometheus scraper needs. Only binds to localhost, if people want this off-box they
+// can put a proxy in front of it.
+pub async fn serve_metrics(port: u16, stop: Arc<Notify>) {
+  let listener = match TcpListener::bind(("127.0.0.1", port)).await {
+    Ok(listener) => listener,
+    Err(e) => {
+      error!("Cannot bind metrics endpoint to port {}: {:?}", port, e);
+      return;
+    }
+  };
+  info!("Serving metrics on http://127.0.0.1:{}/metrics", port);
+  loop {
+    select! {
+      conn = listener.accept() => {
+        let Ok((mut stream, _)) = conn else {
+          continue;
+        };
+        tokio::spawn(async move {
+          // We don't care what the request was, but we need to read it before answering or some
+          // clients will see a connection reset. Anything slow or oversized gets dropped instead.
+          let read = tokio::time::timeout(REQUEST_HEADER_TIMEOUT, read_request_header(&mut stream));
+          if !matches!(read.await, Ok(Ok(_))) {
+            return;
+          }
+          let body = ENGINE_METRICS.render();
+          let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+          );
+          let _ = stream.write_all(response.as_bytes()).await;
+        });
+      }
+      _ = stop.notified() => break
+    }
+  }
+  info!("Metrics endpoint shut down");
+}
@@ -1,4 +1,3 @@
-use flutter_rust_bridge::StreamSink;
 use jni::objects::GlobalRef;
 use jni::{AttachGuard, JNIEnv, JavaVM};
 use once_cell::sync::OnceCell;
@@ -6,7 +5,7 @@ use std::cell::RefCell;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::runtime::Runtime;
 
-use crate::mobile_init::Error;
+use crate::{mobile_init::Error, options};
 
 static CLASS_LOADER: OnceCell<GlobalRef> = OnceCell::new();
 pub static JAVAVM: OnceCell<JavaVM> = OnceCell::new();
@@ -15,7 +14,8 @@ std::thread_local! {
   static JNI_ENV: RefCell<Option<AttachGuard<'static>>> = RefCell::new(None);
 }
 
-pub fn create_runtime(_: StreamSink<String>) -> Result<Runtime, Error> {
+pub fn create_runtime() -> Result<Runtime, Error> {
+  let threads = options::bridge_options().runtime_threads;
   let vm = JAVAVM.get().ok_or(Error::JavaVM)?;
   let env = vm.attach_current_thread().unwrap();
 
@@ -26,6 +26,8 @@ pub fn create_runtime(_: StreamSink<String>) -> Result<Runtime, Error> {
   let runtime = {
     tokio::runtime::Builder::new_multi_thread()
       .enable_all()
+      .worker_threads(threads.worker_threads())
+      .max_blocking_threads(threads.max_blocking_threads())
       .thread_name_fn(|| {
         static ATOMIC_ID: AtomicUsize = AtomicUsize::new(0);
         let id = ATOMIC_ID.fetch_add(1, Ordering::SeqCst);
@@ -1,12 +1,15 @@
-use crate::mobile_init::Error;
-use flutter_rust_bridge::StreamSink;
+use crate::{mobile_init::Error, options};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::runtime::Runtime;
 
-pub fn create_runtime(_: StreamSink<String>) -> Result<Runtime, Error> {
+// Thread limits come from the bridge options, so set those before creating runtimes.
+pub fn create_runtime() -> Result<Runtime, Error> {
+  let threads = options::bridge_options().runtime_threads;
   let runtime = {
     tokio::runtime::Builder::new_multi_thread()
       .enable_all()
+      .worker_threads(threads.worker_threads())
+      .max_blocking_threads(threads.max_blocking_threads())
       .thread_name_fn(|| {
         static ATOMIC_ID: AtomicUsize = AtomicUsize::new(0);
         let id = ATOMIC_ID.fetch_add(1, Ordering::SeqCst);
@@ -0,0 +1,86 @@
+use crate::server_ready;
+use anyhow::Result;
+use std::{
+  io,
+  net::{Ipv4Addr, TcpListener},
+};
+
+// Addresses the websocket server could be bound to, so the frontend can offer a list instead of
+// making people go look up their VPN address. Also whether ports are free, so settings can warn
+// about something else (another Intiface, OBS's websocket server) sitting on a port before the
+// engine fails to start over it.
+
+#[derive(Debug, Clone)]
+pub struct NetworkInterface {
+  pub name: String,
+  pub address: String,
+  pub loopback: bool,
+  pub ipv6: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortStatus {
+  Available,
+  // Our own running engine (or the bridge in front of it) is listening there.
+  UsedByEngine,
+  InUse,
+}
+
+#[derive(Debug, Clone)]
+pub struct PortCheck {
+  pub port: u16,
+  pub status: PortStatus,
+  // What the OS said, when something else has the port.
+  pub message: Option<String>,
+}
+
+// Tries binding the port the way the servers would, on localhost, and on every interface as well if
+// that's what they'll be using.
+pub fn port_bind_error(port: u16, all_interfaces: bool) -> Option<io::Error> {
+  let mut addresses = vec![Ipv4Addr::LOCALHOST];
+  if all_interfaces {
+    addresses.push(Ipv4Addr::UNSPECIFIED);
+  }
+  addresses
+    .into_iter()
+    .find_map(|address| TcpListener::bind((address, port)).err())
+}
+
+pub fn check_port(port: u16, all_interfaces: bool) -> PortCheck {
+  let Some(error) = port_bind_error(port, all_interfaces) else {
+    return PortCheck {
+      port,
+      status: PortStatus::Available,
+      message: None,
+    };
+  };
+  let suffix = format!(":{}", port);
+  let ours = server_ready::addresses()
+    .unwrap_or_default()
+    .iter()
+    .any(|server| server.addresses.iter().any(|x| x.ends_with(&suffix)));
+  PortCheck {
+    port,
+    status: if ours {
+      PortStatus::UsedByEngine
+    } else {
+      PortStatus::InUse
+    },
+    message: Some(error.to_string()),
+  }
+}
+
+pub fn list_network_interfaces() -> Result<Vec<NetworkInterface>> {
+  let mut interfaces: Vec<NetworkInterface> = if_addrs::get_if_addrs()?
+    .into_iter()
+    .map(|interface| NetworkInterface {
+      address: interface.ip().to_string(),
+      loopback: interface.is_loopback(),
+      ipv6: interface.ip().is_ipv6(),
+      name: interface.name,
+    })
+    .collect();
+  // Loopback last, since it's never what people are looking for when picking an interface.
+  interfaces.sort_by(|a, b| (a.loopback, &a.name, a.ipv6).cmp(&(b.loopback, &b.name, b.ipv6)));
+  Ok(interfaces)
+}
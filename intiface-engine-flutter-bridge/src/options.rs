@@ -0,0 +1,379 @@
+use crate::simulator::SimulatedDeviceKind;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::sync::RwLock;
+
+// EngineOptionsExternal is mirrored straight out of Intiface Engine, so we can't add fields to it
+// for things that only the bridge knows how to do. Those live here instead, and get set before
+// run_engine is called.
+#[derive(Debug, Clone, Default)]
+pub struct BridgeEngineOptions {
+  // If set, serve engine metrics in Prometheus format at http://127.0.0.1:<port>/metrics while the
+  // engine is running.
+  pub metrics_port: Option<u16>,
+  // If set, read battery levels on connected devices this often (at most once a second) and send
+  // them out as bridge events.
+  pub battery_poll_interval_ms: Option<u32>,
+  // If set, send per-device command latency and error counts out as bridge events this often (at
+  // most once a second).
+  pub device_stats_interval_ms: Option<u32>,
+  pub reconnect_storm: ReconnectStormOptions,
+  pub runtime_threads: RuntimeThreadOptions,
+  // If set, also serve the websocket server over TLS. Needs websocket_port to be set.
+  pub websocket_tls: Option<WebsocketTlsOptions>,
+  // If set, also serve the device websocket server over TLS. Needs use_device_websocket_server.
+  pub device_websocket_tls: Option<WebsocketTlsOptions>,
+  // If set, websocket clients (plain or TLS) have to pass this token to connect, either as a
+  // "token" query parameter or a bearer Authorization header.
+  pub websocket_auth_token: Option<String>,
+  // If set, only listen for websocket clients (plain or TLS) and websocket devices on this address,
+  // e.g. the address of a VPN or LAN interface from list_network_interfaces. Overrides
+  // websocket_use_all_interfaces.
+  pub websocket_bind_address: Option<String>,
+  // IP versions to listen on, for both the websocket server and the device websocket server.
+  pub websocket_ip_mode: WebsocketIpMode,
+  // Extra TXT records for the mDNS broadcast, when broadcast_server_mdns is on. Can't replace the
+  // standard ones (see mdns_broadcast).
+  pub mdns_txt_records: Vec<MdnsTxtRecord>,
+  pub client_reconnect: ClientReconnectOptions,
+  pub engine_restart: EngineRestartOptions,
+  // Fake devices to connect to the engine, for testing clients without hardware. See simulator.
+  pub simulated_devices: Vec<SimulatedDeviceKind>,
+  pub lovense_connect: LovenseConnectOptions,
+  pub shutdown: ShutdownOptions,
+  pub idle_stop: IdleStopOptions,
+  // Scan for devices while a client is connected, for clients that never ask to (see auto_scan).
+  pub scan_on_client_connect: bool,
+  // If set, stop scanning this long after it was started (see scan_timeout).
+  pub scan_timeout_ms: Option<u32>,
+  pub auto_reconnect: AutoReconnectOptions,
+  // Only connect devices allowed in the user config, and report anything else as blocked (see
+  // allowlist).
+  pub allowlist_only: bool,
+  pub error_reporting: ErrorReportingOptions,
+  pub port_mapping: PortMappingOptions,
+  // If set, peers connecting to the repeater's local port have to pass this token, the same ways as
+  // websocket_auth_token.
+  pub repeater_auth_token: Option<String>,
+  // If set, also take remote clients through a relay we connect out to, for when the server can't
+  // be reached from outside (see tunnel). Needs websocket_port to be set.
+  pub tunnel: Option<TunnelOptions>,
+  pub backdoor_rate_limit: BackdoorRateLimitOptions,
+  // If set, the newest Buttplug spec version the server admits to, for testing older client apps.
+  // Clients asking for anything newer are turned away, as a server that old would. Puts the
+  // websocket server behind the proxy, as that's where it's done (see clients).
+  pub max_spec_version: Option<u32>,
+  pub client_sessions: ClientSessionOptions,
+}
+
+// Limits how fast the frontend can send messages to the backdoor server (see backdoor_throttle).
+// Each message in an array counts. Up to burst can go through at once, refilling at
+// max_messages_per_second.
+#[derive(Debug, Clone)]
+pub struct BackdoorRateLimitOptions {
+  pub enabled: bool,
+  pub max_messages_per_second: u32,
+  pub burst: u32,
+}
+
+impl Default for BackdoorRateLimitOptions {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      max_messages_per_second: 100,
+      burst: 200,
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct TunnelOptions {
+  // ws:// or wss:// address of the relay, including whatever path or query it uses to pair us up
+  // with remote clients.
+  pub relay_url: String,
+  // Sent to the relay as a bearer Authorization header, if set.
+  pub token: Option<String>,
+}
+
+// Asking the router to forward the websocket ports (plain and TLS) over UPnP or NAT-PMP, so clients
+// can connect from outside the network (see port_mapping). Needs the websocket server listening on
+// all interfaces or a LAN address. Mappings are leased for lease_seconds (at least 2 minutes) and
+// renewed until the engine stops.
+#[derive(Debug, Clone)]
+pub struct PortMappingOptions {
+  pub enabled: bool,
+  pub lease_seconds: u32,
+}
+
+impl Default for PortMappingOptions {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      lease_seconds: 3600,
+    }
+  }
+}
+
+// Sending errors that don't crash anything to Sentry, scrubbed (see error_reporting). Needs
+// crash_reporting to have been called. At most max_events_per_hour are sent, and the same error only
+// once an hour.
+#[derive(Debug, Clone)]
+pub struct ErrorReportingOptions {
+  pub enabled: bool,
+  pub max_events_per_hour: u32,
+}
+
+impl Default for ErrorReportingOptions {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      max_events_per_hour: 10,
+    }
+  }
+}
+
+// Scanning for devices that have dropped (see auto_reconnect). Every check_interval_ms (at least 10
+// seconds), if any are missing, scan for scan_duration_ms (at most half the interval).
+#[derive(Debug, Clone)]
+pub struct AutoReconnectOptions {
+  pub enabled: bool,
+  pub check_interval_ms: u32,
+  pub scan_duration_ms: u32,
+}
+
+impl Default for AutoReconnectOptions {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      check_interval_ms: 60_000,
+      scan_duration_ms: 10_000,
+    }
+  }
+}
+
+// Stopping things once no client has been connected for idle_minutes (see idle_stop), with a warning
+// event warning_ms beforehand. Only applies in server mode.
+#[derive(Debug, Clone)]
+pub struct IdleStopOptions {
+  pub enabled: bool,
+  pub idle_minutes: u32,
+  pub warning_ms: u32,
+  pub action: IdleStopAction,
+}
+
+impl Default for IdleStopOptions {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      idle_minutes: 30,
+      warning_ms: 60_000,
+      action: IdleStopAction::StopEngine,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum IdleStopAction {
+  StopEngine,
+  // Stops scanning and any devices that are running, leaving the engine and devices connected.
+  StopDevices,
+}
+
+// How long stop_engine gives each stage of stopping before moving on to the next, more forceful, one
+// (see shutdown). Graceful is the engine and its devices stopping by themselves, cancel is the
+// engine task after it's been cancelled, and runtime is the runtime's own shutdown.
+#[derive(Debug, Clone)]
+pub struct ShutdownOptions {
+  pub graceful_timeout_ms: u32,
+  pub cancel_timeout_ms: u32,
+  pub runtime_timeout_ms: u32,
+}
+
+impl Default for ShutdownOptions {
+  fn default() -> Self {
+    Self {
+      graceful_timeout_ms: 5000,
+      cancel_timeout_ms: 1000,
+      runtime_timeout_ms: 1000,
+    }
+  }
+}
+
+// Settings for the Lovense Connect status check that runs alongside the engine's own discovery when
+// use_lovense_connect is on (see lovense_connect). The engine's discovery doesn't take any of these.
+#[derive(Debug, Clone)]
+pub struct LovenseConnectOptions {
+  // Address of the Lovense Connect app, as ip:port or a full URL, to check directly instead of
+  // asking Lovense's lookup server where it is.
+  pub local_address: Option<String>,
+  // At least a second.
+  pub poll_interval_ms: u32,
+  // Sent along with lookups and app requests as the "token" query parameter, if set.
+  pub token: Option<String>,
+}
+
+impl Default for LovenseConnectOptions {
+  fn default() -> Self {
+    Self {
+      local_address: None,
+      poll_interval_ms: 5000,
+      token: None,
+    }
+  }
+}
+
+// Reconnects for when the engine is connecting out as a websocket client. Backoff doubles each
+// attempt, starting at initial_backoff_ms and capped at max_backoff_ms, then gets spread out by up to
+// the jitter fraction (0.0-1.0) either way. No max_retries means keep trying until stopped.
+#[derive(Debug, Clone)]
+pub struct ClientReconnectOptions {
+  pub enabled: bool,
+  pub max_retries: Option<u32>,
+  pub initial_backoff_ms: u32,
+  pub max_backoff_ms: u32,
+  pub jitter: f64,
+}
+
+impl Default for ClientReconnectOptions {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      max_retries: None,
+      initial_backoff_ms: 1000,
+      max_backoff_ms: 30_000,
+      jitter: 0.2,
+    }
+  }
+}
+
+// Restarts for when the engine stops with an error in server mode (see engine_supervisor). Backoff
+// doubles each attempt, starting at initial_backoff_ms and capped at max_backoff_ms.
+#[derive(Debug, Clone)]
+pub struct EngineRestartOptions {
+  pub enabled: bool,
+  pub max_retries: u32,
+  pub initial_backoff_ms: u32,
+  pub max_backoff_ms: u32,
+}
+
+impl Default for EngineRestartOptions {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      max_retries: 5,
+      initial_backoff_ms: 1000,
+      max_backoff_ms: 60_000,
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct MdnsTxtRecord {
+  pub key: String,
+  pub value: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WebsocketIpMode {
+  // What the engine does by itself.
+  #[default]
+  Ipv4,
+  DualStack,
+  Ipv6,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebsocketTlsOptions {
+  // Port for wss connections. Plain ws connections still go to websocket_port (or
+  // device_websocket_server_port, for the device server).
+  pub port: u16,
+  // PEM encoded. If either is missing, a self-signed certificate is generated for each run.
+  pub certificate_pem: Option<String>,
+  pub private_key_pem: Option<String>,
+}
+
+// Thread limits for the tokio runtimes the bridge creates. Anything left unset gets a default for
+// the platform we're on.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeThreadOptions {
+  pub worker_threads: Option<u32>,
+  pub max_blocking_threads: Option<u32>,
+}
+
+impl RuntimeThreadOptions {
+  pub fn worker_threads(&self) -> usize {
+    match self.worker_threads {
+      Some(threads) => (threads as usize).max(1),
+      // Phones have plenty of cores these days, but a lot of them are low power ones, and we're
+      // sharing them with the UI. Device traffic doesn't need more than a couple of threads anyways.
+      None if cfg!(any(target_os = "android", target_os = "ios")) => 2,
+      // Same as tokio's default.
+      None => std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1),
+    }
+  }
+
+  pub fn max_blocking_threads(&self) -> usize {
+    match self.max_blocking_threads {
+      Some(threads) => (threads as usize).max(1),
+      None if cfg!(any(target_os = "android", target_os = "ios")) => 32,
+      // Same as tokio's default.
+      None => 512,
+    }
+  }
+}
+
+// A device that connects more than max_connections times within window_ms is quarantined for
+// quarantine_ms, during which its connects and disconnects aren't passed on to the frontend.
+#[derive(Debug, Clone)]
+pub struct ReconnectStormOptions {
+  pub enabled: bool,
+  pub max_connections: u32,
+  pub window_ms: u32,
+  pub quarantine_ms: u32,
+}
+
+impl Default for ReconnectStormOptions {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      max_connections: 5,
+      window_ms: 60_000,
+      quarantine_ms: 300_000,
+    }
+  }
+}
+
+// Letting more than one Buttplug client connect at once (see client_sessions). Puts the websocket
+// server behind the proxy, as the engine's only takes one client.
+#[derive(Debug, Clone, Default)]
+pub struct ClientSessionOptions {
+  pub multiple_clients: bool,
+  // Who gets to command which devices, when more than one client is connected.
+  pub arbitration: DeviceArbitration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeviceArbitration {
+  // The first client to command a device gets all of them, until it disconnects.
+  #[default]
+  Exclusive,
+  // Every client can command every device.
+  Shared,
+  // The first client to command a device gets that device, until it disconnects.
+  PerDevice,
+}
+
+lazy_static! {
+  static ref BRIDGE_OPTIONS: RwLock<BridgeEngineOptions> =
+    RwLock::new(BridgeEngineOptions::default());
+}
+
+pub fn set_bridge_options(options: BridgeEngineOptions) {
+  *BRIDGE_OPTIONS.write().unwrap() = options;
+}
+
+pub fn bridge_options() -> BridgeEngineOptions {
+  BRIDGE_OPTIONS.read().unwrap().clone()
+}
@@ -0,0 +1,385 @@
+use crate::{
+  api::EngineOptionsExternal, network_interfaces, options::BridgeEngineOptions,
+  user_config_validation,
+};
+use buttplug::util::device_configuration::load_protocol_configs;
+use serde::Serialize;
+use std::{
+  cmp::Reverse,
+  collections::HashMap,
+  net::{TcpStream, ToSocketAddrs},
+  path::Path,
+  time::Duration,
+};
+
+// Checks engine options before they go to run_engine, which otherwise only finds most of these
+// problems by failing partway through starting, one at a time. Findings are tied to the option
+// that caused them, so the settings UI can show them next to it. Errors will stop the engine from
+// starting or working, warnings are things that probably aren't what was meant.
+
+const CLIENT_ADDRESS_TIMEOUT: Duration = Duration::from_secs(2);
+// Newest Buttplug spec version the engine's library speaks.
+const MAX_SUPPORTED_SPEC_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum OptionsFindingSeverity {
+  Warning,
+  Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum OptionsFindingKind {
+  PortInUse,
+  // Same port given for more than one server.
+  PortConflict,
+  ConflictingModes,
+  MissingSetting,
+  InvalidConfig,
+  MissingConfig,
+  InvalidAddress,
+  UnreachableAddress,
+  // Setting does nothing with the others as they are.
+  IgnoredSetting,
+  NoDeviceTransports,
+  OutOfRange,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OptionsFinding {
+  pub severity: OptionsFindingSeverity,
+  pub kind: OptionsFindingKind,
+  // Name of the option, as in EngineOptionsExternal or BridgeEngineOptions.
+  pub field: String,
+  pub message: String,
+}
+
+fn finding(
+  severity: OptionsFindingSeverity,
+  kind: OptionsFindingKind,
+  field: &str,
+  message: String,
+) -> OptionsFinding {
+  OptionsFinding {
+    severity,
+    kind,
+    field: field.to_owned(),
+    message,
+  }
+}
+
+// Every port we'd be listening on, with the option it came from.
+fn listening_ports(
+  args: &EngineOptionsExternal,
+  bridge_options: &BridgeEngineOptions,
+) -> Vec<(&'static str, u16)> {
+  let client_mode = args.websocket_client_address.is_some();
+  let mut ports = vec![];
+  if !client_mode {
+    if let Some(port) = args.websocket_port {
+      ports.push(("websocket_port", port));
+    }
+    if let Some(tls) = &bridge_options.websocket_tls {
+      ports.push(("websocket_tls.port", tls.port));
+    }
+  }
+  if args.use_device_websocket_server {
+    if let Some(port) = args.device_websocket_server_port {
+      ports.push(("device_websocket_server_port", port));
+    }
+    if let Some(tls) = &bridge_options.device_websocket_tls {
+      ports.push(("device_websocket_tls.port", tls.port));
+    }
+  }
+  if !args.frontend_in_process_channel {
+    if let Some(port) = args.frontend_websocket_port {
+      ports.push(("frontend_websocket_port", port));
+    }
+  }
+  if args.repeater_mode {
+    if let Some(port) = args.repeater_local_port {
+      ports.push(("repeater_local_port", port));
+    }
+  }
+  if let Some(port) = bridge_options.metrics_port {
+    ports.push(("metrics_port", port));
+  }
+  ports
+}
+
+fn check_ports(
+  args: &EngineOptionsExternal,
+  bridge_options: &BridgeEngineOptions,
+  engine_running: bool,
+) -> Vec<OptionsFinding> {
+  let mut findings = vec![];
+  let mut seen: HashMap<u16, &str> = HashMap::new();
+  for (field, port) in listening_ports(args, bridge_options) {
+    if let Some(other) = seen.insert(port, field) {
+      findings.push(finding(
+        OptionsFindingSeverity::Error,
+        OptionsFindingKind::PortConflict,
+        field,
+        format!("Port {} is also set for {}", port, other),
+      ));
+      continue;
+    }
+    // A running engine has these ports itself.
+    if engine_running {
+      continue;
+    }
+    if let Some(e) = network_interfaces::port_bind_error(port, args.websocket_use_all_interfaces) {
+      findings.push(finding(
+        OptionsFindingSeverity::Error,
+        OptionsFindingKind::PortInUse,
+        field,
+        format!("Port {} is not available: {}", port, e),
+      ));
+    }
+  }
+  findings
+}
+
+fn check_modes(
+  args: &EngineOptionsExternal,
+  bridge_options: &BridgeEngineOptions,
+) -> Vec<OptionsFinding> {
+  let mut findings = vec![];
+  let client_mode = args.websocket_client_address.is_some();
+  if args.repeater_mode {
+    if client_mode {
+      findings.push(finding(
+        OptionsFindingSeverity::Error,
+        OptionsFindingKind::ConflictingModes,
+        "repeater_mode",
+        "Repeater mode can't be used while connecting out as a websocket client".to_owned(),
+      ));
+    }
+    if args.repeater_local_port.is_none() {
+      findings.push(finding(
+        OptionsFindingSeverity::Error,
+        OptionsFindingKind::MissingSetting,
+        "repeater_local_port",
+        "Repeater mode needs a local port".to_owned(),
+      ));
+    }
+    if args
+      .repeater_remote_address
+      .as_ref()
+      .is_none_or(|x| x.trim().is_empty())
+    {
+      findings.push(finding(
+        OptionsFindingSeverity::Error,
+        OptionsFindingKind::MissingSetting,
+        "repeater_remote_address",
+        "Repeater mode needs a remote address".to_owned(),
+      ));
+    }
+  } else if !client_mode && args.websocket_port.is_none() {
+    findings.push(finding(
+      OptionsFindingSeverity::Warning,
+      OptionsFindingKind::MissingSetting,
+      "websocket_port",
+      "No websocket port or client address is set, so no clients can connect".to_owned(),
+    ));
+  }
+  if client_mode && args.broadcast_server_mdns {
+    findings.push(finding(
+      OptionsFindingSeverity::Warning,
+      OptionsFindingKind::IgnoredSetting,
+      "broadcast_server_mdns",
+      "mDNS broadcast does nothing while connecting out as a websocket client".to_owned(),
+    ));
+  }
+  if client_mode && bridge_options.client_sessions.multiple_clients {
+    findings.push(finding(
+      OptionsFindingSeverity::Warning,
+      OptionsFindingKind::IgnoredSetting,
+      "client_sessions.multiple_clients",
+      "Multiple clients does nothing while connecting out as a websocket client".to_owned(),
+    ));
+  }
+  if args.use_device_websocket_server && args.device_websocket_server_port.is_none() {
+    findings.push(finding(
+      OptionsFindingSeverity::Error,
+      OptionsFindingKind::MissingSetting,
+      "device_websocket_server_port",
+      "Device websocket server needs a port".to_owned(),
+    ));
+  }
+  if !args.frontend_in_process_channel && args.frontend_websocket_port.is_none() {
+    findings.push(finding(
+      OptionsFindingSeverity::Warning,
+      OptionsFindingKind::MissingSetting,
+      "frontend_websocket_port",
+      "No frontend channel or port is set, so the app won't hear from the engine".to_owned(),
+    ));
+  }
+  findings
+}
+
+fn check_configs(args: &EngineOptionsExternal) -> Vec<OptionsFinding> {
+  let mut findings = vec![];
+  match &args.device_config_json {
+    None => findings.push(finding(
+      OptionsFindingSeverity::Warning,
+      OptionsFindingKind::MissingConfig,
+      "device_config_json",
+      "No device config given, the built in one will be used".to_owned(),
+    )),
+    Some(config) => {
+      if let Err(e) = load_protocol_configs(&Some(config.clone()), &None, false) {
+        findings.push(finding(
+          OptionsFindingSeverity::Error,
+          OptionsFindingKind::InvalidConfig,
+          "device_config_json",
+          format!("Device config doesn't load: {:?}", e),
+        ));
+      }
+    }
+  }
+  if let Some(user_config) = &args.user_device_config_json {
+    let errors = user_config_validation::validate_user_config(user_config);
+    if let Some(first) = errors.first() {
+      findings.push(finding(
+        OptionsFindingSeverity::Error,
+        OptionsFindingKind::InvalidConfig,
+        "user_device_config_json",
+        format!(
+          "User config has {} error(s), first: {}",
+          errors.len(),
+          first.message
+        ),
+      ));
+    }
+  }
+  if let Some(path) = &args.user_device_config_path {
+    if !Path::new(path).exists() {
+      findings.push(finding(
+        OptionsFindingSeverity::Warning,
+        OptionsFindingKind::MissingConfig,
+        "user_device_config_path",
+        format!("User config file {} doesn't exist yet", path),
+      ));
+    }
+  }
+  findings
+}
+
+// Host and port out of a ws:// or wss:// address, or a bare host:port, which the engine also takes.
+fn client_address_host(address: &str) -> Option<String> {
+  let (default_port, rest) = if let Some(rest) = address.strip_prefix("wss://") {
+    (443, rest)
+  } else if let Some(rest) = address.strip_prefix("ws://") {
+    (80, rest)
+  } else if address.contains("://") {
+    return None;
+  } else {
+    (80, address)
+  };
+  let authority = rest.split('/').next()?;
+  if authority.is_empty() {
+    return None;
+  }
+  let has_port = match authority.rfind(']') {
+    Some(bracket) => authority[bracket..].contains(':'),
+    None => authority.contains(':'),
+  };
+  Some(if has_port {
+    authority.to_owned()
+  } else {
+    format!("{}:{}", authority, default_port)
+  })
+}
+
+fn check_client_address(args: &EngineOptionsExternal) -> Vec<OptionsFinding> {
+  let Some(address) = &args.websocket_client_address else {
+    return vec![];
+  };
+  let Some(host) = client_address_host(address.trim()) else {
+    return vec![finding(
+      OptionsFindingSeverity::Error,
+      OptionsFindingKind::InvalidAddress,
+      "websocket_client_address",
+      format!("{} isn't a websocket address", address),
+    )];
+  };
+  let socket_addresses = match host.to_socket_addrs() {
+    Ok(x) => x.collect::<Vec<_>>(),
+    Err(e) => {
+      return vec![finding(
+        OptionsFindingSeverity::Error,
+        OptionsFindingKind::InvalidAddress,
+        "websocket_client_address",
+        format!("Cannot resolve {}: {}", host, e),
+      )]
+    }
+  };
+  // Only a warning, as the server may just not be up yet.
+  let reachable = socket_addresses
+    .iter()
+    .any(|x| TcpStream::connect_timeout(x, CLIENT_ADDRESS_TIMEOUT).is_ok());
+  if reachable {
+    return vec![];
+  }
+  vec![finding(
+    OptionsFindingSeverity::Warning,
+    OptionsFindingKind::UnreachableAddress,
+    "websocket_client_address",
+    format!("Nothing is answering at {}", host),
+  )]
+}
+
+fn check_spec_version(bridge_options: &BridgeEngineOptions) -> Vec<OptionsFinding> {
+  match bridge_options.max_spec_version {
+    Some(version) if version > MAX_SUPPORTED_SPEC_VERSION => vec![finding(
+      OptionsFindingSeverity::Warning,
+      OptionsFindingKind::OutOfRange,
+      "max_spec_version",
+      format!(
+        "Engine only supports spec versions up to {}, so {} does nothing",
+        MAX_SUPPORTED_SPEC_VERSION, version
+      ),
+    )],
+    _ => vec![],
+  }
+}
+
+fn check_device_transports(
+  args: &EngineOptionsExternal,
+  bridge_options: &BridgeEngineOptions,
+) -> Vec<OptionsFinding> {
+  let any_transport = args.use_bluetooth_le
+    || args.use_serial_port
+    || args.use_hid
+    || args.use_lovense_dongle_serial
+    || args.use_lovense_dongle_hid
+    || args.use_xinput
+    || args.use_lovense_connect
+    || args.use_device_websocket_server
+    || !bridge_options.simulated_devices.is_empty();
+  if any_transport {
+    return vec![];
+  }
+  vec![finding(
+    OptionsFindingSeverity::Warning,
+    OptionsFindingKind::NoDeviceTransports,
+    "use_bluetooth_le",
+    "No device transports are on, so no devices can connect".to_owned(),
+  )]
+}
+
+pub fn validate(
+  args: &EngineOptionsExternal,
+  bridge_options: &BridgeEngineOptions,
+  engine_running: bool,
+) -> Vec<OptionsFinding> {
+  let mut findings = check_ports(args, bridge_options, engine_running);
+  findings.append(&mut check_modes(args, bridge_options));
+  findings.append(&mut check_configs(args));
+  findings.append(&mut check_client_address(args));
+  findings.append(&mut check_device_transports(args, bridge_options));
+  findings.append(&mut check_spec_version(bridge_options));
+  // Errors first.
+  findings.sort_by_key(|x| Reverse(x.severity));
+  findings
+}
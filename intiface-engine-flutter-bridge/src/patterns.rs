@@ -0,0 +1,216 @@
+use crate::{actuator_curve::interpolate, api, backdoor, clock, devices};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde_json::json;
+use std::{
+  collections::{BTreeMap, HashMap},
+  f64::consts::PI,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+use tokio::{select, sync::Notify};
+
+// Looping intensity patterns that the bridge plays on device actuators by itself, so the app can
+// offer standalone control without a client connected. Patterns are sent as ScalarCmds through the
+// backdoor, so they get the same ramps and curves as anything else the frontend sends.
+
+const PATTERN_STEP_INTERVAL: Duration = Duration::from_millis(50);
+// Smallest change worth sending. Devices rarely have more than 100 steps.
+const MIN_CHANGE: f64 = 0.005;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternShape {
+  // Rises from min to max over the period, then drops back and starts over.
+  Ramp,
+  // Sine wave between min and max.
+  Wave,
+  // Max for the first half of the period, min for the second.
+  Pulse,
+  // List of (position in the period, intensity) pairs, both 0.0-1.0. Values between points are
+  // linearly interpolated, and the intensity is scaled into min-max like the presets.
+  Points { points: Vec<(f64, f64)> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+  pub shape: PatternShape,
+  pub period_ms: u32,
+  pub min: f64,
+  pub max: f64,
+}
+
+impl Pattern {
+  fn validate(&self) -> Result<()> {
+    if self.period_ms < PATTERN_STEP_INTERVAL.as_millis() as u32 {
+      return Err(anyhow::Error::msg(format!(
+        "Pattern period must be at least {}ms",
+        PATTERN_STEP_INTERVAL.as_millis()
+      )));
+    }
+    if !(0.0..=1.0).contains(&self.min) || !(0.0..=1.0).contains(&self.max) {
+      return Err(anyhow::Error::msg(
+        "Pattern min and max must be between 0.0 and 1.0",
+      ));
+    }
+    if self.min > self.max {
+      return Err(anyhow::Error::msg("Pattern min must not be above max"));
+    }
+    if let PatternShape::Points { points } = &self.shape {
+      if points.is_empty() {
+        return Err(anyhow::Error::msg("Pattern needs at least one point"));
+      }
+      if points.iter().any(|(position, intensity)| {
+        !(0.0..=1.0).contains(position) || !(0.0..=1.0).contains(intensity)
+      }) {
+        return Err(anyhow::Error::msg(
+          "Pattern points must be between 0.0 and 1.0",
+        ));
+      }
+    }
+    Ok(())
+  }
+
+  fn value_at(&self, elapsed: Duration) -> f64 {
+    let period = self.period_ms as f64;
+    let position = (elapsed.as_secs_f64() * 1000.0 % period) / period;
+    let level = match &self.shape {
+      PatternShape::Ramp => position,
+      PatternShape::Wave => (1.0 - (position * 2.0 * PI).cos()) / 2.0,
+      PatternShape::Pulse => {
+        if position < 0.5 {
+          1.0
+        } else {
+          0.0
+        }
+      }
+      PatternShape::Points { points } => interpolate(points, position),
+    };
+    self.min + level.clamp(0.0, 1.0) * (self.max - self.min)
+  }
+}
+
+struct ActivePattern {
+  pattern: Pattern,
+  actuator_type: String,
+  started_at: Instant,
+  last_sent: Option<f64>,
+}
+
+lazy_static! {
+  // (device index, scalar actuator index) -> pattern playing on it
+  static ref PATTERNS: Mutex<HashMap<(u32, u32), ActivePattern>> = Mutex::new(HashMap::new());
+}
+
+// Starts a pattern on a scalar actuator, replacing whatever was playing on it.
+pub fn start(
+  device_index: u32,
+  scalar_index: u32,
+  actuator_type: &str,
+  pattern: Pattern,
+) -> Result<()> {
+  pattern.validate()?;
+  if !api::runtime_started() {
+    return Err(anyhow::Error::msg("Engine is not running"));
+  }
+  if devices::connected_device(device_index).is_none() {
+    return Err(anyhow::Error::msg("No device connected at that index"));
+  }
+  PATTERNS.lock().unwrap().insert(
+    (device_index, scalar_index),
+    ActivePattern {
+      pattern,
+      actuator_type: actuator_type.to_owned(),
+      started_at: clock::now(),
+      last_sent: None,
+    },
+  );
+  Ok(())
+}
+
+// Swaps in new pattern settings without restarting it from the top.
+pub fn update(device_index: u32, scalar_index: u32, pattern: Pattern) -> Result<()> {
+  pattern.validate()?;
+  let mut patterns = PATTERNS.lock().unwrap();
+  let active = patterns
+    .get_mut(&(device_index, scalar_index))
+    .ok_or(anyhow::Error::msg("No pattern playing on that actuator"))?;
+  active.pattern = pattern;
+  Ok(())
+}
+
+pub fn stop(device_index: u32, scalar_index: u32) -> Result<()> {
+  let active = PATTERNS
+    .lock()
+    .unwrap()
+    .remove(&(device_index, scalar_index))
+    .ok_or(anyhow::Error::msg("No pattern playing on that actuator"))?;
+  send_scalars(device_index, &[(scalar_index, 0.0, active.actuator_type)]);
+  Ok(())
+}
+
+pub fn stop_all() {
+  let stopped: Vec<_> = PATTERNS.lock().unwrap().drain().collect();
+  for ((device_index, scalar_index), active) in stopped {
+    send_scalars(device_index, &[(scalar_index, 0.0, active.actuator_type)]);
+  }
+}
+
+pub fn clear() {
+  PATTERNS.lock().unwrap().clear();
+}
+
+fn send_scalars(device_index: u32, scalars: &[(u32, f64, String)]) {
+  let scalars: Vec<_> = scalars
+    .iter()
+    .map(|(index, scalar, actuator_type)| {
+      json!({ "Index": index, "Scalar": scalar, "ActuatorType": actuator_type })
+    })
+    .collect();
+  let msg = json!([{
+    "ScalarCmd": {
+      "Id": backdoor::next_message_id(),
+      "DeviceIndex": device_index,
+      "Scalars": scalars,
+    }
+  }]);
+  api::send_bridge_backdoor_message(msg.to_string());
+}
+
+fn step(now: Instant) {
+  // Device index -> scalars to send, so each device gets one command per step.
+  let mut commands: BTreeMap<u32, Vec<(u32, f64, String)>> = BTreeMap::new();
+  {
+    let mut patterns = PATTERNS.lock().unwrap();
+    patterns.retain(|(device_index, _), _| devices::connected_device(*device_index).is_some());
+    for ((device_index, scalar_index), active) in patterns.iter_mut() {
+      let value = active
+        .pattern
+        .value_at(now.saturating_duration_since(active.started_at));
+      if active
+        .last_sent
+        .is_some_and(|last| (last - value).abs() < MIN_CHANGE)
+      {
+        continue;
+      }
+      active.last_sent = Some(value);
+      commands.entry(*device_index).or_default().push((
+        *scalar_index,
+        value,
+        active.actuator_type.clone(),
+      ));
+    }
+  }
+  for (device_index, scalars) in commands {
+    send_scalars(device_index, &scalars);
+  }
+}
+
+pub async fn run_patterns(stop: Arc<Notify>) {
+  let mut ticker = clock::Ticker::new(PATTERN_STEP_INTERVAL);
+  loop {
+    select! {
+      _ = ticker.tick() => step(clock::now()),
+      _ = stop.notified() => break,
+    }
+  }
+}
@@ -0,0 +1,526 @@
+use crate::{
+  api::EngineOptionsExternal,
+  clock,
+  events::{self, BridgeEvent},
+  options::BridgeEngineOptions,
+  server_ready,
+  websocket_proxy::ListeningServer,
+};
+use anyhow::Result;
+use futures::pin_mut;
+use lazy_static::lazy_static;
+use reqwest::{blocking::Client, header::CONTENT_TYPE, Url};
+use serde::Serialize;
+use std::{
+  net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{select, sync::Notify};
+
+// Asks the router to forward the websocket ports to us, for people who want clients to reach the
+// server from outside their network without setting up forwarding themselves. Tries UPnP first, as
+// that's what most home routers have, then NAT-PMP.
+//
+// Mappings are leased, and renewed at half the lease until the engine stops, when they're taken down
+// again. Routers that only take permanent mappings get one, which is still taken down on stop, but
+// stays if the app dies first.
+//
+// NAT-PMP has to be sent to the gateway, and there's no portable way of asking the OS which that is,
+// so it goes to the first address on the network of the interface we'd reach the internet through.
+// That's the gateway on almost every home network.
+
+const SSDP_ADDRESS: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900);
+const NAT_PMP_PORT: u16 = 5351;
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_LEASE_SECONDS: u32 = 120;
+// How long to wait before trying again after nothing could be mapped.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const MAPPING_DESCRIPTION: &str = "Intiface Central";
+const UPNP_SERVICE_TYPES: [&str; 3] = [
+  "urn:schemas-upnp-org:service:WANIPConnection:2",
+  "urn:schemas-upnp-org:service:WANIPConnection:1",
+  "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+// UPnP error for routers that don't do leases.
+const UPNP_ONLY_PERMANENT_LEASES: &str = "<errorCode>725</errorCode>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PortMappingMethod {
+  Upnp,
+  NatPmp,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortMapping {
+  pub server: ListeningServer,
+  pub internal_port: u16,
+  // Router's public address, if it told us.
+  pub external_address: Option<String>,
+  pub external_port: u16,
+  pub method: PortMappingMethod,
+  // 0 if the router made the mapping permanent.
+  pub lease_seconds: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct PortMappingPlan {
+  ports: Vec<(ListeningServer, u16)>,
+  bind_address: Option<Ipv4Addr>,
+  lease_seconds: u32,
+}
+
+enum Gateway {
+  Upnp {
+    control_url: Url,
+    service_type: &'static str,
+    local_ip: Ipv4Addr,
+  },
+  NatPmp {
+    address: SocketAddrV4,
+  },
+}
+
+lazy_static! {
+  static ref MAPPINGS: Mutex<Vec<PortMapping>> = Mutex::new(vec![]);
+}
+
+pub fn mappings() -> Vec<PortMapping> {
+  MAPPINGS.lock().unwrap().clone()
+}
+
+pub fn clear() {
+  MAPPINGS.lock().unwrap().clear();
+}
+
+// Works out which ports to map. Needs to see args before the websocket proxy moves the engine's
+// ports around, since it's the public ports that get mapped.
+pub fn plan(
+  options: &BridgeEngineOptions,
+  args: &EngineOptionsExternal,
+) -> Option<PortMappingPlan> {
+  if !options.port_mapping.enabled {
+    return None;
+  }
+  if args.websocket_client_address.is_some() {
+    warn!("Port mapping requested while connecting out as a websocket client, not mapping");
+    return None;
+  }
+  let Some(port) = args.websocket_port else {
+    warn!("Port mapping requested without a websocket port, not mapping");
+    return None;
+  };
+  let bind_address = options
+    .websocket_bind_address
+    .as_deref()
+    .and_then(|x| x.trim().parse::<IpAddr>().ok());
+  let bind_address = match bind_address {
+    Some(IpAddr::V4(ip)) if !ip.is_loopback() => Some(ip),
+    None if args.websocket_use_all_interfaces => None,
+    _ => {
+      warn!("Port mapping requested while the websocket server only listens locally, not mapping");
+      return None;
+    }
+  };
+  let mut ports = vec![(ListeningServer::Websocket, port)];
+  if let Some(tls_options) = &options.websocket_tls {
+    ports.push((ListeningServer::SecureWebsocket, tls_options.port));
+  }
+  Some(PortMappingPlan {
+    ports,
+    bind_address,
+    lease_seconds: options.port_mapping.lease_seconds.max(MIN_LEASE_SECONDS),
+  })
+}
+
+// Address of ours the router would see, found by pointing a socket at it. Nothing is sent.
+fn local_ip_towards(address: SocketAddr) -> Result<Ipv4Addr> {
+  let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+  socket.connect(address)?;
+  match socket.local_addr()?.ip() {
+    IpAddr::V4(ip) => Ok(ip),
+    IpAddr::V6(_) => Err(anyhow::Error::msg("No IPv4 route to the gateway")),
+  }
+}
+
+fn tag_value<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+  let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+  let end = xml[start..].find(&format!("</{}>", tag))? + start;
+  Some(xml[start..end].trim())
+}
+
+fn ssdp_location() -> Result<String> {
+  let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+  socket.set_read_timeout(Some(DISCOVERY_TIMEOUT))?;
+  let search = format!(
+    "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\n\r\n",
+    SSDP_ADDRESS
+  );
+  socket.send_to(search.as_bytes(), SSDP_ADDRESS)?;
+  let mut buffer = [0u8; 2048];
+  loop {
+    let (len, _) = socket
+      .recv_from(&mut buffer)
+      .map_err(|_| anyhow::Error::msg("No UPnP gateway answered"))?;
+    let response = String::from_utf8_lossy(&buffer[..len]);
+    let location = response.lines().find_map(|line| {
+      let (name, value) = line.split_once(':')?;
+      name
+        .trim()
+        .eq_ignore_ascii_case("location")
+        .then(|| value.trim().to_owned())
+    });
+    if let Some(location) = location {
+      return Ok(location);
+    }
+  }
+}
+
+fn discover_upnp(client: &Client) -> Result<Gateway> {
+  let location = Url::parse(&ssdp_location()?)?;
+  let description = client.get(location.clone()).send()?.text()?;
+  for service_type in UPNP_SERVICE_TYPES {
+    let Some(service_start) =
+      description.find(&format!("<serviceType>{}</serviceType>", service_type))
+    else {
+      continue;
+    };
+    let service = &description[service_start..];
+    let service = &service[..service.find("</service>").unwrap_or(service.len())];
+    let Some(control_url) = tag_value(service, "controlURL") else {
+      continue;
+    };
+    let gateway_address = location
+      .socket_addrs(|| Some(80))?
+      .into_iter()
+      .next()
+      .ok_or_else(|| anyhow::Error::msg("UPnP gateway has no address"))?;
+    return Ok(Gateway::Upnp {
+      control_url: location.join(control_url)?,
+      service_type,
+      local_ip: local_ip_towards(gateway_address)?,
+    });
+  }
+  Err(anyhow::Error::msg(
+    "UPnP gateway doesn't offer a WAN connection service",
+  ))
+}
+
+fn upnp_request(
+  client: &Client,
+  control_url: &Url,
+  service_type: &str,
+  action: &str,
+  arguments: &[(&str, String)],
+) -> Result<String> {
+  let arguments: String = arguments
+    .iter()
+    .map(|(name, value)| format!("<{}>{}</{}>", name, value, name))
+    .collect();
+  let body = format!(
+    "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:{} xmlns:u=\"{}\">{}</u:{}></s:Body></s:Envelope>",
+    action, service_type, arguments, action
+  );
+  let response = client
+    .post(control_url.clone())
+    .header(CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+    .header("SOAPAction", format!("\"{}#{}\"", service_type, action))
+    .body(body)
+    .send()?;
+  let success = response.status().is_success();
+  let text = response.text()?;
+  if !success {
+    return Err(anyhow::Error::msg(text));
+  }
+  Ok(text)
+}
+
+// Gateway error, without the SOAP around it.
+fn upnp_error(error: &anyhow::Error) -> String {
+  let text = error.to_string();
+  match (
+    tag_value(&text, "errorCode"),
+    tag_value(&text, "errorDescription"),
+  ) {
+    (Some(code), Some(description)) => format!("UPnP error {}: {}", code, description),
+    (Some(code), None) => format!("UPnP error {}", code),
+    _ => text,
+  }
+}
+
+fn nat_pmp_gateway() -> Result<Gateway> {
+  // Anything outside the LAN does, it's only for finding the interface.
+  let local_ip = local_ip_towards(SocketAddr::from((Ipv4Addr::new(1, 1, 1, 1), 80)))?;
+  let netmask = if_addrs::get_if_addrs()?
+    .into_iter()
+    .find_map(|interface| match interface.addr {
+      if_addrs::IfAddr::V4(addr) if addr.ip == local_ip => Some(addr.netmask),
+      _ => None,
+    })
+    .ok_or_else(|| anyhow::Error::msg("Cannot find the network interface for the gateway"))?;
+  let network = u32::from(local_ip) & u32::from(netmask);
+  Ok(Gateway::NatPmp {
+    address: SocketAddrV4::new(Ipv4Addr::from(network + 1), NAT_PMP_PORT),
+  })
+}
+
+fn nat_pmp_request(gateway: SocketAddrV4, request: &[u8], response_len: usize) -> Result<Vec<u8>> {
+  let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+  socket.set_read_timeout(Some(DISCOVERY_TIMEOUT))?;
+  socket.connect(gateway)?;
+  socket.send(request)?;
+  let mut buffer = [0u8; 16];
+  let len = socket
+    .recv(&mut buffer)
+    .map_err(|_| anyhow::Error::msg("No NAT-PMP gateway answered"))?;
+  // Responses are the request opcode + 128.
+  if len < response_len || buffer[0] != 0 || buffer[1] != request[1] + 128 {
+    return Err(anyhow::Error::msg("Bad NAT-PMP response"));
+  }
+  let result = u16::from_be_bytes([buffer[2], buffer[3]]);
+  if result != 0 {
+    return Err(anyhow::Error::msg(format!("NAT-PMP error {}", result)));
+  }
+  Ok(buffer[..len].to_vec())
+}
+
+// Asks for a TCP mapping, or removes it with a lease of 0. Returns the external port and lease the
+// gateway gave us.
+fn nat_pmp_map(gateway: SocketAddrV4, port: u16, lease_seconds: u32) -> Result<(u16, u32)> {
+  let mut request = vec![0, 2, 0, 0];
+  request.extend_from_slice(&port.to_be_bytes());
+  let external_port = if lease_seconds == 0 { 0 } else { port };
+  request.extend_from_slice(&external_port.to_be_bytes());
+  request.extend_from_slice(&lease_seconds.to_be_bytes());
+  let response = nat_pmp_request(gateway, &request, 16)?;
+  Ok((
+    u16::from_be_bytes([response[10], response[11]]),
+    u32::from_be_bytes([response[12], response[13], response[14], response[15]]),
+  ))
+}
+
+impl Gateway {
+  fn discover(client: &Client) -> Result<Gateway> {
+    discover_upnp(client).or_else(|upnp_error| {
+      debug!("UPnP gateway not found, trying NAT-PMP: {:?}", upnp_error);
+      nat_pmp_gateway()
+    })
+  }
+
+  fn method(&self) -> PortMappingMethod {
+    match self {
+      Gateway::Upnp { .. } => PortMappingMethod::Upnp,
+      Gateway::NatPmp { .. } => PortMappingMethod::NatPmp,
+    }
+  }
+
+  fn external_address(&self, client: &Client) -> Result<Ipv4Addr> {
+    match self {
+      Gateway::Upnp {
+        control_url,
+        service_type,
+        ..
+      } => {
+        let response = upnp_request(
+          client,
+          control_url,
+          service_type,
+          "GetExternalIPAddress",
+          &[],
+        )?;
+        Ok(
+          tag_value(&response, "NewExternalIPAddress")
+            .ok_or_else(|| anyhow::Error::msg("UPnP gateway didn't give an external address"))?
+            .parse()?,
+        )
+      }
+      Gateway::NatPmp { address } => {
+        let response = nat_pmp_request(*address, &[0, 0], 12)?;
+        Ok(Ipv4Addr::new(
+          response[8],
+          response[9],
+          response[10],
+          response[11],
+        ))
+      }
+    }
+  }
+
+  fn add(
+    &self,
+    client: &Client,
+    port: u16,
+    bind_address: Option<Ipv4Addr>,
+    lease_seconds: u32,
+  ) -> Result<(u16, u32)> {
+    match self {
+      Gateway::Upnp {
+        control_url,
+        service_type,
+        local_ip,
+      } => {
+        let add = |lease_seconds: u32| {
+          upnp_request(
+            client,
+            control_url,
+            service_type,
+            "AddPortMapping",
+            &[
+              ("NewRemoteHost", String::new()),
+              ("NewExternalPort", port.to_string()),
+              ("NewProtocol", "TCP".to_owned()),
+              ("NewInternalPort", port.to_string()),
+              (
+                "NewInternalClient",
+                bind_address.unwrap_or(*local_ip).to_string(),
+              ),
+              ("NewEnabled", "1".to_owned()),
+              ("NewPortMappingDescription", MAPPING_DESCRIPTION.to_owned()),
+              ("NewLeaseDuration", lease_seconds.to_string()),
+            ],
+          )
+        };
+        match add(lease_seconds) {
+          Ok(_) => Ok((port, lease_seconds)),
+          Err(e) if e.to_string().contains(UPNP_ONLY_PERMANENT_LEASES) => {
+            add(0).map_err(|e| anyhow::Error::msg(upnp_error(&e)))?;
+            Ok((port, 0))
+          }
+          Err(e) => Err(anyhow::Error::msg(upnp_error(&e))),
+        }
+      }
+      // NAT-PMP maps to whichever of our addresses asked.
+      Gateway::NatPmp { address } => nat_pmp_map(*address, port, lease_seconds),
+    }
+  }
+
+  fn remove(&self, client: &Client, mapping: &PortMapping) -> Result<()> {
+    match self {
+      Gateway::Upnp {
+        control_url,
+        service_type,
+        ..
+      } => {
+        upnp_request(
+          client,
+          control_url,
+          service_type,
+          "DeletePortMapping",
+          &[
+            ("NewRemoteHost", String::new()),
+            ("NewExternalPort", mapping.external_port.to_string()),
+            ("NewProtocol", "TCP".to_owned()),
+          ],
+        )
+        .map_err(|e| anyhow::Error::msg(upnp_error(&e)))?;
+        Ok(())
+      }
+      Gateway::NatPmp { address } => nat_pmp_map(*address, mapping.internal_port, 0).map(|_| ()),
+    }
+  }
+}
+
+// Maps (or renews) every port, finding the gateway first if we don't have one yet. Hands back the
+// gateway if anything got mapped.
+fn refresh(gateway: Option<Gateway>, plan: &PortMappingPlan) -> Option<Gateway> {
+  let client = Client::builder().timeout(REQUEST_TIMEOUT).build().ok()?;
+  let gateway = match gateway.map_or_else(|| Gateway::discover(&client), Ok) {
+    Ok(gateway) => gateway,
+    Err(e) => {
+      for (_, port) in &plan.ports {
+        events::emit(BridgeEvent::PortMappingFailed {
+          internal_port: *port,
+          error: format!("No UPnP or NAT-PMP gateway found: {}", e),
+        });
+      }
+      return None;
+    }
+  };
+  let external_address = gateway
+    .external_address(&client)
+    .map_err(|e| debug!("Cannot get external address from gateway: {:?}", e))
+    .ok()
+    .map(|x| x.to_string());
+  let mut mappings = vec![];
+  for (server, port) in &plan.ports {
+    match gateway.add(&client, *port, plan.bind_address, plan.lease_seconds) {
+      Ok((external_port, lease_seconds)) => {
+        let mapping = PortMapping {
+          server: *server,
+          internal_port: *port,
+          external_address: external_address.clone(),
+          external_port,
+          method: gateway.method(),
+          lease_seconds,
+        };
+        events::emit(BridgeEvent::PortMapped(mapping.clone()));
+        mappings.push(mapping);
+      }
+      Err(e) => {
+        warn!("Cannot map port {}: {:?}", port, e);
+        events::emit(BridgeEvent::PortMappingFailed {
+          internal_port: *port,
+          error: e.to_string(),
+        });
+      }
+    }
+  }
+  let mapped = !mappings.is_empty();
+  *MAPPINGS.lock().unwrap() = mappings;
+  mapped.then_some(gateway)
+}
+
+fn remove_all(gateway: Gateway) {
+  let Ok(client) = Client::builder().timeout(DISCOVERY_TIMEOUT).build() else {
+    return;
+  };
+  for mapping in MAPPINGS.lock().unwrap().drain(..) {
+    match gateway.remove(&client, &mapping) {
+      Ok(()) => events::emit(BridgeEvent::PortMappingRemoved {
+        internal_port: mapping.internal_port,
+        external_port: mapping.external_port,
+      }),
+      Err(e) => warn!(
+        "Cannot remove mapping for port {}: {:?}",
+        mapping.internal_port, e
+      ),
+    }
+  }
+}
+
+pub async fn run_port_mapping(plan: PortMappingPlan, stop: Arc<Notify>) {
+  let stopped = stop.notified();
+  pin_mut!(stopped);
+  stopped.as_mut().enable();
+  // Nothing to forward to until the servers are up.
+  select! {
+    _ = server_ready::wait() => {}
+    _ = &mut stopped => return,
+  }
+  let renew_interval = Duration::from_secs(plan.lease_seconds as u64 / 2);
+  let mut gateway = None;
+  loop {
+    let refresh_plan = plan.clone();
+    // Gateways are talked to with blocking sockets, so this can't run on the runtime threads. Not
+    // cut short on stop, so anything it maps still gets taken down.
+    gateway = tokio::task::spawn_blocking(move || refresh(gateway, &refresh_plan))
+      .await
+      .ok()
+      .flatten();
+    let wait = if gateway.is_some() {
+      renew_interval
+    } else {
+      RETRY_INTERVAL
+    };
+    select! {
+      _ = clock::sleep_until(clock::now() + wait) => {}
+      _ = &mut stopped => break,
+    }
+  }
+  if let Some(gateway) = gateway {
+    let _ = tokio::task::spawn_blocking(move || remove_all(gateway)).await;
+  }
+  clear();
+  info!("Port mapping stopped");
+}
@@ -0,0 +1,109 @@
+use crate::api::DEVICE_CONFIG_MANAGER;
+use anyhow::Result;
+use buttplug::{
+  core::message::FeatureType,
+  server::device::{
+    configuration::{BaseDeviceIdentifier, ProtocolCommunicationSpecifier},
+    protocol::get_default_protocol_map,
+  },
+};
+
+// What the device config says about each protocol, for the "add device" UI, which otherwise only
+// has protocol names to offer.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolTransport {
+  BluetoothLE,
+  Serial,
+  Hid,
+  Usb,
+  Websocket,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProtocolInfo {
+  pub name: String,
+  // Name from the protocol's default device definition, or the protocol name if it doesn't have one.
+  pub display_name: String,
+  // Transports the base config has specifiers for.
+  pub transports: Vec<ProtocolTransport>,
+  // Whether devices can be pointed at this protocol with a user specifier. Websocket and serial
+  // devices are matched on a name or port the user picks, everything else on hardware ids that
+  // have to already be in the config.
+  pub supports_user_specifiers: bool,
+}
+
+fn transport(specifier: &ProtocolCommunicationSpecifier) -> ProtocolTransport {
+  match specifier {
+    ProtocolCommunicationSpecifier::BluetoothLE(_) => ProtocolTransport::BluetoothLE,
+    ProtocolCommunicationSpecifier::Serial(_) => ProtocolTransport::Serial,
+    ProtocolCommunicationSpecifier::HID(_) => ProtocolTransport::Hid,
+    ProtocolCommunicationSpecifier::USB(_) => ProtocolTransport::Usb,
+    ProtocolCommunicationSpecifier::Websocket(_) => ProtocolTransport::Websocket,
+  }
+}
+
+pub fn protocol_info() -> Vec<ProtocolInfo> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let mut protocols: Vec<ProtocolInfo> = get_default_protocol_map()
+    .into_keys()
+    .map(|name| {
+      let mut transports = vec![];
+      for specifier in dcm
+        .base_communication_specifiers()
+        .get(&name)
+        .into_iter()
+        .flatten()
+      {
+        let transport = transport(specifier);
+        if !transports.contains(&transport) {
+          transports.push(transport);
+        }
+      }
+      let display_name = dcm
+        .base_device_definitions()
+        .get(&BaseDeviceIdentifier::new(&name, &None))
+        .map(|x| x.name().clone())
+        .unwrap_or_else(|| name.clone());
+      let supports_user_specifiers = transports
+        .iter()
+        .any(|x| matches!(x, ProtocolTransport::Serial | ProtocolTransport::Websocket));
+      ProtocolInfo {
+        name,
+        display_name,
+        transports,
+        supports_user_specifiers,
+      }
+    })
+    .collect();
+  protocols.sort_by(|a, b| a.name.cmp(&b.name));
+  protocols
+}
+
+// Every feature type that shows up in the protocol's default device definitions. Devices of the
+// protocol each get some subset of these, depending on which definition they match.
+pub fn protocol_features(protocol: &str) -> Result<Vec<FeatureType>> {
+  if !get_default_protocol_map().contains_key(protocol) {
+    return Err(anyhow::Error::msg(format!("Unknown protocol {}", protocol)));
+  }
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let mut features = vec![];
+  for (_, definition) in dcm
+    .base_device_definitions()
+    .iter()
+    .filter(|(identifier, _)| identifier.protocol() == protocol)
+  {
+    for feature in definition.features() {
+      if !features.contains(feature.feature_type()) {
+        features.push(*feature.feature_type());
+      }
+    }
+  }
+  // Definitions come out of a map, so put them in a stable order.
+  features.sort_by_key(|x| *x as u32);
+  Ok(features)
+}
@@ -0,0 +1,316 @@
+use crate::backdoor;
+use serde_json::{json, Value};
+use std::{
+  collections::{BTreeMap, HashMap},
+  time::Instant,
+};
+
+// Limits how fast actuator values can change, in scalar units (0.0-1.0) per second, both going up
+// and coming down. Commands that jump further than the rate allows get clamped, and the rest of the
+// move is sent as a series of intermediate commands as time passes. Stop commands are never ramped.
+//
+// Like curve shapes, this can only be applied on command paths the bridge owns: the backdoor, and
+// proxied websocket clients (see websocket_proxy).
+
+struct Ramp {
+  current: f64,
+  target: f64,
+  rate: f64,
+  actuator_type: Value,
+  last_update: Instant,
+}
+
+impl Ramp {
+  fn advance(&mut self, now: Instant) {
+    let elapsed = now
+      .saturating_duration_since(self.last_update)
+      .as_secs_f64();
+    let max_delta = self.rate * elapsed;
+    let delta = self.target - self.current;
+    // Snap to the target when we're close enough, otherwise float error can keep us a hair away
+    // from it forever.
+    if delta.abs() <= max_delta {
+      self.current = self.target;
+    } else {
+      self.current += delta.signum() * max_delta;
+    }
+    self.last_update = now;
+  }
+
+  fn finished(&self) -> bool {
+    self.current == self.target
+  }
+}
+
+#[derive(Default)]
+pub struct RampLimiter {
+  // Keyed on (device index, scalar index)
+  ramps: HashMap<(u32, u32), Ramp>,
+}
+
+impl RampLimiter {
+  // Rewrites any ScalarCmd values in the message that change faster than the rate returned by the
+  // lookup for the device, if any. Returns None if the message can go through unchanged.
+  pub fn limit_message<F>(&mut self, msg: &str, rate_lookup: F, now: Instant) -> Option<String>
+  where
+    F: Fn(u32) -> Option<f64>,
+  {
+    let mut messages: Value = serde_json::from_str(msg).ok()?;
+    let mut changed = false;
+    for message in messages.as_array_mut()? {
+      if message.get("StopAllDevices").is_some() {
+        self.ramps.clear();
+        continue;
+      }
+      if let Some(device_index) = message
+        .get("StopDeviceCmd")
+        .and_then(|x| x.get("DeviceIndex"))
+        .and_then(|x| x.as_u64())
+      {
+        self.clear_device(device_index as u32);
+        continue;
+      }
+      let Some(scalar_cmd) = message.get_mut("ScalarCmd") else {
+        continue;
+      };
+      let Some(device_index) = scalar_cmd
+        .get("DeviceIndex")
+        .and_then(|x| x.as_u64())
+        .map(|x| x as u32)
+      else {
+        continue;
+      };
+      let Some(rate) = rate_lookup(device_index) else {
+        // Settings may have been removed since the last command.
+        self.clear_device(device_index);
+        continue;
+      };
+      let Some(scalars) = scalar_cmd.get_mut("Scalars").and_then(|x| x.as_array_mut()) else {
+        continue;
+      };
+      for scalar in scalars {
+        let (Some(index), Some(value)) = (
+          scalar.get("Index").and_then(|x| x.as_u64()),
+          scalar.get("Scalar").and_then(|x| x.as_f64()),
+        ) else {
+          continue;
+        };
+        let ramp = self
+          .ramps
+          .entry((device_index, index as u32))
+          .or_insert(Ramp {
+            current: 0.0,
+            target: 0.0,
+            rate,
+            actuator_type: Value::Null,
+            last_update: now,
+          });
+        ramp.advance(now);
+        ramp.target = value.clamp(0.0, 1.0);
+        ramp.rate = rate;
+        ramp.actuator_type = scalar.get("ActuatorType").cloned().unwrap_or(Value::Null);
+        if ramp.current != ramp.target {
+          scalar["Scalar"] = Value::from(ramp.current);
+          changed = true;
+        }
+      }
+    }
+    if changed {
+      serde_json::to_string(&messages).ok()
+    } else {
+      None
+    }
+  }
+
+  // Moves all unfinished ramps along, returning the serialized commands to send for them. Uses
+  // bridge message ids, so the responses don't go back to the frontend.
+  pub fn step(&mut self, now: Instant) -> Vec<String> {
+    let mut device_scalars: BTreeMap<u32, Vec<Value>> = BTreeMap::new();
+    for ((device_index, scalar_index), ramp) in self.ramps.iter_mut() {
+      if ramp.finished() {
+        continue;
+      }
+      ramp.advance(now);
+      device_scalars
+        .entry(*device_index)
+        .or_default()
+        .push(json!({
+          "Index": scalar_index,
+          "Scalar": ramp.current,
+          "ActuatorType": ramp.actuator_type,
+        }));
+    }
+    // Once something has ramped all the way down, there's nothing left to track.
+    self
+      .ramps
+      .retain(|_, ramp| !(ramp.finished() && ramp.current == 0.0));
+    device_scalars
+      .into_iter()
+      .map(|(device_index, scalars)| {
+        json!([{
+          "ScalarCmd": {
+            "Id": backdoor::next_message_id(),
+            "DeviceIndex": device_index,
+            "Scalars": scalars,
+          }
+        }])
+        .to_string()
+      })
+      .collect()
+  }
+
+  pub fn clear_device(&mut self, device_index: u32) {
+    self.ramps.retain(|(index, _), _| *index != device_index);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  fn scalar_cmd(device_index: u32, value: f64) -> String {
+    json!([{
+      "ScalarCmd": {
+        "Id": 1,
+        "DeviceIndex": device_index,
+        "Scalars": [{"Index": 0, "Scalar": value, "ActuatorType": "Vibrate"}],
+      }
+    }])
+    .to_string()
+  }
+
+  fn sent_value(msg: &str) -> f64 {
+    let messages: Value = serde_json::from_str(msg).unwrap();
+    messages[0]["ScalarCmd"]["Scalars"][0]["Scalar"]
+      .as_f64()
+      .unwrap()
+  }
+
+  fn one_per_second(_: u32) -> Option<f64> {
+    Some(1.0)
+  }
+
+  #[test]
+  fn devices_without_a_rate_pass_through() {
+    let mut ramps = RampLimiter::default();
+    let now = Instant::now();
+    assert!(ramps
+      .limit_message(&scalar_cmd(0, 1.0), |_| None, now)
+      .is_none());
+    assert!(ramps.step(now + Duration::from_secs(1)).is_empty());
+  }
+
+  #[test]
+  fn ramps_up_over_time() {
+    let mut ramps = RampLimiter::default();
+    let start = Instant::now();
+    let limited = ramps
+      .limit_message(&scalar_cmd(0, 1.0), one_per_second, start)
+      .unwrap();
+    assert_eq!(sent_value(&limited), 0.0);
+
+    let steps = ramps.step(start + Duration::from_millis(250));
+    assert_eq!(steps.len(), 1);
+    assert_eq!(sent_value(&steps[0]), 0.25);
+    let messages: Value = serde_json::from_str(&steps[0]).unwrap();
+    assert_eq!(messages[0]["ScalarCmd"]["DeviceIndex"], 0);
+    assert_eq!(
+      messages[0]["ScalarCmd"]["Scalars"][0]["ActuatorType"],
+      "Vibrate"
+    );
+
+    let steps = ramps.step(start + Duration::from_secs(2));
+    assert_eq!(sent_value(&steps[0]), 1.0);
+    // Nothing left to do once the target is reached.
+    assert!(ramps.step(start + Duration::from_secs(3)).is_empty());
+  }
+
+  #[test]
+  fn new_targets_pick_up_from_where_the_ramp_got_to() {
+    let mut ramps = RampLimiter::default();
+    let start = Instant::now();
+    ramps.limit_message(&scalar_cmd(0, 1.0), one_per_second, start);
+    let limited = ramps
+      .limit_message(
+        &scalar_cmd(0, 0.0),
+        one_per_second,
+        start + Duration::from_millis(500),
+      )
+      .unwrap();
+    assert_eq!(sent_value(&limited), 0.5);
+    let steps = ramps.step(start + Duration::from_millis(750));
+    assert_eq!(sent_value(&steps[0]), 0.25);
+  }
+
+  #[test]
+  fn values_already_reached_go_through_unchanged() {
+    let mut ramps = RampLimiter::default();
+    let start = Instant::now();
+    ramps.limit_message(&scalar_cmd(0, 0.5), one_per_second, start);
+    ramps.step(start + Duration::from_secs(1));
+    assert!(ramps
+      .limit_message(
+        &scalar_cmd(0, 0.5),
+        one_per_second,
+        start + Duration::from_secs(2)
+      )
+      .is_none());
+  }
+
+  #[test]
+  fn ramps_down_and_stops_tracking() {
+    let mut ramps = RampLimiter::default();
+    let start = Instant::now();
+    ramps.limit_message(&scalar_cmd(0, 1.0), one_per_second, start);
+    ramps.step(start + Duration::from_secs(1));
+    let limited = ramps
+      .limit_message(
+        &scalar_cmd(0, 0.0),
+        one_per_second,
+        start + Duration::from_secs(1),
+      )
+      .unwrap();
+    assert_eq!(sent_value(&limited), 1.0);
+    let steps = ramps.step(start + Duration::from_millis(1500));
+    assert_eq!(sent_value(&steps[0]), 0.5);
+    let steps = ramps.step(start + Duration::from_secs(2));
+    assert_eq!(sent_value(&steps[0]), 0.0);
+    assert!(ramps.ramps.is_empty());
+  }
+
+  #[test]
+  fn stop_commands_are_never_ramped() {
+    let mut ramps = RampLimiter::default();
+    let start = Instant::now();
+    ramps.limit_message(&scalar_cmd(0, 1.0), one_per_second, start);
+    ramps.limit_message(&scalar_cmd(1, 1.0), one_per_second, start);
+    let stop_device = json!([{"StopDeviceCmd": {"Id": 2, "DeviceIndex": 0}}]).to_string();
+    assert!(ramps
+      .limit_message(&stop_device, one_per_second, start)
+      .is_none());
+    let steps = ramps.step(start + Duration::from_millis(500));
+    assert_eq!(steps.len(), 1);
+    let messages: Value = serde_json::from_str(&steps[0]).unwrap();
+    assert_eq!(messages[0]["ScalarCmd"]["DeviceIndex"], 1);
+
+    let stop_all = json!([{"StopAllDevices": {"Id": 3}}]).to_string();
+    ramps.limit_message(
+      &stop_all,
+      one_per_second,
+      start + Duration::from_millis(500),
+    );
+    assert!(ramps.step(start + Duration::from_secs(1)).is_empty());
+  }
+
+  #[test]
+  fn removed_rates_clear_ramps() {
+    let mut ramps = RampLimiter::default();
+    let start = Instant::now();
+    ramps.limit_message(&scalar_cmd(0, 1.0), one_per_second, start);
+    assert!(ramps
+      .limit_message(&scalar_cmd(0, 1.0), |_| None, start)
+      .is_none());
+    assert!(ramps.step(start + Duration::from_secs(1)).is_empty());
+  }
+}
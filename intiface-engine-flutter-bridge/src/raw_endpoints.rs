@@ -0,0 +1,383 @@
+use crate::backdoor::{self, BRIDGE_MESSAGE_ID_START, REQUEST_TIMEOUT};
+use anyhow::Result;
+use buttplug::core::message::Endpoint;
+use crossbeam_channel::Sender;
+use flutter_rust_bridge::StreamSink;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::{
+  collections::{HashMap, HashSet},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+  },
+  time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// Raw endpoint access for protocol development, so people can poke at hardware from the app
+// instead of writing their own client. Data goes in and out as hex strings, which is what people
+// will be copying to and from protocol notes anyways. The server only accepts these when the
+// engine is running with raw messages allowed.
+//
+// Subscribed readings come back over the backdoor the same way sensor readings do, so they're
+// tracked and routed the same way as in sensor_stream. Bridge code can also wait on the reply to a
+// write of its own (see write_and_wait), for protocols that answer queries by notification.
+
+#[derive(Debug, Clone)]
+pub struct RawReadingEvent {
+  pub device_index: u32,
+  pub endpoint: String,
+  pub hex_data: String,
+  // Milliseconds since the unix epoch, taken when the reading got to the bridge.
+  pub timestamp_ms: u64,
+}
+
+type EndpointKey = (u32, String);
+// Id, so a waiter can take itself out again, and where its readings go.
+type ReadingWaiter = (u64, Sender<Vec<u8>>);
+
+lazy_static! {
+  static ref BRIDGE_SUBSCRIPTIONS: Mutex<HashMap<EndpointKey, Vec<StreamSink<RawReadingEvent>>>> =
+    Mutex::new(HashMap::new());
+  static ref FRONTEND_SUBSCRIPTIONS: Mutex<HashSet<EndpointKey>> = Mutex::new(HashSet::new());
+  // Locked after the other two, where more than one is needed.
+  static ref READING_WAITERS: Mutex<HashMap<EndpointKey, Vec<ReadingWaiter>>> =
+    Mutex::new(HashMap::new());
+}
+
+static NEXT_WAITER_ID: AtomicU64 = AtomicU64::new(0);
+
+// Normalizes an endpoint name to the form the server uses, and makes sure it's one it knows.
+fn endpoint_name(endpoint: &str) -> Result<String> {
+  let endpoint = endpoint.trim().to_lowercase();
+  serde_json::from_value::<Endpoint>(json!(endpoint))
+    .map_err(|_| anyhow::Error::msg(format!("Unknown endpoint {}", endpoint)))?;
+  Ok(endpoint)
+}
+
+fn parse_hex(hex_data: &str) -> Result<Vec<u8>> {
+  // Allow the usual ways of writing bytes out, e.g. "0a0b", "0a 0b", "0x0a 0x0b".
+  let cleaned: String = hex_data
+    .split_whitespace()
+    .map(|x| x.trim_start_matches("0x"))
+    .collect();
+  hex::decode(cleaned).map_err(|e| anyhow::Error::msg(format!("Invalid hex data: {}", e)))
+}
+
+fn endpoint_key(fields: &Value) -> Option<EndpointKey> {
+  Some((
+    fields.get("DeviceIndex")?.as_u64()? as u32,
+    fields.get("Endpoint")?.as_str()?.to_owned(),
+  ))
+}
+
+fn timestamp_ms() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|x| x.as_millis() as u64)
+    .unwrap_or_default()
+}
+
+fn write_bytes(
+  device_index: u32,
+  endpoint: &str,
+  data: &[u8],
+  write_with_response: bool,
+) -> Result<()> {
+  backdoor::request(
+    "RawWriteCmd",
+    json!({
+      "DeviceIndex": device_index,
+      "Endpoint": endpoint_name(endpoint)?,
+      "Data": data,
+      "WriteWithResponse": write_with_response,
+    }),
+  )?;
+  Ok(())
+}
+
+pub fn write(
+  device_index: u32,
+  endpoint: &str,
+  hex_data: &str,
+  write_with_response: bool,
+) -> Result<()> {
+  write_bytes(
+    device_index,
+    endpoint,
+    &parse_hex(hex_data)?,
+    write_with_response,
+  )
+}
+
+fn remove_waiter(key: &EndpointKey, id: u64) {
+  let still_subscribed = {
+    let subscriptions = BRIDGE_SUBSCRIPTIONS.lock().unwrap();
+    let frontend_subscriptions = FRONTEND_SUBSCRIPTIONS.lock().unwrap();
+    let mut waiters = READING_WAITERS.lock().unwrap();
+    let Some(senders) = waiters.get_mut(key) else {
+      return;
+    };
+    senders.retain(|(waiter_id, _)| *waiter_id != id);
+    if senders.is_empty() {
+      waiters.remove(key);
+    }
+    waiters.contains_key(key)
+      || subscriptions.contains_key(key)
+      || frontend_subscriptions.contains(key)
+  };
+  if still_subscribed {
+    return;
+  }
+  if let Err(e) = backdoor::request(
+    "RawUnsubscribeCmd",
+    json!({ "DeviceIndex": key.0, "Endpoint": key.1 }),
+  ) {
+    debug!(
+      "Cannot unsubscribe from {:?} after waiting on it: {:?}",
+      key, e
+    );
+  }
+}
+
+// Writes to one endpoint and collects what comes back on another until done says it's all there,
+// for protocols that answer over notifications. Subscribes to the reading endpoint for as long as
+// it's waiting, if nothing else already is.
+pub fn write_and_wait(
+  device_index: u32,
+  write_endpoint: &str,
+  data: &[u8],
+  read_endpoint: &str,
+  timeout: Duration,
+  done: impl Fn(&[u8]) -> bool,
+) -> Result<Vec<u8>> {
+  let key = (device_index, endpoint_name(read_endpoint)?);
+  let id = NEXT_WAITER_ID.fetch_add(1, Ordering::Relaxed);
+  let (sender, receiver) = crossbeam_channel::unbounded();
+  let needs_server_subscription = {
+    let subscriptions = BRIDGE_SUBSCRIPTIONS.lock().unwrap();
+    let frontend_subscriptions = FRONTEND_SUBSCRIPTIONS.lock().unwrap();
+    let mut waiters = READING_WAITERS.lock().unwrap();
+    let needs_server_subscription = !subscriptions.contains_key(&key)
+      && !frontend_subscriptions.contains(&key)
+      && !waiters.contains_key(&key);
+    waiters.entry(key.clone()).or_default().push((id, sender));
+    needs_server_subscription
+  };
+  let result = (|| {
+    if needs_server_subscription {
+      backdoor::request(
+        "RawSubscribeCmd",
+        json!({ "DeviceIndex": key.0, "Endpoint": key.1 }),
+      )?;
+    }
+    write_bytes(device_index, write_endpoint, data, false)?;
+    let deadline = Instant::now() + timeout;
+    let mut received = vec![];
+    while !done(&received) {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      let mut reading = receiver
+        .recv_timeout(remaining)
+        .map_err(|_| anyhow::Error::msg("Device didn't answer in time"))?;
+      received.append(&mut reading);
+    }
+    Ok(received)
+  })();
+  remove_waiter(&key, id);
+  result
+}
+
+pub fn read(
+  device_index: u32,
+  endpoint: &str,
+  expected_length: u32,
+  timeout_ms: u32,
+) -> Result<String> {
+  let reply = backdoor::request_with_timeout(
+    "RawReadCmd",
+    json!({
+      "DeviceIndex": device_index,
+      "Endpoint": endpoint_name(endpoint)?,
+      "ExpectedLength": expected_length,
+      "Timeout": timeout_ms,
+    }),
+    // Give the server a chance to time out on its own first, its error is more useful than ours.
+    Duration::from_millis(timeout_ms as u64) + REQUEST_TIMEOUT,
+  )?;
+  let data: Vec<u8> = serde_json::from_value(
+    reply
+      .get("RawReading")
+      .and_then(|x| x.get("Data"))
+      .cloned()
+      .ok_or(anyhow::Error::msg("Unexpected reply to RawReadCmd"))?,
+  )?;
+  Ok(hex::encode(data))
+}
+
+pub fn subscribe(
+  sink: StreamSink<RawReadingEvent>,
+  device_index: u32,
+  endpoint: &str,
+) -> Result<()> {
+  let key = (device_index, endpoint_name(endpoint)?);
+  let needs_server_subscription = {
+    let mut subscriptions = BRIDGE_SUBSCRIPTIONS.lock().unwrap();
+    let sinks = subscriptions.entry(key.clone()).or_default();
+    sinks.push(sink);
+    sinks.len() == 1
+      && !FRONTEND_SUBSCRIPTIONS.lock().unwrap().contains(&key)
+      && !READING_WAITERS.lock().unwrap().contains_key(&key)
+  };
+  if needs_server_subscription {
+    let result = backdoor::request(
+      "RawSubscribeCmd",
+      json!({ "DeviceIndex": key.0, "Endpoint": key.1 }),
+    );
+    if let Err(e) = result {
+      if let Some(sinks) = BRIDGE_SUBSCRIPTIONS.lock().unwrap().remove(&key) {
+        for sink in sinks {
+          sink.close();
+        }
+      }
+      return Err(e);
+    }
+  }
+  Ok(())
+}
+
+pub fn unsubscribe(device_index: u32, endpoint: &str) -> Result<()> {
+  let key = (device_index, endpoint_name(endpoint)?);
+  let Some(sinks) = BRIDGE_SUBSCRIPTIONS.lock().unwrap().remove(&key) else {
+    return Ok(());
+  };
+  for sink in sinks {
+    sink.close();
+  }
+  if FRONTEND_SUBSCRIPTIONS.lock().unwrap().contains(&key)
+    || READING_WAITERS.lock().unwrap().contains_key(&key)
+  {
+    return Ok(());
+  }
+  backdoor::request(
+    "RawUnsubscribeCmd",
+    json!({ "DeviceIndex": key.0, "Endpoint": key.1 }),
+  )?;
+  Ok(())
+}
+
+pub fn track_frontend_message(msg: &str) {
+  if !msg.contains("RawSubscribeCmd") && !msg.contains("RawUnsubscribeCmd") {
+    return;
+  }
+  let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(msg) else {
+    return;
+  };
+  let mut frontend_subscriptions = FRONTEND_SUBSCRIPTIONS.lock().unwrap();
+  for message in messages {
+    let Some((msg_type, fields)) = message.as_object().and_then(|x| x.iter().next()) else {
+      continue;
+    };
+    let is_bridge_message = fields
+      .get("Id")
+      .and_then(|x| x.as_u64())
+      .is_some_and(|id| id >= BRIDGE_MESSAGE_ID_START as u64);
+    let Some(key) = endpoint_key(fields).filter(|_| !is_bridge_message) else {
+      continue;
+    };
+    match msg_type.as_str() {
+      "RawSubscribeCmd" => {
+        frontend_subscriptions.insert(key);
+      }
+      "RawUnsubscribeCmd" => {
+        frontend_subscriptions.remove(&key);
+      }
+      _ => {}
+    }
+  }
+}
+
+// Same as sensor_stream::route_readings, for raw readings.
+pub fn route_readings(msg: &str) -> bool {
+  if !msg.contains("RawReading") {
+    return true;
+  }
+  let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(msg) else {
+    return true;
+  };
+  let subscriptions = BRIDGE_SUBSCRIPTIONS.lock().unwrap();
+  let frontend_subscriptions = FRONTEND_SUBSCRIPTIONS.lock().unwrap();
+  let waiters = READING_WAITERS.lock().unwrap();
+  let mut forward = false;
+  for message in messages {
+    // Replies to reads have ids, and only subscription readings come through with id 0.
+    let Some(fields) = message
+      .get("RawReading")
+      .filter(|x| x.get("Id").and_then(|x| x.as_u64()) == Some(0))
+    else {
+      forward = true;
+      continue;
+    };
+    let Some(key) = endpoint_key(fields) else {
+      forward = true;
+      continue;
+    };
+    let sinks = subscriptions.get(&key);
+    let senders = waiters.get(&key);
+    if sinks.is_none() && senders.is_none() {
+      forward = true;
+      continue;
+    }
+    let data: Vec<u8> = fields
+      .get("Data")
+      .and_then(|x| serde_json::from_value(x.clone()).ok())
+      .unwrap_or_default();
+    for (_, sender) in senders.into_iter().flatten() {
+      let _ = sender.send(data.clone());
+    }
+    if let Some(sinks) = sinks {
+      let hex_data = hex::encode(data);
+      let timestamp_ms = timestamp_ms();
+      for sink in sinks {
+        sink.add(RawReadingEvent {
+          device_index: key.0,
+          endpoint: key.1.clone(),
+          hex_data: hex_data.clone(),
+          timestamp_ms,
+        });
+      }
+    }
+    forward |= frontend_subscriptions.contains(&key);
+  }
+  forward
+}
+
+pub fn remove_device(device_index: u32) {
+  BRIDGE_SUBSCRIPTIONS.lock().unwrap().retain(|key, sinks| {
+    if key.0 != device_index {
+      return true;
+    }
+    for sink in sinks {
+      sink.close();
+    }
+    false
+  });
+  FRONTEND_SUBSCRIPTIONS
+    .lock()
+    .unwrap()
+    .retain(|key| key.0 != device_index);
+  // Dropping the senders ends the wait.
+  READING_WAITERS
+    .lock()
+    .unwrap()
+    .retain(|key, _| key.0 != device_index);
+}
+
+pub fn clear() {
+  for (_, sinks) in BRIDGE_SUBSCRIPTIONS.lock().unwrap().drain() {
+    for sink in sinks {
+      sink.close();
+    }
+  }
+  FRONTEND_SUBSCRIPTIONS.lock().unwrap().clear();
+  READING_WAITERS.lock().unwrap().clear();
+}
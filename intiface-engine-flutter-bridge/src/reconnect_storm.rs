@@ -0,0 +1,295 @@
+use crate::{
+  api::ExposedUserDeviceIdentifier,
+  clock, device_list, devices,
+  events::{self, BridgeEvent},
+  message_sink::SharedMessageSink,
+  options::{self, ReconnectStormOptions},
+};
+use intiface_engine::EngineMessage;
+use lazy_static::lazy_static;
+use std::{
+  collections::{HashMap, VecDeque},
+  sync::Mutex,
+  time::{Duration, Instant},
+};
+
+// Flaky devices (usually ones with bad batteries or at the edge of Bluetooth range) can drop and
+// reconnect dozens of times a minute. Every one of those is a device list rebuild and a config
+// write on the frontend, so once a device is reconnecting often enough to count as a storm, we stop
+// passing its connects and disconnects along until it's had a while to settle down.
+//
+// The engine still handles the device as usual while it's quarantined. When the quarantine ends,
+// the frontend gets the device's most recent connect if it's still connected, so it ends up with
+// the right state without seeing everything that happened in between.
+
+pub enum StormCheck {
+  Forward,
+  // Message is about a quarantined device, and shouldn't go to the frontend.
+  Hold,
+  // Message just pushed a device into quarantine, and shouldn't go to the frontend. Whoever sends
+  // to the frontend needs to run release_at for it.
+  Quarantined {
+    identifier: ExposedUserDeviceIdentifier,
+    until: Instant,
+  },
+}
+
+struct Quarantine {
+  until: Instant,
+  // Latest DeviceConnected for the device, if it's connected right now.
+  held_connect: Option<String>,
+}
+
+#[derive(Default)]
+struct StormState {
+  recent_connections: HashMap<ExposedUserDeviceIdentifier, VecDeque<Instant>>,
+  quarantined: HashMap<ExposedUserDeviceIdentifier, Quarantine>,
+}
+
+lazy_static! {
+  static ref STORM_STATE: Mutex<StormState> = Mutex::new(StormState::default());
+}
+
+impl StormState {
+  fn connected(
+    &mut self,
+    identifier: ExposedUserDeviceIdentifier,
+    serialized: &str,
+    options: &ReconnectStormOptions,
+  ) -> StormCheck {
+    if let Some(quarantine) = self.quarantined.get_mut(&identifier) {
+      quarantine.held_connect = Some(serialized.to_owned());
+      return StormCheck::Hold;
+    }
+    if !options.enabled {
+      return StormCheck::Forward;
+    }
+    let now = clock::now();
+    let window = Duration::from_millis(options.window_ms as u64);
+    let connections = self
+      .recent_connections
+      .entry(identifier.clone())
+      .or_default();
+    connections.push_back(now);
+    while connections
+      .front()
+      .is_some_and(|time| now.duration_since(*time) > window)
+    {
+      connections.pop_front();
+    }
+    let connection_count = connections.len() as u32;
+    if connection_count <= options.max_connections {
+      return StormCheck::Forward;
+    }
+    self.recent_connections.remove(&identifier);
+    let until = now + Duration::from_millis(options.quarantine_ms as u64);
+    self.quarantined.insert(
+      identifier.clone(),
+      Quarantine {
+        until,
+        held_connect: Some(serialized.to_owned()),
+      },
+    );
+    warn!(
+      "Device {:?} connected {} times in {}ms, quarantining for {}ms",
+      identifier, connection_count, options.window_ms, options.quarantine_ms
+    );
+    events::emit(BridgeEvent::ReconnectStormQuarantineStarted {
+      identifier: identifier.clone(),
+      connections: connection_count,
+      quarantine_ms: options.quarantine_ms,
+    });
+    StormCheck::Quarantined { identifier, until }
+  }
+
+  fn disconnected(&mut self, identifier: &ExposedUserDeviceIdentifier) -> StormCheck {
+    match self.quarantined.get_mut(identifier) {
+      Some(quarantine) => {
+        quarantine.held_connect = None;
+        StormCheck::Hold
+      }
+      None => StormCheck::Forward,
+    }
+  }
+
+  // Ends the device's quarantine if its time is up, returning the connect it was holding, if any.
+  // If the engine restarted while we were waiting, this could be a newer quarantine, which is only
+  // ours to end if its time is also up.
+  fn release(&mut self, identifier: &ExposedUserDeviceIdentifier) -> Option<Option<String>> {
+    match self.quarantined.get(identifier) {
+      Some(quarantine) if quarantine.until <= clock::now() => {}
+      _ => return None,
+    }
+    self
+      .quarantined
+      .remove(identifier)
+      .map(|quarantine| quarantine.held_connect)
+  }
+}
+
+pub fn check(msg: &EngineMessage, serialized: &str) -> StormCheck {
+  let mut state = STORM_STATE.lock().unwrap();
+  match msg {
+    EngineMessage::DeviceConnected { .. } => {
+      let Some((_, identifier)) = devices::connected_message_identifier(msg) else {
+        return StormCheck::Forward;
+      };
+      state.connected(
+        identifier,
+        serialized,
+        &options::bridge_options().reconnect_storm,
+      )
+    }
+    EngineMessage::DeviceDisconnected { index } => match devices::connected_device(*index) {
+      Some(device) => state.disconnected(&device.identifier),
+      None => StormCheck::Forward,
+    },
+    EngineMessage::EngineStopped { .. } => {
+      *state = StormState::default();
+      StormCheck::Forward
+    }
+    _ => StormCheck::Forward,
+  }
+}
+
+pub async fn release_at(
+  identifier: ExposedUserDeviceIdentifier,
+  until: Instant,
+  sink: SharedMessageSink,
+) {
+  clock::sleep_until(until).await;
+  let Some(held_connect) = STORM_STATE.lock().unwrap().release(&identifier) else {
+    return;
+  };
+  info!("Device {:?} released from quarantine", identifier);
+  events::emit(BridgeEvent::ReconnectStormQuarantineEnded {
+    identifier: identifier.clone(),
+  });
+  if let Some(msg) = held_connect {
+    device_list::apply_engine_message(&msg);
+    sink.add(msg);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  lazy_static! {
+    // Tests share the manual clock, so they take turns with it.
+    static ref CLOCK_LOCK: Mutex<()> = Mutex::new(());
+  }
+
+  fn device(address: &str) -> ExposedUserDeviceIdentifier {
+    ExposedUserDeviceIdentifier {
+      address: address.to_owned(),
+      protocol: "lovense".to_owned(),
+      identifier: None,
+    }
+  }
+
+  fn options() -> ReconnectStormOptions {
+    ReconnectStormOptions {
+      enabled: true,
+      max_connections: 3,
+      window_ms: 10_000,
+      quarantine_ms: 60_000,
+    }
+  }
+
+  fn connect(state: &mut StormState, address: &str, serial: u32) -> StormCheck {
+    state.connected(device(address), &format!("connect {}", serial), &options())
+  }
+
+  fn with_manual_clock(test: impl FnOnce()) {
+    let _lock = CLOCK_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    clock::set_manual(true);
+    test();
+    clock::set_manual(false);
+  }
+
+  #[test]
+  fn quarantines_after_too_many_connects() {
+    with_manual_clock(|| {
+      let mut state = StormState::default();
+      for i in 0..3 {
+        assert!(matches!(connect(&mut state, "a", i), StormCheck::Forward));
+        clock::advance(Duration::from_secs(1));
+      }
+      let StormCheck::Quarantined { identifier, until } = connect(&mut state, "a", 3) else {
+        panic!("Fourth connect in the window should quarantine");
+      };
+      assert_eq!(identifier, device("a"));
+      assert_eq!(until, clock::now() + Duration::from_secs(60));
+      // Other devices aren't affected.
+      assert!(matches!(connect(&mut state, "b", 0), StormCheck::Forward));
+    });
+  }
+
+  #[test]
+  fn connects_age_out_of_the_window() {
+    with_manual_clock(|| {
+      let mut state = StormState::default();
+      for i in 0..10 {
+        assert!(matches!(connect(&mut state, "a", i), StormCheck::Forward));
+        clock::advance(Duration::from_millis(3_500));
+      }
+    });
+  }
+
+  #[test]
+  fn disabled_never_quarantines() {
+    with_manual_clock(|| {
+      let mut state = StormState::default();
+      let options = ReconnectStormOptions {
+        enabled: false,
+        ..options()
+      };
+      for i in 0..10 {
+        let check = state.connected(device("a"), &format!("connect {}", i), &options);
+        assert!(matches!(check, StormCheck::Forward));
+      }
+    });
+  }
+
+  #[test]
+  fn quarantine_holds_latest_state() {
+    with_manual_clock(|| {
+      let mut state = StormState::default();
+      for i in 0..4 {
+        connect(&mut state, "a", i);
+      }
+      assert!(matches!(state.disconnected(&device("a")), StormCheck::Hold));
+      assert!(matches!(connect(&mut state, "a", 4), StormCheck::Hold));
+      assert!(matches!(
+        state.disconnected(&device("b")),
+        StormCheck::Forward
+      ));
+
+      // Not released until the time's up.
+      clock::advance(Duration::from_secs(59));
+      assert_eq!(state.release(&device("a")), None);
+      clock::advance(Duration::from_secs(1));
+      assert_eq!(
+        state.release(&device("a")),
+        Some(Some("connect 4".to_owned()))
+      );
+      // And it's counted from scratch afterwards.
+      assert!(matches!(connect(&mut state, "a", 5), StormCheck::Forward));
+    });
+  }
+
+  #[test]
+  fn disconnected_devices_come_out_with_nothing_to_send() {
+    with_manual_clock(|| {
+      let mut state = StormState::default();
+      for i in 0..4 {
+        connect(&mut state, "a", i);
+      }
+      state.disconnected(&device("a"));
+      clock::advance(Duration::from_secs(60));
+      assert_eq!(state.release(&device("a")), Some(None));
+      assert_eq!(state.release(&device("a")), None);
+    });
+  }
+}
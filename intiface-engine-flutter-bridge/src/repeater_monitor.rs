@@ -0,0 +1,321 @@
+use crate::{
+  api::EngineOptionsExternal,
+  clock,
+  events::{self, BridgeEvent},
+  websocket_proxy::{self, TokenCheck},
+};
+use anyhow::Result;
+use futures::{SinkExt, StreamExt};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+  net::{SocketAddr, TcpListener as StdTcpListener},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{
+  io::{self, AsyncWriteExt},
+  net::{TcpListener, TcpStream},
+  select,
+  sync::Notify,
+};
+use tokio_tungstenite::{accept_async, connect_async, tungstenite::Message};
+
+// The engine's repeater gives us nothing back about how it's doing. To see what's going on, we
+// point its remote side at a relay of ours on localhost, which connects out to the real remote and
+// passes messages through, keeping track of connection state and traffic as it goes.
+//
+// The engine's repeater also lets anything that can reach its local port use it. With a token set,
+// the engine's repeater is moved to an internal port and we take the local port, only passing
+// through peers whose upgrade request has the token, the same ways websocket clients pass it (see
+// websocket_proxy).
+
+// How often counters go out while a connection is up. State changes go out right away.
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum RepeaterConnectionState {
+  // Waiting for a local client to connect, which is when the repeater connects out.
+  #[default]
+  Idle,
+  Connecting,
+  Connected,
+  Disconnected,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RepeaterStatus {
+  pub remote_address: String,
+  pub state: RepeaterConnectionState,
+  // Every attempt to connect to the remote, including the first.
+  pub connection_attempts: u32,
+  pub messages_to_remote: u64,
+  pub messages_from_remote: u64,
+  pub bytes_to_remote: u64,
+  pub bytes_from_remote: u64,
+  pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RepeaterRelay {
+  remote_url: String,
+  port: u16,
+  front: Option<RepeaterFront>,
+}
+
+#[derive(Debug, Clone)]
+struct RepeaterFront {
+  public_port: u16,
+  engine_port: u16,
+  token: String,
+}
+
+lazy_static! {
+  static ref REPEATER_STATUS: Mutex<Option<RepeaterStatus>> = Mutex::new(None);
+}
+
+pub fn status() -> Option<RepeaterStatus> {
+  REPEATER_STATUS.lock().unwrap().clone()
+}
+
+fn update_status(f: impl FnOnce(&mut RepeaterStatus), emit: bool) {
+  let status = {
+    let mut status = REPEATER_STATUS.lock().unwrap();
+    let Some(status) = status.as_mut() else {
+      return;
+    };
+    f(status);
+    status.clone()
+  };
+  if emit {
+    events::emit(BridgeEvent::RepeaterStatus(status));
+  }
+}
+
+// Swaps the engine's remote address for our relay, if the engine is going to run as a repeater, and
+// its local port for an internal one if peers need a token.
+pub fn plan(
+  args: &mut EngineOptionsExternal,
+  token: Option<String>,
+) -> Result<Option<RepeaterRelay>> {
+  let (true, Some(remote_address)) = (args.repeater_mode, args.repeater_remote_address.clone())
+  else {
+    return Ok(None);
+  };
+  let port = StdTcpListener::bind(("127.0.0.1", 0))?.local_addr()?.port();
+  // The engine takes addresses both with and without a scheme, so keep whatever we were given.
+  let (remote_url, relay_address) = if remote_address.contains("://") {
+    (remote_address.clone(), format!("ws://127.0.0.1:{}", port))
+  } else {
+    (
+      format!("ws://{}", remote_address),
+      format!("127.0.0.1:{}", port),
+    )
+  };
+  args.repeater_remote_address = Some(relay_address);
+  let front = match (token.filter(|x| !x.is_empty()), args.repeater_local_port) {
+    (Some(token), Some(public_port)) => {
+      let engine_port = websocket_proxy::internal_port()?;
+      args.repeater_local_port = Some(engine_port);
+      Some(RepeaterFront {
+        public_port,
+        engine_port,
+        token,
+      })
+    }
+    _ => None,
+  };
+  Ok(Some(RepeaterRelay {
+    remote_url,
+    port,
+    front,
+  }))
+}
+
+pub async fn run_repeater_relay(relay: RepeaterRelay, stop: Arc<Notify>) {
+  *REPEATER_STATUS.lock().unwrap() = Some(RepeaterStatus {
+    remote_address: relay.remote_url.clone(),
+    ..Default::default()
+  });
+  update_status(|_| {}, true);
+  let front = relay.front.clone();
+  tokio::join!(run_relay_listener(relay, stop.clone()), async {
+    if let Some(front) = front {
+      run_repeater_front(front, stop.clone()).await;
+    }
+  });
+  *REPEATER_STATUS.lock().unwrap() = None;
+}
+
+async fn run_relay_listener(relay: RepeaterRelay, stop: Arc<Notify>) {
+  let listener = match TcpListener::bind(("127.0.0.1", relay.port)).await {
+    Ok(listener) => listener,
+    Err(e) => {
+      error!("Cannot bind repeater relay: {:?}", e);
+      update_status(|x| x.last_error = Some(e.to_string()), true);
+      return;
+    }
+  };
+  loop {
+    select! {
+      conn = listener.accept() => {
+        let Ok((stream, _)) = conn else {
+          continue;
+        };
+        let remote_url = relay.remote_url.clone();
+        let stop = stop.clone();
+        tokio::spawn(async move {
+          relay_connection(stream, remote_url, stop).await;
+        });
+      }
+      _ = stop.notified() => break
+    }
+  }
+}
+
+async fn run_repeater_front(front: RepeaterFront, stop: Arc<Notify>) {
+  let listener = match TcpListener::bind(("0.0.0.0", front.public_port)).await {
+    Ok(listener) => listener,
+    Err(e) => {
+      error!("Cannot bind repeater port {}: {:?}", front.public_port, e);
+      update_status(|x| x.last_error = Some(e.to_string()), true);
+      return;
+    }
+  };
+  loop {
+    select! {
+      conn = listener.accept() => {
+        let Ok((stream, peer)) = conn else {
+          continue;
+        };
+        let front = front.clone();
+        tokio::spawn(async move {
+          front_connection(stream, peer, front).await;
+        });
+      }
+      _ = stop.notified() => break
+    }
+  }
+}
+
+async fn front_connection(mut stream: TcpStream, peer: SocketAddr, front: RepeaterFront) {
+  let request = match websocket_proxy::check_request_token(&mut stream, peer, &front.token).await {
+    TokenCheck::Passed(request) => request,
+    TokenCheck::Rejected(reason) => {
+      info!("Rejected repeater connection from {}: {}", peer, reason);
+      events::emit(BridgeEvent::RepeaterConnectionRejected {
+        address: peer.to_string(),
+        reason: reason.to_owned(),
+      });
+      return;
+    }
+    TokenCheck::Failed => return,
+  };
+  let mut engine_stream = match TcpStream::connect(("127.0.0.1", front.engine_port)).await {
+    Ok(engine_stream) => engine_stream,
+    Err(e) => {
+      warn!("Cannot reach engine repeater: {:?}", e);
+      return;
+    }
+  };
+  // The engine's repeater never sees the token, so it doesn't get passed on to the remote.
+  if let Err(e) = engine_stream.write_all(&request).await {
+    debug!("Cannot pass repeater request on: {:?}", e);
+    return;
+  }
+  if let Err(e) = io::copy_bidirectional(&mut stream, &mut engine_stream).await {
+    debug!("Repeater connection from {} ended: {:?}", peer, e);
+  }
+}
+
+async fn relay_connection(stream: tokio::net::TcpStream, remote_url: String, stop: Arc<Notify>) {
+  let engine_ws = match accept_async(stream).await {
+    Ok(engine_ws) => engine_ws,
+    Err(e) => {
+      warn!("Repeater relay handshake with engine failed: {:?}", e);
+      return;
+    }
+  };
+  update_status(
+    |x| {
+      x.state = RepeaterConnectionState::Connecting;
+      x.connection_attempts += 1;
+    },
+    true,
+  );
+  let remote_ws = match connect_async(&remote_url).await {
+    Ok((remote_ws, _)) => remote_ws,
+    Err(e) => {
+      warn!("Repeater cannot connect to {}: {:?}", remote_url, e);
+      update_status(
+        |x| {
+          x.state = RepeaterConnectionState::Disconnected;
+          x.last_error = Some(e.to_string());
+        },
+        true,
+      );
+      // Dropping the engine side lets the repeater know, same as if it had failed itself.
+      return;
+    }
+  };
+  update_status(|x| x.state = RepeaterConnectionState::Connected, true);
+  let (mut engine_sink, mut engine_stream) = engine_ws.split();
+  let (mut remote_sink, mut remote_stream) = remote_ws.split();
+  let mut status_ticker = clock::Ticker::new(STATUS_INTERVAL);
+  let error = loop {
+    select! {
+      msg = engine_stream.next() => {
+        let msg = match msg {
+          Some(Ok(msg)) => msg,
+          Some(Err(e)) => break Some(e.to_string()),
+          None => break None,
+        };
+        let closing = matches!(msg, Message::Close(_));
+        let len = msg.len() as u64;
+        if let Err(e) = remote_sink.send(msg).await {
+          break Some(e.to_string());
+        }
+        update_status(|x| {
+          x.messages_to_remote += 1;
+          x.bytes_to_remote += len;
+        }, false);
+        if closing {
+          break None;
+        }
+      }
+      msg = remote_stream.next() => {
+        let msg = match msg {
+          Some(Ok(msg)) => msg,
+          Some(Err(e)) => break Some(e.to_string()),
+          None => break None,
+        };
+        let closing = matches!(msg, Message::Close(_));
+        let len = msg.len() as u64;
+        if let Err(e) = engine_sink.send(msg).await {
+          break Some(e.to_string());
+        }
+        update_status(|x| {
+          x.messages_from_remote += 1;
+          x.bytes_from_remote += len;
+        }, false);
+        if closing {
+          break None;
+        }
+      }
+      _ = status_ticker.tick() => update_status(|_| {}, true),
+      _ = stop.notified() => break None,
+    }
+  };
+  if let Some(e) = &error {
+    warn!("Repeater connection to {} failed: {}", remote_url, e);
+  }
+  update_status(
+    |x| {
+      x.state = RepeaterConnectionState::Disconnected;
+      if error.is_some() {
+        x.last_error = error;
+      }
+    },
+    true,
+  );
+}
@@ -0,0 +1,102 @@
+use crate::{
+  app_lifecycle,
+  events::{self, BridgeEvent},
+};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::{
+  sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Mutex,
+  },
+  thread,
+  time::Duration,
+};
+
+// Scanning keeps the radio busy until someone stops it, and clients aren't always good about that.
+// With a scan timeout set, scanning is stopped that long after it was last started, whoever started
+// it. Timeouts can be set for a single scan with backdoor_start_scanning.
+//
+// We see StartScanning from the frontend, the bridge itself and clients on the websocket proxy.
+// Clients connected straight to the engine get past us, so their scans only time out if one of the
+// others started scanning too.
+//
+// ScanFinished goes out whenever scanning stops, saying whether it was a timeout that stopped it.
+
+// Counts scan starts, so a timer can tell whether scanning was restarted while it was waiting.
+static SCAN_GENERATION: AtomicU32 = AtomicU32::new(0);
+static SCANNING: AtomicBool = AtomicBool::new(false);
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+// Default timeout from the engine options, 0 for none.
+static DEFAULT_TIMEOUT_MS: AtomicU32 = AtomicU32::new(0);
+
+lazy_static! {
+  // Timeout for the next scan started, from backdoor_start_scanning.
+  static ref NEXT_TIMEOUT_MS: Mutex<Option<u32>> = Mutex::new(None);
+}
+
+pub fn set_default_timeout(timeout_ms: Option<u32>) {
+  DEFAULT_TIMEOUT_MS.store(timeout_ms.unwrap_or(0), Ordering::Relaxed);
+}
+
+// Overrides the timeout for the next scan that starts. 0 means no timeout.
+pub fn set_next_timeout(timeout_ms: u32) {
+  *NEXT_TIMEOUT_MS.lock().unwrap() = Some(timeout_ms);
+}
+
+fn message_types(messages: &[Value]) -> impl Iterator<Item = &str> {
+  messages
+    .iter()
+    .filter_map(|x| x.as_object()?.keys().next().map(|x| x.as_str()))
+}
+
+fn scanning_started() {
+  let generation = SCAN_GENERATION.fetch_add(1, Ordering::Relaxed) + 1;
+  SCANNING.store(true, Ordering::Relaxed);
+  TIMED_OUT.store(false, Ordering::Relaxed);
+  let timeout_ms = NEXT_TIMEOUT_MS
+    .lock()
+    .unwrap()
+    .take()
+    .unwrap_or_else(|| DEFAULT_TIMEOUT_MS.load(Ordering::Relaxed));
+  if timeout_ms == 0 {
+    return;
+  }
+  thread::spawn(move || {
+    thread::sleep(Duration::from_millis(timeout_ms as u64));
+    if SCAN_GENERATION.load(Ordering::Relaxed) != generation || !SCANNING.load(Ordering::Relaxed) {
+      return;
+    }
+    info!("Scanning timed out after {}ms, stopping", timeout_ms);
+    TIMED_OUT.store(true, Ordering::Relaxed);
+    app_lifecycle::send_scanning(false);
+  });
+}
+
+// Messages on their way to the engine, from anyone.
+pub fn track_client_messages(messages: &[Value]) {
+  for message_type in message_types(messages) {
+    match message_type {
+      "StartScanning" => scanning_started(),
+      "StopScanning" => SCANNING.store(false, Ordering::Relaxed),
+      _ => {}
+    }
+  }
+}
+
+// Messages from the backdoor server, which gets told about scanning finishing whoever started it.
+pub fn track_server_messages(messages: &[Value]) {
+  if message_types(messages).any(|x| x == "ScanningFinished") {
+    SCANNING.store(false, Ordering::Relaxed);
+    events::emit(BridgeEvent::ScanFinished {
+      timed_out: TIMED_OUT.swap(false, Ordering::Relaxed),
+    });
+  }
+}
+
+pub fn clear() {
+  SCAN_GENERATION.fetch_add(1, Ordering::Relaxed);
+  SCANNING.store(false, Ordering::Relaxed);
+  TIMED_OUT.store(false, Ordering::Relaxed);
+  NEXT_TIMEOUT_MS.lock().unwrap().take();
+}
@@ -0,0 +1,254 @@
+use crate::{
+  backdoor::{self, BRIDGE_MESSAGE_ID_START},
+  binary_stream::{self, BinaryEvent},
+};
+use anyhow::Result;
+use flutter_rust_bridge::StreamSink;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::{
+  collections::{HashMap, HashSet},
+  sync::Mutex,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+// Sensor subscriptions that stream straight to their own Flutter stream, instead of coming back as
+// JSON over the backdoor channel, so things like pressure graphs can run at whatever rate the
+// device reports without going through the frontend's message handling.
+//
+// The server sends readings for every subscription on the backdoor connection the same way, so we
+// have to keep track of which sensors the frontend's own client has subscribed to. Readings for
+// sensors only the bridge wants are kept out of the frontend's stream.
+
+#[derive(Debug, Clone)]
+pub struct SensorReadingEvent {
+  pub device_index: u32,
+  pub sensor_index: u32,
+  pub sensor_type: String,
+  pub data: Vec<i32>,
+  // Milliseconds since the unix epoch, taken when the reading got to the bridge.
+  pub timestamp_ms: u64,
+}
+
+type SensorKey = (u32, u32, String);
+
+// Where a subscription's readings go.
+enum ReadingSink {
+  Stream(StreamSink<SensorReadingEvent>),
+  // The shared binary stream, which isn't ours to close.
+  Binary,
+}
+
+impl ReadingSink {
+  fn add(&self, event: SensorReadingEvent) {
+    match self {
+      ReadingSink::Stream(sink) => {
+        sink.add(event);
+      }
+      ReadingSink::Binary => binary_stream::emit(&BinaryEvent::SensorReading {
+        device_index: event.device_index,
+        sensor_index: event.sensor_index,
+        sensor_type: event.sensor_type,
+        data: event.data,
+        timestamp_ms: event.timestamp_ms,
+      }),
+    }
+  }
+
+  fn close(&self) {
+    if let ReadingSink::Stream(sink) = self {
+      sink.close();
+    }
+  }
+}
+
+lazy_static! {
+  static ref BRIDGE_SUBSCRIPTIONS: Mutex<HashMap<SensorKey, Vec<ReadingSink>>> =
+    Mutex::new(HashMap::new());
+  static ref FRONTEND_SUBSCRIPTIONS: Mutex<HashSet<SensorKey>> = Mutex::new(HashSet::new());
+}
+
+fn sensor_key(fields: &Value) -> Option<SensorKey> {
+  Some((
+    fields.get("DeviceIndex")?.as_u64()? as u32,
+    fields.get("SensorIndex")?.as_u64()? as u32,
+    fields.get("SensorType")?.as_str()?.to_owned(),
+  ))
+}
+
+fn request_subscription(msg_type: &str, key: &SensorKey) -> Result<()> {
+  backdoor::request(
+    msg_type,
+    json!({ "DeviceIndex": key.0, "SensorIndex": key.1, "SensorType": key.2 }),
+  )?;
+  Ok(())
+}
+
+pub fn subscribe(
+  sink: StreamSink<SensorReadingEvent>,
+  device_index: u32,
+  sensor_index: u32,
+  sensor_type: String,
+) -> Result<()> {
+  add_subscription(
+    ReadingSink::Stream(sink),
+    device_index,
+    sensor_index,
+    sensor_type,
+  )
+}
+
+// Same as subscribe, with readings going out on the binary stream.
+pub fn subscribe_binary(device_index: u32, sensor_index: u32, sensor_type: String) -> Result<()> {
+  if !binary_stream::is_set() {
+    return Err(anyhow::Error::msg("Binary stream is not set up"));
+  }
+  add_subscription(ReadingSink::Binary, device_index, sensor_index, sensor_type)
+}
+
+fn add_subscription(
+  sink: ReadingSink,
+  device_index: u32,
+  sensor_index: u32,
+  sensor_type: String,
+) -> Result<()> {
+  let key = (device_index, sensor_index, sensor_type);
+  let needs_server_subscription = {
+    let mut subscriptions = BRIDGE_SUBSCRIPTIONS.lock().unwrap();
+    let sinks = subscriptions.entry(key.clone()).or_default();
+    sinks.push(sink);
+    sinks.len() == 1 && !FRONTEND_SUBSCRIPTIONS.lock().unwrap().contains(&key)
+  };
+  if needs_server_subscription {
+    if let Err(e) = request_subscription("SensorSubscribeCmd", &key) {
+      if let Some(sinks) = BRIDGE_SUBSCRIPTIONS.lock().unwrap().remove(&key) {
+        for sink in sinks {
+          sink.close();
+        }
+      }
+      return Err(e);
+    }
+  }
+  Ok(())
+}
+
+// Closes every stream for the sensor.
+pub fn unsubscribe(device_index: u32, sensor_index: u32, sensor_type: String) -> Result<()> {
+  let key = (device_index, sensor_index, sensor_type);
+  let Some(sinks) = BRIDGE_SUBSCRIPTIONS.lock().unwrap().remove(&key) else {
+    return Ok(());
+  };
+  for sink in sinks {
+    sink.close();
+  }
+  if FRONTEND_SUBSCRIPTIONS.lock().unwrap().contains(&key) {
+    return Ok(());
+  }
+  request_subscription("SensorUnsubscribeCmd", &key)
+}
+
+// Keeps track of subscriptions made by the frontend's client, from the messages it sends.
+pub fn track_frontend_message(msg: &str) {
+  if !msg.contains("SensorSubscribeCmd") && !msg.contains("SensorUnsubscribeCmd") {
+    return;
+  }
+  let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(msg) else {
+    return;
+  };
+  let mut frontend_subscriptions = FRONTEND_SUBSCRIPTIONS.lock().unwrap();
+  for message in messages {
+    let Some((msg_type, fields)) = message.as_object().and_then(|x| x.iter().next()) else {
+      continue;
+    };
+    let is_bridge_message = fields
+      .get("Id")
+      .and_then(|x| x.as_u64())
+      .is_some_and(|id| id >= BRIDGE_MESSAGE_ID_START as u64);
+    let Some(key) = sensor_key(fields).filter(|_| !is_bridge_message) else {
+      continue;
+    };
+    match msg_type.as_str() {
+      "SensorSubscribeCmd" => {
+        frontend_subscriptions.insert(key);
+      }
+      "SensorUnsubscribeCmd" => {
+        frontend_subscriptions.remove(&key);
+      }
+      _ => {}
+    }
+  }
+}
+
+// Sends readings out to any bridge subscriptions. Returns whether the message should still go to
+// the frontend, which is the case for everything but readings that only the bridge asked for.
+pub fn route_readings(msg: &str) -> bool {
+  if !msg.contains("SensorReading") {
+    return true;
+  }
+  let Ok(Value::Array(messages)) = serde_json::from_str::<Value>(msg) else {
+    return true;
+  };
+  let timestamp_ms = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|x| x.as_millis() as u64)
+    .unwrap_or_default();
+  let subscriptions = BRIDGE_SUBSCRIPTIONS.lock().unwrap();
+  let frontend_subscriptions = FRONTEND_SUBSCRIPTIONS.lock().unwrap();
+  let mut forward = false;
+  for message in messages {
+    let Some(fields) = message.get("SensorReading") else {
+      forward = true;
+      continue;
+    };
+    let Some(key) = sensor_key(fields) else {
+      forward = true;
+      continue;
+    };
+    match subscriptions.get(&key) {
+      Some(sinks) => {
+        let data: Vec<i32> = fields
+          .get("Data")
+          .and_then(|x| serde_json::from_value(x.clone()).ok())
+          .unwrap_or_default();
+        for sink in sinks {
+          sink.add(SensorReadingEvent {
+            device_index: key.0,
+            sensor_index: key.1,
+            sensor_type: key.2.clone(),
+            data: data.clone(),
+            timestamp_ms,
+          });
+        }
+        forward |= frontend_subscriptions.contains(&key);
+      }
+      None => forward = true,
+    }
+  }
+  forward
+}
+
+// Subscriptions don't survive the device going away, so close anything for it.
+pub fn remove_device(device_index: u32) {
+  BRIDGE_SUBSCRIPTIONS.lock().unwrap().retain(|key, sinks| {
+    if key.0 != device_index {
+      return true;
+    }
+    for sink in sinks {
+      sink.close();
+    }
+    false
+  });
+  FRONTEND_SUBSCRIPTIONS
+    .lock()
+    .unwrap()
+    .retain(|key| key.0 != device_index);
+}
+
+pub fn clear() {
+  for (_, sinks) in BRIDGE_SUBSCRIPTIONS.lock().unwrap().drain() {
+    for sink in sinks {
+      sink.close();
+    }
+  }
+  FRONTEND_SUBSCRIPTIONS.lock().unwrap().clear();
+}
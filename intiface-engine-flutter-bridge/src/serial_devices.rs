@@ -0,0 +1,84 @@
+use crate::{
+  api::{ExposedSerialSpecifier, DEVICE_CONFIG_MANAGER},
+  app_lifecycle,
+};
+use anyhow::Result;
+use buttplug::server::device::{
+  configuration::ProtocolCommunicationSpecifier, protocol::get_default_protocol_map,
+};
+use std::time::Duration;
+
+// Registering serial devices by hand, which is how most E-stim boxes and OSR-style strokers get
+// set up, as there's nothing on a serial port to identify what's on the other end. Settings other
+// than the baud rate are 8N1, which is what all of those use.
+
+// Serial ports are only opened while scanning, so a running engine needs a scan to pick the new
+// device up.
+const HOT_ADD_SCAN_DURATION: Duration = Duration::from_secs(5);
+
+// Whether the OS lists the port. Only checked where we can enumerate ports, otherwise the engine
+// will tell us when it tries to open it.
+fn port_exists(port: &str) -> bool {
+  match serialport::available_ports() {
+    Ok(ports) => ports.iter().any(|x| x.port_name == port),
+    Err(e) => {
+      warn!(
+        "Cannot list serial ports, not checking {} exists: {:?}",
+        port, e
+      );
+      true
+    }
+  }
+}
+
+pub fn add_device(port: String, baud_rate: u32, protocol: String, hot_add: bool) -> Result<()> {
+  if !get_default_protocol_map().contains_key(&protocol) {
+    return Err(anyhow::Error::msg(format!("Unknown protocol {}", protocol)));
+  }
+  let specifier = ExposedSerialSpecifier {
+    baud_rate,
+    data_bits: 8,
+    stop_bits: 1,
+    parity: "N".to_owned(),
+    port,
+  };
+  specifier.validate()?;
+  if !port_exists(&specifier.port) {
+    return Err(anyhow::Error::msg(format!(
+      "Serial port {} does not exist",
+      specifier.port
+    )));
+  }
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  // A port can only be opened by one protocol.
+  if let Some(other) = dcm.user_communication_specifiers().iter().find(|kv| {
+    kv.key() != &protocol
+      && kv.value().iter().any(|x| match x {
+        ProtocolCommunicationSpecifier::Serial(serial) => serial.port() == &specifier.port,
+        _ => false,
+      })
+  }) {
+    return Err(anyhow::Error::msg(format!(
+      "Serial port {} is already set up for protocol {}",
+      specifier.port,
+      other.key()
+    )));
+  }
+  info!(
+    "Adding serial device on {} at {} baud for protocol {}",
+    specifier.port, baud_rate, protocol
+  );
+  dcm
+    .add_user_communication_specifier(
+      &protocol,
+      &ProtocolCommunicationSpecifier::Serial(specifier.into()),
+    )
+    .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+  // The running engine shares the loaded config, so scanning is all it needs.
+  if hot_add {
+    app_lifecycle::scan_for(HOT_ADD_SCAN_DURATION);
+  }
+  Ok(())
+}
@@ -0,0 +1,100 @@
+use crate::{
+  events::{self, BridgeEvent},
+  websocket_proxy::{self, ListeningServer},
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{collections::HashSet, net::SocketAddr, sync::Mutex};
+use tokio::sync::Notify;
+
+// run_engine returns as soon as the engine is on its way up, well before anything is listening, so
+// connection details shown or broadcast then may not work yet. This keeps track of which servers
+// this run should have, and sends ServerStarted once they're all accepting connections.
+//
+// Servers we front (see websocket_proxy and websocket_tls) are ready once we've bound them. Servers
+// the engine serves by itself are counted as ready when it says it's created its server, as it
+// starts listening straight after that.
+//
+// If a server can't bind at all, ServerStarted isn't sent for that run, and the reason is in the log.
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerAddresses {
+  pub server: ListeningServer,
+  // ip:port
+  pub addresses: Vec<String>,
+}
+
+#[derive(Default)]
+struct ReadyState {
+  expected: HashSet<ListeningServer>,
+  // Servers the engine is listening on by itself, reported once it's created its server.
+  engine_listening: Vec<(ListeningServer, SocketAddr)>,
+  listening: Vec<ServerAddresses>,
+  started: bool,
+}
+
+lazy_static! {
+  static ref READY_STATE: Mutex<ReadyState> = Mutex::new(ReadyState::default());
+  static ref SERVER_STARTED: Notify = Notify::new();
+}
+
+// Called when starting the engine, with every server the run should end up listening on.
+pub fn begin(expected: Vec<ListeningServer>, engine_listening: Vec<(ListeningServer, SocketAddr)>) {
+  *READY_STATE.lock().unwrap() = ReadyState {
+    expected: expected.into_iter().collect(),
+    engine_listening,
+    ..Default::default()
+  };
+}
+
+pub fn engine_server_created() {
+  let engine_listening = std::mem::take(&mut READY_STATE.lock().unwrap().engine_listening);
+  for (server, address) in engine_listening {
+    websocket_proxy::report_listening(server, vec![address]);
+  }
+}
+
+pub fn server_listening(server: ListeningServer, addresses: &[SocketAddr]) {
+  let mut state = READY_STATE.lock().unwrap();
+  state.listening.retain(|x| x.server != server);
+  state.listening.push(ServerAddresses {
+    server,
+    addresses: addresses.iter().map(|x| x.to_string()).collect(),
+  });
+  if state.started
+    || state.expected.is_empty()
+    || !state
+      .expected
+      .iter()
+      .all(|x| state.listening.iter().any(|y| y.server == *x))
+  {
+    return;
+  }
+  state.started = true;
+  info!("All servers listening");
+  events::emit(BridgeEvent::ServerStarted {
+    servers: state.listening.clone(),
+  });
+  SERVER_STARTED.notify_waiters();
+}
+
+// Addresses for every server, once they're all listening.
+pub fn addresses() -> Option<Vec<ServerAddresses>> {
+  let state = READY_STATE.lock().unwrap();
+  state.started.then(|| state.listening.clone())
+}
+
+// Waits until every server is listening. Never returns for runs without any.
+pub async fn wait() {
+  loop {
+    let started = SERVER_STARTED.notified();
+    if READY_STATE.lock().unwrap().started {
+      return;
+    }
+    started.await;
+  }
+}
+
+pub fn clear() {
+  *READY_STATE.lock().unwrap() = ReadyState::default();
+}
@@ -0,0 +1,257 @@
+use crate::{
+  api::{self, ExposedUserDeviceIdentifier},
+  backdoor, clock, devices,
+  events::{self, BridgeEvent},
+  mobile_init,
+};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+  fs::File,
+  io::{BufRead, BufReader, BufWriter, Write},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+use tokio::{select, sync::Notify};
+
+// Records the device commands going to the engine to a file, and plays them back later, for
+// reproducing bug reports and trying out patterns. Commands are recorded as they were asked for,
+// before ramps and curves, as replays go through those again on their way to the engine.
+//
+// Files are JSON lines: a header, then one line per command with its offset from the start of the
+// recording. Devices are matched up by identifier on replay, so recordings work across restarts
+// (and against simulated devices, which always have the same identifiers), falling back to the
+// recorded index for devices we couldn't identify at the time.
+
+const SESSION_RECORDING_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct RecordingHeader {
+  version: u32,
+  // Milliseconds since the unix epoch.
+  started_at_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedCommand {
+  offset_ms: u64,
+  device_index: u32,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  identifier: Option<ExposedUserDeviceIdentifier>,
+  message_type: String,
+  fields: Value,
+}
+
+struct Recording {
+  started_at: Instant,
+  writer: BufWriter<File>,
+  commands: u32,
+}
+
+// Checked before taking the lock, so recording costs nothing when it's off.
+static RECORDING_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+  static ref RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+  static ref REPLAY_STOP: Mutex<Option<Arc<Notify>>> = Mutex::new(None);
+}
+
+pub fn start_recording(path: &str) -> Result<()> {
+  let mut recording = RECORDING.lock().unwrap();
+  if recording.is_some() {
+    return Err(anyhow::Error::msg("Session recording already running"));
+  }
+  let mut writer = BufWriter::new(File::create(path)?);
+  let header = RecordingHeader {
+    version: SESSION_RECORDING_VERSION,
+    started_at_ms: std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .map(|x| x.as_millis() as u64)
+      .unwrap_or_default(),
+  };
+  writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+  *recording = Some(Recording {
+    started_at: clock::now(),
+    writer,
+    commands: 0,
+  });
+  RECORDING_ACTIVE.store(true, Ordering::Relaxed);
+  info!("Recording session to {}", path);
+  Ok(())
+}
+
+// Returns how many commands were recorded.
+pub fn stop_recording() -> Result<u32> {
+  RECORDING_ACTIVE.store(false, Ordering::Relaxed);
+  let mut recording = RECORDING
+    .lock()
+    .unwrap()
+    .take()
+    .ok_or(anyhow::Error::msg("No session recording running"))?;
+  recording.writer.flush()?;
+  info!("Session recording stopped, {} commands", recording.commands);
+  Ok(recording.commands)
+}
+
+// Gets everything recorded so far onto disk, without stopping.
+pub fn flush() {
+  if let Some(recording) = RECORDING.lock().unwrap().as_mut() {
+    if let Err(e) = recording.writer.flush() {
+      warn!("Cannot flush session recording: {:?}", e);
+    }
+  }
+}
+
+pub fn record(messages: &[Value]) {
+  if !RECORDING_ACTIVE.load(Ordering::Relaxed) {
+    return;
+  }
+  let mut recording = RECORDING.lock().unwrap();
+  let Some(recording) = recording.as_mut() else {
+    return;
+  };
+  let offset_ms = clock::now()
+    .saturating_duration_since(recording.started_at)
+    .as_millis() as u64;
+  for message in messages {
+    let Some((message_type, fields)) = message.as_object().and_then(|x| x.iter().next()) else {
+      continue;
+    };
+    let Some(device_index) = fields
+      .get("DeviceIndex")
+      .and_then(|x| x.as_u64())
+      .map(|x| x as u32)
+    else {
+      continue;
+    };
+    let command = RecordedCommand {
+      offset_ms,
+      device_index,
+      identifier: devices::connected_device(device_index).map(|x| x.identifier),
+      message_type: message_type.clone(),
+      fields: fields.clone(),
+    };
+    let line = serde_json::to_string(&command).expect("Recorded commands should always serialize");
+    if let Err(e) = writeln!(recording.writer, "{}", line) {
+      warn!("Cannot write to session recording: {:?}", e);
+      continue;
+    }
+    recording.commands += 1;
+  }
+}
+
+// Same as record, for serialized message arrays.
+pub fn record_json(msg: &str) {
+  if RECORDING_ACTIVE.load(Ordering::Relaxed) {
+    record(&serde_json::from_str::<Vec<Value>>(msg).unwrap_or_default());
+  }
+}
+
+fn load_recording(path: &str) -> Result<Vec<RecordedCommand>> {
+  let mut lines = BufReader::new(File::open(path)?).lines();
+  let header: RecordingHeader = serde_json::from_str(
+    &lines
+      .next()
+      .ok_or(anyhow::Error::msg("Session recording is empty"))??,
+  )?;
+  if header.version > SESSION_RECORDING_VERSION {
+    return Err(anyhow::Error::msg(format!(
+      "Session recording version {} is newer than supported version {}",
+      header.version, SESSION_RECORDING_VERSION
+    )));
+  }
+  let mut commands = vec![];
+  for line in lines {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    commands.push(serde_json::from_str(&line)?);
+  }
+  Ok(commands)
+}
+
+// Plays a recording back against whatever's connected now. Runs in the background, sending a
+// SessionReplayFinished event when it's done or stopped.
+pub fn start_replay(path: &str) -> Result<()> {
+  if !api::runtime_started() {
+    return Err(anyhow::Error::msg("Engine is not running"));
+  }
+  // Load it all up front, so a bad file fails here rather than halfway through.
+  let commands = load_recording(path)?;
+  let stop = {
+    let mut replay_stop = REPLAY_STOP.lock().unwrap();
+    if replay_stop.is_some() {
+      return Err(anyhow::Error::msg("Session replay already running"));
+    }
+    let stop = Arc::new(Notify::new());
+    *replay_stop = Some(stop.clone());
+    stop
+  };
+  let runtime = match mobile_init::create_runtime() {
+    Ok(runtime) => runtime,
+    Err(e) => {
+      *REPLAY_STOP.lock().unwrap() = None;
+      return Err(e.into());
+    }
+  };
+  let path = path.to_owned();
+  thread::spawn(move || {
+    info!("Replaying {} commands from {}", commands.len(), path);
+    let (commands_sent, completed) = runtime.block_on(replay(commands, stop.clone()));
+    events::emit(BridgeEvent::SessionReplayFinished {
+      path,
+      commands_sent,
+      completed,
+    });
+    // Only clear our own handle, a new replay may have been started after a stop.
+    let mut replay_stop = REPLAY_STOP.lock().unwrap();
+    if replay_stop.as_ref().is_some_and(|x| Arc::ptr_eq(x, &stop)) {
+      *replay_stop = None;
+    }
+  });
+  Ok(())
+}
+
+pub fn stop_replay() {
+  if let Some(stop) = REPLAY_STOP.lock().unwrap().take() {
+    // notify_one, so the stop sticks even if the replay task isn't waiting yet.
+    stop.notify_one();
+  }
+}
+
+// Returns how many commands were sent, and whether it got to the end.
+async fn replay(commands: Vec<RecordedCommand>, stop: Arc<Notify>) -> (u32, bool) {
+  let started_at = clock::now();
+  let mut sent = 0;
+  for command in commands {
+    select! {
+      _ = clock::sleep_until(started_at + Duration::from_millis(command.offset_ms)) => {}
+      _ = stop.notified() => return (sent, false),
+    }
+    let device_index = match &command.identifier {
+      Some(identifier) => devices::device_index(identifier),
+      None => Some(command.device_index),
+    };
+    let Some(device_index) = device_index else {
+      continue;
+    };
+    let mut fields = command.fields;
+    fields["DeviceIndex"] = json!(device_index);
+    // Bridge ids, so the replies don't go to the frontend.
+    fields["Id"] = json!(backdoor::next_message_id());
+    let msg = json!([{ command.message_type: fields }]).to_string();
+    if !api::send_bridge_backdoor_message(msg) {
+      warn!("Engine stopped during session replay");
+      return (sent, false);
+    }
+    sent += 1;
+  }
+  (sent, true)
+}
@@ -0,0 +1,305 @@
+use crate::{
+  api::{
+    feature_with_step_limit, update_customization, ExposedUserDeviceIdentifier,
+    DEVICE_CONFIG_MANAGER,
+  },
+  device_list,
+  device_settings::{self, DeviceSettings},
+};
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use buttplug::server::device::configuration::{UserDeviceDefinition, UserDeviceIdentifier};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::ops::RangeInclusive;
+
+// Share codes pack up how a single device is set up, so people can pass setups around in chat
+// without sending whole config files. They're URL safe base64, so they survive being pasted pretty
+// much anywhere, with a short checksum on the end to catch codes that got cut off.
+//
+// Addresses are left out. They're specific to one physical device, so when importing, the code
+// gets applied to whatever device the user picks, as long as it's the same kind of device.
+
+const SHARE_CODE_PREFIX: &str = "IC1-";
+const CHECKSUM_LENGTH: usize = 4;
+
+// Keys are kept short, as every byte ends up in a chat message.
+#[derive(Debug, Serialize, Deserialize)]
+struct SharedDeviceConfig {
+  #[serde(rename = "p")]
+  protocol: String,
+  #[serde(rename = "i", default, skip_serializing_if = "Option::is_none")]
+  identifier: Option<String>,
+  #[serde(rename = "n", default, skip_serializing_if = "Option::is_none")]
+  display_name: Option<String>,
+  // (feature index, step limit start, step limit end)
+  #[serde(rename = "l", default, skip_serializing_if = "Vec::is_empty")]
+  step_limits: Vec<(u32, u32, u32)>,
+  #[serde(rename = "g", default, skip_serializing_if = "Option::is_none")]
+  message_gap_ms: Option<u32>,
+  #[serde(rename = "s", default, skip_serializing_if = "is_default_settings")]
+  settings: DeviceSettings,
+}
+
+fn is_default_settings(settings: &DeviceSettings) -> bool {
+  *settings == DeviceSettings::default()
+}
+
+// What's in a share code, so the frontend can show it and pick a device to apply it to.
+#[derive(Debug, Clone)]
+pub struct DeviceShareCodeInfo {
+  pub protocol: String,
+  pub identifier: Option<String>,
+  pub display_name: Option<String>,
+}
+
+fn user_device_definition(identifier: &UserDeviceIdentifier) -> Result<UserDeviceDefinition> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  dcm
+    .user_device_definitions()
+    .get(identifier)
+    .map(|x| x.value().clone())
+    .ok_or(anyhow::Error::msg(
+      "No user configuration for device, it must connect at least once.",
+    ))
+}
+
+pub fn encode(identifier: &ExposedUserDeviceIdentifier) -> Result<String> {
+  let definition = user_device_definition(&identifier.clone().into())?;
+  let customization = serde_json::to_value(definition.user_config())?;
+  let step_limits = definition
+    .features()
+    .iter()
+    .enumerate()
+    .filter_map(|(index, feature)| {
+      let actuator = feature.actuator().as_ref()?;
+      // Only bother with limits that have actually been changed.
+      if actuator.step_limit() == actuator.step_range() {
+        return None;
+      }
+      Some((
+        index as u32,
+        *actuator.step_limit().start(),
+        *actuator.step_limit().end(),
+      ))
+    })
+    .collect();
+  let shared = SharedDeviceConfig {
+    protocol: identifier.protocol.clone(),
+    identifier: identifier.identifier.clone(),
+    display_name: definition.user_config().display_name().clone(),
+    step_limits,
+    message_gap_ms: customization
+      .get("message-gap-ms")
+      .and_then(|x| x.as_u64())
+      .map(|x| x as u32),
+    settings: device_settings::settings(identifier),
+  };
+  pack(&shared)
+}
+
+fn pack(shared: &SharedDeviceConfig) -> Result<String> {
+  let mut payload = serde_json::to_vec(shared)?;
+  let checksum = Sha256::digest(&payload);
+  payload.extend_from_slice(&checksum[..CHECKSUM_LENGTH]);
+  Ok(format!(
+    "{}{}",
+    SHARE_CODE_PREFIX,
+    URL_SAFE_NO_PAD.encode(payload)
+  ))
+}
+
+fn decode(code: &str) -> Result<SharedDeviceConfig> {
+  // People will paste these with all sorts of whitespace around them.
+  let code = code.trim();
+  let encoded = code
+    .strip_prefix(SHARE_CODE_PREFIX)
+    .ok_or(anyhow::Error::msg("Not a device share code"))?;
+  let decoded = URL_SAFE_NO_PAD
+    .decode(encoded)
+    .map_err(|_| anyhow::Error::msg("Share code is damaged or incomplete"))?;
+  if decoded.len() <= CHECKSUM_LENGTH {
+    return Err(anyhow::Error::msg("Share code is damaged or incomplete"));
+  }
+  let (payload, checksum) = decoded.split_at(decoded.len() - CHECKSUM_LENGTH);
+  if Sha256::digest(payload)[..CHECKSUM_LENGTH] != *checksum {
+    return Err(anyhow::Error::msg("Share code is damaged or incomplete"));
+  }
+  Ok(serde_json::from_slice(payload)?)
+}
+
+pub fn info(code: &str) -> Result<DeviceShareCodeInfo> {
+  let shared = decode(code)?;
+  Ok(DeviceShareCodeInfo {
+    protocol: shared.protocol,
+    identifier: shared.identifier,
+    display_name: shared.display_name,
+  })
+}
+
+pub fn import(code: &str, target: &ExposedUserDeviceIdentifier) -> Result<()> {
+  let shared = decode(code)?;
+  if shared.protocol != target.protocol || shared.identifier != target.identifier {
+    return Err(anyhow::Error::msg(
+      "Share code is for a different kind of device",
+    ));
+  }
+  let user_identifier: UserDeviceIdentifier = target.clone().into();
+  let definition = user_device_definition(&user_identifier)?;
+
+  // Check everything before changing anything, so a bad code can't leave the device half set up.
+  let mut features = definition.features().clone();
+  for (index, start, end) in &shared.step_limits {
+    let feature = features.get_mut(*index as usize).ok_or(anyhow::Error::msg(
+      "Share code has limits for features this device doesn't have",
+    ))?;
+    *feature = feature_with_step_limit(feature, &RangeInclusive::new(*start, *end)).ok_or(
+      anyhow::Error::msg("Share code has limits that don't fit this device"),
+    )?;
+  }
+  let customization = update_customization(
+    definition.user_config(),
+    "display-name",
+    json!(shared.display_name),
+  )?;
+  let customization = update_customization(
+    &customization,
+    "message-gap-ms",
+    json!(shared.message_gap_ms),
+  )?;
+  for curve in &shared.settings.curves {
+    curve.validate()?;
+  }
+  if let Some(rate) = shared.settings.max_rate_per_second {
+    if !rate.is_finite() || rate <= 0.0 {
+      return Err(anyhow::Error::msg("Share code has an invalid ramp rate"));
+    }
+  }
+
+  DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .add_user_device_definition(
+      &user_identifier,
+      &UserDeviceDefinition::new(definition.name(), &features, &customization),
+    )
+    .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))?;
+  device_list::update_display_name(target, shared.display_name);
+  device_settings::update(target, |settings| *settings = shared.settings);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::actuator_curve::{ActuatorCurve, ActuatorCurveShape};
+
+  fn shared() -> SharedDeviceConfig {
+    SharedDeviceConfig {
+      protocol: "lovense".to_owned(),
+      identifier: Some("Z".to_owned()),
+      display_name: Some("Bedside".to_owned()),
+      step_limits: vec![(0, 2, 15)],
+      message_gap_ms: Some(75),
+      settings: DeviceSettings {
+        curves: vec![ActuatorCurve {
+          feature_index: 0,
+          min: 0.2,
+          max: 0.9,
+          shape: ActuatorCurveShape::EaseIn { exponent: 2.0 },
+        }],
+        max_rate_per_second: Some(0.5),
+        no_auto_reconnect: false,
+      },
+    }
+  }
+
+  fn edit(code: &str, change: impl FnOnce(&mut Vec<u8>)) -> String {
+    let mut decoded = URL_SAFE_NO_PAD
+      .decode(code.strip_prefix(SHARE_CODE_PREFIX).unwrap())
+      .unwrap();
+    change(&mut decoded);
+    format!("{}{}", SHARE_CODE_PREFIX, URL_SAFE_NO_PAD.encode(decoded))
+  }
+
+  #[test]
+  fn round_trip() {
+    let code = pack(&shared()).unwrap();
+    let decoded = decode(&format!("\n  {}  \n", code)).unwrap();
+    assert_eq!(decoded.protocol, "lovense");
+    assert_eq!(decoded.identifier.as_deref(), Some("Z"));
+    assert_eq!(decoded.display_name.as_deref(), Some("Bedside"));
+    assert_eq!(decoded.step_limits, vec![(0, 2, 15)]);
+    assert_eq!(decoded.message_gap_ms, Some(75));
+    assert_eq!(decoded.settings, shared().settings);
+  }
+
+  #[test]
+  fn info_shows_the_device() {
+    let info = info(&pack(&shared()).unwrap()).unwrap();
+    assert_eq!(info.protocol, "lovense");
+    assert_eq!(info.identifier.as_deref(), Some("Z"));
+    assert_eq!(info.display_name.as_deref(), Some("Bedside"));
+  }
+
+  #[test]
+  fn defaults_are_left_out() {
+    let minimal = SharedDeviceConfig {
+      protocol: "lovense".to_owned(),
+      identifier: None,
+      display_name: None,
+      step_limits: vec![],
+      message_gap_ms: None,
+      settings: DeviceSettings::default(),
+    };
+    let code = pack(&minimal).unwrap();
+    let decoded = URL_SAFE_NO_PAD
+      .decode(code.strip_prefix(SHARE_CODE_PREFIX).unwrap())
+      .unwrap();
+    assert_eq!(
+      &decoded[..decoded.len() - CHECKSUM_LENGTH],
+      br#"{"p":"lovense"}"#
+    );
+    assert_eq!(decode(&code).unwrap().settings, DeviceSettings::default());
+  }
+
+  #[test]
+  fn damaged_codes_are_caught() {
+    let code = pack(&shared()).unwrap();
+    let truncated = &code[..code.len() - 3];
+    assert!(decode(truncated).is_err());
+    let edited = edit(&code, |decoded| decoded[5] ^= 1);
+    assert_eq!(
+      decode(&edited).unwrap_err().to_string(),
+      "Share code is damaged or incomplete"
+    );
+    let too_short = edit(&code, |decoded| decoded.truncate(CHECKSUM_LENGTH));
+    assert!(decode(&too_short).is_err());
+  }
+
+  #[test]
+  fn not_a_share_code() {
+    assert_eq!(
+      decode("ICP1-abc").unwrap_err().to_string(),
+      "Not a device share code"
+    );
+    assert!(decode("IC1-not base64!").is_err());
+  }
+
+  #[test]
+  fn codes_only_import_onto_the_same_kind_of_device() {
+    let code = pack(&shared()).unwrap();
+    let other = ExposedUserDeviceIdentifier {
+      address: "aa:bb".to_owned(),
+      protocol: "lovense".to_owned(),
+      identifier: Some("P".to_owned()),
+    };
+    assert_eq!(
+      import(&code, &other).unwrap_err().to_string(),
+      "Share code is for a different kind of device"
+    );
+  }
+}
@@ -0,0 +1,119 @@
+use crate::api::ExposedUserDeviceIdentifier;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+  collections::HashMap,
+  sync::{Condvar, Mutex},
+  time::{Duration, Instant},
+};
+
+// Keeps track of how far along the engine is in stopping, so stop_engine can wait for it to actually
+// finish rather than sleeping and hoping. Stopping is done once the main engine task has exited and
+// every device that was connected when we asked has disconnected (which on Android means its JNI
+// teardown has run).
+//
+// Slow Bluetooth stacks can hold that up indefinitely, so if it takes too long, stop_engine cancels
+// the engine task, and if even that doesn't finish, takes the runtime down under it. Either way we
+// don't leave the bridge half stopped.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ShutdownStage {
+  // The engine and its devices stopped by themselves.
+  Graceful,
+  // The engine task had to be cancelled, and stopped after that.
+  TaskCancelled,
+  // The runtime had to be shut down under the engine task.
+  RuntimeShutdown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceShutdownResult {
+  pub index: u32,
+  pub identifier: ExposedUserDeviceIdentifier,
+  // False if we ran out of time before the engine said it was gone.
+  pub disconnected: bool,
+}
+
+#[derive(Default)]
+struct ShutdownState {
+  engine_exited: bool,
+  // Devices still to disconnect, while a stop is in progress.
+  pending_devices: HashMap<u32, ExposedUserDeviceIdentifier>,
+  disconnected_devices: Vec<(u32, ExposedUserDeviceIdentifier)>,
+}
+
+lazy_static! {
+  static ref SHUTDOWN_STATE: Mutex<ShutdownState> = Mutex::new(ShutdownState::default());
+  static ref SHUTDOWN_CHANGED: Condvar = Condvar::new();
+}
+
+pub fn engine_started() {
+  *SHUTDOWN_STATE.lock().unwrap() = ShutdownState::default();
+}
+
+pub fn has_exited() -> bool {
+  SHUTDOWN_STATE.lock().unwrap().engine_exited
+}
+
+pub fn engine_exited() {
+  SHUTDOWN_STATE.lock().unwrap().engine_exited = true;
+  SHUTDOWN_CHANGED.notify_all();
+}
+
+pub fn device_disconnected(index: u32) {
+  let mut state = SHUTDOWN_STATE.lock().unwrap();
+  if let Some(identifier) = state.pending_devices.remove(&index) {
+    state.disconnected_devices.push((index, identifier));
+    SHUTDOWN_CHANGED.notify_all();
+  }
+}
+
+// Call before telling the engine to stop, with whatever is connected at the time.
+pub fn begin(devices: Vec<(u32, ExposedUserDeviceIdentifier)>) {
+  let mut state = SHUTDOWN_STATE.lock().unwrap();
+  state.pending_devices = devices.into_iter().collect();
+  state.disconnected_devices.clear();
+}
+
+// Blocks until the engine has finished stopping or the timeout runs out. Returns whether it
+// finished, along with how each device went.
+pub fn wait(timeout: Duration) -> (bool, Vec<DeviceShutdownResult>) {
+  let deadline = Instant::now() + timeout;
+  let mut state = SHUTDOWN_STATE.lock().unwrap();
+  loop {
+    if state.engine_exited && state.pending_devices.is_empty() {
+      break;
+    }
+    let now = Instant::now();
+    if now >= deadline {
+      break;
+    }
+    state = SHUTDOWN_CHANGED
+      .wait_timeout(state, deadline - now)
+      .unwrap()
+      .0;
+  }
+  let completed = state.engine_exited && state.pending_devices.is_empty();
+  let state = &mut *state;
+  let mut results: Vec<DeviceShutdownResult> = state
+    .disconnected_devices
+    .drain(..)
+    .map(|(index, identifier)| DeviceShutdownResult {
+      index,
+      identifier,
+      disconnected: true,
+    })
+    .chain(
+      state
+        .pending_devices
+        .drain()
+        .map(|(index, identifier)| DeviceShutdownResult {
+          index,
+          identifier,
+          disconnected: false,
+        }),
+    )
+    .collect();
+  results.sort_by_key(|x| x.index);
+  (completed, results)
+}
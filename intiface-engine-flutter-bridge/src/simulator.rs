@@ -0,0 +1,152 @@
+use crate::{
+  api::{add_websocket_specifier, remove_websocket_specifier, EngineOptionsExternal},
+  events::{self, BridgeEvent},
+  options::BridgeEngineOptions,
+  websocket_proxy,
+};
+use anyhow::Result;
+use futures::{future::join_all, SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use tokio::{select, sync::Notify};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+// Fake devices, so client app developers can test against Intiface Central without any hardware.
+// They connect to the engine's device websocket server like any other websocket device, pretending
+// to be Lovense toys. Lovense's protocol is the same over websockets as over Bluetooth, and works out
+// what a device can do by asking it, so the engine treats them exactly like the real thing. Commands
+// the engine sends them go out as bridge events, so the frontend can show what a client is doing.
+
+// Websocket device name the simulated devices identify as, registered for the Lovense protocol
+// while the engine runs.
+pub const SIMULATOR_WEBSOCKET_NAME: &str = "IntifaceSimulator";
+const SIMULATOR_PROTOCOL: &str = "lovense";
+const SIMULATED_BATTERY_LEVEL: u32 = 85;
+// The device server comes up a little after the engine starts, and comes back with it if the engine
+// restarts in client mode.
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SimulatedDeviceKind {
+  // Two vibrators.
+  Vibrator,
+  Stroker,
+  // Vibrator with a battery sensor.
+  Sensor,
+}
+
+impl SimulatedDeviceKind {
+  // Lovense device type the device reports, which decides which toy it's treated as.
+  fn lovense_type(self) -> &'static str {
+    match self {
+      // Edge
+      SimulatedDeviceKind::Vibrator => "P",
+      // Solace
+      SimulatedDeviceKind::Stroker => "BA",
+      // Lush
+      SimulatedDeviceKind::Sensor => "S",
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulatorPlan {
+  port: u16,
+  devices: Vec<SimulatedDeviceKind>,
+}
+
+// Makes sure the engine has a device websocket server for the simulated devices to connect to. If
+// it wasn't asked for one, it gets one on a localhost port only we know about. Needs to run after
+// the websocket proxy plan, so that port doesn't get fronted.
+pub fn plan(
+  options: &BridgeEngineOptions,
+  args: &mut EngineOptionsExternal,
+) -> Result<Option<SimulatorPlan>> {
+  if options.simulated_devices.is_empty() {
+    return Ok(None);
+  }
+  if !args.use_device_websocket_server {
+    args.use_device_websocket_server = true;
+    args.device_websocket_server_port = Some(websocket_proxy::internal_port()?);
+  }
+  let port = args.device_websocket_server_port.ok_or(anyhow::Error::msg(
+    "Device websocket server port needs to be set when using simulated devices",
+  ))?;
+  add_websocket_specifier(
+    SIMULATOR_PROTOCOL.to_owned(),
+    SIMULATOR_WEBSOCKET_NAME.to_owned(),
+  )?;
+  Ok(Some(SimulatorPlan {
+    port,
+    devices: options.simulated_devices.clone(),
+  }))
+}
+
+fn reply(kind: SimulatedDeviceKind, address: &str, command: &str) -> String {
+  match command {
+    "DeviceType;" => format!("{}:1:{};", kind.lovense_type(), address),
+    "Battery;" => format!("{};", SIMULATED_BATTERY_LEVEL),
+    "Status:1;" => "2;".to_owned(),
+    _ => "OK;".to_owned(),
+  }
+}
+
+// One connection's worth of a simulated device. Returns once the engine drops it.
+async fn run_device_connection(port: u16, kind: SimulatedDeviceKind, address: &str) -> Result<()> {
+  let (mut ws, _) = connect_async(format!("ws://127.0.0.1:{}", port)).await?;
+  ws.send(Message::text(
+    json!({ "identifier": SIMULATOR_WEBSOCKET_NAME, "address": address, "version": 1 }).to_string(),
+  ))
+  .await?;
+  while let Some(msg) = ws.next().await {
+    let data = match msg? {
+      Message::Binary(data) => data.to_vec(),
+      Message::Text(text) => text.as_bytes().to_vec(),
+      Message::Close(_) => break,
+      _ => continue,
+    };
+    let command = String::from_utf8_lossy(&data).into_owned();
+    events::emit(BridgeEvent::SimulatedDeviceCommand {
+      kind,
+      address: address.to_owned(),
+      command: command.clone(),
+    });
+    ws.send(Message::binary(reply(kind, address, &command).into_bytes()))
+      .await?;
+  }
+  Ok(())
+}
+
+async fn run_device(port: u16, kind: SimulatedDeviceKind, index: usize) {
+  // Lovense addresses are 12 hex digits.
+  let address = format!("51D0000000{:02X}", index);
+  loop {
+    if let Err(e) = run_device_connection(port, kind, &address).await {
+      debug!("Simulated device {} disconnected: {:?}", address, e);
+    }
+    tokio::time::sleep(CONNECT_RETRY_INTERVAL).await;
+  }
+}
+
+pub async fn run_simulator(plan: SimulatorPlan, stop: Arc<Notify>) {
+  info!("Starting {} simulated devices", plan.devices.len());
+  let devices = plan
+    .devices
+    .iter()
+    .enumerate()
+    .map(|(index, kind)| run_device(plan.port, *kind, index));
+  select! {
+    _ = join_all(devices) => {}
+    _ = stop.notified() => {}
+  }
+  info!("Simulated devices shut down");
+}
+
+// Takes the simulator's websocket specifier back out, so it doesn't end up saved in the user config.
+pub fn clear() {
+  remove_websocket_specifier(
+    SIMULATOR_PROTOCOL.to_owned(),
+    SIMULATOR_WEBSOCKET_NAME.to_owned(),
+  );
+}
@@ -0,0 +1,169 @@
+use crate::{mobile_init, network_interfaces, user_config_validation};
+use btleplug::api::{Central, Manager as _};
+
+// Runs every check we know how to do from the native side and collects the results into a single
+// report, so the frontend can show one troubleshooting screen instead of calling around to
+// everything itself. Checks never fail the call, problems only show up as item statuses.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SystemCheckStatus {
+  Pass,
+  Warn,
+  Fail,
+}
+
+#[derive(Debug, Clone)]
+pub struct SystemCheckItem {
+  pub name: String,
+  pub status: SystemCheckStatus,
+  pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SystemCheckReport {
+  // Worst status out of all the items.
+  pub status: SystemCheckStatus,
+  pub items: Vec<SystemCheckItem>,
+}
+
+fn item(name: &str, status: SystemCheckStatus, message: String) -> SystemCheckItem {
+  SystemCheckItem {
+    name: name.to_owned(),
+    status,
+    message,
+  }
+}
+
+pub fn run_system_check(
+  websocket_port: Option<u16>,
+  user_config: Option<String>,
+  engine_running: bool,
+) -> SystemCheckReport {
+  let mut items = vec![check_capabilities()];
+  if let Some(port) = websocket_port {
+    items.push(check_port(port, engine_running));
+  }
+  if let Some(user_config) = user_config {
+    items.push(check_user_config(&user_config));
+  }
+  items.append(&mut check_bluetooth());
+  SystemCheckReport {
+    status: items
+      .iter()
+      .map(|item| item.status)
+      .max()
+      .unwrap_or(SystemCheckStatus::Pass),
+    items,
+  }
+}
+
+fn check_capabilities() -> SystemCheckItem {
+  let mut transports = vec!["Bluetooth LE", "Websocket Devices"];
+  if cfg!(not(any(target_os = "android", target_os = "ios"))) {
+    transports.extend(["Serial", "HID", "Lovense Dongle"]);
+  }
+  if cfg!(target_os = "windows") {
+    transports.push("XInput");
+  }
+  item(
+    "capabilities",
+    SystemCheckStatus::Pass,
+    format!("Supported device transports: {}", transports.join(", ")),
+  )
+}
+
+fn check_port(port: u16, engine_running: bool) -> SystemCheckItem {
+  if engine_running {
+    return item(
+      "websocket_port",
+      SystemCheckStatus::Pass,
+      format!("Port {} is in use by the running engine", port),
+    );
+  }
+  match network_interfaces::port_bind_error(port, false) {
+    None => item(
+      "websocket_port",
+      SystemCheckStatus::Pass,
+      format!("Port {} is available", port),
+    ),
+    Some(e) => item(
+      "websocket_port",
+      SystemCheckStatus::Fail,
+      format!("Port {} is not available: {}", port, e),
+    ),
+  }
+}
+
+fn check_user_config(user_config: &str) -> SystemCheckItem {
+  let errors = user_config_validation::validate_user_config(user_config);
+  match errors.first() {
+    None => item(
+      "user_config",
+      SystemCheckStatus::Pass,
+      "User config is valid".to_owned(),
+    ),
+    Some(first) => item(
+      "user_config",
+      SystemCheckStatus::Fail,
+      format!(
+        "User config has {} error(s), first: {}",
+        errors.len(),
+        first.message
+      ),
+    ),
+  }
+}
+
+// Returns items for both permissions and the adapter, as the same call tells us about both.
+fn check_bluetooth() -> Vec<SystemCheckItem> {
+  let runtime = match mobile_init::create_runtime() {
+    Ok(runtime) => runtime,
+    Err(e) => {
+      return vec![item(
+        "bluetooth_adapter",
+        SystemCheckStatus::Fail,
+        format!("Cannot set up runtime for Bluetooth check: {}", e),
+      )]
+    }
+  };
+  let result = runtime.block_on(async {
+    let manager = btleplug::platform::Manager::new().await?;
+    let mut adapter_info = vec![];
+    for adapter in manager.adapters().await? {
+      adapter_info.push(adapter.adapter_info().await?);
+    }
+    Ok::<Vec<String>, btleplug::Error>(adapter_info)
+  });
+  match result {
+    Ok(adapters) => vec![
+      item(
+        "bluetooth_permission",
+        SystemCheckStatus::Pass,
+        "Bluetooth access granted".to_owned(),
+      ),
+      if adapters.is_empty() {
+        item(
+          "bluetooth_adapter",
+          SystemCheckStatus::Fail,
+          "No Bluetooth adapter found".to_owned(),
+        )
+      } else {
+        item(
+          "bluetooth_adapter",
+          SystemCheckStatus::Pass,
+          format!("Found Bluetooth adapter(s): {}", adapters.join(", ")),
+        )
+      },
+    ],
+    Err(btleplug::Error::PermissionDenied) => vec![item(
+      "bluetooth_permission",
+      SystemCheckStatus::Fail,
+      "Bluetooth permission denied".to_owned(),
+    )],
+    Err(e) => vec![item(
+      "bluetooth_adapter",
+      SystemCheckStatus::Fail,
+      format!("Bluetooth adapter check failed: {}", e),
+    )],
+  }
+}
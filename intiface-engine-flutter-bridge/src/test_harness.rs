@@ -0,0 +1,335 @@
+use crate::{
+  api::{send_backend_server_message, start_engine, stop_engine},
+  message_sink::MessageSink,
+};
+use anyhow::Result;
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use intiface_engine::EngineOptionsExternal;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use std::{
+  collections::VecDeque,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    Arc, Mutex, MutexGuard,
+  },
+  time::{Duration, Instant},
+};
+use tokio::{net::TcpStream, runtime::Runtime};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+// Pieces for driving the bridge from Rust tests, without Flutter: an engine whose messages are
+// collected instead of going to a Dart stream, devices simulated over the device websocket server,
+// and a Buttplug client that sends whatever JSON the test scripts. Everything here blocks, so tests
+// can be plain #[test] functions.
+//
+// The bridge keeps its state in process wide statics, same as the engine it wraps, so only one
+// TestEngine can exist at a time. Creating one waits for any other to be dropped, which keeps tests
+// in the same binary from trampling each other even when cargo runs them in parallel.
+
+lazy_static! {
+  static ref ENGINE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// Messages that have come in but haven't been asked for yet, so waiting for one kind of message
+// doesn't throw away others that arrive first.
+struct MessageQueue {
+  receiver: Receiver<Value>,
+  pending: Mutex<VecDeque<Value>>,
+}
+
+impl MessageQueue {
+  fn new(receiver: Receiver<Value>) -> Self {
+    Self {
+      receiver,
+      pending: Mutex::new(VecDeque::new()),
+    }
+  }
+
+  fn take<F>(&self, matches: F, timeout: Duration) -> Option<Value>
+  where
+    F: Fn(&Value) -> bool,
+  {
+    let mut pending = self.pending.lock().unwrap();
+    if let Some(position) = pending.iter().position(&matches) {
+      return pending.remove(position);
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+      let remaining = deadline.saturating_duration_since(Instant::now());
+      match self.receiver.recv_timeout(remaining) {
+        Ok(msg) if matches(&msg) => return Some(msg),
+        Ok(msg) => pending.push_back(msg),
+        Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => return None,
+      }
+    }
+  }
+}
+
+// Type name of a message in the externally tagged form both engine and Buttplug messages use.
+fn message_type(msg: &Value) -> Option<&str> {
+  msg.as_object()?.keys().next().map(|x| x.as_str())
+}
+
+struct CollectingSink {
+  // Dropped on close, which is how the queue finds out the engine is done.
+  sender: Mutex<Option<Sender<Value>>>,
+}
+
+impl MessageSink for CollectingSink {
+  fn add(&self, msg: String) -> bool {
+    let Ok(value) = serde_json::from_str(&msg) else {
+      warn!("Test sink got a message that isn't JSON: {}", msg);
+      return false;
+    };
+    match self.sender.lock().unwrap().as_ref() {
+      Some(sender) => sender.send(value).is_ok(),
+      None => false,
+    }
+  }
+
+  fn close(&self) -> bool {
+    self.sender.lock().unwrap().take().is_some()
+  }
+}
+
+// Options for an engine that only talks over localhost, with no hardware access, and with the
+// device websocket server up so SimulatedDevices can connect.
+pub fn test_engine_options(server_port: u16, device_port: u16) -> EngineOptionsExternal {
+  EngineOptionsExternal {
+    server_name: "Intiface Test Engine".to_owned(),
+    websocket_port: Some(server_port),
+    frontend_in_process_channel: true,
+    use_device_websocket_server: true,
+    device_websocket_server_port: Some(device_port),
+    ..Default::default()
+  }
+}
+
+pub struct TestEngine {
+  messages: MessageQueue,
+  _lock: MutexGuard<'static, ()>,
+}
+
+impl TestEngine {
+  pub fn start(options: EngineOptionsExternal) -> Result<Self> {
+    // A test that panicked while holding the lock doesn't make the engine state any worse than a
+    // normal stop does.
+    let lock = ENGINE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let (sender, receiver) = channel();
+    start_engine(
+      Arc::new(CollectingSink {
+        sender: Mutex::new(Some(sender)),
+      }),
+      options,
+    )?;
+    Ok(Self {
+      messages: MessageQueue::new(receiver),
+      _lock: lock,
+    })
+  }
+
+  // Next engine message of the given type (e.g. "EngineServerCreated"), skipping over others, which
+  // are kept for later calls.
+  pub fn wait_for(&self, msg_type: &str, timeout: Duration) -> Result<Value> {
+    self
+      .messages
+      .take(|msg| message_type(msg) == Some(msg_type), timeout)
+      .map(|msg| msg[msg_type].clone())
+      .ok_or(anyhow::Error::msg(format!(
+        "Timed out waiting for engine message {}",
+        msg_type
+      )))
+  }
+
+  pub fn next_message(&self, timeout: Duration) -> Option<Value> {
+    self.messages.take(|_| true, timeout)
+  }
+
+  pub fn send_backdoor(&self, msg: &str) {
+    send_backend_server_message(msg.to_owned());
+  }
+
+  // Stops the engine the same way the app does, and fails if the engine didn't close its message
+  // stream on the way down.
+  pub fn stop(self) -> Result<()> {
+    stop_engine();
+    // Anything still queued is from before the stop, so all we care about is whether the sink got
+    // closed.
+    while self.messages.take(|_| true, Duration::ZERO).is_some() {}
+    match self.messages.receiver.recv_timeout(Duration::from_secs(2)) {
+      Err(RecvTimeoutError::Disconnected) => Ok(()),
+      _ => Err(anyhow::Error::msg(
+        "Engine did not close its message stream on stop",
+      )),
+    }
+  }
+}
+
+type WebsocketSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+// Runtime plus the write half of a websocket, with a task forwarding whatever comes in on the read
+// half into a MessageQueue.
+struct WebsocketConnection {
+  runtime: Runtime,
+  writer: tokio::sync::Mutex<WebsocketSink>,
+}
+
+impl WebsocketConnection {
+  fn connect<F>(url: &str, on_message: F) -> Result<Self>
+  where
+    F: Fn(Message) + Send + 'static,
+  {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+      .worker_threads(1)
+      .enable_all()
+      .build()?;
+    let (stream, _) = runtime.block_on(connect_async(url))?;
+    let (writer, mut reader) = stream.split();
+    runtime.spawn(async move {
+      while let Some(Ok(msg)) = reader.next().await {
+        on_message(msg);
+      }
+    });
+    Ok(Self {
+      runtime,
+      writer: tokio::sync::Mutex::new(writer),
+    })
+  }
+
+  fn send(&self, msg: Message) -> Result<()> {
+    Ok(
+      self
+        .runtime
+        .block_on(async { self.writer.lock().await.send(msg).await })?,
+    )
+  }
+
+  fn close(&self) {
+    let _ = self
+      .runtime
+      .block_on(async { self.writer.lock().await.close().await });
+  }
+}
+
+// A device connected through the engine's device websocket server. It'll be picked up by whatever
+// protocol has a websocket specifier with the same name as the identifier, so tests will usually
+// add one with add_websocket_specifier first.
+pub struct SimulatedDevice {
+  connection: WebsocketConnection,
+  received: Receiver<Vec<u8>>,
+}
+
+impl SimulatedDevice {
+  pub fn connect(device_port: u16, identifier: &str, address: &str) -> Result<Self> {
+    let (sender, received) = channel();
+    let connection =
+      WebsocketConnection::connect(&format!("ws://127.0.0.1:{}", device_port), move |msg| {
+        if let Message::Binary(data) = msg {
+          let _ = sender.send(data.to_vec());
+        }
+      })?;
+    connection.send(Message::text(
+      json!({ "identifier": identifier, "address": address, "version": 1 }).to_string(),
+    ))?;
+    Ok(Self {
+      connection,
+      received,
+    })
+  }
+
+  // Next command the engine sent to the device, in protocol bytes.
+  pub fn next_command(&self, timeout: Duration) -> Option<Vec<u8>> {
+    self.received.recv_timeout(timeout).ok()
+  }
+
+  // Sends bytes as if the hardware had sent them, e.g. sensor readings or notifications.
+  pub fn send(&self, data: &[u8]) -> Result<()> {
+    self.connection.send(Message::binary(data.to_vec()))
+  }
+
+  pub fn disconnect(self) {
+    self.connection.close();
+  }
+}
+
+// Buttplug client that sends raw JSON, so tests can script exactly what goes over the wire,
+// including things a well behaved client library wouldn't send.
+pub struct TestClient {
+  connection: WebsocketConnection,
+  messages: MessageQueue,
+  next_id: AtomicU32,
+}
+
+impl TestClient {
+  pub fn connect(server_port: u16, timeout: Duration) -> Result<Self> {
+    let (sender, receiver) = channel();
+    let connection =
+      WebsocketConnection::connect(&format!("ws://127.0.0.1:{}", server_port), move |msg| {
+        let Message::Text(text) = msg else {
+          return;
+        };
+        // Buttplug always sends arrays of messages, split them up so they can be waited on
+        // individually.
+        if let Ok(Value::Array(msgs)) = serde_json::from_str(&text) {
+          for msg in msgs {
+            let _ = sender.send(msg);
+          }
+        }
+      })?;
+    let client = Self {
+      connection,
+      messages: MessageQueue::new(receiver),
+      next_id: AtomicU32::new(1),
+    };
+    client.request(
+      "RequestServerInfo",
+      json!({ "ClientName": "Intiface Test Client", "MessageVersion": 3 }),
+      timeout,
+    )?;
+    Ok(client)
+  }
+
+  // Sends a message with the next message id filled in, returning the id.
+  pub fn send(&self, msg_type: &str, mut fields: Value) -> Result<u32> {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    fields["Id"] = json!(id);
+    self
+      .connection
+      .send(Message::text(json!([{ msg_type: fields }]).to_string()))?;
+    Ok(id)
+  }
+
+  // Sends a message and waits for the server's reply to it, which is returned whole (e.g.
+  // {"Ok": {"Id": 3}}) so tests can check errors as well.
+  pub fn request(&self, msg_type: &str, fields: Value, timeout: Duration) -> Result<Value> {
+    let id = self.send(msg_type, fields)?;
+    self
+      .messages
+      .take(
+        |msg| message_type(msg).and_then(|x| msg[x]["Id"].as_u64()) == Some(id as u64),
+        timeout,
+      )
+      .ok_or(anyhow::Error::msg(format!(
+        "Timed out waiting for reply to {}",
+        msg_type
+      )))
+  }
+
+  // Next server event of the given type (e.g. "DeviceAdded"), skipping over others.
+  pub fn wait_for(&self, msg_type: &str, timeout: Duration) -> Result<Value> {
+    self
+      .messages
+      .take(|msg| message_type(msg) == Some(msg_type), timeout)
+      .map(|msg| msg[msg_type].clone())
+      .ok_or(anyhow::Error::msg(format!(
+        "Timed out waiting for server message {}",
+        msg_type
+      )))
+  }
+
+  pub fn disconnect(self) {
+    self.connection.close();
+  }
+}
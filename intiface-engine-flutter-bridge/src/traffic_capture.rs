@@ -0,0 +1,129 @@
+use crate::log_sanitizer;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+  collections::VecDeque,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+  },
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+// Message inspector for debugging client app integrations. While capturing, every Buttplug message
+// between clients and the engine that the bridge can see (clients on the websocket proxy, and the
+// frontend through the backdoor) is kept in a ring buffer, oldest dropped first, until it's exported.
+
+// Name used for messages to and from the backdoor, which doesn't have a client name of its own.
+pub const BACKDOOR_CLIENT_NAME: &str = "backdoor";
+
+const MAX_CAPTURE_SIZE: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum CaptureDirection {
+  ClientToServer,
+  ServerToClient,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedMessage {
+  pub direction: CaptureDirection,
+  // Milliseconds since the unix epoch.
+  pub timestamp_ms: u64,
+  // None until a proxied client has sent its RequestServerInfo.
+  pub client: Option<String>,
+  pub device_index: Option<u32>,
+  pub message: Value,
+}
+
+struct Capture {
+  max_messages: usize,
+  messages: VecDeque<CapturedMessage>,
+  // Messages that fell out of the buffer, so exports can say they're incomplete.
+  dropped: u64,
+}
+
+#[derive(Serialize)]
+struct CaptureExport<'a> {
+  dropped: u64,
+  messages: &'a VecDeque<CapturedMessage>,
+}
+
+// Checked before taking the lock, so capturing costs nothing when it's off.
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+  static ref CAPTURE: Mutex<Capture> = Mutex::new(Capture {
+    max_messages: 0,
+    messages: VecDeque::new(),
+    dropped: 0,
+  });
+}
+
+// Starts a new capture, throwing out whatever was captured before.
+pub fn start(max_messages: u32) -> Result<()> {
+  if max_messages == 0 || max_messages > MAX_CAPTURE_SIZE {
+    return Err(anyhow::Error::msg(format!(
+      "Capture size must be between 1 and {}",
+      MAX_CAPTURE_SIZE
+    )));
+  }
+  let mut capture = CAPTURE.lock().unwrap();
+  capture.max_messages = max_messages as usize;
+  capture.messages.clear();
+  capture.dropped = 0;
+  CAPTURING.store(true, Ordering::Relaxed);
+  Ok(())
+}
+
+// Stops capturing, keeping what's been captured for export.
+pub fn stop() {
+  CAPTURING.store(false, Ordering::Relaxed);
+}
+
+pub fn is_capturing() -> bool {
+  CAPTURING.load(Ordering::Relaxed)
+}
+
+pub fn export() -> String {
+  let capture = CAPTURE.lock().unwrap();
+  log_sanitizer::sanitize(
+    serde_json::to_string(&CaptureExport {
+      dropped: capture.dropped,
+      messages: &capture.messages,
+    })
+    .expect("Captures should always serialize"),
+  )
+}
+
+pub fn record(direction: CaptureDirection, client: Option<&str>, messages: &[Value]) {
+  if !is_capturing() {
+    return;
+  }
+  let timestamp_ms = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|x| x.as_millis() as u64)
+    .unwrap_or_default();
+  let mut capture = CAPTURE.lock().unwrap();
+  for message in messages {
+    let device_index = message
+      .as_object()
+      .and_then(|x| x.values().next())
+      .and_then(|x| x.get("DeviceIndex"))
+      .and_then(|x| x.as_u64())
+      .map(|x| x as u32);
+    if capture.messages.len() >= capture.max_messages {
+      capture.messages.pop_front();
+      capture.dropped += 1;
+    }
+    capture.messages.push_back(CapturedMessage {
+      direction,
+      timestamp_ms,
+      client: client.map(|x| x.to_owned()),
+      device_index,
+      message: message.clone(),
+    });
+  }
+}
@@ -0,0 +1,119 @@
+use crate::{
+  api::EngineOptionsExternal,
+  events::{self, BridgeEvent},
+};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+// Turning device transports on and off while the engine runs. The engine builds its comm managers
+// once, when its server is created, and has no way to add or remove one after that. So a change
+// here stops the engine's server and runs it again with the new set, without the bridge's own tasks
+// (proxy, backdoor, monitors) going anywhere. Devices and clients get disconnected for the moment
+// that takes, same as an engine restart, and come back on their own if they reconnect.
+//
+// Changes made while the engine isn't running have nothing to apply to, so they're refused. Set the
+// transport in the engine options for the next run instead.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Transport {
+  BluetoothLe,
+  SerialPort,
+  Hid,
+  LovenseConnect,
+  Xinput,
+}
+
+const TRANSPORTS: [Transport; 5] = [
+  Transport::BluetoothLe,
+  Transport::SerialPort,
+  Transport::Hid,
+  Transport::LovenseConnect,
+  Transport::Xinput,
+];
+
+lazy_static! {
+  // Options the engine's running with, transports included.
+  static ref CURRENT_ARGS: Mutex<Option<EngineOptionsExternal>> = Mutex::new(None);
+  // Transport changes not yet applied.
+  static ref PENDING: Mutex<Vec<(Transport, bool)>> = Mutex::new(vec![]);
+  static ref CHANGE_REQUESTED: Arc<Notify> = Arc::new(Notify::new());
+}
+
+fn flag(args: &mut EngineOptionsExternal, transport: Transport) -> &mut bool {
+  match transport {
+    Transport::BluetoothLe => &mut args.use_bluetooth_le,
+    Transport::SerialPort => &mut args.use_serial_port,
+    Transport::Hid => &mut args.use_hid,
+    Transport::LovenseConnect => &mut args.use_lovense_connect,
+    Transport::Xinput => &mut args.use_xinput,
+  }
+}
+
+pub fn engine_started(args: &EngineOptionsExternal) {
+  *CURRENT_ARGS.lock().unwrap() = Some(args.clone());
+  PENDING.lock().unwrap().clear();
+}
+
+pub fn engine_stopped() {
+  CURRENT_ARGS.lock().unwrap().take();
+  PENDING.lock().unwrap().clear();
+}
+
+pub fn enabled() -> Vec<Transport> {
+  let mut args = CURRENT_ARGS.lock().unwrap();
+  let Some(args) = args.as_mut() else {
+    return vec![];
+  };
+  TRANSPORTS.into_iter().filter(|x| *flag(args, *x)).collect()
+}
+
+pub fn set_enabled(transport: Transport, enabled: bool) -> Result<()> {
+  let mut args = CURRENT_ARGS.lock().unwrap();
+  let Some(args) = args.as_mut() else {
+    return Err(anyhow::Error::msg(
+      "Engine isn't running, set the transport in the engine options instead",
+    ));
+  };
+  let mut pending = PENDING.lock().unwrap();
+  // Later requests for the same transport replace earlier ones.
+  pending.retain(|(x, _)| *x != transport);
+  if *flag(args, transport) != enabled {
+    pending.push((transport, enabled));
+  }
+  // notify_one, so it sticks if the engine task isn't waiting right now.
+  CHANGE_REQUESTED.notify_one();
+  Ok(())
+}
+
+// Resolves when there are changes to apply, for the engine task to stop the server on.
+pub async fn change_requested() {
+  loop {
+    CHANGE_REQUESTED.notified().await;
+    if !PENDING.lock().unwrap().is_empty() {
+      return;
+    }
+  }
+}
+
+// Applies pending changes to the options the engine will be run with next, and sends out an event
+// for each transport going up or down. Returns whether there were any.
+pub fn apply_pending(args: &mut EngineOptionsExternal) -> bool {
+  let pending: Vec<(Transport, bool)> = PENDING.lock().unwrap().drain(..).collect();
+  if pending.is_empty() {
+    return false;
+  }
+  for (transport, enabled) in pending {
+    *flag(args, transport) = enabled;
+    info!(
+      "{} transport {:?}",
+      if enabled { "Starting" } else { "Stopping" },
+      transport
+    );
+    events::emit(BridgeEvent::TransportChanged { transport, enabled });
+  }
+  *CURRENT_ARGS.lock().unwrap() = Some(args.clone());
+  true
+}
@@ -0,0 +1,339 @@
+use crate::{
+  api::EngineOptionsExternal,
+  client_reconnect, clock,
+  events::{self, BridgeEvent},
+  options::{BridgeEngineOptions, ClientReconnectOptions, TunnelOptions},
+  server_ready,
+  websocket_proxy::ListeningServer,
+};
+use anyhow::Result;
+use futures::{pin_mut, SinkExt, StreamExt};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+use tokio::{net::TcpStream, select, sync::Notify};
+use tokio_tungstenite::{
+  connect_async,
+  tungstenite::{
+    client::IntoClientRequest,
+    http::{header::AUTHORIZATION, HeaderValue},
+    Message,
+  },
+  MaybeTlsStream, WebSocketStream,
+};
+
+// Remote access for when the server can't be reached from outside, e.g. partners in different
+// places, both behind NAT. The repeater only helps on the same network. Instead of waiting for
+// clients to come in, we connect out to a relay the user gives us (ws or wss), and the relay passes
+// a remote client's messages through that connection as they are. We open the connection to our own
+// websocket server once the first message comes in, so the engine's one client slot isn't held while
+// nobody's there, and pass messages both ways until either side closes.
+//
+// Each relay connection carries one client. Once it closes we connect to the relay again for the
+// next one, backing off if the relay can't be reached.
+
+// How often counters go out while a client is connected. State changes go out right away.
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+type Websocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub enum TunnelState {
+  #[default]
+  Connecting,
+  // Connected to the relay, no remote client yet.
+  Waiting,
+  // Remote client's messages are going through to the server.
+  Connected,
+  Disconnected,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TunnelStatus {
+  pub relay_url: String,
+  pub state: TunnelState,
+  // Every attempt to connect to the relay, including the first.
+  pub connection_attempts: u32,
+  // Remote clients that have come through, including the current one.
+  pub sessions: u32,
+  pub messages_to_remote: u64,
+  pub messages_from_remote: u64,
+  pub bytes_to_remote: u64,
+  pub bytes_from_remote: u64,
+  pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TunnelPlan {
+  options: TunnelOptions,
+  // Needed to get through our own server, if it checks.
+  server_token: Option<String>,
+}
+
+lazy_static! {
+  static ref TUNNEL_STATUS: Mutex<Option<TunnelStatus>> = Mutex::new(None);
+}
+
+pub fn status() -> Option<TunnelStatus> {
+  TUNNEL_STATUS.lock().unwrap().clone()
+}
+
+fn update_status(f: impl FnOnce(&mut TunnelStatus), emit: bool) {
+  let status = {
+    let mut status = TUNNEL_STATUS.lock().unwrap();
+    let Some(status) = status.as_mut() else {
+      return;
+    };
+    f(status);
+    status.clone()
+  };
+  if emit {
+    events::emit(BridgeEvent::TunnelStatus(status));
+  }
+}
+
+pub fn plan(options: &BridgeEngineOptions, args: &EngineOptionsExternal) -> Option<TunnelPlan> {
+  let tunnel_options = options.tunnel.clone()?;
+  if args.websocket_client_address.is_some() {
+    warn!("Tunnel requested while connecting out as a websocket client, not starting tunnel");
+    return None;
+  }
+  if args.websocket_port.is_none() {
+    warn!("Tunnel requested without a websocket port, not starting tunnel");
+    return None;
+  }
+  Some(TunnelPlan {
+    options: tunnel_options,
+    server_token: options
+      .websocket_auth_token
+      .clone()
+      .filter(|x| !x.is_empty()),
+  })
+}
+
+// Where to reach our own websocket server, which may only be listening on a specific address.
+fn server_address() -> Option<SocketAddr> {
+  let servers = server_ready::addresses()?;
+  let server = servers
+    .iter()
+    .find(|x| x.server == ListeningServer::Websocket)?;
+  let mut address: SocketAddr = server.addresses.first()?.parse().ok()?;
+  match address.ip() {
+    IpAddr::V4(ip) if ip.is_unspecified() => address.set_ip(Ipv4Addr::LOCALHOST.into()),
+    IpAddr::V6(ip) if ip.is_unspecified() => address.set_ip(Ipv6Addr::LOCALHOST.into()),
+    _ => {}
+  }
+  Some(address)
+}
+
+async fn connect(url: &str, token: &Option<String>) -> Result<Websocket> {
+  let mut request = url.into_client_request()?;
+  if let Some(token) = token {
+    request.headers_mut().insert(
+      AUTHORIZATION,
+      HeaderValue::from_str(&format!("Bearer {}", token))?,
+    );
+  }
+  let (websocket, _) = connect_async(request).await?;
+  Ok(websocket)
+}
+
+// Passes messages between a remote client and our server until either side closes. Returns the
+// error, if it was one.
+async fn pipe(relay: Websocket, server: Websocket, first: Message) -> Option<String> {
+  let (mut relay_sink, mut relay_stream) = relay.split();
+  let (mut server_sink, mut server_stream) = server.split();
+  let len = first.len() as u64;
+  if let Err(e) = server_sink.send(first).await {
+    return Some(e.to_string());
+  }
+  update_status(
+    |x| {
+      x.messages_from_remote += 1;
+      x.bytes_from_remote += len;
+    },
+    false,
+  );
+  let mut status_ticker = clock::Ticker::new(STATUS_INTERVAL);
+  loop {
+    select! {
+      msg = server_stream.next() => {
+        let msg = match msg {
+          Some(Ok(msg)) => msg,
+          Some(Err(e)) => return Some(e.to_string()),
+          None => return None,
+        };
+        let closing = matches!(msg, Message::Close(_));
+        let len = msg.len() as u64;
+        if let Err(e) = relay_sink.send(msg).await {
+          return Some(e.to_string());
+        }
+        update_status(|x| {
+          x.messages_to_remote += 1;
+          x.bytes_to_remote += len;
+        }, false);
+        if closing {
+          return None;
+        }
+      }
+      msg = relay_stream.next() => {
+        let msg = match msg {
+          Some(Ok(msg)) => msg,
+          Some(Err(e)) => return Some(e.to_string()),
+          None => return None,
+        };
+        let closing = matches!(msg, Message::Close(_));
+        let len = msg.len() as u64;
+        if let Err(e) = server_sink.send(msg).await {
+          return Some(e.to_string());
+        }
+        update_status(|x| {
+          x.messages_from_remote += 1;
+          x.bytes_from_remote += len;
+        }, false);
+        if closing {
+          return None;
+        }
+      }
+      _ = status_ticker.tick() => update_status(|_| {}, true),
+    }
+  }
+}
+
+// One relay connection, and the remote client on it if one turns up. Returns whether a client came
+// through.
+async fn run_session(plan: &TunnelPlan) -> bool {
+  update_status(
+    |x| {
+      x.state = TunnelState::Connecting;
+      x.connection_attempts += 1;
+    },
+    true,
+  );
+  let mut relay = match connect(&plan.options.relay_url, &plan.options.token).await {
+    Ok(relay) => relay,
+    Err(e) => {
+      warn!("Cannot connect to tunnel relay: {:?}", e);
+      update_status(
+        |x| {
+          x.state = TunnelState::Disconnected;
+          x.last_error = Some(e.to_string());
+        },
+        true,
+      );
+      return false;
+    }
+  };
+  update_status(|x| x.state = TunnelState::Waiting, true);
+  // Relay pings are answered while reading, so nothing else needs doing while we wait.
+  let first = loop {
+    match relay.next().await {
+      Some(Ok(msg @ (Message::Text(_) | Message::Binary(_)))) => break msg,
+      Some(Ok(Message::Close(_))) | None => {
+        update_status(|x| x.state = TunnelState::Disconnected, true);
+        return false;
+      }
+      Some(Ok(_)) => continue,
+      Some(Err(e)) => {
+        update_status(
+          |x| {
+            x.state = TunnelState::Disconnected;
+            x.last_error = Some(e.to_string());
+          },
+          true,
+        );
+        return false;
+      }
+    }
+  };
+  let server = match server_address() {
+    Some(address) => connect(&format!("ws://{}", address), &plan.server_token).await,
+    None => Err(anyhow::Error::msg("Websocket server isn't listening")),
+  };
+  let server = match server {
+    Ok(server) => server,
+    Err(e) => {
+      warn!("Tunnel cannot connect to websocket server: {:?}", e);
+      // Closing lets the remote client know, same as if the server had turned it away.
+      let _ = relay.close(None).await;
+      update_status(
+        |x| {
+          x.state = TunnelState::Disconnected;
+          x.last_error = Some(e.to_string());
+        },
+        true,
+      );
+      return false;
+    }
+  };
+  info!("Remote client connected through tunnel");
+  update_status(
+    |x| {
+      x.state = TunnelState::Connected;
+      x.sessions += 1;
+    },
+    true,
+  );
+  let error = pipe(relay, server, first).await;
+  if let Some(e) = &error {
+    warn!("Tunnel connection failed: {}", e);
+  }
+  update_status(
+    |x| {
+      x.state = TunnelState::Disconnected;
+      if error.is_some() {
+        x.last_error = error;
+      }
+    },
+    true,
+  );
+  true
+}
+
+pub async fn run_tunnel(plan: TunnelPlan, stop: Arc<Notify>) {
+  let stopped = stop.notified();
+  pin_mut!(stopped);
+  stopped.as_mut().enable();
+  // Nothing to pass clients on to until the server is up.
+  select! {
+    _ = server_ready::wait() => {}
+    _ = &mut stopped => return,
+  }
+  *TUNNEL_STATUS.lock().unwrap() = Some(TunnelStatus {
+    relay_url: plan.options.relay_url.clone(),
+    ..Default::default()
+  });
+  // Same backoff as the engine reconnecting as a client, without giving up.
+  let backoff = ClientReconnectOptions {
+    enabled: true,
+    max_retries: None,
+    ..Default::default()
+  };
+  // Relay connections that closed or failed without a client coming through. Relays dropping idle
+  // connections back off too, so one doing it straight away doesn't get hammered.
+  let mut failures = 0;
+  loop {
+    let had_client = select! {
+      had_client = run_session(&plan) => had_client,
+      _ = &mut stopped => break,
+    };
+    if had_client {
+      failures = 0;
+      continue;
+    }
+    failures += 1;
+    let Some(delay) = client_reconnect::reconnect_delay(&backoff, failures) else {
+      break;
+    };
+    select! {
+      _ = clock::sleep_until(clock::now() + delay) => {}
+      _ = &mut stopped => break,
+    }
+  }
+  *TUNNEL_STATUS.lock().unwrap() = None;
+  info!("Tunnel stopped");
+}
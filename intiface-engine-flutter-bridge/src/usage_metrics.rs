@@ -0,0 +1,217 @@
+use crate::api::{EngineOptionsExternal, ExposedUserDeviceIdentifier};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use reqwest::{blocking::Client, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::{BTreeMap, HashSet},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+  },
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Anonymous usage counts, so we know which transports and protocols are worth the work. Strictly
+// opt-in: nothing is counted until the frontend turns this on, and turning it off throws away
+// anything not yet sent.
+//
+// All that's kept is how many times the engine started, how many runs used each transport, and how
+// many devices of each protocol connected (each device once per run). No addresses, names, client
+// details or anything else that could identify someone. Counts are batched locally, saved by the
+// frontend as their own JSON document between app runs, and only uploaded while the frontend says
+// we're on Wi-Fi.
+
+const USAGE_METRICS_VERSION: u32 = 1;
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct UsageCounts {
+  // Milliseconds since the unix epoch, when counting started for this batch.
+  #[serde(default)]
+  period_start_ms: u64,
+  #[serde(default)]
+  engine_starts: u32,
+  #[serde(default)]
+  transports: BTreeMap<String, u32>,
+  #[serde(default)]
+  protocols: BTreeMap<String, u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UsageMetricsFile {
+  version: u32,
+  #[serde(default)]
+  counts: UsageCounts,
+}
+
+#[derive(Serialize)]
+struct UsageMetricsUpload<'a> {
+  version: u32,
+  app_version: &'static str,
+  platform: &'static str,
+  #[serde(flatten)]
+  counts: &'a UsageCounts,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static ON_WIFI: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+  static ref COUNTS: Mutex<UsageCounts> = Mutex::new(UsageCounts::default());
+  // Devices counted this engine run, so reconnects don't count twice.
+  static ref RUN_DEVICES: Mutex<HashSet<ExposedUserDeviceIdentifier>> = Mutex::new(HashSet::new());
+}
+
+fn now_ms() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|x| x.as_millis() as u64)
+    .unwrap_or(0)
+}
+
+fn new_counts() -> UsageCounts {
+  UsageCounts {
+    period_start_ms: now_ms(),
+    ..Default::default()
+  }
+}
+
+pub fn set_enabled(enabled: bool) {
+  ENABLED.store(enabled, Ordering::Relaxed);
+  let mut counts = COUNTS.lock().unwrap();
+  if !enabled {
+    *counts = UsageCounts::default();
+  } else if counts.period_start_ms == 0 {
+    *counts = new_counts();
+  }
+}
+
+pub fn enabled() -> bool {
+  ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_on_wifi(on_wifi: bool) {
+  ON_WIFI.store(on_wifi, Ordering::Relaxed);
+}
+
+pub fn load(metrics_json: &str) -> Result<()> {
+  let file: UsageMetricsFile = serde_json::from_str(metrics_json)?;
+  if file.version > USAGE_METRICS_VERSION {
+    return Err(anyhow::Error::msg(format!(
+      "Usage metrics version {} is newer than supported version {}",
+      file.version, USAGE_METRICS_VERSION
+    )));
+  }
+  if enabled() {
+    *COUNTS.lock().unwrap() = file.counts;
+  }
+  Ok(())
+}
+
+pub fn save() -> String {
+  let file = UsageMetricsFile {
+    version: USAGE_METRICS_VERSION,
+    counts: COUNTS.lock().unwrap().clone(),
+  };
+  serde_json::to_string(&file).expect("Usage metrics should always serialize")
+}
+
+pub fn engine_started(args: &EngineOptionsExternal) {
+  if !enabled() {
+    return;
+  }
+  let transports = [
+    ("bluetooth_le", args.use_bluetooth_le),
+    ("serial_port", args.use_serial_port),
+    ("hid", args.use_hid),
+    ("lovense_dongle_serial", args.use_lovense_dongle_serial),
+    ("lovense_dongle_hid", args.use_lovense_dongle_hid),
+    ("xinput", args.use_xinput),
+    ("lovense_connect", args.use_lovense_connect),
+    ("device_websocket_server", args.use_device_websocket_server),
+    ("websocket_server", args.websocket_port.is_some()),
+    ("websocket_client", args.websocket_client_address.is_some()),
+    ("repeater", args.repeater_mode),
+  ];
+  let mut counts = COUNTS.lock().unwrap();
+  counts.engine_starts += 1;
+  for (transport, _) in transports.iter().filter(|(_, used)| *used) {
+    *counts.transports.entry(transport.to_string()).or_default() += 1;
+  }
+}
+
+pub fn device_connected(identifier: &ExposedUserDeviceIdentifier) {
+  if !enabled() || !RUN_DEVICES.lock().unwrap().insert(identifier.clone()) {
+    return;
+  }
+  *COUNTS
+    .lock()
+    .unwrap()
+    .protocols
+    .entry(identifier.protocol.clone())
+    .or_default() += 1;
+}
+
+pub fn clear_run() {
+  RUN_DEVICES.lock().unwrap().clear();
+}
+
+// Sends the batch, and starts a new one if that worked. Returns false without sending if metrics
+// are off, there's nothing to send, or we're not on Wi-Fi.
+pub fn upload(url: &str) -> Result<bool> {
+  if !enabled() || !ON_WIFI.load(Ordering::Relaxed) {
+    return Ok(false);
+  }
+  let counts = COUNTS.lock().unwrap().clone();
+  if counts.engine_starts == 0 && counts.protocols.is_empty() {
+    return Ok(false);
+  }
+  let upload = UsageMetricsUpload {
+    version: USAGE_METRICS_VERSION,
+    app_version: env!("CARGO_PKG_VERSION"),
+    platform: std::env::consts::OS,
+    counts: &counts,
+  };
+  let response = Client::builder()
+    .timeout(UPLOAD_TIMEOUT)
+    .build()?
+    .post(url)
+    .header(CONTENT_TYPE, "application/json")
+    .body(serde_json::to_string(&upload)?)
+    .send()?;
+  if !response.status().is_success() {
+    return Err(anyhow::Error::msg(format!(
+      "Usage metrics upload failed with status {}",
+      response.status()
+    )));
+  }
+  // Anything counted while we were sending stays for next time.
+  let mut current = COUNTS.lock().unwrap();
+  if !enabled() {
+    return Ok(true);
+  }
+  let mut remaining = new_counts();
+  remaining.engine_starts = current.engine_starts.saturating_sub(counts.engine_starts);
+  for (field, sent, counted) in [
+    (
+      &mut remaining.transports,
+      &counts.transports,
+      &current.transports,
+    ),
+    (
+      &mut remaining.protocols,
+      &counts.protocols,
+      &current.protocols,
+    ),
+  ] {
+    for (key, value) in counted {
+      let value = value.saturating_sub(sent.get(key).copied().unwrap_or(0));
+      if value > 0 {
+        field.insert(key.clone(), value);
+      }
+    }
+  }
+  *current = remaining;
+  Ok(true)
+}
@@ -0,0 +1,210 @@
+use crate::user_config_migration::CURRENT_USER_CONFIG_MAJOR_VERSION;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+
+// Compares two user configs, so the frontend can show what's about to change before overwriting the
+// user's config file, and so sync conflicts can be shown as individual device and specifier changes
+// rather than two whole files. Both configs need to be the current version, as older versions store
+// devices differently and would show everything as changed.
+
+#[derive(Debug, Error)]
+pub enum UserConfigDiffError {
+  #[error("{side} user config is not valid JSON: {source}")]
+  InvalidJson {
+    side: &'static str,
+    source: serde_json::Error,
+  },
+
+  #[error("{side} user config is not the current version, and needs to be migrated first")]
+  OutdatedVersion { side: &'static str },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum UserConfigChangeKind {
+  Added,
+  Removed,
+  Changed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UserConfigDeviceChange {
+  pub kind: UserConfigChangeKind,
+  pub address: String,
+  pub protocol: String,
+  pub identifier: Option<String>,
+  // Config fields that differ, e.g. "user-config.display-name" or "features". Empty for added and
+  // removed devices.
+  pub changed_fields: Vec<String>,
+}
+
+// Specifiers don't have anything to identify them besides their contents, so they only ever show
+// up as added or removed.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserConfigSpecifierChange {
+  pub kind: UserConfigChangeKind,
+  pub protocol: String,
+  // The specifier in config file form, e.g. {"websocket":{"name":"..."}}.
+  pub specifier: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UserConfigDiff {
+  pub devices: Vec<UserConfigDeviceChange>,
+  pub specifiers: Vec<UserConfigSpecifierChange>,
+}
+
+type DeviceKey = (String, String, Option<String>);
+
+fn parse(user_config: &str, side: &'static str) -> Result<Value, UserConfigDiffError> {
+  let config: Value = serde_json::from_str(user_config)
+    .map_err(|source| UserConfigDiffError::InvalidJson { side, source })?;
+  let version = config
+    .get("version")
+    .and_then(|x| x.get("major"))
+    .and_then(|x| x.as_u64());
+  if version != Some(CURRENT_USER_CONFIG_MAJOR_VERSION) {
+    return Err(UserConfigDiffError::OutdatedVersion { side });
+  }
+  Ok(config)
+}
+
+fn devices(config: &Value) -> BTreeMap<DeviceKey, Value> {
+  config
+    .get("user-configs")
+    .and_then(|x| x.get("devices"))
+    .and_then(|x| x.as_array())
+    .map(|devices| {
+      devices
+        .iter()
+        .filter_map(|device| {
+          let identifier = device.get("identifier")?;
+          let key = (
+            identifier.get("address")?.as_str()?.to_owned(),
+            identifier.get("protocol")?.as_str()?.to_owned(),
+            identifier
+              .get("identifier")
+              .and_then(|x| x.as_str())
+              .map(|x| x.to_owned()),
+          );
+          Some((key, device.get("config").cloned().unwrap_or(Value::Null)))
+        })
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+// Protocol -> serialized specifiers. Serialized so they can go in a set, serde_json sorts object
+// keys so equal specifiers always serialize the same.
+fn specifiers(config: &Value) -> BTreeMap<String, BTreeSet<String>> {
+  config
+    .get("user-configs")
+    .and_then(|x| x.get("protocols"))
+    .and_then(|x| x.as_object())
+    .map(|protocols| {
+      protocols
+        .iter()
+        .map(|(protocol, protocol_config)| {
+          let specifiers = protocol_config
+            .get("communication")
+            .and_then(|x| x.as_array())
+            .map(|x| x.iter().map(|x| x.to_string()).collect())
+            .unwrap_or_default();
+          (protocol.clone(), specifiers)
+        })
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn changed_fields(old: &Value, new: &Value) -> Vec<String> {
+  let empty = Map::new();
+  let old = old.as_object().unwrap_or(&empty);
+  let new = new.as_object().unwrap_or(&empty);
+  let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+  let mut fields = vec![];
+  for key in keys {
+    match (old.get(key), new.get(key)) {
+      (a, b) if a == b => {}
+      // Customizations are what users actually change, so say which one.
+      (Some(Value::Object(_)), Some(Value::Object(_))) if key == "user-config" => {
+        fields.extend(
+          changed_fields(&old[key], &new[key])
+            .into_iter()
+            .map(|field| format!("{}.{}", key, field)),
+        );
+      }
+      _ => fields.push(key.clone()),
+    }
+  }
+  fields
+}
+
+fn device_change(
+  kind: UserConfigChangeKind,
+  key: DeviceKey,
+  changed_fields: Vec<String>,
+) -> UserConfigDeviceChange {
+  let (address, protocol, identifier) = key;
+  UserConfigDeviceChange {
+    kind,
+    address,
+    protocol,
+    identifier,
+    changed_fields,
+  }
+}
+
+pub fn diff_user_configs(
+  old_json: &str,
+  new_json: &str,
+) -> Result<UserConfigDiff, UserConfigDiffError> {
+  let old = parse(old_json, "Old")?;
+  let new = parse(new_json, "New")?;
+  let mut diff = UserConfigDiff::default();
+
+  let mut old_devices = devices(&old);
+  for (key, new_config) in devices(&new) {
+    match old_devices.remove(&key) {
+      None => diff
+        .devices
+        .push(device_change(UserConfigChangeKind::Added, key, vec![])),
+      Some(old_config) => {
+        let fields = changed_fields(&old_config, &new_config);
+        if !fields.is_empty() {
+          diff
+            .devices
+            .push(device_change(UserConfigChangeKind::Changed, key, fields));
+        }
+      }
+    }
+  }
+  for key in old_devices.into_keys() {
+    diff
+      .devices
+      .push(device_change(UserConfigChangeKind::Removed, key, vec![]));
+  }
+
+  let old_specifiers = specifiers(&old);
+  let new_specifiers = specifiers(&new);
+  let empty = BTreeSet::new();
+  let protocols: BTreeSet<&String> = old_specifiers.keys().chain(new_specifiers.keys()).collect();
+  for protocol in protocols {
+    let old_set = old_specifiers.get(protocol).unwrap_or(&empty);
+    let new_set = new_specifiers.get(protocol).unwrap_or(&empty);
+    for (kind, specifiers) in [
+      (UserConfigChangeKind::Removed, old_set.difference(new_set)),
+      (UserConfigChangeKind::Added, new_set.difference(old_set)),
+    ] {
+      diff
+        .specifiers
+        .extend(specifiers.map(|specifier| UserConfigSpecifierChange {
+          kind,
+          protocol: protocol.clone(),
+          specifier: specifier.clone(),
+        }));
+    }
+  }
+  Ok(diff)
+}
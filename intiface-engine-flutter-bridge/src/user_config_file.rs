@@ -0,0 +1,140 @@
+use crate::{
+  api::{
+    apply_user_device_specifiers, user_device_specifiers, ExposedUserDeviceDefinition,
+    ExposedUserDeviceIdentifier, ExposedUserDeviceSpecifiers,
+  },
+  device_config_updater::write_atomically,
+  user_config_migration::{self, MigrationError},
+};
+use buttplug::{
+  server::device::configuration::DeviceConfigurationManager,
+  util::device_configuration::{load_protocol_configs, save_user_config},
+};
+use std::{
+  fs::{self, File},
+  io,
+  path::Path,
+  time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+// Reading and writing the user config file. Writing it from Dart meant a crash partway through left
+// a truncated file, and with it, every device setup the user had. Here, the old file is copied to a
+// timestamped backup, and the new one replaces it in a single rename once it's synced to disk.
+
+// Backups past this many are deleted, oldest first.
+const MAX_BACKUPS: usize = 5;
+
+pub struct ExposedUserConfig {
+  // User specifiers, by protocol.
+  pub specifiers: Vec<(String, ExposedUserDeviceSpecifiers)>,
+  pub devices: Vec<(ExposedUserDeviceIdentifier, ExposedUserDeviceDefinition)>,
+}
+
+#[derive(Debug, Error)]
+pub enum UserConfigFileError {
+  #[error("User config file {0} does not exist")]
+  NotFound(String),
+
+  #[error("Cannot access user config file: {0}")]
+  Io(#[from] io::Error),
+
+  #[error(transparent)]
+  Migration(#[from] MigrationError),
+
+  #[error("User config was rejected by the library: {0}")]
+  Rejected(String),
+}
+
+fn base_config_manager() -> Result<DeviceConfigurationManager, UserConfigFileError> {
+  load_protocol_configs(&None, &None, false)
+    .and_then(|mut builder| builder.finish())
+    .map_err(|e| UserConfigFileError::Rejected(format!("{:?}", e)))
+}
+
+// Older versions are migrated on load, so the next save writes the current version.
+pub fn load(path: &str) -> Result<ExposedUserConfig, UserConfigFileError> {
+  let contents = match fs::read_to_string(path) {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+      return Err(UserConfigFileError::NotFound(path.to_owned()))
+    }
+    Err(e) => return Err(e.into()),
+  };
+  let contents = user_config_migration::migrate_user_config(&contents)?;
+  let dcm = load_protocol_configs(&None, &Some(contents), false)
+    .and_then(|mut builder| builder.finish())
+    .map_err(|e| UserConfigFileError::Rejected(format!("{:?}", e)))?;
+  Ok(ExposedUserConfig {
+    specifiers: user_device_specifiers(&dcm),
+    devices: dcm
+      .user_device_definitions()
+      .iter()
+      .map(|kv| (kv.key().clone().into(), kv.value().clone().into()))
+      .collect(),
+  })
+}
+
+pub fn save(path: &str, config: ExposedUserConfig) -> Result<(), UserConfigFileError> {
+  // Let the library write the file, so it's always in the version it expects.
+  let dcm = base_config_manager()?;
+  for (protocol, specifiers) in config.specifiers {
+    apply_user_device_specifiers(&dcm, &protocol, specifiers)
+      .map_err(|e| UserConfigFileError::Rejected(e.to_string()))?;
+  }
+  for (identifier, definition) in config.devices {
+    dcm
+      .add_user_device_definition(&identifier.into(), &definition.into())
+      .map_err(|e| UserConfigFileError::Rejected(format!("{:?}", e)))?;
+  }
+  let contents =
+    save_user_config(&dcm).map_err(|e| UserConfigFileError::Rejected(format!("{:?}", e)))?;
+  let path = Path::new(path);
+  if path.exists() {
+    backup(path)?;
+  }
+  write_atomically(path, contents.as_bytes())?;
+  Ok(())
+}
+
+// Copies the file to [name].[unix time in ms].bak next to it, then clears out old backups.
+fn backup(path: &Path) -> io::Result<()> {
+  let Some(file_name) = path.file_name().and_then(|x| x.to_str()) else {
+    return Ok(());
+  };
+  let timestamp = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|x| x.as_millis())
+    .unwrap_or_default();
+  let backup_path = path.with_file_name(format!("{}.{}.bak", file_name, timestamp));
+  fs::copy(path, &backup_path)?;
+  File::open(&backup_path)?.sync_all()?;
+
+  let directory = match path.parent() {
+    Some(parent) if !parent.as_os_str().is_empty() => parent,
+    _ => Path::new("."),
+  };
+  let prefix = format!("{}.", file_name);
+  let mut backups: Vec<_> = fs::read_dir(directory)?
+    .filter_map(|entry| entry.ok())
+    .map(|entry| entry.path())
+    .filter(|x| {
+      x.file_name()
+        .and_then(|x| x.to_str())
+        .is_some_and(|x| x.starts_with(&prefix) && x.ends_with(".bak"))
+    })
+    .collect();
+  // Timestamps are all the same length, so sorting by name sorts by age.
+  backups.sort();
+  if backups.len() > MAX_BACKUPS {
+    for old_backup in &backups[..backups.len() - MAX_BACKUPS] {
+      if let Err(e) = fs::remove_file(old_backup) {
+        warn!(
+          "Cannot remove old user config backup {:?}: {:?}",
+          old_backup, e
+        );
+      }
+    }
+  }
+  Ok(())
+}
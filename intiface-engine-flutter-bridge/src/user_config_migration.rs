@@ -0,0 +1,438 @@
+use buttplug::{
+  core::message::DeviceFeature,
+  server::device::configuration::{
+    BaseDeviceIdentifier, DeviceConfigurationManager, ProtocolCommunicationSpecifier,
+    SerialSpecifier, UserDeviceCustomization, UserDeviceDefinition, UserDeviceIdentifier,
+    WebsocketSpecifier,
+  },
+  util::device_configuration::{load_protocol_configs, save_user_config},
+};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+// Upgrades user config files written by older versions of the library to the current format.
+//
+// v1 and v2 files only stored device customizations (allow/deny, display name, index), while the
+// current format stores the full device definition alongside them. To fill those in, we look the
+// device up in the base config built into the library, then let the library itself write out the
+// result, so we always end up with whatever version it expects. This doesn't touch the shared DCM,
+// so it can run before that's been set up.
+
+pub(crate) const CURRENT_USER_CONFIG_MAJOR_VERSION: u64 = 3;
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+  #[error("User config is not valid JSON: {0}")]
+  InvalidJson(#[from] serde_json::Error),
+
+  #[error("Cannot determine user config version")]
+  UnknownVersion,
+
+  #[error(
+    "User config version {0} is newer than supported version {CURRENT_USER_CONFIG_MAJOR_VERSION}"
+  )]
+  UnsupportedVersion(u64),
+
+  #[error("User config field {0} is missing or has the wrong type")]
+  InvalidField(String),
+
+  #[error("No device definition found for protocol {protocol}, identifier {identifier:?}")]
+  UnknownDevice {
+    protocol: String,
+    identifier: Option<String>,
+  },
+
+  #[error("Migrated config was rejected by the library: {0}")]
+  Rejected(String),
+}
+
+struct OldDeviceConfig {
+  identifier: UserDeviceIdentifier,
+  customization: UserDeviceCustomization,
+}
+
+pub fn migrate_user_config(old_json: &str) -> Result<String, MigrationError> {
+  migrate_user_config_with_base(old_json, &None)
+}
+
+// Same as migrate_user_config, but looks devices up in the given base config rather than the one
+// built into the library.
+fn migrate_user_config_with_base(
+  old_json: &str,
+  base_config: &Option<String>,
+) -> Result<String, MigrationError> {
+  let config: Value = serde_json::from_str(old_json)?;
+  let version = config_major_version(&config).ok_or(MigrationError::UnknownVersion)?;
+  if version > CURRENT_USER_CONFIG_MAJOR_VERSION {
+    return Err(MigrationError::UnsupportedVersion(version));
+  }
+  if version == CURRENT_USER_CONFIG_MAJOR_VERSION {
+    // Already current, just make sure the library is happy with it.
+    load_protocol_configs(base_config, &Some(old_json.to_owned()), false)
+      .map_err(|e| MigrationError::Rejected(format!("{:?}", e)))?;
+    return Ok(old_json.to_owned());
+  }
+  info!("Migrating user config from version {}", version);
+
+  // v1 used "user-config", v2 switched to "user-configs". Specifiers are laid out the same in both.
+  let user_configs = config
+    .get("user-configs")
+    .or_else(|| config.get("user-config"))
+    .and_then(|x| x.as_object())
+    .ok_or(MigrationError::InvalidField("user-configs".to_owned()))?;
+  let specifiers = match user_configs.get("specifiers") {
+    Some(specifiers) => parse_specifiers(specifiers)?,
+    None => vec![],
+  };
+  let devices = match (version, user_configs.get("devices")) {
+    (_, None) => vec![],
+    (1, Some(devices)) => parse_v1_devices(devices)?,
+    (_, Some(devices)) => parse_v2_devices(devices)?,
+  };
+
+  let new_dcm = load_protocol_configs(base_config, &None, false)
+    .and_then(|mut builder| builder.finish())
+    .map_err(|e| MigrationError::Rejected(format!("{:?}", e)))?;
+  for (protocol, specifier) in specifiers {
+    new_dcm
+      .add_user_communication_specifier(&protocol, &specifier)
+      .map_err(|e| MigrationError::Rejected(format!("{:?}", e)))?;
+  }
+  for device in devices {
+    let (name, features) = find_base_definition(&new_dcm, &device.identifier)?;
+    let definition = UserDeviceDefinition::new(&name, &features, &device.customization);
+    new_dcm
+      .add_user_device_definition(&device.identifier, &definition)
+      .map_err(|e| MigrationError::Rejected(format!("{:?}", e)))?;
+  }
+  save_user_config(&new_dcm).map_err(|e| MigrationError::Rejected(format!("{:?}", e)))
+}
+
+fn config_major_version(config: &Value) -> Option<u64> {
+  match config.get("version")? {
+    // v1 files just had a bare number
+    Value::Number(version) => version.as_u64(),
+    Value::Object(version) => version.get("major")?.as_u64(),
+    _ => None,
+  }
+}
+
+fn parse_specifiers(
+  specifiers: &Value,
+) -> Result<Vec<(String, ProtocolCommunicationSpecifier)>, MigrationError> {
+  let specifiers = specifiers
+    .as_object()
+    .ok_or(MigrationError::InvalidField("specifiers".to_owned()))?;
+  let mut parsed = vec![];
+  for (protocol, protocol_specifiers) in specifiers {
+    if let Some(names) = protocol_specifiers
+      .get("websocket")
+      .and_then(|x| x.get("names"))
+      .and_then(|x| x.as_array())
+    {
+      for name in names {
+        let name = name.as_str().ok_or(MigrationError::InvalidField(format!(
+          "specifiers.{}.websocket.names",
+          protocol
+        )))?;
+        parsed.push((
+          protocol.clone(),
+          ProtocolCommunicationSpecifier::Websocket(WebsocketSpecifier::new(name)),
+        ));
+      }
+    }
+    if let Some(ports) = protocol_specifiers.get("serial").and_then(|x| x.as_array()) {
+      for port in ports {
+        let field = |name: &str| format!("specifiers.{}.serial.{}", protocol, name);
+        let port = port
+          .as_object()
+          .ok_or(MigrationError::InvalidField(format!(
+            "specifiers.{}.serial",
+            protocol
+          )))?;
+        let number = |name: &str| {
+          port
+            .get(name)
+            .and_then(|x| x.as_u64())
+            .ok_or(MigrationError::InvalidField(field(name)))
+        };
+        parsed.push((
+          protocol.clone(),
+          ProtocolCommunicationSpecifier::Serial(SerialSpecifier::new(
+            port
+              .get("port")
+              .and_then(|x| x.as_str())
+              .ok_or(MigrationError::InvalidField(field("port")))?,
+            number("baud-rate")? as u32,
+            number("data-bits")? as u8,
+            number("stop-bits")? as u8,
+            port
+              .get("parity")
+              .and_then(|x| x.as_str())
+              .and_then(|x| x.chars().next())
+              .unwrap_or('N'),
+          )),
+        ));
+      }
+    }
+  }
+  Ok(parsed)
+}
+
+fn parse_customization(
+  config: &Map<String, Value>,
+  index_key: &str,
+  field: &str,
+) -> Result<UserDeviceCustomization, MigrationError> {
+  Ok(UserDeviceCustomization::new(
+    &config
+      .get("display-name")
+      .and_then(|x| x.as_str())
+      .map(|x| x.to_owned()),
+    config
+      .get("allow")
+      .and_then(|x| x.as_bool())
+      .unwrap_or(false),
+    config
+      .get("deny")
+      .and_then(|x| x.as_bool())
+      .unwrap_or(false),
+    config
+      .get(index_key)
+      .and_then(|x| x.as_u64())
+      .ok_or(MigrationError::InvalidField(format!(
+        "{}.{}",
+        field, index_key
+      )))? as u32,
+  ))
+}
+
+// v1 devices are a map keyed on address, with the protocol stored in the config body.
+fn parse_v1_devices(devices: &Value) -> Result<Vec<OldDeviceConfig>, MigrationError> {
+  let devices = devices
+    .as_object()
+    .ok_or(MigrationError::InvalidField("devices".to_owned()))?;
+  let mut parsed = vec![];
+  for (address, config) in devices {
+    let field = format!("devices.{}", address);
+    let config = config
+      .as_object()
+      .ok_or(MigrationError::InvalidField(field.clone()))?;
+    let protocol = config
+      .get("protocol")
+      .and_then(|x| x.as_str())
+      .ok_or(MigrationError::InvalidField(format!("{}.protocol", field)))?;
+    let identifier = config
+      .get("identifier")
+      .and_then(|x| x.as_str())
+      .map(|x| x.to_owned());
+    parsed.push(OldDeviceConfig {
+      identifier: UserDeviceIdentifier::new(address, protocol, &identifier),
+      customization: parse_customization(config, "reserved-index", &field)?,
+    });
+  }
+  Ok(parsed)
+}
+
+// v2 devices are a list of identifier/config pairs, which is what the current format builds on.
+fn parse_v2_devices(devices: &Value) -> Result<Vec<OldDeviceConfig>, MigrationError> {
+  let devices = devices
+    .as_array()
+    .ok_or(MigrationError::InvalidField("devices".to_owned()))?;
+  let mut parsed = vec![];
+  for (i, device) in devices.iter().enumerate() {
+    let field = format!("devices[{}]", i);
+    let identifier =
+      device
+        .get("identifier")
+        .and_then(|x| x.as_object())
+        .ok_or(MigrationError::InvalidField(format!(
+          "{}.identifier",
+          field
+        )))?;
+    let string_field = |name: &str| {
+      identifier
+        .get(name)
+        .and_then(|x| x.as_str())
+        .ok_or(MigrationError::InvalidField(format!(
+          "{}.identifier.{}",
+          field, name
+        )))
+    };
+    let config = device
+      .get("config")
+      .and_then(|x| x.as_object())
+      .ok_or(MigrationError::InvalidField(format!("{}.config", field)))?;
+    parsed.push(OldDeviceConfig {
+      identifier: UserDeviceIdentifier::new(
+        string_field("address")?,
+        string_field("protocol")?,
+        &identifier
+          .get("identifier")
+          .and_then(|x| x.as_str())
+          .map(|x| x.to_owned()),
+      ),
+      customization: parse_customization(config, "index", &format!("{}.config", field))?,
+    });
+  }
+  Ok(parsed)
+}
+
+// Looks for a definition for the exact device identifier first, then falls back to the protocol
+// default, same as the library does when a device connects.
+pub(crate) fn find_base_definition(
+  dcm: &DeviceConfigurationManager,
+  identifier: &UserDeviceIdentifier,
+) -> Result<(String, Vec<DeviceFeature>), MigrationError> {
+  let definitions = dcm.base_device_definitions();
+  definitions
+    .get(&BaseDeviceIdentifier::new(
+      identifier.protocol(),
+      identifier.identifier(),
+    ))
+    .or_else(|| definitions.get(&BaseDeviceIdentifier::new(identifier.protocol(), &None)))
+    .map(|definition| (definition.name().clone(), definition.features().clone()))
+    .ok_or(MigrationError::UnknownDevice {
+      protocol: identifier.protocol().clone(),
+      identifier: identifier.identifier().clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn invalid_field(old_json: &str) -> String {
+    match migrate_user_config(old_json) {
+      Err(MigrationError::InvalidField(field)) => field,
+      other => panic!("Expected InvalidField, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn invalid_json() {
+    assert!(matches!(
+      migrate_user_config("{"),
+      Err(MigrationError::InvalidJson(_))
+    ));
+  }
+
+  #[test]
+  fn versions() {
+    for old_json in ["{}", "{\"version\":\"1\"}", "{\"version\":{}}"] {
+      assert!(
+        matches!(
+          migrate_user_config(old_json),
+          Err(MigrationError::UnknownVersion)
+        ),
+        "{}",
+        old_json
+      );
+    }
+    assert!(matches!(
+      migrate_user_config("{\"version\":{\"major\":4,\"minor\":0}}"),
+      Err(MigrationError::UnsupportedVersion(4))
+    ));
+  }
+
+  #[test]
+  fn v1_and_v2_number_their_versions_differently() {
+    assert_eq!(
+      config_major_version(&serde_json::json!({"version": 1})),
+      Some(1)
+    );
+    assert_eq!(
+      config_major_version(&serde_json::json!({"version": {"major": 2, "minor": 0}})),
+      Some(2)
+    );
+  }
+
+  #[test]
+  fn missing_fields_are_named() {
+    assert_eq!(invalid_field("{\"version\":1}"), "user-configs");
+    assert_eq!(
+      invalid_field(
+        "{\"version\":1,\"user-config\":{\"devices\":{\"a1\":{\"reserved-index\":0}}}}"
+      ),
+      "devices.a1.protocol"
+    );
+    assert_eq!(
+      invalid_field(
+        "{\"version\":1,\"user-config\":{\"devices\":{\"a1\":{\"protocol\":\"lovense\"}}}}"
+      ),
+      "devices.a1.reserved-index"
+    );
+    assert_eq!(
+      invalid_field(
+        "{\"version\":{\"major\":2,\"minor\":0},\"user-configs\":{\"devices\":[{\"config\":{}}]}}"
+      ),
+      "devices[0].identifier"
+    );
+    assert_eq!(
+      invalid_field(
+        "{\"version\":{\"major\":2,\"minor\":0},\"user-configs\":{\"devices\":\
+         [{\"identifier\":{\"address\":\"a1\"},\"config\":{\"index\":0}}]}}"
+      ),
+      "devices[0].identifier.protocol"
+    );
+    assert_eq!(
+      invalid_field(
+        "{\"version\":{\"major\":2,\"minor\":0},\"user-configs\":{\"specifiers\":\
+         {\"lovense\":{\"serial\":[{\"port\":\"COM1\",\"baud-rate\":9600}]}}}}"
+      ),
+      "specifiers.lovense.serial.data-bits"
+    );
+  }
+
+  #[test]
+  fn devices_need_a_base_definition() {
+    let result = migrate_user_config(
+      "{\"version\":{\"major\":2,\"minor\":0},\"user-configs\":{\"devices\":\
+       [{\"identifier\":{\"address\":\"a1\",\"protocol\":\"not-a-protocol\"},\
+       \"config\":{\"index\":0}}]}}",
+    );
+    match result {
+      Err(MigrationError::UnknownDevice {
+        protocol,
+        identifier,
+      }) => {
+        assert_eq!(protocol, "not-a-protocol");
+        assert_eq!(identifier, None);
+      }
+      other => panic!("Expected UnknownDevice, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn v2_devices_are_carried_over() {
+    // Our own base config, so the test doesn't depend on what's in the bundled one.
+    let base_config = serde_json::json!({
+      "version": { "major": CURRENT_USER_CONFIG_MAJOR_VERSION, "minor": 0 },
+      "protocols": {
+        "lovense": {
+          "defaults": {
+            "name": "Test Device",
+            "features": [{
+              "feature-type": "Vibrate",
+              "actuator": { "step-range": [0, 20], "messages": ["ScalarCmd"] },
+            }],
+          },
+        },
+      },
+    });
+    let migrated = migrate_user_config_with_base(
+      "{\"version\":{\"major\":2,\"minor\":0},\"user-configs\":{\"devices\":\
+       [{\"identifier\":{\"address\":\"a1\",\"protocol\":\"lovense\"},\
+       \"config\":{\"index\":3,\"allow\":true,\"display-name\":\"Bedside\"}}]}}",
+      &Some(base_config.to_string()),
+    )
+    .unwrap();
+    let config: Value = serde_json::from_str(&migrated).unwrap();
+    assert_eq!(
+      config_major_version(&config),
+      Some(CURRENT_USER_CONFIG_MAJOR_VERSION)
+    );
+    assert!(migrated.contains("\"a1\""));
+    assert!(migrated.contains("Bedside"));
+  }
+}
@@ -0,0 +1,281 @@
+use crate::user_config_migration::CURRENT_USER_CONFIG_MAJOR_VERSION;
+use buttplug::{
+  server::device::protocol::get_default_protocol_map,
+  util::device_configuration::load_protocol_configs,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+// Checks a user config before it gets anywhere near the DCM, which just fails the whole load on the
+// first problem it finds. Here we try to collect everything that's wrong, with a path to where it
+// is, so the settings UI can show each error next to the field that caused it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum UserConfigErrorKind {
+  InvalidJson,
+  // Config is from an older library version, and needs to go through migrate_user_config first.
+  OutdatedVersion,
+  UnsupportedVersion,
+  BadIdentifier,
+  UnknownProtocol,
+  IndexCollision,
+  // Passed our checks, but the library still wouldn't load it.
+  Rejected,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UserConfigError {
+  pub kind: UserConfigErrorKind,
+  // Dotted path to the offending field, e.g. "user-configs.devices[2].identifier.protocol". Empty
+  // if the error applies to the whole file.
+  pub field: String,
+  pub message: String,
+}
+
+impl UserConfigError {
+  fn new(kind: UserConfigErrorKind, field: &str, message: String) -> Self {
+    Self {
+      kind,
+      field: field.to_owned(),
+      message,
+    }
+  }
+}
+
+pub fn validate_user_config(user_config: &str) -> Vec<UserConfigError> {
+  let config: Value = match serde_json::from_str(user_config) {
+    Ok(config) => config,
+    Err(e) => {
+      return vec![UserConfigError::new(
+        UserConfigErrorKind::InvalidJson,
+        "",
+        e.to_string(),
+      )]
+    }
+  };
+  match config
+    .get("version")
+    .and_then(|x| x.get("major"))
+    .and_then(|x| x.as_u64())
+  {
+    Some(CURRENT_USER_CONFIG_MAJOR_VERSION) => {}
+    Some(version) if version > CURRENT_USER_CONFIG_MAJOR_VERSION => {
+      return vec![UserConfigError::new(
+        UserConfigErrorKind::UnsupportedVersion,
+        "version",
+        format!(
+          "Version {} is newer than this version of Intiface supports",
+          version
+        ),
+      )]
+    }
+    _ => {
+      return vec![UserConfigError::new(
+        UserConfigErrorKind::OutdatedVersion,
+        "version",
+        "Config is from an older version of Intiface and needs to be migrated".to_owned(),
+      )]
+    }
+  }
+
+  let protocol_names: HashSet<String> = get_default_protocol_map().into_keys().collect();
+  let mut errors = vec![];
+  let user_configs = config.get("user-configs");
+
+  if let Some(protocols) = user_configs
+    .and_then(|x| x.get("protocols"))
+    .and_then(|x| x.as_object())
+  {
+    for protocol in protocols.keys() {
+      if !protocol_names.contains(protocol) {
+        errors.push(UserConfigError::new(
+          UserConfigErrorKind::UnknownProtocol,
+          &format!("user-configs.protocols.{}", protocol),
+          format!("Unknown protocol {}", protocol),
+        ));
+      }
+    }
+  }
+
+  let devices = user_configs
+    .and_then(|x| x.get("devices"))
+    .and_then(|x| x.as_array())
+    .map(|x| x.as_slice())
+    .unwrap_or_default();
+  // Index -> field of the first device we saw using it
+  let mut indexes: HashMap<u64, String> = HashMap::new();
+  for (i, device) in devices.iter().enumerate() {
+    let field = format!("user-configs.devices[{}]", i);
+    let identifier = device.get("identifier");
+    match identifier
+      .and_then(|x| x.get("address"))
+      .and_then(|x| x.as_str())
+    {
+      Some(address) if !address.is_empty() => {}
+      _ => errors.push(UserConfigError::new(
+        UserConfigErrorKind::BadIdentifier,
+        &format!("{}.identifier.address", field),
+        "Device identifier needs a non-empty address".to_owned(),
+      )),
+    }
+    match identifier
+      .and_then(|x| x.get("protocol"))
+      .and_then(|x| x.as_str())
+    {
+      Some(protocol) if protocol_names.contains(protocol) => {}
+      Some(protocol) => errors.push(UserConfigError::new(
+        UserConfigErrorKind::UnknownProtocol,
+        &format!("{}.identifier.protocol", field),
+        format!("Unknown protocol {}", protocol),
+      )),
+      None => errors.push(UserConfigError::new(
+        UserConfigErrorKind::BadIdentifier,
+        &format!("{}.identifier.protocol", field),
+        "Device identifier needs a protocol".to_owned(),
+      )),
+    }
+    let index_field = format!("{}.config.user-config.index", field);
+    if let Some(index) = device
+      .get("config")
+      .and_then(|x| x.get("user-config"))
+      .and_then(|x| x.get("index"))
+      .and_then(|x| x.as_u64())
+    {
+      if let Some(other_field) = indexes.get(&index) {
+        errors.push(UserConfigError::new(
+          UserConfigErrorKind::IndexCollision,
+          &index_field,
+          format!("Index {} is already used by {}", index, other_field),
+        ));
+      } else {
+        indexes.insert(index, index_field);
+      }
+    }
+  }
+
+  if errors.is_empty() {
+    if let Err(e) = load_protocol_configs(&None, &Some(user_config.to_owned()), false) {
+      errors.push(UserConfigError::new(
+        UserConfigErrorKind::Rejected,
+        "",
+        format!("{:?}", e),
+      ));
+    }
+  }
+  errors
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config(devices: &str) -> String {
+    format!(
+      "{{\"version\":{{\"major\":3,\"minor\":0}},\"user-configs\":{{\"devices\":{}}}}}",
+      devices
+    )
+  }
+
+  fn of_kind(errors: &[UserConfigError], kind: UserConfigErrorKind) -> Vec<&str> {
+    errors
+      .iter()
+      .filter(|x| x.kind == kind)
+      .map(|x| x.field.as_str())
+      .collect()
+  }
+
+  #[test]
+  fn invalid_json() {
+    let errors = validate_user_config("{");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, UserConfigErrorKind::InvalidJson);
+    assert_eq!(errors[0].field, "");
+  }
+
+  #[test]
+  fn versions() {
+    for (config, kind) in [
+      ("{}", UserConfigErrorKind::OutdatedVersion),
+      ("{\"version\":2}", UserConfigErrorKind::OutdatedVersion),
+      (
+        "{\"version\":{\"major\":2,\"minor\":0}}",
+        UserConfigErrorKind::OutdatedVersion,
+      ),
+      (
+        "{\"version\":{\"major\":4,\"minor\":0}}",
+        UserConfigErrorKind::UnsupportedVersion,
+      ),
+    ] {
+      let errors = validate_user_config(config);
+      assert_eq!(errors.len(), 1, "{}", config);
+      assert_eq!(errors[0].kind, kind, "{}", config);
+      assert_eq!(errors[0].field, "version");
+    }
+  }
+
+  #[test]
+  fn bad_identifiers() {
+    let errors = validate_user_config(&config(
+      "[{\"identifier\":{\"address\":\"\",\"protocol\":\"not-a-protocol\"}},\
+       {\"identifier\":{\"address\":\"a1\"}}]",
+    ));
+    assert_eq!(
+      of_kind(&errors, UserConfigErrorKind::BadIdentifier),
+      vec![
+        "user-configs.devices[0].identifier.address",
+        "user-configs.devices[1].identifier.protocol"
+      ]
+    );
+    assert_eq!(
+      of_kind(&errors, UserConfigErrorKind::UnknownProtocol),
+      vec!["user-configs.devices[0].identifier.protocol"]
+    );
+  }
+
+  #[test]
+  fn unknown_protocol_settings() {
+    let errors = validate_user_config(
+      "{\"version\":{\"major\":3,\"minor\":0},\
+       \"user-configs\":{\"protocols\":{\"not-a-protocol\":{}}}}",
+    );
+    assert_eq!(
+      of_kind(&errors, UserConfigErrorKind::UnknownProtocol),
+      vec!["user-configs.protocols.not-a-protocol"]
+    );
+  }
+
+  #[test]
+  fn index_collisions_point_at_the_first_user() {
+    let device = |address: &str, index: u32| {
+      format!(
+        "{{\"identifier\":{{\"address\":\"{}\",\"protocol\":\"not-a-protocol\"}},\
+         \"config\":{{\"user-config\":{{\"index\":{}}}}}}}",
+        address, index
+      )
+    };
+    let errors = validate_user_config(&config(&format!(
+      "[{},{},{}]",
+      device("a1", 0),
+      device("a2", 1),
+      device("a3", 0)
+    )));
+    let collisions: Vec<&UserConfigError> = errors
+      .iter()
+      .filter(|x| x.kind == UserConfigErrorKind::IndexCollision)
+      .collect();
+    assert_eq!(collisions.len(), 1);
+    assert_eq!(
+      collisions[0].field,
+      "user-configs.devices[2].config.user-config.index"
+    );
+    assert!(collisions[0]
+      .message
+      .contains("user-configs.devices[0].config.user-config.index"));
+  }
+
+  #[test]
+  fn empty_config_is_fine() {
+    assert!(validate_user_config(&config("[]")).is_empty());
+  }
+}
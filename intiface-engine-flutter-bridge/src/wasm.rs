@@ -0,0 +1,23 @@
+use crate::{user_config_diff, user_config_migration, user_config_validation};
+use wasm_bindgen::prelude::*;
+
+// Web entry points for the parts of the bridge that don't need hardware or a runtime, so a web
+// config editor can check and upgrade configs with the same code the app uses. Structured results
+// are returned as JSON strings, same as we do for the Flutter side.
+
+#[wasm_bindgen(js_name = validateUserConfig)]
+pub fn validate_user_config(user_config: &str) -> String {
+  serde_json::to_string(&user_config_validation::validate_user_config(user_config))
+    .expect("Validation errors should always serialize")
+}
+
+#[wasm_bindgen(js_name = migrateUserConfig)]
+pub fn migrate_user_config(old_json: &str) -> Result<String, JsError> {
+  Ok(user_config_migration::migrate_user_config(old_json)?)
+}
+
+#[wasm_bindgen(js_name = diffUserConfigs)]
+pub fn diff_user_configs(old_json: &str, new_json: &str) -> Result<String, JsError> {
+  let diff = user_config_diff::diff_user_configs(old_json, new_json)?;
+  Ok(serde_json::to_string(&diff)?)
+}
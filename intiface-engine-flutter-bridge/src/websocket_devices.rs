@@ -0,0 +1,363 @@
+use crate::{
+  api::{
+    self, ExposedUserDeviceDefinition, ExposedUserDeviceIdentifier, ExposedWebsocketSpecifier,
+    DEVICE_CONFIG_MANAGER,
+  },
+  events::{self, BridgeEvent},
+  mobile_init, user_config_migration,
+};
+use anyhow::Result;
+use buttplug::server::device::configuration::{
+  ProtocolCommunicationSpecifier, UserDeviceCustomization, UserDeviceDefinition,
+  UserDeviceIdentifier,
+};
+use futures::{SinkExt, StreamExt};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+  },
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+  io::{AsyncRead, AsyncWrite},
+  net::TcpListener,
+  select,
+  sync::Notify,
+  time::timeout,
+};
+use tokio_tungstenite::{accept_async, connect_async, tungstenite::Message};
+
+// Devices connecting to the device websocket server (TCode-over-wifi builds, ESP32 projects, the
+// simulator's fakes) go through us a message at a time, so we can keep track of what's connected.
+// The engine drops devices it can't match to a protocol without a word, which is the usual way these
+// setups go wrong, so each connection is listed along with the name from its handshake and the
+// protocol that name maps to, if any.
+//
+// For adding a device in the first place, discover_device stands in for the engine's device server
+// until a device connects and says what it is, then hands back the specifier and config entry to
+// save for it.
+
+// The engine's default device websocket server port, which is what most device firmware expects.
+pub const DEFAULT_DEVICE_WEBSOCKET_PORT: u16 = 54817;
+// Devices send their handshake as soon as they connect, so anything slower isn't one of them.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebsocketDeviceConnection {
+  pub connection_id: u32,
+  // ip:port the device connected from.
+  pub remote_address: String,
+  // Whether it came in over the TLS device server.
+  pub secure: bool,
+  // Milliseconds since the unix epoch.
+  pub connected_at_ms: u64,
+  // Identifier and address from the device's handshake, None until it's sent one.
+  pub name: Option<String>,
+  pub device_address: Option<String>,
+  // Protocol with a websocket specifier for the name. None means the engine won't take the device.
+  pub protocol: Option<String>,
+}
+
+pub struct WebsocketDeviceDiscovery {
+  pub remote_address: String,
+  pub name: String,
+  pub device_address: Option<String>,
+  // The protocol asked for, or otherwise the one the name already maps to, if any.
+  pub protocol: Option<String>,
+  // For add_websocket_specifier or set_user_device_specifiers.
+  pub specifier: ExposedWebsocketSpecifier,
+  // For update_user_config, when we have a protocol to build them from.
+  pub identifier: Option<ExposedUserDeviceIdentifier>,
+  pub user_config: Option<ExposedUserDeviceDefinition>,
+}
+
+static NEXT_CONNECTION_ID: AtomicU32 = AtomicU32::new(1);
+
+lazy_static! {
+  static ref CONNECTIONS: Mutex<Vec<WebsocketDeviceConnection>> = Mutex::new(vec![]);
+  static ref DISCOVERY_STOP: Mutex<Option<Arc<Notify>>> = Mutex::new(None);
+}
+
+pub fn connections() -> Vec<WebsocketDeviceConnection> {
+  CONNECTIONS.lock().unwrap().clone()
+}
+
+pub fn clear() {
+  CONNECTIONS.lock().unwrap().clear();
+}
+
+// User specifiers first, since those are the ones people set up for their own hardware.
+pub(crate) fn protocol_for_name(name: &str) -> Option<String> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let matches = |specifiers: &[ProtocolCommunicationSpecifier]| {
+    specifiers.iter().any(|x| match x {
+      ProtocolCommunicationSpecifier::Websocket(ws) => ws.name() == name,
+      _ => false,
+    })
+  };
+  if let Some(kv) = dcm
+    .user_communication_specifiers()
+    .iter()
+    .find(|kv| matches(kv.value()))
+  {
+    return Some(kv.key().clone());
+  }
+  dcm
+    .base_communication_specifiers()
+    .iter()
+    .find(|(_, specifiers)| matches(specifiers))
+    .map(|(protocol, _)| protocol.clone())
+}
+
+// Blocks until a device connects and sends its handshake, the timeout runs out, or
+// cancel_discovery is called.
+pub fn discover_device(
+  port: u16,
+  protocol: Option<String>,
+  timeout_ms: u32,
+) -> Result<WebsocketDeviceDiscovery> {
+  if api::runtime_started() {
+    return Err(anyhow::Error::msg(
+      "Stop the engine before adding a websocket device, it's using the device websocket port",
+    ));
+  }
+  let stop = {
+    let mut discovery_stop = DISCOVERY_STOP.lock().unwrap();
+    if discovery_stop.is_some() {
+      return Err(anyhow::Error::msg(
+        "Websocket device discovery already running",
+      ));
+    }
+    let stop = Arc::new(Notify::new());
+    *discovery_stop = Some(stop.clone());
+    stop
+  };
+  info!("Waiting for a websocket device to connect on port {}", port);
+  let result = mobile_init::create_runtime()
+    .map_err(anyhow::Error::from)
+    .and_then(|runtime| {
+      runtime.block_on(async {
+        select! {
+          result = timeout(Duration::from_millis(timeout_ms as u64), wait_for_handshake(port)) => {
+            result.map_err(|_| anyhow::Error::msg("No websocket device connected in time"))?
+          }
+          _ = stop.notified() => Err(anyhow::Error::msg("Websocket device discovery cancelled")),
+        }
+      })
+    });
+  *DISCOVERY_STOP.lock().unwrap() = None;
+  let (peer, name, device_address) = result?;
+  let protocol = protocol.or_else(|| protocol_for_name(&name));
+  info!(
+    "Websocket device {} connected from {}, protocol {:?}",
+    name, peer, protocol
+  );
+  let (identifier, user_config) = match &protocol {
+    Some(protocol) => {
+      let (identifier, definition) =
+        user_config_entry(protocol, device_address.as_deref().unwrap_or(&name), &name)?;
+      (Some(identifier), Some(definition))
+    }
+    None => (None, None),
+  };
+  Ok(WebsocketDeviceDiscovery {
+    remote_address: peer.to_string(),
+    specifier: ExposedWebsocketSpecifier { name: name.clone() },
+    name,
+    device_address,
+    protocol,
+    identifier,
+    user_config,
+  })
+}
+
+pub fn cancel_discovery() {
+  if let Some(stop) = DISCOVERY_STOP.lock().unwrap().take() {
+    // notify_one, so the cancel sticks even if we're not waiting yet.
+    stop.notify_one();
+  }
+}
+
+// Protocol default definition, with the handshake name as the display name and the next free index.
+fn user_config_entry(
+  protocol: &str,
+  address: &str,
+  name: &str,
+) -> Result<(ExposedUserDeviceIdentifier, ExposedUserDeviceDefinition)> {
+  let dcm = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point");
+  let identifier = UserDeviceIdentifier::new(address, protocol, &None);
+  let (definition_name, features) = user_config_migration::find_base_definition(&dcm, &identifier)?;
+  let index = dcm
+    .user_device_definitions()
+    .iter()
+    .map(|kv| kv.value().user_config().index() + 1)
+    .max()
+    .unwrap_or_default();
+  let definition = UserDeviceDefinition::new(
+    &definition_name,
+    &features,
+    &UserDeviceCustomization::new(&Some(name.to_owned()), false, false, index),
+  );
+  Ok((identifier.into(), definition.into()))
+}
+
+// Returns the first connection to send a handshake, as (peer, name, address).
+async fn wait_for_handshake(port: u16) -> Result<(SocketAddr, String, Option<String>)> {
+  let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+  loop {
+    let (stream, peer) = listener.accept().await?;
+    match timeout(HANDSHAKE_TIMEOUT, read_handshake(stream)).await {
+      Ok(Ok(Some((name, address)))) => return Ok((peer, name, address)),
+      Ok(Ok(None)) | Err(_) => debug!("Connection from {} sent no handshake", peer),
+      Ok(Err(e)) => debug!("Connection from {} failed: {:?}", peer, e),
+    }
+  }
+}
+
+async fn read_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+  stream: S,
+) -> Result<Option<(String, Option<String>)>> {
+  let mut ws = accept_async(stream).await?;
+  while let Some(msg) = ws.next().await {
+    if let Message::Text(text) = msg? {
+      let Ok(handshake) = serde_json::from_str::<Value>(text.as_str()) else {
+        continue;
+      };
+      if let Some(name) = handshake.get("identifier").and_then(|x| x.as_str()) {
+        let address = handshake
+          .get("address")
+          .and_then(|x| x.as_str())
+          .map(|x| x.to_owned());
+        let _ = ws.close(None).await;
+        return Ok(Some((name.to_owned(), address)));
+      }
+    }
+  }
+  Ok(None)
+}
+
+fn record_handshake(connection_id: u32, handshake: &Value) {
+  let Some(name) = handshake.get("identifier").and_then(|x| x.as_str()) else {
+    return;
+  };
+  let connection = {
+    let mut connections = CONNECTIONS.lock().unwrap();
+    let Some(connection) = connections
+      .iter_mut()
+      .find(|x| x.connection_id == connection_id)
+    else {
+      return;
+    };
+    connection.name = Some(name.to_owned());
+    connection.device_address = handshake
+      .get("address")
+      .and_then(|x| x.as_str())
+      .map(|x| x.to_owned());
+    connection.protocol = protocol_for_name(name);
+    connection.clone()
+  };
+  match &connection.protocol {
+    Some(protocol) => info!(
+      "Websocket device {} connected from {}, using protocol {}",
+      name, connection.remote_address, protocol
+    ),
+    None => warn!(
+      "Websocket device {} connected from {}, but no protocol has a websocket specifier for it",
+      name, connection.remote_address
+    ),
+  }
+  events::emit(BridgeEvent::WebsocketDeviceConnected(connection));
+}
+
+// Passes a device connection through to the engine's device websocket server.
+pub async fn relay_device<S: AsyncRead + AsyncWrite + Unpin>(
+  stream: S,
+  peer: SocketAddr,
+  engine_port: u16,
+  secure: bool,
+) {
+  let connection_id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+  CONNECTIONS.lock().unwrap().push(WebsocketDeviceConnection {
+    connection_id,
+    remote_address: peer.to_string(),
+    secure,
+    connected_at_ms: SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|x| x.as_millis() as u64)
+      .unwrap_or_default(),
+    name: None,
+    device_address: None,
+    protocol: None,
+  });
+  if let Err(e) = relay(stream, engine_port, connection_id).await {
+    debug!("Websocket device connection from {} ended: {:?}", peer, e);
+  }
+  let connection = {
+    let mut connections = CONNECTIONS.lock().unwrap();
+    connections
+      .iter()
+      .position(|x| x.connection_id == connection_id)
+      .map(|index| connections.remove(index))
+  };
+  // Devices that never got as far as a handshake still get reported, so there's some sign they
+  // reached us.
+  if let Some(connection) = connection {
+    events::emit(BridgeEvent::WebsocketDeviceDisconnected(connection));
+  }
+}
+
+async fn relay<S: AsyncRead + AsyncWrite + Unpin>(
+  stream: S,
+  engine_port: u16,
+  connection_id: u32,
+) -> Result<()> {
+  let device_ws = accept_async(stream).await?;
+  let (engine_ws, _) = connect_async(format!("ws://127.0.0.1:{}", engine_port)).await?;
+  let (mut device_sink, mut device_stream) = device_ws.split();
+  let (mut engine_sink, mut engine_stream) = engine_ws.split();
+  let mut handshake_seen = false;
+  loop {
+    select! {
+      msg = device_stream.next() => {
+        let Some(msg) = msg else {
+          break;
+        };
+        let msg = msg?;
+        let closing = matches!(msg, Message::Close(_));
+        if !handshake_seen {
+          if let Message::Text(text) = &msg {
+            if let Ok(handshake) = serde_json::from_str::<Value>(text.as_str()) {
+              handshake_seen = true;
+              record_handshake(connection_id, &handshake);
+            }
+          }
+        }
+        engine_sink.send(msg).await?;
+        if closing {
+          break;
+        }
+      }
+      msg = engine_stream.next() => {
+        let Some(msg) = msg else {
+          break;
+        };
+        let msg = msg?;
+        let closing = matches!(msg, Message::Close(_));
+        device_sink.send(msg).await?;
+        if closing {
+          break;
+        }
+      }
+    }
+  }
+  Ok(())
+}
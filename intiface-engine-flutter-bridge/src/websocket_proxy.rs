@@ -0,0 +1,799 @@
+use crate::{
+  actuator_curve,
+  api::EngineOptionsExternal,
+  app_lifecycle, backdoor,
+  client_permissions::{self, ClientFilter},
+  client_sessions::{self, RelayMessage},
+  clients, clock, device_settings,
+  device_stats::CommandTracker,
+  events::{self, BridgeEvent},
+  metrics::ENGINE_METRICS,
+  options::{self, BridgeEngineOptions, WebsocketIpMode},
+  ramp::RampLimiter,
+  scan_timeout, server_ready, session_recording,
+  traffic_capture::{self, CaptureDirection},
+  websocket_devices,
+};
+use anyhow::Result;
+use futures::{
+  future::{self, select_all},
+  Sink, SinkExt, Stream, StreamExt,
+};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+  io,
+  net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener as StdTcpListener},
+  pin::Pin,
+  sync::Arc,
+  task::{Context, Poll},
+  time::{Duration, Instant},
+};
+use tokio::{
+  io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+  net::{TcpListener, TcpStream},
+  select,
+  sync::Notify,
+};
+use tokio_tungstenite::{
+  accept_async, connect_async,
+  tungstenite::{Error as WsError, Message},
+  WebSocketStream,
+};
+
+// The engine's websocket servers can only listen on IPv4 localhost or everywhere, and will take
+// anything that connects. When we need more than that (a token check, a specific address, IPv6), the
+// engine gets moved to a localhost port only we know about, and we sit on the public port instead,
+// passing connections through once they've been checked. Client connections are passed through a
+// message at a time, so we can see who the client is and apply its device permissions, curves and
+// ramps, and so more than one client can share the engine (see client_sessions). The device server
+// is always fronted, so we can keep track of what's connected to it (see websocket_devices).
+//
+// Browsers can't set headers on websocket connections, so the token can be passed either as a
+// "token" query parameter (ws://host:port/?token=...) or as an "Authorization: Bearer ..." header.
+
+// Upgrade requests are tiny, anything bigger than this isn't a client we want to talk to. The metrics
+// endpoint uses the same limits.
+const MAX_REQUEST_HEADER_SIZE: usize = 8192;
+pub(crate) const REQUEST_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+// How often relays check whether they need to keep their client alive.
+const KEEP_ALIVE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// How often proxied clients get a websocket ping, to measure the round trip to them.
+const LATENCY_PING_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ListeningServer {
+  Websocket,
+  SecureWebsocket,
+  DeviceWebsocket,
+  SecureDeviceWebsocket,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenAddress {
+  pub ip: IpAddr,
+  // Only matters for the unspecified address, where it decides whether IPv4 clients get in too.
+  pub v6_only: bool,
+}
+
+impl ListenAddress {
+  fn new(ip: impl Into<IpAddr>, v6_only: bool) -> Self {
+    Self {
+      ip: ip.into(),
+      v6_only,
+    }
+  }
+}
+
+// What we're fronting for this engine run. Ports are (public, engine).
+#[derive(Debug, Clone)]
+pub struct WebsocketProxyPlan {
+  pub addresses: Vec<ListenAddress>,
+  pub token: Option<String>,
+  pub websocket: Option<(u16, u16)>,
+  pub device_websocket: Option<(u16, u16)>,
+  // Where the engine's client websocket server ended up, for TLS to pass connections to.
+  pub engine_websocket_port: Option<u16>,
+  // Same for the device websocket server.
+  pub engine_device_websocket_port: Option<u16>,
+  // Servers the engine is listening on by itself, so we can report them along with ours.
+  engine_listening: Vec<(ListeningServer, SocketAddr)>,
+}
+
+// Works out whether the engine can serve websockets how we've been asked to by itself, and if not,
+// moves its servers to internal ports in args, so we can take their place.
+pub fn plan(
+  options: &BridgeEngineOptions,
+  args: &mut EngineOptionsExternal,
+) -> Result<WebsocketProxyPlan> {
+  let token = options
+    .websocket_auth_token
+    .clone()
+    .filter(|x| !x.is_empty());
+  let bind_address: Option<IpAddr> = options
+    .websocket_bind_address
+    .as_deref()
+    .map(|x| {
+      x.trim()
+        .parse()
+        .map_err(|_| anyhow::Error::msg(format!("Invalid websocket bind address {}", x)))
+    })
+    .transpose()?;
+  let addresses = listen_addresses(
+    options.websocket_ip_mode,
+    args.websocket_use_all_interfaces,
+    bind_address,
+  );
+  let mut plan = WebsocketProxyPlan {
+    addresses,
+    token,
+    websocket: None,
+    device_websocket: None,
+    engine_websocket_port: args.websocket_port,
+    engine_device_websocket_port: None,
+    engine_listening: vec![],
+  };
+  // The device server always goes through us. If nothing below moves the engine to localhost, its
+  // device server stays on the same interfaces, just on a new port, and we take over the old one.
+  if args.use_device_websocket_server {
+    let public_port = args.device_websocket_server_port.ok_or(anyhow::Error::msg(
+      "Device websocket server port needs to be set when using the device websocket server",
+    ))?;
+    let engine_port = internal_port()?;
+    args.device_websocket_server_port = Some(engine_port);
+    plan.device_websocket = Some((public_port, engine_port));
+    plan.engine_device_websocket_port = Some(engine_port);
+  }
+  let proxied = plan.token.is_some()
+    || bind_address.is_some()
+    || options.websocket_ip_mode != WebsocketIpMode::Ipv4
+    || client_permissions::has_permissions()
+    // Only checked at startup, curves and ramps set up later apply to clients from the next engine
+    // run.
+    || device_settings::has_command_filters()
+    || options.max_spec_version.is_some()
+    || options.client_sessions.multiple_clients;
+  if !proxied {
+    let engine_ip: IpAddr = if args.websocket_use_all_interfaces {
+      Ipv4Addr::UNSPECIFIED.into()
+    } else {
+      Ipv4Addr::LOCALHOST.into()
+    };
+    if let Some(port) = args.websocket_port {
+      plan
+        .engine_listening
+        .push((ListeningServer::Websocket, SocketAddr::new(engine_ip, port)));
+    }
+    return Ok(plan);
+  }
+  if let Some(public_port) = args.websocket_port {
+    let engine_port = internal_port()?;
+    args.websocket_port = Some(engine_port);
+    plan.websocket = Some((public_port, engine_port));
+  }
+  args.websocket_use_all_interfaces = false;
+  plan.engine_websocket_port = args.websocket_port;
+  Ok(plan)
+}
+
+impl WebsocketProxyPlan {
+  pub fn engine_listening(&self) -> Vec<(ListeningServer, SocketAddr)> {
+    self.engine_listening.clone()
+  }
+
+  // Every server this run will listen on, whether it's us or the engine doing it.
+  pub fn servers(&self, options: &BridgeEngineOptions) -> Vec<ListeningServer> {
+    let mut servers: Vec<ListeningServer> = self
+      .engine_listening
+      .iter()
+      .map(|(server, _)| *server)
+      .collect();
+    if self.websocket.is_some() {
+      servers.push(ListeningServer::Websocket);
+    }
+    if self.device_websocket.is_some() {
+      servers.push(ListeningServer::DeviceWebsocket);
+    }
+    if options.websocket_tls.is_some() && self.engine_websocket_port.is_some() {
+      servers.push(ListeningServer::SecureWebsocket);
+    }
+    if options.device_websocket_tls.is_some() && self.engine_device_websocket_port.is_some() {
+      servers.push(ListeningServer::SecureDeviceWebsocket);
+    }
+    servers
+  }
+}
+
+pub async fn run_proxies(plan: WebsocketProxyPlan, stop: Arc<Notify>) {
+  let websocket = async {
+    if let Some((public_port, engine_port)) = plan.websocket {
+      run_proxy(
+        ListeningServer::Websocket,
+        &plan.addresses,
+        public_port,
+        engine_port,
+        plan.token.clone(),
+        stop.clone(),
+      )
+      .await;
+    }
+  };
+  // Devices have no way of passing a token, so they don't get checked.
+  let device_websocket = async {
+    if let Some((public_port, engine_port)) = plan.device_websocket {
+      run_proxy(
+        ListeningServer::DeviceWebsocket,
+        &plan.addresses,
+        public_port,
+        engine_port,
+        None,
+        stop.clone(),
+      )
+      .await;
+    }
+  };
+  tokio::join!(websocket, device_websocket);
+}
+
+// Grabs a free localhost port for the engine to listen on instead of the public one.
+pub fn internal_port() -> Result<u16> {
+  Ok(StdTcpListener::bind(("127.0.0.1", 0))?.local_addr()?.port())
+}
+
+pub fn listen_addresses(
+  mode: WebsocketIpMode,
+  use_all_interfaces: bool,
+  bind_address: Option<IpAddr>,
+) -> Vec<ListenAddress> {
+  if let Some(ip) = bind_address {
+    return vec![ListenAddress::new(ip, mode == WebsocketIpMode::Ipv6)];
+  }
+  match (mode, use_all_interfaces) {
+    (WebsocketIpMode::Ipv4, true) => vec![ListenAddress::new(Ipv4Addr::UNSPECIFIED, false)],
+    (WebsocketIpMode::Ipv4, false) => vec![ListenAddress::new(Ipv4Addr::LOCALHOST, false)],
+    (WebsocketIpMode::DualStack, true) => vec![ListenAddress::new(Ipv6Addr::UNSPECIFIED, false)],
+    // There's no dual stack localhost, so that's two listeners.
+    (WebsocketIpMode::DualStack, false) => vec![
+      ListenAddress::new(Ipv4Addr::LOCALHOST, false),
+      ListenAddress::new(Ipv6Addr::LOCALHOST, true),
+    ],
+    (WebsocketIpMode::Ipv6, true) => vec![ListenAddress::new(Ipv6Addr::UNSPECIFIED, true)],
+    (WebsocketIpMode::Ipv6, false) => vec![ListenAddress::new(Ipv6Addr::LOCALHOST, true)],
+  }
+}
+
+// tokio's bind doesn't let us choose whether IPv6 sockets take IPv4 clients (and the OS defaults
+// differ), so build the socket ourselves.
+fn bind_listener(address: ListenAddress, port: u16) -> io::Result<TcpListener> {
+  let socket_address = SocketAddr::new(address.ip, port);
+  let socket = Socket::new(
+    Domain::for_address(socket_address),
+    Type::STREAM,
+    Some(Protocol::TCP),
+  )?;
+  if address.ip.is_ipv6() {
+    socket.set_only_v6(address.v6_only)?;
+  }
+  // Same as tokio does, so restarting the engine doesn't trip over connections in TIME_WAIT.
+  #[cfg(not(windows))]
+  socket.set_reuse_address(true)?;
+  socket.set_nonblocking(true)?;
+  socket.bind(&socket_address.into())?;
+  socket.listen(1024)?;
+  TcpListener::from_std(socket.into())
+}
+
+// Binds everything it can, logging whatever it can't, and lets the frontend know where we ended up
+// listening.
+pub fn bind_listeners(
+  server: ListeningServer,
+  addresses: &[ListenAddress],
+  port: u16,
+) -> Vec<TcpListener> {
+  let listeners: Vec<TcpListener> = addresses
+    .iter()
+    .filter_map(|address| match bind_listener(*address, port) {
+      Ok(listener) => Some(listener),
+      Err(e) => {
+        error!(
+          "Cannot bind {:?} server to {}: {:?}",
+          server,
+          SocketAddr::new(address.ip, port),
+          e
+        );
+        None
+      }
+    })
+    .collect();
+  report_listening(
+    server,
+    listeners
+      .iter()
+      .filter_map(|x| x.local_addr().ok())
+      .collect(),
+  );
+  listeners
+}
+
+pub fn report_listening(server: ListeningServer, addresses: Vec<SocketAddr>) {
+  if addresses.is_empty() {
+    return;
+  }
+  info!("{:?} server listening on {:?}", server, addresses);
+  events::emit(BridgeEvent::ServerListening {
+    server,
+    addresses: addresses.iter().map(|x| x.to_string()).collect(),
+  });
+  server_ready::server_listening(server, &addresses);
+}
+
+// Cancel safe, so it can be used as a select! branch. Never returns if there are no listeners.
+pub async fn accept_any(listeners: &[TcpListener]) -> io::Result<(TcpStream, SocketAddr)> {
+  if listeners.is_empty() {
+    return futures::future::pending().await;
+  }
+  select_all(listeners.iter().map(|x| Box::pin(x.accept())))
+    .await
+    .0
+}
+
+fn request_token(request: &str) -> Option<String> {
+  let mut lines = request.lines();
+  let target = lines.next()?.split_whitespace().nth(1)?;
+  let query_token = target
+    .split_once('?')
+    .and_then(|(_, query)| query.split('&').find_map(|x| x.strip_prefix("token=")));
+  if let Some(token) = query_token {
+    return percent_decode(token);
+  }
+  lines.find_map(|line| {
+    let (name, value) = line.split_once(':')?;
+    if !name.trim().eq_ignore_ascii_case("authorization") {
+      return None;
+    }
+    value
+      .trim()
+      .strip_prefix("Bearer ")
+      .map(|x| x.trim().to_owned())
+  })
+}
+
+// Decodes a query string value, so tokens with characters clients escape (like + and / in base64)
+// still match. None if the escapes are malformed or don't decode to UTF-8.
+fn percent_decode(value: &str) -> Option<String> {
+  let mut bytes = Vec::with_capacity(value.len());
+  let mut input = value.bytes();
+  while let Some(byte) = input.next() {
+    match byte {
+      b'%' => {
+        let high = (input.next()? as char).to_digit(16)?;
+        let low = (input.next()? as char).to_digit(16)?;
+        bytes.push((high * 16 + low) as u8);
+      }
+      b'+' => bytes.push(b' '),
+      _ => bytes.push(byte),
+    }
+  }
+  String::from_utf8(bytes).ok()
+}
+
+// Compares digests rather than the strings themselves, so the time taken doesn't leak how much of
+// the token was right.
+fn token_matches(given: &str, expected: &str) -> bool {
+  Sha256::digest(given.as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+pub(crate) async fn read_request_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+  let mut buf = Vec::with_capacity(1024);
+  let mut chunk = [0u8; 1024];
+  while !buf.windows(4).any(|x| x == b"\r\n\r\n") {
+    if buf.len() > MAX_REQUEST_HEADER_SIZE {
+      return Err(anyhow::Error::msg("Request header too large"));
+    }
+    let read = stream.read(&mut chunk).await?;
+    if read == 0 {
+      return Err(anyhow::Error::msg(
+        "Connection closed before request finished",
+      ));
+    }
+    buf.extend_from_slice(&chunk[..read]);
+  }
+  Ok(buf)
+}
+
+pub enum TokenCheck {
+  // Request that was read, to pass on.
+  Passed(Vec<u8>),
+  // Peer has already been told.
+  Rejected(&'static str),
+  // Couldn't read a request at all.
+  Failed,
+}
+
+// Reads a websocket upgrade request and checks it has the token.
+pub async fn check_request_token<S: AsyncRead + AsyncWrite + Unpin>(
+  stream: &mut S,
+  peer: SocketAddr,
+  token: &str,
+) -> TokenCheck {
+  let request =
+    match tokio::time::timeout(REQUEST_HEADER_TIMEOUT, read_request_header(&mut *stream)).await {
+      Ok(Ok(request)) => request,
+      Ok(Err(e)) => {
+        debug!("Cannot read websocket request from {}: {:?}", peer, e);
+        return TokenCheck::Failed;
+      }
+      Err(_) => {
+        debug!("Timed out reading websocket request from {}", peer);
+        return TokenCheck::Failed;
+      }
+    };
+  let reason = match request_token(&String::from_utf8_lossy(&request)) {
+    Some(given) if token_matches(&given, token) => return TokenCheck::Passed(request),
+    Some(_) => "Invalid token",
+    None => "Missing token",
+  };
+  let _ = stream
+    .write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+    .await;
+  TokenCheck::Rejected(reason)
+}
+
+// Passes a client connection through to the engine's websocket server, checking its token first if
+// one is required.
+pub async fn proxy_connection<S: AsyncRead + AsyncWrite + Unpin>(
+  server: ListeningServer,
+  mut stream: S,
+  peer: SocketAddr,
+  engine_port: u16,
+  token: Option<&str>,
+) {
+  let mut request = vec![];
+  if let Some(token) = token {
+    request = match check_request_token(&mut stream, peer, token).await {
+      TokenCheck::Passed(request) => request,
+      TokenCheck::Rejected(reason) => {
+        info!("Rejected websocket connection from {}: {}", peer, reason);
+        events::emit(BridgeEvent::WebsocketConnectionRejected {
+          address: peer.to_string(),
+          reason: reason.to_owned(),
+        });
+        return;
+      }
+      TokenCheck::Failed => return,
+    };
+  }
+  let stream = PrefixedStream {
+    prefix: request,
+    position: 0,
+    inner: stream,
+  };
+  if matches!(
+    server,
+    ListeningServer::DeviceWebsocket | ListeningServer::SecureDeviceWebsocket
+  ) {
+    let secure = server == ListeningServer::SecureDeviceWebsocket;
+    websocket_devices::relay_device(stream, peer, engine_port, secure).await;
+    return;
+  }
+  let (connection_id, close) = clients::register_connection();
+  select! {
+    result = relay_client(stream, peer, engine_port, connection_id) => {
+      if let Err(e) = result {
+        debug!("Proxied connection from {} ended: {:?}", peer, e);
+      }
+    }
+    _ = close.notified() => debug!("Closing proxied connection from {}", peer),
+  }
+  clients::unregister_connection(connection_id);
+}
+
+fn parse_messages(msg: &Message) -> Option<Vec<Value>> {
+  match msg {
+    Message::Text(text) => serde_json::from_str(text.as_str()).ok(),
+    _ => None,
+  }
+}
+
+fn is_bridge_reply(message: &Value) -> bool {
+  message
+    .as_object()
+    .and_then(|x| x.values().next())
+    .and_then(|x| x.get("Id"))
+    .and_then(|x| x.as_u64())
+    .is_some_and(|id| id >= backdoor::BRIDGE_MESSAGE_ID_START as u64)
+}
+
+fn messages_to_ws(messages: Vec<Value>) -> Message {
+  Message::text(Value::Array(messages).to_string())
+}
+
+// Ramps and curves, same as backdoor commands get. Each connection ramps on its own, so one client's
+// commands don't hold up another's.
+fn apply_command_filters(messages: Vec<Value>, ramps: &mut RampLimiter) -> Vec<Value> {
+  let Ok(msg) = serde_json::to_string(&messages) else {
+    return messages;
+  };
+  let msg = backdoor::apply_command_filters(msg, ramps, clock::now());
+  serde_json::from_str(&msg).unwrap_or(messages)
+}
+
+async fn relay_client<S: AsyncRead + AsyncWrite + Unpin>(
+  stream: S,
+  peer: SocketAddr,
+  engine_port: u16,
+  connection_id: u32,
+) -> Result<()> {
+  let client_ws = accept_async(stream).await?;
+  if options::bridge_options().client_sessions.multiple_clients {
+    let (engine_sink, engine_stream) = client_sessions::join(engine_port, connection_id).await?;
+    return relay(client_ws, engine_sink, engine_stream, peer, connection_id).await;
+  }
+  let (engine_ws, _) = connect_async(format!("ws://127.0.0.1:{}", engine_port)).await?;
+  let (engine_sink, engine_stream) = engine_ws.split();
+  let engine_sink = engine_sink.with(|x: RelayMessage| future::ok::<Message, WsError>(x.into()));
+  relay(client_ws, engine_sink, engine_stream, peer, connection_id).await
+}
+
+// Passes messages between a client and its connection to the engine, which is either its own, or
+// its end of the one client_sessions shares.
+async fn relay<S, E, R>(
+  client_ws: WebSocketStream<S>,
+  mut engine_sink: E,
+  mut engine_stream: R,
+  peer: SocketAddr,
+  connection_id: u32,
+) -> Result<()>
+where
+  S: AsyncRead + AsyncWrite + Unpin,
+  E: Sink<RelayMessage, Error = WsError> + Unpin,
+  R: Stream<Item = Result<Message, WsError>> + Unpin,
+{
+  let (mut client_sink, mut client_stream) = client_ws.split();
+  let mut filter = ClientFilter::default();
+  let mut commands = CommandTracker::default();
+  let mut ramps = RampLimiter::default();
+  let mut ramp_ticker = clock::Ticker::new(backdoor::RAMP_STEP_INTERVAL);
+  let mut client_name: Option<String> = None;
+  let mut keep_alive = clock::Ticker::new(KEEP_ALIVE_CHECK_INTERVAL);
+  let mut last_keep_alive = clock::now();
+  let mut latency_ping = clock::Ticker::new(LATENCY_PING_INTERVAL);
+  // Payload and send time of the ping we're waiting on a pong for. Measured in real time, as it's
+  // the network we're timing.
+  let mut pending_ping: Option<(Vec<u8>, Instant)> = None;
+  let mut ping_count: u32 = 0;
+  loop {
+    select! {
+      _ = latency_ping.tick() => {
+        ping_count = ping_count.wrapping_add(1);
+        let payload = ping_count.to_be_bytes().to_vec();
+        pending_ping = Some((payload.clone(), Instant::now()));
+        client_sink.send(Message::Ping(payload.into())).await?;
+      }
+      _ = ramp_ticker.tick() => {
+        // Bridge ids, so the replies are dropped on the way back.
+        for msg in ramps.step(clock::now()) {
+          let msg = actuator_curve::shape_scalar_commands(
+            &msg,
+            device_settings::curve_shape_for_scalar,
+          )
+          .unwrap_or(msg);
+          engine_sink.send(RelayMessage::Relay(Message::text(msg))).await?;
+        }
+      }
+      _ = keep_alive.tick() => {
+        // Pings on the client's behalf while the app is throttled (see app_lifecycle). Replies have
+        // bridge ids, so they're dropped on the way back.
+        if let Some(interval) = app_lifecycle::keep_alive_interval() {
+          if clock::now().saturating_duration_since(last_keep_alive) >= interval {
+            last_keep_alive = clock::now();
+            let msg = Message::text(app_lifecycle::ping_message().to_string());
+            engine_sink.send(RelayMessage::Relay(msg)).await?;
+          }
+        }
+      }
+      msg = client_stream.next() => {
+        let Some(msg) = msg else {
+          break;
+        };
+        let msg = msg?;
+        // Pongs to our own pings stop here. Anything else goes on to the engine.
+        if let Message::Pong(payload) = &msg {
+          if let Some((_, sent_at)) = pending_ping
+            .take_if(|(expected, _)| expected.as_slice() == payload.as_ref())
+          {
+            clients::record_round_trip(connection_id, sent_at.elapsed());
+            continue;
+          }
+        }
+        let closing = matches!(msg, Message::Close(_));
+        match parse_messages(&msg) {
+          Some(messages) => {
+            ENGINE_METRICS.record_client_messages(messages.len());
+            clients::record_message(connection_id);
+            if let Some((name, spec_version)) = clients::parse_handshake(&messages) {
+              if let Some(rejection) = clients::spec_version_rejection(&messages, spec_version) {
+                info!("Turning away client {} asking for spec version {}", name, spec_version);
+                events::emit(BridgeEvent::ClientSpecVersionRejected {
+                  name,
+                  address: peer.to_string(),
+                  spec_version,
+                  max_spec_version: options::bridge_options().max_spec_version.unwrap_or_default(),
+                });
+                client_sink.send(messages_to_ws(vec![rejection])).await?;
+                continue;
+              }
+              filter.set_client_name(&name);
+              client_name = Some(name.clone());
+              clients::record_handshake(name, spec_version, peer, connection_id);
+            }
+            traffic_capture::record(
+              CaptureDirection::ClientToServer,
+              client_name.as_deref(),
+              &messages,
+            );
+            let (allowed, mut rejected) = filter.filter_from_client(messages);
+            // Before ramps get a look, so commands for devices another client has don't leave
+            // anything behind for the ramp to send later.
+            let (allowed, refused) = client_sessions::arbitrate(connection_id, allowed);
+            rejected.extend(refused);
+            let allowed = apply_command_filters(allowed, &mut ramps);
+            if !allowed.is_empty() {
+              commands.track_sent(&allowed);
+              scan_timeout::track_client_messages(&allowed);
+              session_recording::record(&allowed);
+              engine_sink.send(RelayMessage::Client(messages_to_ws(allowed))).await?;
+            }
+            if !rejected.is_empty() {
+              traffic_capture::record(
+                CaptureDirection::ServerToClient,
+                client_name.as_deref(),
+                &rejected,
+              );
+              client_sink.send(messages_to_ws(rejected)).await?;
+            }
+          }
+          None => engine_sink.send(RelayMessage::Client(msg)).await?,
+        }
+        if closing {
+          break;
+        }
+      }
+      msg = engine_stream.next() => {
+        let Some(msg) = msg else {
+          break;
+        };
+        let msg = msg?;
+        let closing = matches!(msg, Message::Close(_));
+        match parse_messages(&msg) {
+          Some(messages) => {
+            let mut messages: Vec<Value> = messages
+              .into_iter()
+              .filter(|x| !is_bridge_reply(x))
+              .collect();
+            if messages.is_empty() {
+              continue;
+            }
+            ENGINE_METRICS.record_client_messages(messages.len());
+            clients::cap_server_info(&mut messages);
+            commands.track_replies(&messages);
+            let allowed = filter.filter_from_engine(messages).await;
+            if !allowed.is_empty() {
+              traffic_capture::record(
+                CaptureDirection::ServerToClient,
+                client_name.as_deref(),
+                &allowed,
+              );
+              client_sink.send(messages_to_ws(allowed)).await?;
+            }
+          }
+          None => client_sink.send(msg).await?,
+        }
+        if closing {
+          break;
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+// Client stream with whatever we already read off it (while checking the token) put back in front,
+// so the websocket handshake sees the whole request.
+struct PrefixedStream<S> {
+  prefix: Vec<u8>,
+  position: usize,
+  inner: S,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PrefixedStream<S> {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    if self.position < self.prefix.len() {
+      let remaining = &self.prefix[self.position..];
+      let count = remaining.len().min(buf.remaining());
+      buf.put_slice(&remaining[..count]);
+      self.position += count;
+      return Poll::Ready(Ok(()));
+    }
+    Pin::new(&mut self.inner).poll_read(cx, buf)
+  }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PrefixedStream<S> {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<io::Result<usize>> {
+    Pin::new(&mut self.inner).poll_write(cx, buf)
+  }
+
+  fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_flush(cx)
+  }
+
+  fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_shutdown(cx)
+  }
+}
+
+async fn run_proxy(
+  server: ListeningServer,
+  addresses: &[ListenAddress],
+  public_port: u16,
+  engine_port: u16,
+  token: Option<String>,
+  stop: Arc<Notify>,
+) {
+  let listeners = bind_listeners(server, addresses, public_port);
+  if listeners.is_empty() {
+    return;
+  }
+  let token = Arc::new(token);
+  loop {
+    select! {
+      conn = accept_any(&listeners) => {
+        let Ok((stream, peer)) = conn else {
+          continue;
+        };
+        let token = token.clone();
+        tokio::spawn(async move {
+          proxy_connection(server, stream, peer, engine_port, token.as_deref()).await;
+        });
+      }
+      _ = stop.notified() => break
+    }
+  }
+  info!("{:?} proxy shut down", server);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn query_tokens_are_percent_decoded() {
+    let request = "GET /?token=a%2Bb%2Fc%3D%3D HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let given = request_token(request).unwrap();
+    assert_eq!(given, "a+b/c==");
+    assert!(token_matches(&given, "a+b/c=="));
+  }
+
+  #[test]
+  fn malformed_query_tokens_are_rejected() {
+    let request = "GET /?token=abc%2 HTTP/1.1\r\n\r\n";
+    assert_eq!(request_token(request), None);
+  }
+
+  #[test]
+  fn bearer_tokens_are_used_as_is() {
+    let request = "GET / HTTP/1.1\r\nAuthorization: Bearer a%2Bb\r\n\r\n";
+    assert_eq!(request_token(request).as_deref(), Some("a%2Bb"));
+  }
+}
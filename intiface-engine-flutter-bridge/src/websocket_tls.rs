@@ -0,0 +1,189 @@
+use crate::{
+  options::WebsocketTlsOptions,
+  websocket_proxy::{self, ListenAddress, ListeningServer},
+};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+use tokio::{select, sync::Notify};
+use tokio_rustls::{
+  rustls::{
+    crypto::ring::default_provider,
+    pki_types::{CertificateDer, PrivateKeyDer},
+    ServerConfig,
+  },
+  TlsAcceptor,
+};
+
+// Intiface Engine only serves plain websockets, which browsers won't let pages served over https
+// connect to (unless it's localhost, and even then not all of them). So for wss, we sit in front of
+// the engine's websocket server, terminate TLS, and pass the bytes through to it untouched. Clients
+// connect to the TLS port, and the engine just sees connections coming from localhost. Token checks,
+// if they're on, happen after the handshake, same as for plain connections. The device websocket
+// server can get a TLS front the same way, for devices that will only connect over wss.
+
+#[derive(Debug, Clone)]
+pub struct TlsCertificate {
+  pub certificate_pem: String,
+  pub private_key_pem: String,
+  // SHA-256 of the DER encoded certificate, as colon separated hex, so users can check it against
+  // what their browser shows when accepting a self-signed certificate.
+  pub fingerprint: String,
+}
+
+// Certificate a running proxy is using.
+#[derive(Clone)]
+struct ActiveCertificate {
+  fingerprint: String,
+  private_key_pem: String,
+}
+
+lazy_static! {
+  static ref ACTIVE_CERTIFICATES: Mutex<HashMap<ListeningServer, ActiveCertificate>> =
+    Mutex::new(HashMap::new());
+}
+
+fn fingerprint(certificate_der: &[u8]) -> String {
+  Sha256::digest(certificate_der)
+    .iter()
+    .map(|x| format!("{:02X}", x))
+    .collect::<Vec<String>>()
+    .join(":")
+}
+
+// Certificate good for localhost. Frontends should hang on to this and pass it back in the options,
+// otherwise users will need to accept a new certificate every time the engine starts.
+pub fn generate_self_signed() -> Result<TlsCertificate> {
+  let certified = rcgen::generate_simple_self_signed(vec![
+    "localhost".to_owned(),
+    "127.0.0.1".to_owned(),
+    "::1".to_owned(),
+  ])?;
+  Ok(TlsCertificate {
+    certificate_pem: certified.cert.pem(),
+    private_key_pem: certified.key_pair.serialize_pem(),
+    fingerprint: fingerprint(certified.cert.der()),
+  })
+}
+
+pub fn active_fingerprint(server: ListeningServer) -> Option<String> {
+  ACTIVE_CERTIFICATES
+    .lock()
+    .unwrap()
+    .get(&server)
+    .map(|x| x.fingerprint.clone())
+}
+
+// For signing things that clients can then tie to the certificate (see connection_payload).
+pub fn active_private_key_pem(server: ListeningServer) -> Option<String> {
+  ACTIVE_CERTIFICATES
+    .lock()
+    .unwrap()
+    .get(&server)
+    .map(|x| x.private_key_pem.clone())
+}
+
+fn server_config(options: &WebsocketTlsOptions) -> Result<(ServerConfig, ActiveCertificate)> {
+  let (certificate_pem, private_key_pem) =
+    match (&options.certificate_pem, &options.private_key_pem) {
+      (Some(certificate_pem), Some(private_key_pem)) => {
+        (certificate_pem.clone(), private_key_pem.clone())
+      }
+      _ => {
+        info!("No TLS certificate given, generating a self-signed one for this run");
+        let generated = generate_self_signed()?;
+        (generated.certificate_pem, generated.private_key_pem)
+      }
+    };
+  let certificates = rustls_pemfile::certs(&mut certificate_pem.as_bytes())
+    .collect::<Result<Vec<CertificateDer<'static>>, _>>()?;
+  let leaf_fingerprint = fingerprint(certificates.first().ok_or(anyhow::Error::msg(
+    "No certificates found in certificate PEM",
+  ))?);
+  let private_key: PrivateKeyDer<'static> =
+    rustls_pemfile::private_key(&mut private_key_pem.as_bytes())?.ok_or(anyhow::Error::msg(
+      "No private key found in private key PEM",
+    ))?;
+  // Use ring explicitly rather than the process default, since what that is depends on what else
+  // has pulled in rustls.
+  let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(certificates, private_key)?;
+  Ok((
+    config,
+    ActiveCertificate {
+      fingerprint: leaf_fingerprint,
+      private_key_pem,
+    },
+  ))
+}
+
+// Server is either SecureWebsocket or SecureDeviceWebsocket, and decides what the engine port is for.
+pub async fn run_tls_proxy(
+  server: ListeningServer,
+  options: WebsocketTlsOptions,
+  addresses: Vec<ListenAddress>,
+  engine_port: u16,
+  token: Option<String>,
+  stop: Arc<Notify>,
+) {
+  let (config, certificate) = match server_config(&options) {
+    Ok(config) => config,
+    Err(e) => {
+      error!("Cannot set up TLS for {:?} server: {:?}", server, e);
+      return;
+    }
+  };
+  let listeners = websocket_proxy::bind_listeners(server, &addresses, options.port);
+  if listeners.is_empty() {
+    return;
+  }
+  info!(
+    "{:?} certificate fingerprint {}",
+    server, certificate.fingerprint
+  );
+  ACTIVE_CERTIFICATES
+    .lock()
+    .unwrap()
+    .insert(server, certificate);
+  let acceptor = TlsAcceptor::from(Arc::new(config));
+  let token = Arc::new(token);
+  loop {
+    select! {
+      conn = websocket_proxy::accept_any(&listeners) => {
+        let Ok((stream, peer)) = conn else {
+          continue;
+        };
+        let acceptor = acceptor.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+          let tls_stream = match acceptor.accept(stream).await {
+            Ok(tls_stream) => tls_stream,
+            Err(e) => {
+              // Browsers that haven't accepted our certificate yet will hang up here, so not an
+              // error worth shouting about.
+              debug!("TLS handshake with {} failed: {:?}", peer, e);
+              return;
+            }
+          };
+          websocket_proxy::proxy_connection(
+            server,
+            tls_stream,
+            peer,
+            engine_port,
+            token.as_deref(),
+          )
+          .await;
+        });
+      }
+      _ = stop.notified() => break
+    }
+  }
+  ACTIVE_CERTIFICATES.lock().unwrap().remove(&server);
+  info!("{:?} server shut down", server);
+}
@@ -0,0 +1,147 @@
+use crate::{
+  api::{
+    set_feature_step_limit, update_customization, ExposedUserDeviceIdentifier,
+    DEVICE_CONFIG_MANAGER,
+  },
+  devices,
+};
+use anyhow::Result;
+use buttplug::server::device::configuration::{UserDeviceDefinition, UserDeviceIdentifier};
+use serde_json::json;
+
+// Per-slot settings for XInput controllers. The engine picks up every connected controller slot as a
+// device when use_xinput is on, and has no settings of its own for them, so these are done through
+// each controller's user config: disabled slots get denied, and motors that shouldn't rumble get a
+// step limit of zero. Controllers show up under the xinput protocol, with the slot number at the end
+// of their address. Like any other user config setting, a slot's controller has to have connected
+// at least once before it can be configured.
+
+const XINPUT_PROTOCOL: &str = "xinput";
+const XINPUT_SLOTS: u32 = 4;
+// XInput devices have the large, low frequency motor first, then the small high frequency one.
+const LOW_FREQUENCY_MOTOR: u32 = 0;
+const HIGH_FREQUENCY_MOTOR: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XInputMotors {
+  Both,
+  LowFrequencyOnly,
+  HighFrequencyOnly,
+}
+
+#[derive(Debug, Clone)]
+pub struct XInputSlot {
+  // 0-3
+  pub slot: u32,
+  // None if nothing has connected in this slot yet.
+  pub identifier: Option<ExposedUserDeviceIdentifier>,
+  pub connected: bool,
+  pub enabled: bool,
+  pub motors: XInputMotors,
+}
+
+fn slot_number(identifier: &UserDeviceIdentifier) -> Option<u32> {
+  if identifier.protocol() != XINPUT_PROTOCOL {
+    return None;
+  }
+  let slot = identifier.address().chars().last()?.to_digit(10)?;
+  (slot < XINPUT_SLOTS).then_some(slot)
+}
+
+fn motor_enabled(definition: &UserDeviceDefinition, motor: u32) -> bool {
+  definition
+    .features()
+    .get(motor as usize)
+    .and_then(|x| x.actuator().clone())
+    .is_none_or(|x| x.step_limit().end() > x.step_limit().start())
+}
+
+fn slot_definition(slot: u32) -> Result<(UserDeviceIdentifier, UserDeviceDefinition)> {
+  if slot >= XINPUT_SLOTS {
+    return Err(anyhow::Error::msg(format!(
+      "XInput slot must be between 0 and {}",
+      XINPUT_SLOTS - 1
+    )));
+  }
+  DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .user_device_definitions()
+    .iter()
+    .find(|x| slot_number(x.key()) == Some(slot))
+    .map(|x| (x.key().clone(), x.value().clone()))
+    .ok_or(anyhow::Error::msg(
+      "No user configuration for this slot, a controller must connect in it at least once.",
+    ))
+}
+
+pub fn slots() -> Vec<XInputSlot> {
+  let configured: Vec<(UserDeviceIdentifier, UserDeviceDefinition)> = DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .user_device_definitions()
+    .iter()
+    .filter(|x| slot_number(x.key()).is_some())
+    .map(|x| (x.key().clone(), x.value().clone()))
+    .collect();
+  (0..XINPUT_SLOTS)
+    .map(|slot| {
+      let Some((identifier, definition)) = configured
+        .iter()
+        .find(|(identifier, _)| slot_number(identifier) == Some(slot))
+      else {
+        return XInputSlot {
+          slot,
+          identifier: None,
+          connected: false,
+          enabled: true,
+          motors: XInputMotors::Both,
+        };
+      };
+      let identifier: ExposedUserDeviceIdentifier = identifier.clone().into();
+      let motors = match (
+        motor_enabled(definition, LOW_FREQUENCY_MOTOR),
+        motor_enabled(definition, HIGH_FREQUENCY_MOTOR),
+      ) {
+        (true, false) => XInputMotors::LowFrequencyOnly,
+        (false, true) => XInputMotors::HighFrequencyOnly,
+        _ => XInputMotors::Both,
+      };
+      XInputSlot {
+        slot,
+        connected: devices::device_index(&identifier).is_some(),
+        identifier: Some(identifier),
+        enabled: !definition.user_config().deny(),
+        motors,
+      }
+    })
+    .collect()
+}
+
+// Disabling a slot keeps the engine from connecting whatever controller is in it. Takes effect the
+// next time the controller connects.
+pub fn set_enabled(slot: u32, enabled: bool) -> Result<()> {
+  let (identifier, definition) = slot_definition(slot)?;
+  let customization = update_customization(definition.user_config(), "deny", json!(!enabled))?;
+  DEVICE_CONFIG_MANAGER
+    .try_read()
+    .expect("We should have a reader at this point")
+    .add_user_device_definition(
+      &identifier,
+      &UserDeviceDefinition::new(definition.name(), definition.features(), &customization),
+    )
+    .map_err(|e| anyhow::Error::msg(format!("{:?}", e)))
+}
+
+pub fn set_motors(slot: u32, motors: XInputMotors) -> Result<()> {
+  let (identifier, _) = slot_definition(slot)?;
+  let identifier: ExposedUserDeviceIdentifier = identifier.into();
+  let off = Some((0.0, 0.0));
+  let (low, high) = match motors {
+    XInputMotors::Both => (None, None),
+    XInputMotors::LowFrequencyOnly => (None, off),
+    XInputMotors::HighFrequencyOnly => (off, None),
+  };
+  set_feature_step_limit(&identifier, LOW_FREQUENCY_MOTOR, low)?;
+  set_feature_step_limit(&identifier, HIGH_FREQUENCY_MOTOR, high)
+}
@@ -72,7 +72,13 @@ class UserDeviceConfigurationCubit extends Cubit<UserDeviceConfigurationState> {
   }
 
   Future<void> addWebsocketDeviceName(String protocol, String name) async {
-    await api!.addWebsocketSpecifier(protocol: protocol, name: name);
+    try {
+      await api!.addWebsocketSpecifier(protocol: protocol, name: name);
+    } catch (e) {
+      logError("Error adding websocket device $name for $protocol");
+      logError(e);
+      return;
+    }
     await _saveConfigFile();
   }
 
@@ -83,8 +89,14 @@ class UserDeviceConfigurationCubit extends Cubit<UserDeviceConfigurationState> {
 
   Future<void> addSerialPort(
       String protocol, String port, int baudRate, int dataBits, int stopBits, String parity) async {
-    await api!.addSerialSpecifier(
-        protocol: protocol, port: port, baudRate: baudRate, dataBits: dataBits, stopBits: stopBits, parity: parity);
+    try {
+      await api!.addSerialSpecifier(
+          protocol: protocol, port: port, baudRate: baudRate, dataBits: dataBits, stopBits: stopBits, parity: parity);
+    } catch (e) {
+      logError("Error adding serial port $port for $protocol");
+      logError(e);
+      return;
+    }
     await _saveConfigFile();
   }
 
@@ -127,7 +139,13 @@ class UserDeviceConfigurationCubit extends Cubit<UserDeviceConfigurationState> {
   }
 
   Future<void> updateDefinition(ExposedUserDeviceIdentifier deviceIdentifier, ExposedUserDeviceDefinition def) async {
-    await api!.updateUserConfig(identifier: deviceIdentifier, config: def);
+    try {
+      await api!.updateUserConfig(identifier: deviceIdentifier, config: def);
+    } catch (e) {
+      logError("Error updating config for ${def.name}");
+      logError(e);
+      return;
+    }
     await _saveConfigFile();
   }
 
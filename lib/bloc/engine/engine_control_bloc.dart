@@ -2,7 +2,7 @@ import 'dart:convert';
 
 import 'package:bloc/bloc.dart';
 import 'package:buttplug/buttplug.dart';
-import 'package:intiface_central/bridge_generated.dart';
+import 'package:intiface_central/bridge_generated.dart' hide IntifaceMessage;
 import 'package:intiface_central/bloc/engine/engine_messages.dart';
 import 'package:intiface_central/bloc/engine/engine_repository.dart';
 import 'package:loggy/loggy.dart';
@@ -1,6 +1,7 @@
 import 'dart:convert';
 
 import 'package:intiface_central/bridge_generated.dart';
+import 'package:intiface_central/bridge_generated.dart' as bridge;
 import 'package:json_annotation/json_annotation.dart';
 import 'package:equatable/equatable.dart';
 
@@ -32,6 +33,16 @@ class IntifaceMessage {
   Map<String, dynamic> toJson() => _$IntifaceMessageToJson(this);
 
   IntifaceMessage();
+
+  // The bridge takes engine messages typed now, but they still get passed around here as JSON (the
+  // foreground task can only hand strings across), so they're converted right before sending.
+  static bridge.IntifaceMessage toBridge(String msgJson) {
+    var msg = IntifaceMessage.fromJson(jsonDecode(msgJson));
+    if (msg.requestEngineVersion != null) {
+      return bridge.IntifaceMessage.requestEngineVersion(expectedVersion: msg.requestEngineVersion!.expectedVersion);
+    }
+    return const bridge.IntifaceMessage.stop();
+  }
 }
 
 @JsonSerializable()
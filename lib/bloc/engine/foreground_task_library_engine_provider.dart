@@ -4,7 +4,7 @@ import 'dart:ui';
 import 'package:flutter_rust_bridge/flutter_rust_bridge.dart';
 import 'package:intiface_central/bloc/configuration/intiface_configuration_cubit.dart';
 import 'package:intiface_central/bloc/engine/engine_messages.dart';
-import "../../ffi.dart";
+import "../../ffi.dart" hide IntifaceMessage;
 import 'package:intiface_central/bloc/engine/engine_provider.dart';
 import 'package:intiface_central/util/intiface_util.dart';
 import 'package:loggy/loggy.dart';
@@ -98,7 +98,7 @@ class IntifaceEngineTaskHandler extends TaskHandler {
       }
     });
     _serverMessageReceivePort.listen((element) async {
-      await api!.send(msgJson: element);
+      await api!.send(msg: IntifaceMessage.toBridge(element));
     });
     _backdoorMessageReceivePort.listen((element) async {
       await api!.sendBackendServerMessage(msg: element);
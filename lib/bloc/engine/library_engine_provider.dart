@@ -1,5 +1,6 @@
 import 'dart:async';
-import "../../ffi.dart";
+import "../../ffi.dart" hide IntifaceMessage;
+import 'package:intiface_central/bloc/engine/engine_messages.dart';
 import 'package:intiface_central/bloc/engine/engine_provider.dart';
 import 'package:loggy/loggy.dart';
 
@@ -47,7 +48,7 @@ class LibraryEngineProvider implements EngineProvider {
 
   @override
   void send(String msg) {
-    api!.send(msgJson: msg);
+    api!.send(msg: IntifaceMessage.toBridge(msg));
   }
 
   @override
@@ -7,9 +7,12 @@ import 'dart:async';
 import 'package:meta/meta.dart';
 import 'package:flutter_rust_bridge/flutter_rust_bridge.dart';
 import 'package:uuid/uuid.dart';
+import 'package:freezed_annotation/freezed_annotation.dart' hide protected;
 
 import 'dart:ffi' as ffi;
 
+part 'bridge_generated.freezed.dart';
+
 abstract class IntifaceEngineFlutterBridge {
   Future<bool> runtimeStarted({dynamic hint});
 
@@ -19,7 +22,7 @@ abstract class IntifaceEngineFlutterBridge {
 
   FlutterRustBridgeTaskConstMeta get kRunEngineConstMeta;
 
-  Future<void> send({required String msgJson, dynamic hint});
+  Future<void> send({required IntifaceMessage msg, dynamic hint});
 
   FlutterRustBridgeTaskConstMeta get kSendConstMeta;
 
@@ -188,12 +191,14 @@ class ExposedDeviceFeature {
   final FeatureType featureType;
   final ExposedDeviceFeatureActuator? actuator;
   final ExposedDeviceFeatureSensor? sensor;
+  final bool enabled;
 
   const ExposedDeviceFeature({
     required this.description,
     required this.featureType,
     this.actuator,
     this.sensor,
+    required this.enabled,
   });
 }
 
@@ -296,6 +301,14 @@ enum FeatureType {
   Raw,
 }
 
+@freezed
+sealed class IntifaceMessage with _$IntifaceMessage {
+  const factory IntifaceMessage.requestEngineVersion({
+    required int expectedVersion,
+  }) = IntifaceMessage_RequestEngineVersion;
+  const factory IntifaceMessage.stop() = IntifaceMessage_Stop;
+}
+
 class IntifaceEngineFlutterBridgeImpl implements IntifaceEngineFlutterBridge {
   final IntifaceEngineFlutterBridgePlatform _platform;
   factory IntifaceEngineFlutterBridgeImpl(ExternalLibrary dylib) =>
@@ -342,14 +355,14 @@ class IntifaceEngineFlutterBridgeImpl implements IntifaceEngineFlutterBridge {
         argNames: ["args"],
       );
 
-  Future<void> send({required String msgJson, dynamic hint}) {
-    var arg0 = _platform.api2wire_String(msgJson);
+  Future<void> send({required IntifaceMessage msg, dynamic hint}) {
+    var arg0 = _platform.api2wire_box_autoadd_intiface_message(msg);
     return _platform.executeNormal(FlutterRustBridgeTask(
       callFfi: (port_) => _platform.inner.wire_send(port_, arg0),
       parseSuccessData: _wire2api_unit,
       parseErrorData: null,
       constMeta: kSendConstMeta,
-      argValues: [msgJson],
+      argValues: [msg],
       hint: hint,
     ));
   }
@@ -357,7 +370,7 @@ class IntifaceEngineFlutterBridgeImpl implements IntifaceEngineFlutterBridge {
   FlutterRustBridgeTaskConstMeta get kSendConstMeta =>
       const FlutterRustBridgeTaskConstMeta(
         debugName: "send",
-        argNames: ["msgJson"],
+        argNames: ["msg"],
       );
 
   Future<void> stopEngine({dynamic hint}) {
@@ -404,7 +417,7 @@ class IntifaceEngineFlutterBridgeImpl implements IntifaceEngineFlutterBridge {
       callFfi: (port_) => _platform.inner
           .wire_setup_device_configuration_manager(port_, arg0, arg1),
       parseSuccessData: _wire2api_unit,
-      parseErrorData: null,
+      parseErrorData: _wire2api_FrbAnyhowException,
       constMeta: kSetupDeviceConfigurationManagerConstMeta,
       argValues: [baseConfig, userConfig],
       hint: hint,
@@ -505,7 +518,7 @@ class IntifaceEngineFlutterBridgeImpl implements IntifaceEngineFlutterBridge {
       callFfi: (port_) =>
           _platform.inner.wire_add_websocket_specifier(port_, arg0, arg1),
       parseSuccessData: _wire2api_unit,
-      parseErrorData: null,
+      parseErrorData: _wire2api_FrbAnyhowException,
       constMeta: kAddWebsocketSpecifierConstMeta,
       argValues: [protocol, name],
       hint: hint,
@@ -557,7 +570,7 @@ class IntifaceEngineFlutterBridgeImpl implements IntifaceEngineFlutterBridge {
       callFfi: (port_) => _platform.inner
           .wire_add_serial_specifier(port_, arg0, arg1, arg2, arg3, arg4, arg5),
       parseSuccessData: _wire2api_unit,
-      parseErrorData: null,
+      parseErrorData: _wire2api_FrbAnyhowException,
       constMeta: kAddSerialSpecifierConstMeta,
       argValues: [protocol, port, baudRate, dataBits, stopBits, parity],
       hint: hint,
@@ -610,7 +623,7 @@ class IntifaceEngineFlutterBridgeImpl implements IntifaceEngineFlutterBridge {
       callFfi: (port_) =>
           _platform.inner.wire_update_user_config(port_, arg0, arg1),
       parseSuccessData: _wire2api_unit,
-      parseErrorData: null,
+      parseErrorData: _wire2api_FrbAnyhowException,
       constMeta: kUpdateUserConfigConstMeta,
       argValues: [identifier, config],
       hint: hint,
@@ -816,14 +829,15 @@ class IntifaceEngineFlutterBridgeImpl implements IntifaceEngineFlutterBridge {
 
   ExposedDeviceFeature _wire2api_exposed_device_feature(dynamic raw) {
     final arr = raw as List<dynamic>;
-    if (arr.length != 4)
-      throw Exception('unexpected arr length: expect 4 but see ${arr.length}');
+    if (arr.length != 5)
+      throw Exception('unexpected arr length: expect 5 but see ${arr.length}');
     return ExposedDeviceFeature(
       description: _wire2api_String(arr[0]),
       featureType: _wire2api_feature_type(arr[1]),
       actuator:
           _wire2api_opt_box_autoadd_exposed_device_feature_actuator(arr[2]),
       sensor: _wire2api_opt_box_autoadd_exposed_device_feature_sensor(arr[3]),
+      enabled: _wire2api_bool(arr[4]),
     );
   }
 
@@ -1102,6 +1116,14 @@ class IntifaceEngineFlutterBridgePlatform
     return ptr;
   }
 
+  @protected
+  ffi.Pointer<wire_IntifaceMessage> api2wire_box_autoadd_intiface_message(
+      IntifaceMessage raw) {
+    final ptr = inner.new_box_autoadd_intiface_message_0();
+    _api_fill_to_wire_intiface_message(raw, ptr.ref);
+    return ptr;
+  }
+
   @protected
   ffi.Pointer<ffi.Uint16> api2wire_box_autoadd_u16(int raw) {
     return inner.new_box_autoadd_u16_0(api2wire_u16(raw));
@@ -1231,6 +1253,11 @@ class IntifaceEngineFlutterBridgePlatform
     _api_fill_to_wire_exposed_user_device_identifier(apiObj, wireObj.ref);
   }
 
+  void _api_fill_to_wire_box_autoadd_intiface_message(
+      IntifaceMessage apiObj, ffi.Pointer<wire_IntifaceMessage> wireObj) {
+    _api_fill_to_wire_intiface_message(apiObj, wireObj.ref);
+  }
+
   void _api_fill_to_wire_engine_options_external(
       EngineOptionsExternal apiObj, wire_EngineOptionsExternal wireObj) {
     wireObj.device_config_json = api2wire_opt_String(apiObj.deviceConfigJson);
@@ -1281,6 +1308,7 @@ class IntifaceEngineFlutterBridgePlatform
         apiObj.actuator);
     wireObj.sensor =
         api2wire_opt_box_autoadd_exposed_device_feature_sensor(apiObj.sensor);
+    wireObj.enabled = api2wire_bool(apiObj.enabled);
   }
 
   void _api_fill_to_wire_exposed_device_feature_actuator(
@@ -1325,6 +1353,23 @@ class IntifaceEngineFlutterBridgePlatform
     wireObj.protocol = api2wire_String(apiObj.protocol);
     wireObj.identifier = api2wire_opt_String(apiObj.identifier);
   }
+
+  void _api_fill_to_wire_intiface_message(
+      IntifaceMessage apiObj, wire_IntifaceMessage wireObj) {
+    if (apiObj is IntifaceMessage_RequestEngineVersion) {
+      var pre_expected_version = api2wire_u32(apiObj.expectedVersion);
+      wireObj.tag = 0;
+      wireObj.kind = inner.inflate_IntifaceMessage_RequestEngineVersion();
+      wireObj.kind.ref.RequestEngineVersion.ref.expected_version =
+          pre_expected_version;
+      return;
+    }
+    if (apiObj is IntifaceMessage_Stop) {
+      wireObj.tag = 1;
+      wireObj.kind = inner.inflate_IntifaceMessage_Stop();
+      return;
+    }
+  }
 }
 
 // ignore_for_file: camel_case_types, non_constant_identifier_names, avoid_positional_boolean_parameters, annotate_overrides, constant_identifier_names
@@ -1456,20 +1501,20 @@ class IntifaceEngineFlutterBridgeWire implements FlutterRustBridgeWireBase {
 
   void wire_send(
     int port_,
-    ffi.Pointer<wire_uint_8_list> msg_json,
+    ffi.Pointer<wire_IntifaceMessage> msg,
   ) {
     return _wire_send(
       port_,
-      msg_json,
+      msg,
     );
   }
 
   late final _wire_sendPtr = _lookup<
       ffi.NativeFunction<
           ffi.Void Function(
-              ffi.Int64, ffi.Pointer<wire_uint_8_list>)>>('wire_send');
+              ffi.Int64, ffi.Pointer<wire_IntifaceMessage>)>>('wire_send');
   late final _wire_send = _wire_sendPtr
-      .asFunction<void Function(int, ffi.Pointer<wire_uint_8_list>)>();
+      .asFunction<void Function(int, ffi.Pointer<wire_IntifaceMessage>)>();
 
   void wire_stop_engine(
     int port_,
@@ -1853,6 +1898,17 @@ class IntifaceEngineFlutterBridgeWire implements FlutterRustBridgeWireBase {
       _new_box_autoadd_exposed_user_device_identifier_0Ptr.asFunction<
           ffi.Pointer<wire_ExposedUserDeviceIdentifier> Function()>();
 
+  ffi.Pointer<wire_IntifaceMessage> new_box_autoadd_intiface_message_0() {
+    return _new_box_autoadd_intiface_message_0();
+  }
+
+  late final _new_box_autoadd_intiface_message_0Ptr =
+      _lookup<ffi.NativeFunction<ffi.Pointer<wire_IntifaceMessage> Function()>>(
+          'new_box_autoadd_intiface_message_0');
+  late final _new_box_autoadd_intiface_message_0 =
+      _new_box_autoadd_intiface_message_0Ptr
+          .asFunction<ffi.Pointer<wire_IntifaceMessage> Function()>();
+
   ffi.Pointer<ffi.Uint16> new_box_autoadd_u16_0(
     int value,
   ) {
@@ -1951,6 +2007,28 @@ class IntifaceEngineFlutterBridgeWire implements FlutterRustBridgeWireBase {
   late final _new_uint_8_list_0 = _new_uint_8_list_0Ptr
       .asFunction<ffi.Pointer<wire_uint_8_list> Function(int)>();
 
+  ffi.Pointer<IntifaceMessageKind>
+      inflate_IntifaceMessage_RequestEngineVersion() {
+    return _inflate_IntifaceMessage_RequestEngineVersion();
+  }
+
+  late final _inflate_IntifaceMessage_RequestEngineVersionPtr = _lookup<
+          ffi.NativeFunction<ffi.Pointer<IntifaceMessageKind> Function()>>(
+      'inflate_IntifaceMessage_RequestEngineVersion');
+  late final _inflate_IntifaceMessage_RequestEngineVersion =
+      _inflate_IntifaceMessage_RequestEngineVersionPtr
+          .asFunction<ffi.Pointer<IntifaceMessageKind> Function()>();
+
+  ffi.Pointer<IntifaceMessageKind> inflate_IntifaceMessage_Stop() {
+    return _inflate_IntifaceMessage_Stop();
+  }
+
+  late final _inflate_IntifaceMessage_StopPtr = _lookup<
+          ffi.NativeFunction<ffi.Pointer<IntifaceMessageKind> Function()>>(
+      'inflate_IntifaceMessage_Stop');
+  late final _inflate_IntifaceMessage_Stop = _inflate_IntifaceMessage_StopPtr
+      .asFunction<ffi.Pointer<IntifaceMessageKind> Function()>();
+
   void free_WireSyncReturn(
     WireSyncReturn ptr,
   ) {
@@ -2063,6 +2141,27 @@ final class wire_EngineOptionsExternal extends ffi.Struct {
   external ffi.Pointer<wire_uint_8_list> repeater_remote_address;
 }
 
+final class wire_IntifaceMessage_RequestEngineVersion extends ffi.Struct {
+  @ffi.Uint32()
+  external int expected_version;
+}
+
+final class wire_IntifaceMessage_Stop extends ffi.Opaque {}
+
+final class IntifaceMessageKind extends ffi.Union {
+  external ffi.Pointer<wire_IntifaceMessage_RequestEngineVersion>
+      RequestEngineVersion;
+
+  external ffi.Pointer<wire_IntifaceMessage_Stop> Stop;
+}
+
+final class wire_IntifaceMessage extends ffi.Struct {
+  @ffi.Int32()
+  external int tag;
+
+  external ffi.Pointer<IntifaceMessageKind> kind;
+}
+
 final class wire_ExposedUserDeviceIdentifier extends ffi.Struct {
   external ffi.Pointer<wire_uint_8_list> address;
 
@@ -2133,6 +2232,9 @@ final class wire_ExposedDeviceFeature extends ffi.Struct {
   external ffi.Pointer<wire_ExposedDeviceFeatureActuator> actuator;
 
   external ffi.Pointer<wire_ExposedDeviceFeatureSensor> sensor;
+
+  @ffi.Bool()
+  external bool enabled;
 }
 
 final class wire_list_exposed_device_feature extends ffi.Struct {
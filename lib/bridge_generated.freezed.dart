@@ -0,0 +1,331 @@
+// coverage:ignore-file
+// GENERATED CODE - DO NOT MODIFY BY HAND
+// ignore_for_file: type=lint
+// ignore_for_file: unused_element, deprecated_member_use, deprecated_member_use_from_same_package, use_function_type_syntax_for_parameters, unnecessary_const, avoid_init_to_null, invalid_override_different_default_values_named, prefer_expression_function_bodies, annotate_overrides, invalid_annotation_target, unnecessary_question_mark
+
+part of 'bridge_generated.dart';
+
+// **************************************************************************
+// FreezedGenerator
+// **************************************************************************
+
+T _$identity<T>(T value) => value;
+
+final _privateConstructorUsedError = UnsupportedError(
+    'It seems like you constructed your class using `MyClass._()`. This constructor is only meant to be used by freezed and you are not supposed to need it nor use it.\nPlease check the documentation here for more information: https://github.com/rrousselGit/freezed#adding-getters-and-methods-to-our-models');
+
+/// @nodoc
+mixin _$IntifaceMessage {
+  @optionalTypeArgs
+  TResult when<TResult extends Object?>({
+    required TResult Function(int expectedVersion) requestEngineVersion,
+    required TResult Function() stop,
+  }) =>
+      throw _privateConstructorUsedError;
+  @optionalTypeArgs
+  TResult? whenOrNull<TResult extends Object?>({
+    TResult? Function(int expectedVersion)? requestEngineVersion,
+    TResult? Function()? stop,
+  }) =>
+      throw _privateConstructorUsedError;
+  @optionalTypeArgs
+  TResult maybeWhen<TResult extends Object?>({
+    TResult Function(int expectedVersion)? requestEngineVersion,
+    TResult Function()? stop,
+    required TResult orElse(),
+  }) =>
+      throw _privateConstructorUsedError;
+  @optionalTypeArgs
+  TResult map<TResult extends Object?>({
+    required TResult Function(IntifaceMessage_RequestEngineVersion value)
+        requestEngineVersion,
+    required TResult Function(IntifaceMessage_Stop value) stop,
+  }) =>
+      throw _privateConstructorUsedError;
+  @optionalTypeArgs
+  TResult? mapOrNull<TResult extends Object?>({
+    TResult? Function(IntifaceMessage_RequestEngineVersion value)?
+        requestEngineVersion,
+    TResult? Function(IntifaceMessage_Stop value)? stop,
+  }) =>
+      throw _privateConstructorUsedError;
+  @optionalTypeArgs
+  TResult maybeMap<TResult extends Object?>({
+    TResult Function(IntifaceMessage_RequestEngineVersion value)?
+        requestEngineVersion,
+    TResult Function(IntifaceMessage_Stop value)? stop,
+    required TResult orElse(),
+  }) =>
+      throw _privateConstructorUsedError;
+}
+
+/// @nodoc
+abstract class $IntifaceMessageCopyWith<$Res> {
+  factory $IntifaceMessageCopyWith(
+          IntifaceMessage value, $Res Function(IntifaceMessage) then) =
+      _$IntifaceMessageCopyWithImpl<$Res, IntifaceMessage>;
+}
+
+/// @nodoc
+class _$IntifaceMessageCopyWithImpl<$Res, $Val extends IntifaceMessage>
+    implements $IntifaceMessageCopyWith<$Res> {
+  _$IntifaceMessageCopyWithImpl(this._value, this._then);
+
+  // ignore: unused_field
+  final $Val _value;
+  // ignore: unused_field
+  final $Res Function($Val) _then;
+}
+
+/// @nodoc
+abstract class _$$IntifaceMessage_RequestEngineVersionImplCopyWith<$Res> {
+  factory _$$IntifaceMessage_RequestEngineVersionImplCopyWith(
+          _$IntifaceMessage_RequestEngineVersionImpl value,
+          $Res Function(_$IntifaceMessage_RequestEngineVersionImpl) then) =
+      __$$IntifaceMessage_RequestEngineVersionImplCopyWithImpl<$Res>;
+  @useResult
+  $Res call({int expectedVersion});
+}
+
+/// @nodoc
+class __$$IntifaceMessage_RequestEngineVersionImplCopyWithImpl<$Res>
+    extends _$IntifaceMessageCopyWithImpl<$Res,
+        _$IntifaceMessage_RequestEngineVersionImpl>
+    implements _$$IntifaceMessage_RequestEngineVersionImplCopyWith<$Res> {
+  __$$IntifaceMessage_RequestEngineVersionImplCopyWithImpl(
+      _$IntifaceMessage_RequestEngineVersionImpl _value,
+      $Res Function(_$IntifaceMessage_RequestEngineVersionImpl) _then)
+      : super(_value, _then);
+
+  @pragma('vm:prefer-inline')
+  @override
+  $Res call({
+    Object? expectedVersion = null,
+  }) {
+    return _then(_$IntifaceMessage_RequestEngineVersionImpl(
+      expectedVersion: null == expectedVersion
+          ? _value.expectedVersion
+          : expectedVersion // ignore: cast_nullable_to_non_nullable
+              as int,
+    ));
+  }
+}
+
+/// @nodoc
+
+class _$IntifaceMessage_RequestEngineVersionImpl
+    implements IntifaceMessage_RequestEngineVersion {
+  const _$IntifaceMessage_RequestEngineVersionImpl(
+      {required this.expectedVersion});
+
+  @override
+  final int expectedVersion;
+
+  @override
+  String toString() {
+    return 'IntifaceMessage.requestEngineVersion(expectedVersion: $expectedVersion)';
+  }
+
+  @override
+  bool operator ==(Object other) {
+    return identical(this, other) ||
+        (other.runtimeType == runtimeType &&
+            other is _$IntifaceMessage_RequestEngineVersionImpl &&
+            (identical(other.expectedVersion, expectedVersion) ||
+                other.expectedVersion == expectedVersion));
+  }
+
+  @override
+  int get hashCode => Object.hash(runtimeType, expectedVersion);
+
+  @JsonKey(ignore: true)
+  @override
+  @pragma('vm:prefer-inline')
+  _$$IntifaceMessage_RequestEngineVersionImplCopyWith<
+          _$IntifaceMessage_RequestEngineVersionImpl>
+      get copyWith => __$$IntifaceMessage_RequestEngineVersionImplCopyWithImpl<
+          _$IntifaceMessage_RequestEngineVersionImpl>(this, _$identity);
+
+  @override
+  @optionalTypeArgs
+  TResult when<TResult extends Object?>({
+    required TResult Function(int expectedVersion) requestEngineVersion,
+    required TResult Function() stop,
+  }) {
+    return requestEngineVersion(expectedVersion);
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult? whenOrNull<TResult extends Object?>({
+    TResult? Function(int expectedVersion)? requestEngineVersion,
+    TResult? Function()? stop,
+  }) {
+    return requestEngineVersion?.call(expectedVersion);
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult maybeWhen<TResult extends Object?>({
+    TResult Function(int expectedVersion)? requestEngineVersion,
+    TResult Function()? stop,
+    required TResult orElse(),
+  }) {
+    if (requestEngineVersion != null) {
+      return requestEngineVersion(expectedVersion);
+    }
+    return orElse();
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult map<TResult extends Object?>({
+    required TResult Function(IntifaceMessage_RequestEngineVersion value)
+        requestEngineVersion,
+    required TResult Function(IntifaceMessage_Stop value) stop,
+  }) {
+    return requestEngineVersion(this);
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult? mapOrNull<TResult extends Object?>({
+    TResult? Function(IntifaceMessage_RequestEngineVersion value)?
+        requestEngineVersion,
+    TResult? Function(IntifaceMessage_Stop value)? stop,
+  }) {
+    return requestEngineVersion?.call(this);
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult maybeMap<TResult extends Object?>({
+    TResult Function(IntifaceMessage_RequestEngineVersion value)?
+        requestEngineVersion,
+    TResult Function(IntifaceMessage_Stop value)? stop,
+    required TResult orElse(),
+  }) {
+    if (requestEngineVersion != null) {
+      return requestEngineVersion(this);
+    }
+    return orElse();
+  }
+}
+
+abstract class IntifaceMessage_RequestEngineVersion implements IntifaceMessage {
+  const factory IntifaceMessage_RequestEngineVersion(
+          {required final int expectedVersion}) =
+      _$IntifaceMessage_RequestEngineVersionImpl;
+
+  int get expectedVersion;
+  @JsonKey(ignore: true)
+  _$$IntifaceMessage_RequestEngineVersionImplCopyWith<
+          _$IntifaceMessage_RequestEngineVersionImpl>
+      get copyWith => throw _privateConstructorUsedError;
+}
+
+/// @nodoc
+abstract class _$$IntifaceMessage_StopImplCopyWith<$Res> {
+  factory _$$IntifaceMessage_StopImplCopyWith(_$IntifaceMessage_StopImpl value,
+          $Res Function(_$IntifaceMessage_StopImpl) then) =
+      __$$IntifaceMessage_StopImplCopyWithImpl<$Res>;
+}
+
+/// @nodoc
+class __$$IntifaceMessage_StopImplCopyWithImpl<$Res>
+    extends _$IntifaceMessageCopyWithImpl<$Res, _$IntifaceMessage_StopImpl>
+    implements _$$IntifaceMessage_StopImplCopyWith<$Res> {
+  __$$IntifaceMessage_StopImplCopyWithImpl(_$IntifaceMessage_StopImpl _value,
+      $Res Function(_$IntifaceMessage_StopImpl) _then)
+      : super(_value, _then);
+}
+
+/// @nodoc
+
+class _$IntifaceMessage_StopImpl implements IntifaceMessage_Stop {
+  const _$IntifaceMessage_StopImpl();
+
+  @override
+  String toString() {
+    return 'IntifaceMessage.stop()';
+  }
+
+  @override
+  bool operator ==(Object other) {
+    return identical(this, other) ||
+        (other.runtimeType == runtimeType &&
+            other is _$IntifaceMessage_StopImpl);
+  }
+
+  @override
+  int get hashCode => runtimeType.hashCode;
+
+  @override
+  @optionalTypeArgs
+  TResult when<TResult extends Object?>({
+    required TResult Function(int expectedVersion) requestEngineVersion,
+    required TResult Function() stop,
+  }) {
+    return stop();
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult? whenOrNull<TResult extends Object?>({
+    TResult? Function(int expectedVersion)? requestEngineVersion,
+    TResult? Function()? stop,
+  }) {
+    return stop?.call();
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult maybeWhen<TResult extends Object?>({
+    TResult Function(int expectedVersion)? requestEngineVersion,
+    TResult Function()? stop,
+    required TResult orElse(),
+  }) {
+    if (stop != null) {
+      return stop();
+    }
+    return orElse();
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult map<TResult extends Object?>({
+    required TResult Function(IntifaceMessage_RequestEngineVersion value)
+        requestEngineVersion,
+    required TResult Function(IntifaceMessage_Stop value) stop,
+  }) {
+    return stop(this);
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult? mapOrNull<TResult extends Object?>({
+    TResult? Function(IntifaceMessage_RequestEngineVersion value)?
+        requestEngineVersion,
+    TResult? Function(IntifaceMessage_Stop value)? stop,
+  }) {
+    return stop?.call(this);
+  }
+
+  @override
+  @optionalTypeArgs
+  TResult maybeMap<TResult extends Object?>({
+    TResult Function(IntifaceMessage_RequestEngineVersion value)?
+        requestEngineVersion,
+    TResult Function(IntifaceMessage_Stop value)? stop,
+    required TResult orElse(),
+  }) {
+    if (stop != null) {
+      return stop(this);
+    }
+    return orElse();
+  }
+}
+
+abstract class IntifaceMessage_Stop implements IntifaceMessage {
+  const factory IntifaceMessage_Stop() = _$IntifaceMessage_StopImpl;
+}
@@ -59,7 +59,8 @@ class ActuatorFeatureConfigWidget extends StatelessWidget {
                                       description: feature.description,
                                       featureType: feature.featureType,
                                       actuator: featureActuator,
-                                      sensor: feature.sensor);
+                                      sensor: feature.sensor,
+                                      enabled: feature.enabled);
                                   await userConfigCubit.updateFeature(
                                       _deviceIdentifier, _deviceDefinition, index, newFeature);
                                 }),
@@ -92,7 +93,8 @@ class ActuatorFeatureConfigWidget extends StatelessWidget {
                                       description: feature.description,
                                       featureType: feature.featureType,
                                       actuator: featureActuator,
-                                      sensor: feature.sensor);
+                                      sensor: feature.sensor,
+                                      enabled: feature.enabled);
                                   await userConfigCubit.updateFeature(
                                       _deviceIdentifier, _deviceDefinition, index, newFeature);
                                 }),